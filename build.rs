@@ -0,0 +1,8 @@
+//! Passes the target on to the tests, which compile `tests/ffi.c` for it
+//! with the `cc` crate.
+
+fn main() {
+    let target = std::env::var("TARGET").unwrap();
+    println!("cargo:rustc-env=SPATIOCYTE_TARGET={}", target);
+    println!("cargo:rerun-if-changed=build.rs");
+}
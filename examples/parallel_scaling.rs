@@ -0,0 +1,41 @@
+//! Times `ParallelSimulator` on a crowded lattice with 1 to 8 threads.
+//!
+//! Run with `cargo run --release --features rayon --example parallel_scaling`.
+
+use spatiocyte::{HCPLatticeSize, HCPLatticeSpace, MoleculeInfo, ParallelSimulator, Species};
+use std::time::{Duration, Instant};
+
+const SIZE: usize = 96;
+const SLABS: usize = 8;
+
+fn run(threads: usize) -> Duration {
+    let mut space = HCPLatticeSpace::new(1e-8, HCPLatticeSize::new(SIZE, SIZE, SIZE));
+    space.set_periodic(true).unwrap();
+    let a = space.register_species(Species::new("A"), None);
+    space
+        .set_molecule_info(
+            a,
+            MoleculeInfo {
+                radius: 1e-8,
+                diffusion_coefficient: 1e-12,
+            },
+        )
+        .unwrap();
+    for coordinate in space.coordinates().step_by(3).collect::<Vec<_>>() {
+        space.place_particle(a, coordinate).unwrap();
+    }
+    let mut sim = ParallelSimulator::new(space, SLABS, 1).unwrap();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .unwrap();
+    let start = Instant::now();
+    pool.install(|| sim.run(1e-3)).unwrap();
+    start.elapsed()
+}
+
+fn main() {
+    for threads in [1, 2, 4, 8] {
+        println!("{} threads: {:?}", threads, run(threads));
+    }
+}
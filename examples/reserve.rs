@@ -0,0 +1,29 @@
+//! Compares seeding a tracked species with and without `reserve`.
+//!
+//! Run with `cargo run --release --example reserve`.
+
+use spatiocyte::{HCPLatticeSize, HCPLatticeSpace, Species};
+use std::time::{Duration, Instant};
+
+const SIZE: usize = 200;
+const MOLECULES: usize = 4_000_000;
+
+fn seed(reserve: bool) -> Duration {
+    let mut space = HCPLatticeSpace::new(1e-8, HCPLatticeSize::new(SIZE, SIZE, SIZE));
+    let a = space.register_species(Species::new("A"), None);
+    let coordinates: Vec<_> = space.coordinates().step_by(2).take(MOLECULES).collect();
+    let start = Instant::now();
+    if reserve {
+        space.reserve(a, MOLECULES).unwrap();
+    }
+    for coordinate in coordinates {
+        space.place_particle(a, coordinate).unwrap();
+    }
+    start.elapsed()
+}
+
+fn main() {
+    for _ in 0..3 {
+        println!("growing: {:?}, reserved: {:?}", seed(false), seed(true));
+    }
+}
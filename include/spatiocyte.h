@@ -0,0 +1,333 @@
+/* The C interface of spatiocyte, enabled by its `ffi` feature. See the
+ * documentation of the `ffi` module for ownership and status codes: every
+ * function taking or returning a handle documents whether it takes,
+ * borrows or returns ownership of it. */
+
+#ifndef SPATIOCYTE_H
+#define SPATIOCYTE_H
+
+/* Generated by cbindgen from src/ffi.rs: do not edit. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+typedef struct SpatiocyteSpace SpatiocyteSpace;
+
+/**
+ * A success.
+ */
+#define SPATIOCYTE_OK 0
+
+/**
+ * A panic inside the engine.
+ */
+#define SPATIOCYTE_PANIC -1
+
+/**
+ * A null handle or pointer where one was required.
+ */
+#define SPATIOCYTE_NULL_POINTER -2
+
+/**
+ * A name that is not valid UTF-8.
+ */
+#define SPATIOCYTE_INVALID_NAME -3
+
+/**
+ * A buffer shorter than what is written into it.
+ */
+#define SPATIOCYTE_BUFFER_TOO_SMALL -4
+
+/**
+ * `Error::OutOfRange`.
+ */
+#define SPATIOCYTE_OUT_OF_RANGE 1
+
+/**
+ * `Error::ParticleNotFound`.
+ */
+#define SPATIOCYTE_PARTICLE_NOT_FOUND 2
+
+/**
+ * `Error::InvalidLocation`.
+ */
+#define SPATIOCYTE_INVALID_LOCATION 3
+
+/**
+ * `Error::InvalidWeights`.
+ */
+#define SPATIOCYTE_INVALID_WEIGHTS 4
+
+/**
+ * `Error::NotAdjacent`.
+ */
+#define SPATIOCYTE_NOT_ADJACENT 5
+
+/**
+ * `Error::PositionOutOfRange`.
+ */
+#define SPATIOCYTE_POSITION_OUT_OF_RANGE 6
+
+/**
+ * `Error::SpeciesNotFound`.
+ */
+#define SPATIOCYTE_SPECIES_NOT_FOUND 7
+
+/**
+ * `Error::InvalidReaction`.
+ */
+#define SPATIOCYTE_INVALID_REACTION 8
+
+/**
+ * `Error::InsufficientSpace`.
+ */
+#define SPATIOCYTE_INSUFFICIENT_SPACE 9
+
+/**
+ * `Error::UnknownParticle`.
+ */
+#define SPATIOCYTE_UNKNOWN_PARTICLE 10
+
+/**
+ * `Error::InvalidPeriodicSize`.
+ */
+#define SPATIOCYTE_INVALID_PERIODIC_SIZE 11
+
+/**
+ * `Error::InvalidDecomposition`.
+ */
+#define SPATIOCYTE_INVALID_DECOMPOSITION 12
+
+/**
+ * `Error::SizeOverflow`.
+ */
+#define SPATIOCYTE_SIZE_OVERFLOW 13
+
+/**
+ * `Error::InvalidTransition`.
+ */
+#define SPATIOCYTE_INVALID_TRANSITION 14
+
+/**
+ * `Error::OutOfPlane`.
+ */
+#define SPATIOCYTE_OUT_OF_PLANE 15
+
+/**
+ * `Error::InvalidFraction`.
+ */
+#define SPATIOCYTE_INVALID_FRACTION 16
+
+/**
+ * `Error::NotBulk`.
+ */
+#define SPATIOCYTE_NOT_BULK 17
+
+/**
+ * `Error::TrackingRequired`.
+ */
+#define SPATIOCYTE_TRACKING_REQUIRED 18
+
+/**
+ * `Error::PeriodicBoundary`.
+ */
+#define SPATIOCYTE_PERIODIC_BOUNDARY 19
+
+/**
+ * `Error::DiffusionLimited`.
+ */
+#define SPATIOCYTE_DIFFUSION_LIMITED 20
+
+/**
+ * `Error::StructureInUse`.
+ */
+#define SPATIOCYTE_STRUCTURE_IN_USE 21
+
+/**
+ * `Error::VoxelRadiusMismatch`.
+ */
+#define SPATIOCYTE_VOXEL_RADIUS_MISMATCH 22
+
+/**
+ * `Error::StaggeredOffset`.
+ */
+#define SPATIOCYTE_STAGGERED_OFFSET 23
+
+/**
+ * `Error::UnknownSpecies`.
+ */
+#define SPATIOCYTE_UNKNOWN_SPECIES 24
+
+/**
+ * `Error::Io`.
+ */
+#define SPATIOCYTE_IO 25
+
+/**
+ * `Error::Parse`.
+ */
+#define SPATIOCYTE_PARSE 26
+
+/**
+ * `Error::InvalidLength`.
+ */
+#define SPATIOCYTE_INVALID_LENGTH 27
+
+/**
+ * `Error::LotInUse`.
+ */
+#define SPATIOCYTE_LOT_IN_USE 28
+
+/**
+ * `Error::InvalidDuration`.
+ */
+#define SPATIOCYTE_INVALID_DURATION 29
+
+/**
+ * A simulator, owning its space.
+ */
+typedef struct SpatiocyteSimulator SpatiocyteSimulator;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates an empty space of `rows × cols × layers` voxels of radius
+ * `radius`, in meters, and returns it, or null if its number of voxels
+ * overflows or a panic occurred. The caller owns the space and frees it
+ * with `spatiocyte_space_free`, unless it passes it on to
+ * `spatiocyte_simulator_new`.
+ */
+SpatiocyteSpace *spatiocyte_space_new(double radius, size_t rows, size_t cols, size_t layers);
+
+/**
+ * Frees a space from `spatiocyte_space_new`. Null is ignored.
+ *
+ * # Safety
+ *
+ * `space` must be null or a space from `spatiocyte_space_new` not freed
+ * nor passed to `spatiocyte_simulator_new` yet.
+ */
+void spatiocyte_space_free(SpatiocyteSpace *space);
+
+/**
+ * Registers a tracked bulk species named `name`, diffusing with
+ * `diffusion_coefficient` in m²/s, and writes its index to `id`. `name`
+ * is borrowed for the call and copied.
+ *
+ * # Safety
+ *
+ * `space` must be a live space, `name` a NUL-terminated string and `id`
+ * writable.
+ */
+int spatiocyte_add_species(SpatiocyteSpace *space,
+                           const char *name,
+                           double diffusion_coefficient,
+                           size_t *id);
+
+/**
+ * Places a molecule of the species `species` on the voxel `coordinate`,
+ * as `HCPLatticeSpace::place_particle`.
+ *
+ * # Safety
+ *
+ * `space` must be a live space.
+ */
+int spatiocyte_add_particle(SpatiocyteSpace *space, size_t species, size_t coordinate);
+
+/**
+ * Returns the number of species of the space, 0 for null.
+ *
+ * # Safety
+ *
+ * `space` must be null or a live space.
+ */
+size_t spatiocyte_num_species(const SpatiocyteSpace *space);
+
+/**
+ * Writes the number of molecules of each species, by index, to the first
+ * `spatiocyte_num_species` entries of `buffer`, which holds `len`. The
+ * buffer stays the caller's.
+ *
+ * # Safety
+ *
+ * `space` must be a live space and `buffer` writable for `len` entries.
+ */
+int spatiocyte_counts_into(const SpatiocyteSpace *space, size_t *buffer, size_t len);
+
+/**
+ * Creates a simulator of `space`, drawing from a generator seeded with
+ * `seed`, and returns it, or null if `space` is null or a panic occurred.
+ * The simulator takes `space`, which the caller must not use nor free
+ * afterwards, even on failure, and reaches through
+ * `spatiocyte_simulator_space`. The caller owns the simulator and frees it
+ * with `spatiocyte_simulator_free`.
+ *
+ * # Safety
+ *
+ * `space` must be null or a live space.
+ */
+SpatiocyteSimulator *spatiocyte_simulator_new(SpatiocyteSpace *space, uint64_t seed);
+
+/**
+ * Frees a simulator and its space. Null is ignored.
+ *
+ * # Safety
+ *
+ * `simulator` must be null or a simulator from `spatiocyte_simulator_new`
+ * not freed yet.
+ */
+void spatiocyte_simulator_free(SpatiocyteSimulator *simulator);
+
+/**
+ * Returns the space of `simulator`, null for null. The space stays the
+ * simulator's: it lives until the simulator is freed, and must not be
+ * freed itself.
+ *
+ * # Safety
+ *
+ * `simulator` must be null or a live simulator.
+ */
+const SpatiocyteSpace *spatiocyte_simulator_space(const SpatiocyteSimulator *simulator);
+
+/**
+ * Adds the reaction of the `num_reactants` species named in `reactants`
+ * into the `num_products` named in `products` with rate `k`, as
+ * `Simulator::add_reaction`. The names are borrowed for the call.
+ *
+ * # Safety
+ *
+ * `simulator` must be a live simulator and `reactants` and `products`
+ * arrays of as many NUL-terminated strings as given.
+ */
+int spatiocyte_add_reaction(SpatiocyteSimulator *simulator,
+                            const char *const *reactants,
+                            size_t num_reactants,
+                            const char *const *products,
+                            size_t num_products,
+                            double k);
+
+/**
+ * Runs `simulator` for `duration` seconds, as `Simulator::run`.
+ *
+ * # Safety
+ *
+ * `simulator` must be a live simulator.
+ */
+int spatiocyte_run(SpatiocyteSimulator *simulator, double duration);
+
+/**
+ * Returns the time of `simulator`, NaN for null.
+ *
+ * # Safety
+ *
+ * `simulator` must be null or a live simulator.
+ */
+double spatiocyte_time(const SpatiocyteSimulator *simulator);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SPATIOCYTE_H */
@@ -0,0 +1,423 @@
+//! Analysis of recorded trajectories and of where the molecules of a
+//! space are.
+//!
+//! `density_profile` bins the molecules of a species along an axis of the
+//! box of `periodic_lengths`, folding positions into it, so that a periodic
+//! lattice wraps as it does and the staggered half voxels overhanging the
+//! box of a bounded one fall in the first bin. Each bin stands for the
+//! voxels centered in it, `4√2 r³` each as in the `units` module, rather
+//! than for its width: a bin catching fewer voxel centers than another,
+//! because of the stagger or of the bins not dividing the layers, holds a
+//! smaller volume, and a uniformly filled lattice gives a flat profile.
+//! Counted species keep their voxels, so that these functions and `rdf`
+//! work for them as for tracked ones.
+//!
+//! `rdf` normalizes by counts rather than by shell volumes, which are
+//! discrete on a lattice and cut by the faces of a bounded one: every bin
+//! compares the `B` molecules found around each `A` molecule with the
+//! voxels counted in the same bin around it, as many as an ideal uniform
+//! configuration would place molecules on, scaled by the density of `B`
+//! over the voxels other than that of the `A` molecule.
+
+use crate::units::AVOGADRO;
+use crate::{Error, HCPLatticeSpace, Region, Result, Species, SpeciesID, Trajectory};
+use std::collections::HashSet;
+
+/// An axis of real space.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Returns the mean squared displacement `(lag, msd)` for every lag up to
+/// `max_lag`, averaged over particles and time origins.
+///
+/// The trajectories must share one sampling interval, taken from the first
+/// trajectory with two samples. Positions are used as recorded, so with
+/// periodic boundaries they should come from an unwrapped
+/// `TrajectoryObserver`.
+pub fn msd(trajectories: &[Trajectory], max_lag: f64) -> Vec<(f64, f64)> {
+    let interval = match trajectories
+        .iter()
+        .find(|trajectory| trajectory.times.len() >= 2)
+    {
+        Some(trajectory) => trajectory.times[1] - trajectory.times[0],
+        None => return Vec::new(),
+    };
+    let max_steps = (max_lag / interval + 1e-9).floor() as usize;
+
+    let mut sums = vec![0.0; max_steps + 1];
+    let mut counts = vec![0usize; max_steps + 1];
+    for trajectory in trajectories {
+        let positions = &trajectory.positions;
+        for lag in 0..=max_steps.min(positions.len().saturating_sub(1)) {
+            for (p, q) in positions.iter().zip(&positions[lag..]) {
+                sums[lag] += (0..3).map(|i| (q[i] - p[i]).powi(2)).sum::<f64>();
+                counts[lag] += 1;
+            }
+        }
+    }
+    sums.iter()
+        .zip(&counts)
+        .enumerate()
+        .take_while(|(_, (_, &count))| count > 0)
+        .map(|(lag, (sum, &count))| (lag as f64 * interval, sum / count as f64))
+        .collect()
+}
+
+/// Fits `msd = 6 D t + c` by least squares over the nonzero lags and
+/// returns `D`, or NaN if there are fewer than two of them.
+pub fn fit_diffusion_coefficient(msd: &[(f64, f64)]) -> f64 {
+    fit_diffusion_coefficient_in(msd, 3)
+}
+
+/// Same as `fit_diffusion_coefficient` in `dimensions` dimensions, fitting
+/// `msd = 2 dimensions D t + c`; 2 for a lattice from
+/// `HCPLatticeSpace::new_2d`.
+pub fn fit_diffusion_coefficient_in(msd: &[(f64, f64)], dimensions: usize) -> f64 {
+    let points: Vec<(f64, f64)> = msd.iter().copied().filter(|&(t, _)| t > 0.0).collect();
+    if points.len() < 2 {
+        return f64::NAN;
+    }
+    let n = points.len() as f64;
+    let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+    let mean_m = points.iter().map(|(_, m)| m).sum::<f64>() / n;
+    let (cov, var) = points.iter().fold((0.0, 0.0), |(cov, var), (t, m)| {
+        (
+            cov + (t - mean_t) * (m - mean_m),
+            var + (t - mean_t).powi(2),
+        )
+    });
+    cov / var / (2 * dimensions) as f64
+}
+
+/// Returns the pair-correlation function `g(r)` of the molecules of `b`
+/// around those of `a`, `(r, g)` at the center of each of `bins` equal bins
+/// up to `r_max`, with distances between anchor voxels to the nearest
+/// periodic image. See the module documentation for the normalization.
+///
+/// For `a == b` a molecule is not paired with itself, and each pair is
+/// found from both ends, in the counts as in the ideal configuration. A bin
+/// no voxel distance falls in, as below one voxel diameter, is NaN, as is
+/// every bin if `a` has no molecules or `space` does not register it.
+pub fn rdf(
+    space: &HCPLatticeSpace,
+    a: &Species,
+    b: &Species,
+    r_max: f64,
+    bins: usize,
+) -> Vec<(f64, f64)> {
+    if bins == 0 {
+        return Vec::new();
+    }
+    let anchors = |species: &Species| {
+        space
+            .species_id(species)
+            .and_then(|id| space.coordinates_of(id).ok())
+            .unwrap_or_default()
+    };
+    let centers = anchors(a);
+    let others: HashSet<usize> = anchors(b).into_iter().map(|c| c.0).collect();
+    let width = r_max / bins as f64;
+    let mut found = vec![0usize; bins];
+    let mut ideal = vec![0usize; bins];
+    for &center in &centers {
+        // Every voxel within reach is a neighbor of one nearer to the
+        // center by less than a hop, as for `within_radius`.
+        let reach = r_max + 2.0 * space.voxel_radius;
+        let mut visited = HashSet::new();
+        visited.insert(center.0);
+        let mut frontier = vec![center];
+        while let Some(c) = frontier.pop() {
+            for neighbor in space.neighbors(c).expect("a voxel of the lattice") {
+                let d = space
+                    .distance(center, neighbor)
+                    .expect("a voxel of the lattice");
+                if d > reach || !visited.insert(neighbor.0) {
+                    continue;
+                }
+                frontier.push(neighbor);
+                if d < r_max {
+                    // Distances on a bin edge, like the voxel diameter, go
+                    // to the bin above whatever their rounding.
+                    let bin = ((d / width + 1e-9) as usize).min(bins - 1);
+                    ideal[bin] += 1;
+                    found[bin] += others.contains(&neighbor.0) as usize;
+                }
+            }
+        }
+    }
+    let density = (others.len() - (a == b) as usize) as f64 / (space.num_voxels() - 1) as f64;
+    found
+        .iter()
+        .zip(&ideal)
+        .enumerate()
+        .map(|(i, (&found, &ideal))| {
+            (
+                (i as f64 + 0.5) * width,
+                found as f64 / (ideal as f64 * density),
+            )
+        })
+        .collect()
+}
+
+impl HCPLatticeSpace {
+    /// Returns the center of each of `bins` equal slabs of the box along
+    /// `axis` and the molar concentration of `species` over the voxels
+    /// centered in it, NaN for a slab holding none. Multi-voxel molecules
+    /// count once, at their anchor. Fails with `SpeciesNotFound` for a
+    /// species not registered.
+    pub fn density_profile(
+        &self,
+        species: &Species,
+        axis: Axis,
+        bins: usize,
+    ) -> Result<Vec<(f64, f64)>> {
+        let id = self.registered(species)?;
+        if bins == 0 {
+            return Ok(Vec::new());
+        }
+        let length = self.periodic_lengths()[axis.index()];
+        let bin = |c| {
+            let p = self
+                .coordinate_to_position(c)
+                .expect("iterating over the lattice");
+            let t = (p[axis.index()] / length).rem_euclid(1.0);
+            // Tolerate the rounding of centers lying on a bin edge.
+            ((t * bins as f64 + 1e-9) as usize) % bins
+        };
+        let mut voxels = vec![0usize; bins];
+        for c in self.coordinates() {
+            voxels[bin(c)] += 1;
+        }
+        let mut counts = vec![0usize; bins];
+        for c in self.coordinates_of(id)? {
+            counts[bin(c)] += 1;
+        }
+        let liters = self.voxel_volume() * 1e3;
+        let width = length / bins as f64;
+        Ok((0..bins)
+            .map(|i| {
+                let molar = counts[i] as f64 / (AVOGADRO * liters * voxels[i] as f64);
+                ((i as f64 + 0.5) * width, molar)
+            })
+            .collect())
+    }
+
+    /// Returns the number of molecules of `species` centered in `region`,
+    /// multi-voxel ones by their anchor. Fails with `SpeciesNotFound` for a
+    /// species not registered.
+    pub fn count_in_region(&self, species: &Species, region: &dyn Region) -> Result<usize> {
+        let id = self.registered(species)?;
+        Ok(self
+            .coordinates_of(id)?
+            .into_iter()
+            .filter(|&c| {
+                region.contains(
+                    self.coordinate_to_position(c)
+                        .expect("a molecule on the lattice"),
+                )
+            })
+            .count())
+    }
+
+    fn registered(&self, species: &Species) -> Result<SpeciesID> {
+        self.species_id(species)
+            .ok_or_else(|| Error::SpeciesNotFound(species.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Coordinate, Cuboid, HCPLatticeSize, MoleculeInfo, Simulator, TrajectoryTarget};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn fit_recovers_diffusion_coefficient() {
+        let r = 1e-8;
+        let d = 1e-12;
+        let mut space = HCPLatticeSpace::new(r, HCPLatticeSize::new(30, 30, 30));
+        space.set_periodic(true).unwrap();
+        let a = space.register_species(Species::new("A"), None);
+        space
+            .set_molecule_info(
+                a,
+                MoleculeInfo {
+                    radius: r,
+                    diffusion_coefficient: d,
+                },
+            )
+            .unwrap();
+        for i in 0..200 {
+            space.place_particle(a, Coordinate(i * 131)).unwrap();
+        }
+        let mut sim = Simulator::new(space, StdRng::seed_from_u64(0));
+        let dt = 2.0 * r * r / (3.0 * d);
+        let observer = sim
+            .add_trajectory_observer(TrajectoryTarget::Species(Species::new("A")), dt, true)
+            .unwrap();
+        sim.run(400.0 * dt).unwrap();
+
+        let msd = msd(sim.trajectory_observer(observer).trajectories(), 20.0 * dt);
+        assert_eq!(msd.len(), 21);
+        assert_eq!(msd[0], (0.0, 0.0));
+        let fitted = fit_diffusion_coefficient(&msd);
+        assert!((fitted / d - 1.0).abs() < 0.05, "fitted D = {}", fitted);
+    }
+
+    #[test]
+    fn planar_msd_fits_4dt() {
+        let r = 1e-8;
+        let d = 1e-12;
+        let mut space = HCPLatticeSpace::new_2d(r, 100, 100);
+        space.set_periodic(true).unwrap();
+        let a = space.register_species(Species::new("A"), None);
+        space
+            .set_molecule_info(
+                a,
+                MoleculeInfo {
+                    radius: r,
+                    diffusion_coefficient: d,
+                },
+            )
+            .unwrap();
+        for i in 0..100 {
+            space.place_particle(a, Coordinate(i * 97)).unwrap();
+        }
+        let mut sim = Simulator::new(space, StdRng::seed_from_u64(1));
+        let dt = r * r / d;
+        assert_eq!(sim.space().diffusion_interval(a), Some(dt));
+        let observer = sim
+            .add_trajectory_observer(TrajectoryTarget::Species(Species::new("A")), dt, true)
+            .unwrap();
+        sim.run(400.0 * dt).unwrap();
+
+        let trajectories = sim.trajectory_observer(observer).trajectories();
+        assert!(trajectories
+            .iter()
+            .all(|t| t.positions.iter().all(|p| p[2] == 0.0)));
+        let msd = msd(trajectories, 20.0 * dt);
+        let fitted = fit_diffusion_coefficient_in(&msd, 2);
+        assert!((fitted / d - 1.0).abs() < 0.05, "fitted D = {}", fitted);
+    }
+
+    #[test]
+    fn uniform_lattice_has_a_flat_profile() {
+        let mut space = HCPLatticeSpace::new(1e-8, HCPLatticeSize::new(20, 20, 20));
+        space.set_periodic(true).unwrap();
+        let a = space.register_counted_species(Species::new("A"), None);
+        let mut rng = StdRng::seed_from_u64(3);
+        for i in 0..8000 {
+            if rng.gen::<f64>() < 0.3 {
+                space.place_particle(a, Coordinate(i)).unwrap();
+            }
+        }
+        let mean = space.count_to_concentration(space.num_molecules(a).unwrap());
+        for &axis in &[Axis::X, Axis::Y, Axis::Z] {
+            // 7 bins do not divide the 20 rows, columns or layers.
+            let profile = space.density_profile(&Species::new("A"), axis, 7).unwrap();
+            assert_eq!(profile.len(), 7);
+            let width = space.periodic_lengths()[axis.index()] / 7.0;
+            for (i, &(x, molar)) in profile.iter().enumerate() {
+                assert!((x - (i as f64 + 0.5) * width).abs() < 1e-15);
+                assert!((molar / mean - 1.0).abs() < 0.1, "{:?} {}", axis, molar);
+            }
+        }
+        assert!(matches!(
+            space.density_profile(&Species::new("B"), Axis::X, 7),
+            Err(Error::SpeciesNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn half_filled_lattice_has_a_step() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(10, 10, 10));
+        let a = space.register_species(Species::new("A"), None);
+        let [lx, ly, lz] = space.periodic_lengths();
+        let lower = Cuboid::new([-lx, -ly, -1.0], [2.0 * lx, 2.0 * ly, lz / 2.0 - 0.1]);
+        let inside: Vec<Coordinate> = space.coordinates_in(&lower).collect();
+        assert_eq!(inside.len(), 500);
+        for c in inside {
+            space.place_particle(a, c).unwrap();
+        }
+        let full = space.count_to_concentration(space.num_voxels());
+        // Layers 0 to 3, 4 to 6 and 7 to 9, the middle bin holding layer
+        // 4 filled and layers 5 and 6 empty.
+        let profile = space
+            .density_profile(&Species::new("A"), Axis::Z, 3)
+            .unwrap();
+        let step: Vec<f64> = profile.iter().map(|&(_, molar)| molar / full).collect();
+        assert!((step[0] - 1.0).abs() < 1e-12);
+        assert!((step[1] - 1.0 / 3.0).abs() < 1e-12);
+        assert_eq!(step[2], 0.0);
+        assert_eq!(
+            space.count_in_region(&Species::new("A"), &lower).unwrap(),
+            500
+        );
+        let upper = |p: [f64; 3]| p[2] > lz / 2.0;
+        assert_eq!(
+            space.count_in_region(&Species::new("A"), &upper).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn uncorrelated_molecules_have_a_flat_rdf() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(20, 20, 20));
+        space.set_periodic(true).unwrap();
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_counted_species(Species::new("B"), None);
+        let mut rng = StdRng::seed_from_u64(5);
+        for i in 0..8000 {
+            let draw = rng.gen::<f64>();
+            if draw < 0.1 {
+                space.place_particle(a, Coordinate(i)).unwrap();
+            } else if draw < 0.2 {
+                space.place_particle(b, Coordinate(i)).unwrap();
+            }
+        }
+        for (x, y) in &[("A", "B"), ("A", "A"), ("B", "A")] {
+            let g = rdf(&space, &Species::new(x), &Species::new(y), 8.0, 8);
+            assert_eq!(g.len(), 8);
+            // Nothing is nearer than a voxel diameter.
+            assert!(g[0].1.is_nan() && g[1].1.is_nan());
+            for &(r, g) in &g[2..] {
+                assert!((g - 1.0).abs() < 0.1, "{}-{} g({}) = {}", x, y, r, g);
+            }
+        }
+    }
+
+    #[test]
+    fn full_lattice_is_ideal() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let a = space.register_species(Species::new("A"), None);
+        for i in 0..216 {
+            space.place_particle(a, Coordinate(i)).unwrap();
+        }
+        let g = rdf(&space, &Species::new("A"), &Species::new("A"), 5.0, 5);
+        assert_eq!(g[2], (2.5, 1.0));
+        assert!(g[2..].iter().all(|&(_, g)| g == 1.0));
+        assert!(rdf(&space, &Species::new("X"), &Species::new("A"), 5.0, 5)
+            .iter()
+            .all(|&(_, g)| g.is_nan()));
+    }
+
+    #[test]
+    fn degenerate_input() {
+        assert!(msd(&[], 1.0).is_empty());
+        assert!(fit_diffusion_coefficient(&[(0.0, 0.0), (1.0, 6.0)]).is_nan());
+        let line = [(0.0, 1.0), (1.0, 7.0), (2.0, 13.0)];
+        assert!((fit_diffusion_coefficient(&line) - 1.0).abs() < 1e-12);
+        assert!((fit_diffusion_coefficient_in(&line, 2) - 1.5).abs() < 1e-12);
+    }
+}
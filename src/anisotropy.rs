@@ -0,0 +1,194 @@
+//! Anisotropic diffusion, faster within the layers of the lattice than
+//! across them or the other way round.
+//!
+//! A `DiffusionTensor` sets a coefficient `Dxy` within the xy plane of the
+//! layers and `Dz` along z. A molecule still attempts one hop per interval
+//! `τ`, but draws its direction with weights depending on the class of the
+//! direction. An in-plane hop moves it by `2r` in the plane; an
+//! inter-layer hop moves it by `h = 2r√(2/3)` along z and `2r/√3` in the
+//! plane. Matching the mean squared displacements per interval,
+//!
+//! - `2 Dz τ = 6 p_l h²` along z,
+//! - `4 Dxy τ = 6 p_p (2r)² + 6 p_l (2r/√3)²` in the plane,
+//!
+//! with `p_p` and `p_l` the probabilities of each in-plane and inter-layer
+//! direction, `6 p_p + 6 p_l = 1`, gives `τ = 2r²/(2Dxy + Dz)` and weights
+//! in the ratio `4Dxy - Dz` to `3Dz`. An isotropic tensor gives the uniform
+//! weights and the usual `2r²/(3D)`; a tensor needs `Dz ≤ 4Dxy`, beyond
+//! which in-plane hops alone cannot carry the inter-layer ones' share of
+//! the lateral displacement.
+
+use crate::{Error, HCPLatticeSpace, Result, SpeciesID};
+
+/// Diffusion coefficients within the layers and across them.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DiffusionTensor {
+    pub in_plane: f64,
+    pub inter_layer: f64,
+}
+
+impl DiffusionTensor {
+    pub fn isotropic(d: f64) -> Self {
+        Self {
+            in_plane: d,
+            inter_layer: d,
+        }
+    }
+}
+
+impl HCPLatticeSpace {
+    /// Makes `species` diffuse with `tensor`, which then takes over from the
+    /// coefficient of its `MoleculeInfo` for hopping; `None` goes back to
+    /// that coefficient. Fails with `InvalidWeights` unless both
+    /// coefficients are non-negative and `inter_layer ≤ 4 in_plane`, and
+    /// with `OutOfPlane` on a 2D lattice.
+    pub fn set_diffusion_tensor(
+        &mut self,
+        species: SpeciesID,
+        tensor: Option<DiffusionTensor>,
+    ) -> Result<()> {
+        self.check_species(species)?;
+        if let Some(tensor) = tensor {
+            if self.planar {
+                return Err(Error::OutOfPlane);
+            }
+            let DiffusionTensor {
+                in_plane,
+                inter_layer,
+            } = tensor;
+            if !(in_plane >= 0.0 && inter_layer >= 0.0 && inter_layer <= 4.0 * in_plane) {
+                return Err(Error::InvalidWeights);
+            }
+        }
+        self.get_species_cache_mut(species)?.tensor = tensor;
+        Ok(())
+    }
+
+    /// Returns the tensor of `species`, `None` if isotropic or no species
+    /// of this space.
+    pub fn diffusion_tensor(&self, species: SpeciesID) -> Option<DiffusionTensor> {
+        self.get_species_cache(species).ok()?.tensor
+    }
+
+    /// Returns the interval between hops for `tensor`, `None` if it does
+    /// not diffuse.
+    pub(crate) fn tensor_interval(&self, tensor: DiffusionTensor) -> Option<f64> {
+        let rate = 2.0 * tensor.in_plane + tensor.inter_layer;
+        if rate > 0.0 {
+            let r = self.voxel_radius;
+            Some(2.0 * r * r / rate)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the direction weights of an unbiased walk of `species`,
+    /// scaled so that an isotropic tensor gives the uniform weights exactly.
+    pub(crate) fn hop_weights(&self, species: SpeciesID) -> [f64; 12] {
+        let tensor = match self.species_cache[species.0].tensor {
+            Some(tensor) if tensor.in_plane > 0.0 => tensor,
+            _ => return self.uniform_weights(),
+        };
+        let in_plane = (4.0 * tensor.in_plane - tensor.inter_layer) / (3.0 * tensor.in_plane);
+        let inter_layer = tensor.inter_layer / tensor.in_plane;
+        let mut weights = [0.0; 12];
+        for (i, weight) in weights.iter_mut().enumerate() {
+            *weight = if i < 6 { in_plane } else { inter_layer };
+        }
+        weights
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Coordinate, HCPLatticeSize, MoleculeInfo, ParticleID, Species};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn dilute_space() -> (HCPLatticeSpace, SpeciesID, Vec<ParticleID>) {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(30, 30, 30));
+        space.set_periodic(true).unwrap();
+        space.set_image_tracking(true);
+        let a = space.register_species(Species::new("A"), None);
+        space
+            .set_molecule_info(
+                a,
+                MoleculeInfo {
+                    radius: 1.0,
+                    diffusion_coefficient: 1.0,
+                },
+            )
+            .unwrap();
+        let pids = (0..27000)
+            .step_by(23)
+            .map(|i| space.place_particle(a, Coordinate(i)).unwrap())
+            .collect();
+        (space, a, pids)
+    }
+
+    #[test]
+    fn msd_follows_the_tensor() {
+        let (mut space, a, pids) = dilute_space();
+        let tensor = DiffusionTensor {
+            in_plane: 1.0,
+            inter_layer: 0.25,
+        };
+        space.set_diffusion_tensor(a, Some(tensor)).unwrap();
+        let dt = space.diffusion_interval(a).unwrap();
+        assert!((dt - 2.0 / 2.25).abs() < 1e-12);
+        let starts: Vec<[f64; 3]> = pids
+            .iter()
+            .map(|&pid| space.unwrapped_position(pid).unwrap())
+            .collect();
+        let mut rng = StdRng::seed_from_u64(8);
+        let sweeps = 60;
+        for _ in 0..sweeps {
+            space.walk(a, &mut rng).unwrap();
+        }
+        let mut msd = [0.0; 3];
+        for (&pid, start) in pids.iter().zip(&starts) {
+            let end = space.unwrapped_position(pid).unwrap();
+            for axis in 0..3 {
+                msd[axis] += (end[axis] - start[axis]).powi(2) / pids.len() as f64;
+            }
+        }
+        let t = sweeps as f64 * dt;
+        for (axis, d) in [1.0, 1.0, 0.25].iter().enumerate() {
+            let fitted = msd[axis] / (2.0 * t);
+            assert!((fitted / d - 1.0).abs() < 0.1, "{:?} {}", msd, t);
+        }
+    }
+
+    #[test]
+    fn isotropic_tensor_changes_nothing() {
+        let (mut plain, a, _) = dilute_space();
+        let (mut tensor, _, _) = dilute_space();
+        tensor
+            .set_diffusion_tensor(a, Some(DiffusionTensor::isotropic(1.0)))
+            .unwrap();
+        assert_eq!(tensor.diffusion_interval(a), plain.diffusion_interval(a));
+        let mut rng1 = StdRng::seed_from_u64(2);
+        let mut rng2 = StdRng::seed_from_u64(2);
+        for _ in 0..5 {
+            plain.walk(a, &mut rng1).unwrap();
+            tensor.walk(a, &mut rng2).unwrap();
+        }
+        assert_eq!(plain.voxels, tensor.voxels);
+
+        let too_fast = DiffusionTensor {
+            in_plane: 1.0,
+            inter_layer: 4.5,
+        };
+        assert!(matches!(
+            tensor.set_diffusion_tensor(a, Some(too_fast)),
+            Err(Error::InvalidWeights)
+        ));
+        let mut planar = HCPLatticeSpace::new_2d(1.0, 4, 4);
+        let b = planar.register_species(Species::new("B"), None);
+        assert!(matches!(
+            planar.set_diffusion_tensor(b, Some(DiffusionTensor::isotropic(1.0))),
+            Err(Error::OutOfPlane)
+        ));
+    }
+}
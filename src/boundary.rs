@@ -0,0 +1,150 @@
+//! The voxels on the faces of the lattice, for boundary conditions.
+//!
+//! A face is the set of voxels whose row, column or layer index is the first
+//! or the last one, whatever the positions of their centers: the staggered
+//! rows and layers of the HCP lattice make the real faces ragged. Along an
+//! axis of length 1 the first index is also the last, so that every voxel
+//! is on both faces across it; in particular every voxel of a single-layer
+//! lattice, 2D ones included, is on the `Down` and `Up` faces. Faces only
+//! depend on the indices, so a periodic lattice has them too.
+
+use crate::{Coordinate, HCPLatticeSpace};
+
+/// A face of the lattice, named like the directions crossing it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Face {
+    /// Row 0, at the lowest y.
+    South,
+    /// The last row.
+    North,
+    /// Column 0, at the lowest x.
+    West,
+    /// The last column.
+    East,
+    /// Layer 0, at the lowest z.
+    Down,
+    /// The last layer.
+    Up,
+    /// Any of the six faces.
+    All,
+}
+
+impl Face {
+    /// The six faces, without `All`.
+    pub const SIDES: [Face; 6] = [
+        Face::South,
+        Face::North,
+        Face::West,
+        Face::East,
+        Face::Down,
+        Face::Up,
+    ];
+}
+
+impl HCPLatticeSpace {
+    /// Iterates over the voxels on `face` in coordinate order, each once
+    /// even if it is on several of the faces asked for.
+    pub fn boundary_coordinates(&self, face: Face) -> impl Iterator<Item = Coordinate> {
+        let (nrow, ncol, nlayer) = (self.size.row, self.size.col, self.size.layer);
+        let last = |len: usize| len.saturating_sub(1);
+        let [rows, cols, layers] = match face {
+            Face::South => [0..nrow.min(1), 0..ncol, 0..nlayer],
+            Face::North => [last(nrow)..nrow, 0..ncol, 0..nlayer],
+            Face::West => [0..nrow, 0..ncol.min(1), 0..nlayer],
+            Face::East => [0..nrow, last(ncol)..ncol, 0..nlayer],
+            Face::Down => [0..nrow, 0..ncol, 0..nlayer.min(1)],
+            Face::Up => [0..nrow, 0..ncol, last(nlayer)..nlayer],
+            Face::All => [0..nrow, 0..ncol, 0..nlayer],
+        };
+        // Off the outer layers and columns only the first and last rows are
+        // on a face, `last(nrow)` apart.
+        let all = face == Face::All;
+        let inner_step = last(nrow).max(1);
+        layers.flat_map(move |layer| {
+            let rows = rows.clone();
+            cols.clone().flat_map(move |col| {
+                let outer = layer == 0 || layer == last(nlayer) || col == 0 || col == last(ncol);
+                let step = if all && !outer { inner_step } else { 1 };
+                rows.clone()
+                    .step_by(step)
+                    .map(move |row| Coordinate(row + nrow * (col + ncol * layer)))
+            })
+        })
+    }
+
+    /// Returns true if `coordinate` is a voxel on any face of the lattice.
+    pub fn is_boundary(&self, coordinate: Coordinate) -> bool {
+        let (row, col, layer) = match self.coordinate_to_global(coordinate) {
+            Ok(global) => global,
+            Err(_) => return false,
+        };
+        let on_face = |index: usize, len: usize| index == 0 || index + 1 == len;
+        on_face(row, self.size.row)
+            || on_face(col, self.size.col)
+            || on_face(layer, self.size.layer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HCPLatticeSize;
+
+    #[test]
+    fn count_boundary_voxels() {
+        let inner = |len: usize| len.saturating_sub(2);
+        for &(nrow, ncol, nlayer) in &[
+            (6, 5, 4),
+            (2, 7, 3),
+            (1, 4, 5),
+            (4, 1, 3),
+            (3, 3, 1),
+            (1, 1, 1),
+        ] {
+            let space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(nrow, ncol, nlayer));
+            let all: Vec<Coordinate> = space.boundary_coordinates(Face::All).collect();
+            assert_eq!(
+                all.len(),
+                nrow * ncol * nlayer - inner(nrow) * inner(ncol) * inner(nlayer)
+            );
+            assert!(all.windows(2).all(|w| w[0].0 < w[1].0));
+            let scanned: Vec<Coordinate> = space
+                .coordinates()
+                .filter(|&c| space.is_boundary(c))
+                .collect();
+            assert_eq!(all, scanned);
+
+            let faces: Vec<usize> = Face::SIDES
+                .iter()
+                .map(|&face| space.boundary_coordinates(face).count())
+                .collect();
+            let expected = [
+                ncol * nlayer,
+                ncol * nlayer,
+                nrow * nlayer,
+                nrow * nlayer,
+                nrow * ncol,
+                nrow * ncol,
+            ];
+            assert_eq!(faces, expected);
+        }
+    }
+
+    #[test]
+    fn faces_by_index() {
+        let space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 5, 6));
+        for c in space.boundary_coordinates(Face::North) {
+            assert_eq!(space.coordinate_to_global(c).unwrap().0, 3);
+        }
+        for c in space.boundary_coordinates(Face::West) {
+            assert_eq!(space.coordinate_to_global(c).unwrap().1, 0);
+        }
+        let top: Vec<Coordinate> = space.boundary_coordinates(Face::Up).collect();
+        assert_eq!(top, (100..120).map(Coordinate).collect::<Vec<_>>());
+
+        let planar = HCPLatticeSpace::new_2d(1.0, 3, 3);
+        assert_eq!(planar.boundary_coordinates(Face::Down).count(), 9);
+        assert!(planar.is_boundary(Coordinate(4)));
+        assert!(!space.is_boundary(Coordinate(120)));
+    }
+}
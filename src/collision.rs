@@ -0,0 +1,462 @@
+//! Bimolecular reactions on collision, `A + B -> C` or `A + B -> ∅`.
+//!
+//! `set_collision(a, b, product, p)` lets a hop of an `a` molecule into a
+//! voxel of a `b` molecule, or of a `b` into an `a`, react with probability
+//! `p`: both molecules are removed and the product, if any, is placed on
+//! the voxel hopped into. Otherwise the hop is rejected. Species without
+//! collisions walk without drawing any extra random number.
+//!
+//! Following Spatiocyte, the probability matching a macroscopic rate `k` in
+//! the reaction-limited regime equates the mean rate of collisions of a
+//! pair with `k`. A molecule hopping every `τ` towards one of its `n`
+//! directions tries each neighbor at a rate `1/(n τ)`, and the partner is
+//! on any of its `z` neighbors with the probability `z v` over the volume,
+//! `v` being the volume of a voxel, so that
+//!
+//! `p = k / (z v (1/(n_A τ_A) + 1/(n_B τ_B)))`.
+//!
+//! Inside an HCP lattice this gives the 3D relation `p = k/(6√2 r D)`,
+//! `D = D_A + D_B`, with `k` in M⁻¹s⁻¹ converted with lengths in meters; on
+//! a surface, where `k` is in m²s⁻¹, `z = 6` and `v` is the area `2√3 r²`
+//! of a voxel, which assumes a flat surface. A reaction needing `p > 1` is
+//! diffusion-limited beyond what the lattice can represent at its voxel
+//! radius; a smaller radius raises the rate of collisions.
+//!
+//! `acceptance_probability` gives the same relations from the sum of the
+//! diffusion coefficients alone, for reactions set up by hand.
+
+use crate::units::AVOGADRO;
+use crate::{Coordinate, Error, HCPLatticeSpace, ReactionRule, Result, SpeciesID};
+use rand::Rng;
+
+/// Returns the probability per collision of two molecules whose diffusion
+/// coefficients sum to `d_sum` matching the rate `k` of their reaction, on
+/// an HCP lattice of voxels of radius `voxel_radius` where each voxel has
+/// `coordination` neighbors: 12 in a volume, with `k` in m³s⁻¹, or 6 on a
+/// flat surface, with `k` in m²s⁻¹. This is `k/(6√2 r D)` in a volume and
+/// `k/(2√3 D)` on a surface, clamped to `[0, 1]`; a rate in M⁻¹s⁻¹ is
+/// divided by `1e3 N_A` first.
+///
+/// The relation holds in the reaction-limited regime, where collisions
+/// react rarely enough that the molecules stay well mixed around each
+/// other. As it nears 1, depletion of partners around a molecule makes the
+/// lattice react slower than `k`, and the clamped probability of 1 is the
+/// fastest reaction the lattice can represent at that voxel radius.
+///
+/// Panics unless `coordination` is 6 or 12.
+pub fn acceptance_probability(k: f64, d_sum: f64, voxel_radius: f64, coordination: usize) -> f64 {
+    unclamped_acceptance(k, d_sum, voxel_radius, coordination).clamp(0.0, 1.0)
+}
+
+/// `acceptance_probability` before clamping, which may exceed 1.
+fn unclamped_acceptance(k: f64, d_sum: f64, voxel_radius: f64, coordination: usize) -> f64 {
+    let r = voxel_radius;
+    let (volume, dimensions) = lattice_voxel(r, coordination);
+    // A hop every 2r²/(dD) into one of the z neighbors, d being 3 or 2,
+    // meets a partner on each neighbor at the rate d D/(2 r² z).
+    2.0 * r * r * k / (volume * dimensions * d_sum)
+}
+
+/// Returns the volume, or area, of a voxel of radius `r` with
+/// `coordination` neighbors and the dimensions it diffuses in.
+fn lattice_voxel(r: f64, coordination: usize) -> (f64, f64) {
+    match coordination {
+        12 => (4.0 * 2f64.sqrt() * r.powi(3), 3.0),
+        6 => (2.0 * 3f64.sqrt() * r * r, 2.0),
+        _ => panic!("an HCP lattice has 6 or 12 neighbors, not {}", coordination),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Collision {
+    reactants: (SpeciesID, SpeciesID),
+    product: Option<SpeciesID>,
+    probability: f64,
+    /// The reaction as a rule whose rate is `probability`.
+    rule: ReactionRule,
+}
+
+impl HCPLatticeSpace {
+    /// Makes the molecules of `a` and `b` react into `product` on collision
+    /// with `probability`. Setting the pair again in either order replaces
+    /// its reaction.
+    ///
+    /// Both reactants must be distinct single-voxel species sharing their
+    /// location and not obstacles, the product must share it too, and
+    /// `probability` must be within `[0, 1]`; it fails with
+    /// `InvalidReaction` otherwise.
+    pub fn set_collision(
+        &mut self,
+        a: SpeciesID,
+        b: SpeciesID,
+        product: Option<SpeciesID>,
+        probability: f64,
+    ) -> Result<()> {
+        for &id in [a, b].iter().chain(&product) {
+            self.check_species(id)?;
+        }
+        let valid = |id: SpeciesID| {
+            let cache = &self.species_cache[id.0];
+            cache.voxel_count == 1 && !cache.obstacle
+        };
+        let location = self.location_of(a)?;
+        if a == b
+            || !valid(a)
+            || !valid(b)
+            || self.location_of(b)? != location
+            || product.is_some_and(|product| {
+                !valid(product) || self.species_cache[product.0].location != location
+            })
+            || !(0.0..=1.0).contains(&probability)
+        {
+            return Err(Error::InvalidReaction);
+        }
+        let name = |id: SpeciesID| self.species_cache[id.0].species.clone();
+        let rule = ReactionRule::new(
+            vec![name(a), name(b)],
+            product.into_iter().map(name).collect(),
+            probability,
+        );
+        self.collisions
+            .retain(|c| c.reactants != (a, b) && c.reactants != (b, a));
+        self.collisions.push(Collision {
+            reactants: (a, b),
+            product,
+            probability,
+            rule,
+        });
+        Ok(())
+    }
+
+    /// Sets the collision of a rule `A + B -> C` or `A + B -> ∅` whose rate
+    /// is the probability per collision, as made by
+    /// `ReactionRule::from_macroscopic`.
+    pub fn add_collision_reaction(&mut self, rule: &ReactionRule) -> Result<()> {
+        let lookup = |species: &crate::Species| {
+            self.species_id(species)
+                .ok_or_else(|| Error::SpeciesNotFound(species.clone()))
+        };
+        let (a, b) = match rule.reactants() {
+            [a, b] => (lookup(a)?, lookup(b)?),
+            _ => return Err(Error::InvalidReaction),
+        };
+        let product = match rule.products() {
+            [] => None,
+            [product] => Some(lookup(product)?),
+            _ => return Err(Error::InvalidReaction),
+        };
+        self.set_collision(a, b, product, rule.k())
+    }
+
+    /// Returns the probability of the collision of `a` and `b`, zero if
+    /// unset.
+    pub fn collision(&self, a: SpeciesID, b: SpeciesID) -> f64 {
+        self.find_collision(a, b).map_or(0.0, |c| c.probability)
+    }
+
+    /// Returns the rule of the collision of `a` and `b`, in either order,
+    /// `None` if unset: their reaction, with the reactants in the order it
+    /// was set in and the probability per collision as its rate. Lets a
+    /// caller skip pairs that cannot react before drawing anything.
+    pub fn reactive_pair(&self, a: SpeciesID, b: SpeciesID) -> Option<&ReactionRule> {
+        self.find_collision(a, b).map(|c| &c.rule)
+    }
+
+    fn find_collision(&self, a: SpeciesID, b: SpeciesID) -> Option<&Collision> {
+        self.collisions
+            .iter()
+            .find(|c| c.reactants == (a, b) || c.reactants == (b, a))
+    }
+
+    /// Returns the probability per collision of `a` and `b` matching the
+    /// macroscopic rate `k`, in M⁻¹s⁻¹ for bulk species and in m²s⁻¹ for
+    /// species on a surface or on a 2D lattice; see the module
+    /// documentation. It may exceed 1. Fails with `InvalidReaction` unless
+    /// they share their location and one of them diffuses.
+    ///
+    /// This is `acceptance_probability`, unclamped, of the sum of the
+    /// diffusion coefficients that hop at the rates of `a` and `b` in the
+    /// dimensions of the lattice: the sum of theirs, except for species
+    /// with a diffusion tensor or on a surface inside a volume.
+    pub fn collision_probability(&self, k: f64, a: SpeciesID, b: SpeciesID) -> Result<f64> {
+        let location = self.location_of(a)?;
+        if location != self.location_of(b)? {
+            return Err(Error::InvalidReaction);
+        }
+        let directions = self.directions().len() as f64;
+        let rate = |id| {
+            self.diffusion_interval(id)
+                .map_or(0.0, |t| 1.0 / (directions * t))
+        };
+        let hops = rate(a) + rate(b);
+        if hops <= 0.0 {
+            return Err(Error::InvalidReaction);
+        }
+        let r = self.voxel_radius;
+        let (coordination, k) = if self.planar || location.is_some() {
+            (6, k)
+        } else {
+            (12, k / (1e3 * AVOGADRO))
+        };
+        let (_, dimensions) = lattice_voxel(r, coordination);
+        // The inverse of the rate d D/(2 r² z) of meeting a partner.
+        let d_sum = hops * 2.0 * r * r * coordination as f64 / dimensions;
+        Ok(unclamped_acceptance(k, d_sum, r, coordination))
+    }
+
+    /// Handles the hop of the molecule of `species` at `from` to `to` if
+    /// `to` holds a partner in a collision, and returns whether it did.
+    pub(crate) fn try_collision<R: Rng>(
+        &mut self,
+        species: SpeciesID,
+        from: Coordinate,
+        to: Coordinate,
+        rng: &mut R,
+    ) -> Result<bool> {
+        if self.collisions.is_empty() {
+            return Ok(false);
+        }
+        let partner = match self.voxel(to) {
+            Some(partner) => partner,
+            None => return Ok(false),
+        };
+        let (product, probability) = match self.find_collision(species, partner) {
+            Some(c) => (c.product, c.probability),
+            None => return Ok(false),
+        };
+        if rng.gen::<f64>() >= probability {
+            return Ok(true);
+        }
+        self.remove_at(from)?;
+        self.remove_at(to)?;
+        if let Some(product) = product {
+            self.place_particle(product, to)?;
+        }
+        Ok(true)
+    }
+}
+
+impl ReactionRule {
+    /// Returns the rule `a + b -> ∅` whose rate is the probability per
+    /// collision of `set_collision` matching the macroscopic rate `ka`, in
+    /// M⁻¹s⁻¹ for bulk species and m²s⁻¹ on a surface, with the diffusion
+    /// coefficients of the species and the voxel radius of `space`; see
+    /// the `collision` module. `with_products` gives it products.
+    ///
+    /// Fails with `DiffusionLimited` if the probability would exceed 1,
+    /// which a smaller voxel radius brings down, with `SpeciesNotFound` for
+    /// an unregistered species, and like `collision_probability`.
+    pub fn from_macroscopic(
+        ka: f64,
+        species_a: &crate::Species,
+        species_b: &crate::Species,
+        space: &HCPLatticeSpace,
+    ) -> Result<Self> {
+        let lookup = |species: &crate::Species| {
+            space
+                .species_id(species)
+                .ok_or_else(|| Error::SpeciesNotFound(species.clone()))
+        };
+        let p = space.collision_probability(ka, lookup(species_a)?, lookup(species_b)?)?;
+        if p > 1.0 {
+            return Err(Error::DiffusionLimited(p));
+        }
+        Ok(Self::new(
+            vec![species_a.clone(), species_b.clone()],
+            Vec::new(),
+            p,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HCPLatticeSize, MoleculeInfo, Simulator, Species};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn info(r: f64, d: f64) -> MoleculeInfo {
+        MoleculeInfo {
+            radius: r,
+            diffusion_coefficient: d,
+        }
+    }
+
+    #[test]
+    fn probabilities_of_the_spatiocyte_relations() {
+        let (r, d) = (5e-9, 1e-12);
+        let mut space = HCPLatticeSpace::new(r, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        let c = space.register_species(Species::new("C"), None);
+        space.set_molecule_info(a, info(r, d)).unwrap();
+        space.set_molecule_info(b, info(r, 3.0 * d)).unwrap();
+        let ka = 1e6;
+        let p = space.collision_probability(ka, a, b).unwrap();
+        let k = ka / (1e3 * AVOGADRO);
+        let expected = k / (6.0 * 2f64.sqrt() * r * 4.0 * d);
+        assert!((p / expected - 1.0).abs() < 1e-12);
+        assert!(matches!(
+            space.collision_probability(ka, a, c),
+            Ok(p) if (p / expected - 4.0).abs() < 1e-12
+        ));
+
+        let mut planar = HCPLatticeSpace::new_2d(r, 4, 4);
+        let a = planar.register_species(Species::new("A"), None);
+        let b = planar.register_species(Species::new("B"), None);
+        planar.set_molecule_info(a, info(r, d)).unwrap();
+        planar.set_molecule_info(b, info(r, d)).unwrap();
+        let k = 1e-12;
+        let p = planar.collision_probability(k, a, b).unwrap();
+        assert!((p / (k / (2.0 * 3f64.sqrt() * 2.0 * d)) - 1.0).abs() < 1e-12);
+
+        let rule =
+            ReactionRule::from_macroscopic(1e9, &Species::new("A"), &Species::new("B"), &space);
+        assert!(matches!(rule, Err(Error::DiffusionLimited(p)) if p > 1.0));
+        assert!(matches!(
+            ReactionRule::from_macroscopic(ka, &Species::new("A"), &Species::new("X"), &space),
+            Err(Error::SpeciesNotFound(_))
+        ));
+        let still = space.register_species(Species::new("S"), None);
+        assert!(matches!(
+            space.collision_probability(ka, still, c),
+            Err(Error::InvalidReaction)
+        ));
+    }
+
+    /// The relation of E-Cell4 Spatiocyte in a volume, `p = k/(6√2 r D)`
+    /// from Arjunan and Tomita, Syst. Synth. Biol. 4, 35 (2010), and that
+    /// of the module on a surface, `p = k/(2√3 D)`, for 5 nm voxels and
+    /// 1 µm²/s molecules.
+    #[test]
+    fn acceptance_of_the_ecell4_relations() {
+        let (r, d) = (5e-9, 1e-12);
+        let k = 1e6 / (1e3 * AVOGADRO);
+        let p = acceptance_probability(k, 2.0 * d, r, 12);
+        // In the form of E-Cell4, `k * factor` of `1 / (6√2 (D_A + D_B) r_v)`.
+        let factor = 1.0 / (6.0 * 2f64.sqrt() * (d + d) * r);
+        assert!((p / (k * factor) - 1.0).abs() < 1e-12, "{}", p);
+        assert_eq!(acceptance_probability(1e3 * k, 2.0 * d, r, 12), 1.0);
+        assert_eq!(acceptance_probability(0.0, 2.0 * d, r, 12), 0.0);
+
+        let k = 1e-13;
+        let p = acceptance_probability(k, 2.0 * d, r, 6);
+        assert!((p - k / (2.0 * 3f64.sqrt() * 2.0 * d)).abs() < 1e-15);
+
+        let mut space = HCPLatticeSpace::new(r, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        space.set_molecule_info(a, info(r, d)).unwrap();
+        space.set_molecule_info(b, info(r, 3.0 * d)).unwrap();
+        let p = space.collision_probability(1e6, a, b).unwrap();
+        let expected = acceptance_probability(1e6 / (1e3 * AVOGADRO), 4.0 * d, r, 12);
+        assert!((p / expected - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn invalid_collisions() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        let s = space.register_species(Species::new("S"), Some(membrane));
+        let wall = space.register_obstacle(Species::new("W"));
+        for &(x, y, product, p) in &[
+            (a, a, None, 0.5),
+            (a, s, None, 0.5),
+            (a, wall, None, 0.5),
+            (a, b, Some(s), 0.5),
+            (a, b, None, 1.5),
+        ] {
+            assert!(matches!(
+                space.set_collision(x, y, product, p),
+                Err(Error::InvalidReaction)
+            ));
+        }
+        space.set_collision(a, b, None, 0.5).unwrap();
+        space.set_collision(b, a, Some(a), 0.25).unwrap();
+        assert_eq!(space.collision(a, b), 0.25);
+        assert_eq!(space.collisions.len(), 1);
+    }
+
+    #[test]
+    fn reactive_pairs_in_either_order() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        let c = space.register_species(Species::new("C"), None);
+        space.set_collision(b, a, Some(c), 0.5).unwrap();
+        let rule = ReactionRule::new(
+            vec![Species::new("B"), Species::new("A")],
+            vec![Species::new("C")],
+            0.5,
+        );
+        assert_eq!(space.reactive_pair(a, b), Some(&rule));
+        assert_eq!(space.reactive_pair(b, a), Some(&rule));
+        assert_eq!(space.reactive_pair(a, c), None);
+        assert_eq!(space.reactive_pair(a, a), None);
+
+        let annihilation =
+            ReactionRule::new(vec![Species::new("A"), Species::new("C")], vec![], 0.1);
+        space.add_collision_reaction(&annihilation).unwrap();
+        assert_eq!(space.reactive_pair(c, a), Some(&annihilation));
+        assert_eq!(space.reactive_pair(b, a), Some(&rule));
+    }
+
+    /// `A + B -> C` from equal counts, for which `1/N - 1/N0 = k t / V`.
+    #[test]
+    fn reaction_limited_rate() {
+        let (r, d) = (5e-9, 1e-12);
+        let mut space = HCPLatticeSpace::new(r, HCPLatticeSize::new(20, 20, 20));
+        space.set_periodic(true).unwrap();
+        let ids: Vec<SpeciesID> = ["A", "B", "C"]
+            .iter()
+            .map(|name| space.register_species(Species::new(name), None))
+            .collect();
+        for &id in &ids {
+            space.set_molecule_info(id, info(r, d)).unwrap();
+            space.set_tracking(id, false).unwrap();
+        }
+        let n0 = 1000;
+        for i in 0..2 * n0 {
+            space.place_particle(ids[i % 2], Coordinate(4 * i)).unwrap();
+        }
+        let ka = 1e6;
+        let rule =
+            ReactionRule::from_macroscopic(ka, &Species::new("A"), &Species::new("B"), &space)
+                .unwrap()
+                .with_products(vec![Species::new("C")]);
+        assert!(rule.k() > 0.01 && rule.k() < 0.05);
+        space.add_collision_reaction(&rule).unwrap();
+        let k = ka / (1e3 * AVOGADRO);
+        let volume = space.num_voxels() as f64 * space.voxel_volume();
+        let half_life = volume / (k * n0 as f64);
+
+        let mut sim = Simulator::new(space, StdRng::seed_from_u64(6));
+        let observer = sim
+            .add_number_observer(
+                vec![Species::new("A"), Species::new("B"), Species::new("C")],
+                half_life / 10.0,
+            )
+            .unwrap();
+        sim.run(2.0 * half_life).unwrap();
+        sim.space().validate().unwrap();
+        let data = sim.number_observer(observer).data();
+        let (mut sxy, mut sxx) = (0.0, 0.0);
+        for (t, counts) in data {
+            assert_eq!(counts[0], counts[1]);
+            assert_eq!(counts[0] + counts[2], n0);
+            let y = 1.0 / counts[0] as f64 - 1.0 / n0 as f64;
+            sxy += t * y;
+            sxx += t * t;
+        }
+        let fitted = sxy / sxx * volume;
+        assert!(
+            (fitted / k - 1.0).abs() < 0.1,
+            "fitted {} expected {}",
+            fitted,
+            k
+        );
+    }
+}
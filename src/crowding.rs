@@ -0,0 +1,204 @@
+//! Occupied volume fractions, for setting up crowded systems.
+//!
+//! The occupancy of a lattice is the fraction of its voxels holding a
+//! molecule. Structures (species other species are located on) and the
+//! molecules located on them are not part of the bulk, so the bulk occupancy
+//! is the fraction of the remaining voxels holding a molecule, which is what
+//! `fill_to_fraction` brings to a target.
+
+use crate::region::Region;
+use crate::{Coordinate, Error, HCPLatticeSpace, Result, Species, SpeciesID, TrackingType};
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+impl HCPLatticeSpace {
+    /// Returns the fraction of voxels holding a molecule of any species.
+    pub fn occupancy(&self) -> f64 {
+        self.total_molecules() as f64 / self.num_voxels() as f64
+    }
+
+    /// Returns the fraction of bulk voxels holding a molecule, the bulk
+    /// being every voxel but those of structures and of the molecules on
+    /// them. NaN if there is no bulk.
+    pub fn bulk_occupancy(&self) -> f64 {
+        let (occupied, bulk) = self.bulk_counts();
+        occupied as f64 / bulk as f64
+    }
+
+    /// Returns the fraction of the voxels centered in `region` holding a
+    /// molecule, NaN if there are none.
+    pub fn occupancy_in_region(&self, region: &dyn Region) -> f64 {
+        let (occupied, total) = self
+            .coordinates_in(region)
+            .fold((0, 0), |(occupied, total), c| {
+                (occupied + self.voxel(c).is_some() as usize, total + 1)
+            });
+        occupied as f64 / total as f64
+    }
+
+    /// Returns the numbers of occupied bulk voxels and of bulk voxels.
+    fn bulk_counts(&self) -> (usize, usize) {
+        let mut occupied = 0;
+        let mut excluded = 0;
+        for (i, cache) in self.species_cache.iter().enumerate() {
+            let voxels = match &cache.cache {
+                TrackingType::Tracking(entries) => entries.len(),
+                TrackingType::Count(count) => *count,
+            };
+            if cache.location.is_some() || self.is_structure(SpeciesID(i)) {
+                excluded += voxels;
+            } else {
+                occupied += voxels;
+            }
+        }
+        (occupied, self.num_voxels() - excluded)
+    }
+
+    /// Places molecules of `species`, which must be located on vacant
+    /// voxels, on vacant voxels drawn uniformly until the bulk occupancy is
+    /// `phi` to within one molecule, and returns how many it placed.
+    /// Molecules already there count towards `phi`, and nothing is placed if
+    /// the occupancy is already above it. Multi-voxel molecules are placed
+    /// where they fit, which may be short of `phi` in a crowded lattice.
+    ///
+    /// Fails with `InvalidFraction` unless `phi` is within `[0, 1]`, and with
+    /// `NotBulk` for a structure or a species located on one.
+    pub fn fill_to_fraction<R: Rng>(
+        &mut self,
+        species: &Species,
+        phi: f64,
+        rng: &mut R,
+    ) -> Result<usize> {
+        if !(0.0..=1.0).contains(&phi) {
+            return Err(Error::InvalidFraction(phi));
+        }
+        let id = self
+            .find_species(species.name())
+            .ok_or_else(|| Error::SpeciesNotFound(species.clone()))?;
+        if self.location_of(id)?.is_some() || self.is_structure(id) {
+            return Err(Error::NotBulk(species.clone()));
+        }
+        let (occupied, bulk) = self.bulk_counts();
+        let target = (phi * bulk as f64).round() as usize;
+        let voxel_count = self.species_cache[id.0].voxel_count;
+        let count = target.saturating_sub(occupied) / voxel_count;
+        let mut vacant: Vec<Coordinate> = self
+            .coordinates()
+            .filter(|&c| self.voxel(c).is_none())
+            .collect();
+        if voxel_count == 1 {
+            let (chosen, _) = vacant.partial_shuffle(rng, count);
+            for &coordinate in chosen.iter() {
+                self.place_particle(id, coordinate)?;
+            }
+            return Ok(count);
+        }
+
+        // Multi-voxel molecules do not fit everywhere, so try the vacant
+        // voxels in random order until enough of them fit.
+        vacant.shuffle(rng);
+        let mut placed = 0;
+        for coordinate in vacant {
+            if placed == count {
+                break;
+            }
+            match self.place_particle(id, coordinate) {
+                Ok(_) => placed += 1,
+                Err(Error::InvalidLocation(..)) | Err(Error::InsufficientSpace(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(placed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::region::Cuboid;
+    use crate::HCPLatticeSize;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn fill_to_a_fraction() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(10, 10, 10));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        for i in 0..50 {
+            space.place_particle(b, Coordinate(i * 20)).unwrap();
+        }
+        let mut rng = StdRng::seed_from_u64(0);
+        let added = space
+            .fill_to_fraction(&Species::new("A"), 0.3, &mut rng)
+            .unwrap();
+        assert_eq!(added, 250);
+        assert_eq!(space.num_molecules(a).unwrap(), 250);
+        assert!((space.occupancy() - 0.3).abs() <= 1e-3);
+        assert_eq!(space.occupancy(), space.bulk_occupancy());
+        space.validate().unwrap();
+
+        let [lx, ly, lz] = space.periodic_lengths();
+        let everything = Cuboid::new([-1.0; 3], [lx, ly, lz]);
+        assert_eq!(space.occupancy_in_region(&everything), space.occupancy());
+        let half = Cuboid::new([-1.0; 3], [lx, ly, lz / 2.0]);
+        assert!((space.occupancy_in_region(&half) - 0.3).abs() < 0.05);
+
+        let again = space.fill_to_fraction(&Species::new("A"), 0.2, &mut rng);
+        assert_eq!(again.unwrap(), 0);
+        assert!(matches!(
+            space.fill_to_fraction(&Species::new("A"), 1.5, &mut rng),
+            Err(Error::InvalidFraction(_))
+        ));
+        assert!(matches!(
+            space.fill_to_fraction(&Species::new("X"), 0.5, &mut rng),
+            Err(Error::SpeciesNotFound(_))
+        ));
+        assert_eq!(space.total_molecules(), 300);
+    }
+
+    #[test]
+    fn structures_are_not_bulk() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(10, 10, 10));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), None);
+        let a_s = space.register_species(Species::new("As"), Some(membrane));
+        for c in space.coordinates().take(200).collect::<Vec<_>>() {
+            space.place_particle(membrane, c).unwrap();
+        }
+        space.place_particle(a_s, Coordinate(0)).unwrap();
+        assert_eq!(space.bulk_occupancy(), 0.0);
+        assert!((space.occupancy() - 0.2).abs() < 1e-12);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let added = space
+            .fill_to_fraction(&Species::new("A"), 0.5, &mut rng)
+            .unwrap();
+        assert_eq!(added, 400);
+        assert_eq!(space.bulk_occupancy(), 0.5);
+        assert!(space.coordinates_of(a).unwrap().iter().all(|&c| space
+            .coordinates_of(membrane)
+            .unwrap()
+            .iter()
+            .all(|&m| m != c)));
+        assert!(matches!(
+            space.fill_to_fraction(&Species::new("As"), 0.5, &mut rng),
+            Err(Error::NotBulk(_))
+        ));
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn fill_with_clusters() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(10, 10, 10));
+        let big = space.register_multi_voxel_species(Species::new("R"), None, 4);
+        let mut rng = StdRng::seed_from_u64(2);
+        let added = space
+            .fill_to_fraction(&Species::new("R"), 0.2, &mut rng)
+            .unwrap();
+        assert_eq!(added, 50);
+        assert_eq!(space.num_molecules(big.id()).unwrap(), 50);
+        assert_eq!(space.occupancy(), 0.2);
+        space.validate().unwrap();
+    }
+}
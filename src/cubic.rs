@@ -0,0 +1,355 @@
+//! A simple cubic lattice, for comparison with lattice models using one.
+//!
+//! Voxels are cubes of edge `spacing` indexed by `(x, y, z)`, flattened as
+//! `x + nx (y + ny z)`, with centers at `spacing` times their indices. Each
+//! voxel has 6 neighbors, at `±spacing` along each axis. A hop moves a
+//! molecule by `λ = spacing`, so that diffusion with coefficient `D` takes
+//! one hop every `λ²/(6D)`; with `λ = 2r` this is the `2r²/(3D)` of the HCP
+//! lattice.
+//!
+//! The space keeps the same bookkeeping as `HCPLatticeSpace`: a species is
+//! located on vacant voxels or on the molecules of another species, and
+//! every molecule has a `ParticleID`. Multi-voxel species, interactions and
+//! transitions are HCP only.
+
+use crate::lattice::LatticeSpace;
+use crate::{
+    Coordinate, Error, MoleculeInfo, ParticleID, PidAllocator, Result, Species, SpeciesID,
+};
+use rand::Rng;
+
+#[derive(Clone, Debug)]
+struct CubicSpecies {
+    species: Species,
+    location: Option<SpeciesID>,
+    info: MoleculeInfo,
+    molecules: Vec<(ParticleID, Coordinate)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CubicLatticeSpace {
+    spacing: f64,
+    size: [usize; 3],
+    voxels: Vec<Option<SpeciesID>>,
+    species: Vec<CubicSpecies>,
+    pids: PidAllocator,
+    periodic: bool,
+}
+
+/// The `(x, y, z)` offsets of the 6 neighbors.
+const OFFSETS: [[isize; 3]; 6] = [
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+];
+
+impl CubicLatticeSpace {
+    /// Creates a lattice of `size[0] × size[1] × size[2]` vacant voxels.
+    pub fn new(spacing: f64, size: [usize; 3]) -> Self {
+        let num_voxels = size[0]
+            .checked_mul(size[1])
+            .and_then(|n| n.checked_mul(size[2]))
+            .expect("the number of voxels overflows usize");
+        Self {
+            spacing,
+            size,
+            voxels: vec![None; num_voxels],
+            species: Vec::new(),
+            pids: PidAllocator::new(),
+            periodic: false,
+        }
+    }
+
+    pub fn spacing(&self) -> f64 {
+        self.spacing
+    }
+
+    pub fn set_periodic(&mut self, periodic: bool) {
+        self.periodic = periodic;
+    }
+
+    pub fn global_to_coordinate(&self, x: usize, y: usize, z: usize) -> Result<Coordinate> {
+        let [nx, ny, nz] = self.size;
+        let coordinate = Coordinate(x + nx * (y + ny * z));
+        if x >= nx || y >= ny || z >= nz {
+            return Err(Error::OutOfRange(coordinate));
+        }
+        Ok(coordinate)
+    }
+
+    pub fn coordinate_to_global(&self, coordinate: Coordinate) -> Result<(usize, usize, usize)> {
+        if coordinate.0 >= self.voxels.len() {
+            return Err(Error::OutOfRange(coordinate));
+        }
+        let [nx, ny, _] = self.size;
+        Ok((
+            coordinate.0 % nx,
+            coordinate.0 / nx % ny,
+            coordinate.0 / (nx * ny),
+        ))
+    }
+
+    fn neighbor(&self, coordinate: Coordinate, offset: [isize; 3]) -> Result<Option<Coordinate>> {
+        let (x, y, z) = self.coordinate_to_global(coordinate)?;
+        let mut shifted = [0; 3];
+        for (axis, &index) in [x, y, z].iter().enumerate() {
+            let index = index as isize + offset[axis];
+            let len = self.size[axis] as isize;
+            shifted[axis] = if self.periodic {
+                index.rem_euclid(len)
+            } else if (0..len).contains(&index) {
+                index
+            } else {
+                return Ok(None);
+            } as usize;
+        }
+        self.global_to_coordinate(shifted[0], shifted[1], shifted[2])
+            .map(Some)
+    }
+
+    fn voxel(&self, coordinate: Coordinate) -> Result<Option<SpeciesID>> {
+        self.voxels
+            .get(coordinate.0)
+            .copied()
+            .ok_or(Error::OutOfRange(coordinate))
+    }
+
+    fn next_pid(&mut self) -> ParticleID {
+        self.pids.next()
+    }
+
+    fn registered(&self, species: SpeciesID) -> Result<&CubicSpecies> {
+        self.species
+            .get(species.0)
+            .ok_or(Error::UnknownSpecies(species))
+    }
+
+    fn entry(&self, species: SpeciesID, coordinate: Coordinate) -> Option<usize> {
+        self.species[species.0]
+            .molecules
+            .iter()
+            .position(|&(_, c)| c == coordinate)
+    }
+}
+
+impl LatticeSpace for CubicLatticeSpace {
+    fn num_voxels(&self) -> usize {
+        self.voxels.len()
+    }
+
+    fn neighbors(&self, coordinate: Coordinate) -> Result<Vec<Coordinate>> {
+        let mut neighbors = Vec::with_capacity(OFFSETS.len());
+        for &offset in &OFFSETS {
+            if let Some(neighbor) = self.neighbor(coordinate, offset)? {
+                neighbors.push(neighbor);
+            }
+        }
+        Ok(neighbors)
+    }
+
+    fn position(&self, coordinate: Coordinate) -> Result<[f64; 3]> {
+        let (x, y, z) = self.coordinate_to_global(coordinate)?;
+        Ok([x, y, z].map(|index| self.spacing * index as f64))
+    }
+
+    fn is_periodic(&self) -> bool {
+        self.periodic
+    }
+
+    fn periodic_lengths(&self) -> [f64; 3] {
+        self.size.map(|len| self.spacing * len as f64)
+    }
+
+    fn species_at(&self, coordinate: Coordinate) -> Result<Option<SpeciesID>> {
+        self.voxel(coordinate)
+    }
+
+    fn register_species(&mut self, species: Species, location: Option<SpeciesID>) -> SpeciesID {
+        self.species.push(CubicSpecies {
+            species,
+            location,
+            info: MoleculeInfo {
+                radius: self.spacing / 2.0,
+                diffusion_coefficient: 0.0,
+            },
+            molecules: Vec::new(),
+        });
+        SpeciesID(self.species.len() - 1)
+    }
+
+    fn num_species(&self) -> usize {
+        self.species.len()
+    }
+
+    fn find_species(&self, name: &str) -> Option<SpeciesID> {
+        self.species
+            .iter()
+            .position(|species| species.species.name() == name)
+            .map(SpeciesID)
+    }
+
+    fn location_of(&self, species: SpeciesID) -> Result<Option<SpeciesID>> {
+        Ok(self.registered(species)?.location)
+    }
+
+    fn molecule_info(&self, species: SpeciesID) -> Result<MoleculeInfo> {
+        Ok(self.registered(species)?.info)
+    }
+
+    fn set_molecule_info(&mut self, species: SpeciesID, info: MoleculeInfo) -> Result<()> {
+        self.registered(species)?;
+        self.species[species.0].info = info;
+        Ok(())
+    }
+
+    fn diffusion_interval(&self, species: SpeciesID) -> Option<f64> {
+        let d = self.registered(species).ok()?.info.diffusion_coefficient;
+        if d > 0.0 {
+            Some(self.spacing * self.spacing / (6.0 * d))
+        } else {
+            None
+        }
+    }
+
+    fn num_molecules(&self, species: SpeciesID) -> Result<usize> {
+        Ok(self.registered(species)?.molecules.len())
+    }
+
+    fn coordinates_of(&self, species: SpeciesID) -> Result<Vec<Coordinate>> {
+        Ok(self
+            .registered(species)?
+            .molecules
+            .iter()
+            .map(|&(_, c)| c)
+            .collect())
+    }
+
+    fn particles_of(&self, species: SpeciesID) -> Result<Vec<ParticleID>> {
+        Ok(self
+            .registered(species)?
+            .molecules
+            .iter()
+            .map(|&(pid, _)| pid)
+            .collect())
+    }
+
+    fn find_particle(&self, pid: ParticleID) -> Option<(&Species, Coordinate)> {
+        self.species.iter().find_map(|species| {
+            species
+                .molecules
+                .iter()
+                .find(|&&(id, _)| id == pid)
+                .map(|&(_, c)| (&species.species, c))
+        })
+    }
+
+    fn particle_coordinate(&self, species: SpeciesID, pid: ParticleID) -> Option<Coordinate> {
+        self.registered(species)
+            .ok()?
+            .molecules
+            .iter()
+            .find(|&&(id, _)| id == pid)
+            .map(|&(_, c)| c)
+    }
+
+    fn place_particle(&mut self, species: SpeciesID, coordinate: Coordinate) -> Result<ParticleID> {
+        self.registered(species)?;
+        let current = self.voxel(coordinate)?;
+        if !self.can_occupy(species, coordinate) {
+            return Err(Error::InvalidLocation(coordinate, coordinate));
+        }
+        if let Some(location) = current {
+            let entry = self.entry(location, coordinate).expect("listed");
+            self.species[location.0].molecules.remove(entry);
+        }
+        let pid = self.next_pid();
+        self.species[species.0].molecules.push((pid, coordinate));
+        self.voxels[coordinate.0] = Some(species);
+        Ok(pid)
+    }
+
+    fn move_particle(&mut self, from: Coordinate, to: Coordinate) -> Result<()> {
+        let species = self.voxel(from)?.ok_or(Error::ParticleNotFound(from))?;
+        let target = self.voxel(to)?;
+        if !self.can_occupy(species, to) {
+            return Err(Error::InvalidLocation(from, to));
+        }
+        let entry = self.entry(species, from).expect("listed");
+        self.species[species.0].molecules[entry].1 = to;
+        if let Some(location) = target {
+            let entry = self.entry(location, to).expect("listed");
+            self.species[location.0].molecules[entry].1 = from;
+        }
+        self.voxels.swap(from.0, to.0);
+        Ok(())
+    }
+
+    fn remove_at(&mut self, coordinate: Coordinate) -> Result<(SpeciesID, Option<ParticleID>)> {
+        let species = self
+            .voxel(coordinate)?
+            .ok_or(Error::ParticleNotFound(coordinate))?;
+        let entry = self.entry(species, coordinate).expect("listed");
+        let (pid, _) = self.species[species.0].molecules.remove(entry);
+        let location = self.species[species.0].location;
+        if let Some(location) = location {
+            let pid = self.next_pid();
+            self.species[location.0].molecules.push((pid, coordinate));
+        }
+        self.voxels[coordinate.0] = location;
+        Ok((species, Some(pid)))
+    }
+
+    fn change_species_at(&mut self, coordinate: Coordinate, into: SpeciesID) -> Result<()> {
+        let species = self
+            .voxel(coordinate)?
+            .ok_or(Error::ParticleNotFound(coordinate))?;
+        if self.species[species.0].location != self.registered(into)?.location {
+            return Err(Error::InvalidLocation(coordinate, coordinate));
+        }
+        if species != into {
+            let entry = self.entry(species, coordinate).expect("listed");
+            let molecule = self.species[species.0].molecules.remove(entry);
+            self.species[into.0].molecules.push(molecule);
+            self.voxels[coordinate.0] = Some(into);
+        }
+        Ok(())
+    }
+
+    fn walk<R: Rng>(&mut self, species: SpeciesID, rng: &mut R) -> Result<()> {
+        for from in self.coordinates_of(species)? {
+            let offset = OFFSETS[rng.gen_range(0..OFFSETS.len())];
+            if let Some(to) = self.neighbor(from, offset)? {
+                match self.move_particle(from, to) {
+                    Ok(()) | Err(Error::InvalidLocation(..)) => {}
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn six_neighbors() {
+        let mut space = CubicLatticeSpace::new(2.0, [4, 5, 6]);
+        let c = space.global_to_coordinate(1, 2, 3).unwrap();
+        assert_eq!(space.coordinate_to_global(c).unwrap(), (1, 2, 3));
+        assert_eq!(space.position(c).unwrap(), [2.0, 4.0, 6.0]);
+        for n in space.neighbors(c).unwrap() {
+            let [x, y, z] = space.position(n).unwrap();
+            assert_eq!((x - 2.0).abs() + (y - 4.0).abs() + (z - 6.0).abs(), 2.0);
+        }
+        assert_eq!(space.neighbors(c).unwrap().len(), 6);
+        assert_eq!(space.neighbors(Coordinate(0)).unwrap().len(), 3);
+        space.set_periodic(true);
+        assert_eq!(space.neighbors(Coordinate(0)).unwrap().len(), 6);
+        assert_eq!(space.periodic_lengths(), [8.0, 10.0, 12.0]);
+    }
+}
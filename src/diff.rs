@@ -0,0 +1,229 @@
+//! Where two spaces disagree, for tests and debugging.
+//!
+//! `diff` compares the geometry, the species tables, the contents of every
+//! voxel and the molecule counts of two spaces. Species are matched by
+//! name, so that spaces registering the same species in different orders
+//! compare equal when they hold the same molecules. `ParticleID`s, the
+//! order of the molecules and the extensions of the other modules (sinks,
+//! properties, potentials) are not compared.
+
+use crate::{Coordinate, HCPLatticeSize, HCPLatticeSpace, MoleculeInfo, Species, SpeciesID};
+use std::fmt;
+
+/// How many differing voxels a `LatticeDiff` lists at most.
+pub const MAX_REPORTED_VOXELS: usize = 20;
+
+/// The differences between a space and another, `self` and `other` of
+/// `HCPLatticeSpace::diff` in that order in each pair.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct LatticeDiff {
+    pub size: Option<(HCPLatticeSize, HCPLatticeSize)>,
+    pub voxel_radius: Option<(f64, f64)>,
+    pub only_in_self: Vec<Species>,
+    pub only_in_other: Vec<Species>,
+    /// The species of both spaces whose location, `MoleculeInfo`, obstacle
+    /// flag or tracking differ.
+    pub attributes: Vec<Species>,
+    /// The first voxels whose occupants differ, in coordinate order, at most
+    /// `MAX_REPORTED_VOXELS` of them. Not compared for different sizes.
+    pub voxels: Vec<(Coordinate, Option<Species>, Option<Species>)>,
+    /// The number of voxels whose occupants differ, listed or not.
+    pub num_voxels: usize,
+    /// The species of both spaces with different numbers of molecules.
+    pub counts: Vec<(Species, usize, usize)>,
+}
+
+impl LatticeDiff {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for LatticeDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no differences");
+        }
+        if let Some((a, b)) = self.size {
+            writeln!(f, "size {:?} != {:?}", a, b)?;
+        }
+        if let Some((a, b)) = self.voxel_radius {
+            writeln!(f, "voxel radius {} != {}", a, b)?;
+        }
+        let names = |species: &[Species]| {
+            species
+                .iter()
+                .map(|species| species.name())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        if !self.only_in_self.is_empty() {
+            writeln!(f, "only in self: {}", names(&self.only_in_self))?;
+        }
+        if !self.only_in_other.is_empty() {
+            writeln!(f, "only in other: {}", names(&self.only_in_other))?;
+        }
+        if !self.attributes.is_empty() {
+            writeln!(f, "different attributes: {}", names(&self.attributes))?;
+        }
+        let name = |species: &Option<Species>| {
+            species
+                .as_ref()
+                .map_or("vacant".to_string(), |species| species.name().to_string())
+        };
+        for (c, a, b) in &self.voxels {
+            writeln!(f, "voxel {}: {} != {}", c.0, name(a), name(b))?;
+        }
+        if self.num_voxels > self.voxels.len() {
+            writeln!(
+                f,
+                "... and {} more voxels",
+                self.num_voxels - self.voxels.len()
+            )?;
+        }
+        for (species, a, b) in &self.counts {
+            writeln!(f, "{} count {} != {}", species.name(), a, b)?;
+        }
+        Ok(())
+    }
+}
+
+/// What `diff` compares of a species besides its name.
+#[derive(PartialEq)]
+struct Attributes {
+    location: Option<Species>,
+    info: MoleculeInfo,
+    obstacle: bool,
+    tracking: bool,
+}
+
+impl HCPLatticeSpace {
+    /// Compares this space with `other`; see the module documentation.
+    pub fn diff(&self, other: &HCPLatticeSpace) -> LatticeDiff {
+        let mut diff = LatticeDiff::default();
+        if self.size != other.size {
+            diff.size = Some((self.size, other.size));
+        }
+        if self.voxel_radius != other.voxel_radius {
+            diff.voxel_radius = Some((self.voxel_radius, other.voxel_radius));
+        }
+        for (id, species, count) in self.species() {
+            let other_id = match other.species_id(species) {
+                Some(other_id) => other_id,
+                None => {
+                    diff.only_in_self.push(species.clone());
+                    continue;
+                }
+            };
+            if self.attributes(id) != other.attributes(other_id) {
+                diff.attributes.push(species.clone());
+            }
+            let other_count = other.species_cache[other_id.0].num_molecules();
+            if count != other_count {
+                diff.counts.push((species.clone(), count, other_count));
+            }
+        }
+        diff.only_in_other = other
+            .species()
+            .filter(|(_, species, _)| self.species_id(species).is_none())
+            .map(|(_, species, _)| species.clone())
+            .collect();
+
+        if diff.size.is_none() {
+            let name = |space: &HCPLatticeSpace, c: Coordinate| {
+                space
+                    .voxel(c)
+                    .map(|id| space.species_cache[id.0].species.clone())
+            };
+            for c in self.coordinates() {
+                let (a, b) = (name(self, c), name(other, c));
+                if a != b {
+                    diff.num_voxels += 1;
+                    if diff.voxels.len() < MAX_REPORTED_VOXELS {
+                        diff.voxels.push((c, a, b));
+                    }
+                }
+            }
+        }
+        diff
+    }
+
+    fn attributes(&self, species: SpeciesID) -> Attributes {
+        let cache = &self.species_cache[species.0];
+        Attributes {
+            location: cache
+                .location
+                .map(|location| self.species_cache[location.0].species.clone()),
+            info: cache.info,
+            obstacle: cache.obstacle,
+            tracking: self.is_tracking(species),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn species_matched_by_name() {
+        let mut a = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let mut b = a.clone();
+        let (x, y) = (
+            a.register_species(Species::new("X"), None),
+            a.register_species(Species::new("Y"), None),
+        );
+        let (y2, x2) = (
+            b.register_species(Species::new("Y"), None),
+            b.register_species(Species::new("X"), None),
+        );
+        for i in 0..10 {
+            a.place_particle(x, Coordinate(i)).unwrap();
+            b.place_particle(x2, Coordinate(i)).unwrap();
+        }
+        a.place_particle(y, Coordinate(40)).unwrap();
+        b.place_particle(y2, Coordinate(40)).unwrap();
+        assert!(a.diff(&b).is_empty(), "{}", a.diff(&b));
+        assert_eq!(a.diff(&b).to_string(), "no differences\n");
+
+        b.move_particle(Coordinate(40), Coordinate(41)).unwrap();
+        b.register_counted_species(Species::new("Z"), None);
+        let diff = a.diff(&b);
+        assert_eq!(
+            diff.voxels,
+            vec![
+                (Coordinate(40), Some(Species::new("Y")), None),
+                (Coordinate(41), None, Some(Species::new("Y"))),
+            ]
+        );
+        assert_eq!(diff.only_in_other, vec![Species::new("Z")]);
+        assert!(diff.counts.is_empty());
+        assert_eq!(
+            diff.to_string(),
+            "only in other: Z\nvoxel 40: Y != vacant\nvoxel 41: vacant != Y\n"
+        );
+    }
+
+    #[test]
+    fn long_lists_are_capped() {
+        let mut a = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let x = a.register_species(Species::new("X"), None);
+        let mut b = a.clone();
+        b.set_tracking(x, false).unwrap();
+        for i in 0..30 {
+            a.place_particle(x, Coordinate(i)).unwrap();
+        }
+        let diff = a.diff(&b);
+        assert_eq!(diff.num_voxels, 30);
+        assert_eq!(diff.voxels.len(), MAX_REPORTED_VOXELS);
+        assert_eq!(diff.counts, vec![(Species::new("X"), 30, 0)]);
+        assert_eq!(diff.attributes, vec![Species::new("X")]);
+        assert!(diff.to_string().contains("... and 10 more voxels\n"));
+
+        let other = HCPLatticeSpace::new(2.0, HCPLatticeSize::new(4, 4, 2));
+        let diff = a.diff(&other);
+        assert!(diff.size.is_some() && diff.voxel_radius == Some((1.0, 2.0)));
+        assert_eq!(diff.num_voxels, 0);
+        assert_eq!(diff.only_in_self, vec![Species::new("X")]);
+    }
+}
@@ -0,0 +1,428 @@
+//! Diffusion on slabs of the lattice walked by separate threads, behind the
+//! `rayon` feature.
+//!
+//! `ParallelSimulator` cuts the lattice into slabs of consecutive layers and
+//! keeps, for every diffusing species, the molecules of each slab in a list
+//! of their own. A sweep of a species walks its molecules in two phases:
+//! first the even slabs, then the odd ones. The slabs of a phase are walked
+//! in parallel, each from its own `Pcg64` stream, against the space as left
+//! by the previous phase; their hops are then applied serially in slab
+//! order. A hop changes the layer by at most one, so with at least two
+//! layers per slab the slabs of one phase never reach the same voxel and
+//! every hop allowed on a slab is still allowed when applied. Molecules that
+//! crossed into another slab are handed over to its list after the sweep, in
+//! slab order.
+//!
+//! Nothing depends on the number of threads, so that a run is reproducible
+//! given the seed and the number of slabs on any thread pool. It is not the
+//! run a `Simulator` makes from the same seed, but it has the same
+//! statistics.
+//!
+//! Only diffusion is simulated. Multi-voxel species, pair interactions,
+//! location transitions and diffusing species located on another diffusing
+//! species are not supported.
+
+use crate::{
+    encode, sample_direction, Coordinate, Error, HCPLatticeSpace, Result, SpeciesID, TrackingType,
+    VoxelStore,
+};
+use rand_pcg::Pcg64;
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// A molecule listed in a slab, `entry` being its index in the species
+/// cache, so that its hops are applied without searching the cache.
+#[derive(Clone, Copy, Debug)]
+struct Molecule {
+    entry: usize,
+    coordinate: Coordinate,
+}
+
+/// The molecules of a diffusing species, by slab.
+#[derive(Clone, Debug)]
+struct Diffuser {
+    species: SpeciesID,
+    interval: f64,
+    num_sweeps: u64,
+    slabs: Vec<Vec<Molecule>>,
+}
+
+pub struct ParallelSimulator {
+    space: HCPLatticeSpace,
+    t: f64,
+    /// The first layer of every slab, then the number of layers.
+    bounds: Vec<usize>,
+    rngs: Vec<Pcg64>,
+    diffusers: Vec<Diffuser>,
+}
+
+impl ParallelSimulator {
+    /// Cuts `space` into `num_slabs` slabs of nearly equal thickness, slab
+    /// `k` drawing from the `Pcg64` stream `k` of `seed`.
+    ///
+    /// Fails with `InvalidDecomposition` if a slab would be thinner than two
+    /// layers, if a periodic lattice would have an odd number of slabs other
+    /// than one, or if the space uses something this simulator does not
+    /// support.
+    pub fn new(space: HCPLatticeSpace, num_slabs: usize, seed: u64) -> Result<Self> {
+        let num_layers = space.size.layer;
+        if num_slabs == 0
+            || num_layers < 2 * num_slabs
+            || (space.periodic && num_slabs > 1 && num_slabs % 2 == 1)
+            || !space.interactions.is_empty()
+        {
+            return Err(Error::InvalidDecomposition);
+        }
+        let bounds: Vec<usize> = (0..=num_slabs)
+            .map(|k| k * num_layers / num_slabs)
+            .collect();
+
+        let mut diffusers = Vec::new();
+        for (i, cache) in space.species_cache.iter().enumerate() {
+            let species = SpeciesID(i);
+            let interval = match space.diffusion_interval(species) {
+                Some(interval) => interval,
+                None => continue,
+            };
+            let on_diffuser = cache
+                .location
+                .is_some_and(|location| space.diffusion_interval(location).is_some());
+            if cache.voxel_count > 1 || on_diffuser || !cache.transitions.is_empty() {
+                return Err(Error::InvalidDecomposition);
+            }
+            let mut slabs = vec![Vec::new(); num_slabs];
+            for (entry, coordinate) in space.coordinates_of(species)?.into_iter().enumerate() {
+                slabs[slab_of(&bounds, layer_of(&space, coordinate))]
+                    .push(Molecule { entry, coordinate });
+            }
+            diffusers.push(Diffuser {
+                species,
+                interval,
+                num_sweeps: 0,
+                slabs,
+            });
+        }
+
+        let rngs = (0..num_slabs)
+            .map(|k| Pcg64::new(seed.into(), k as u128))
+            .collect();
+        Ok(Self {
+            space,
+            t: 0.0,
+            bounds,
+            rngs,
+            diffusers,
+        })
+    }
+
+    pub fn t(&self) -> f64 {
+        self.t
+    }
+
+    pub fn space(&self) -> &HCPLatticeSpace {
+        &self.space
+    }
+
+    pub fn into_space(self) -> HCPLatticeSpace {
+        self.space
+    }
+
+    pub fn num_slabs(&self) -> usize {
+        self.rngs.len()
+    }
+
+    /// Sweeps every diffusing species every `2r²/(3D)` up to and including
+    /// `t + duration`, species due at the same time in registration order.
+    pub fn run(&mut self, duration: f64) -> Result<()> {
+        let end = self.t + duration;
+        let tolerance = end.abs() * 1e-12;
+        loop {
+            let next = self
+                .diffusers
+                .iter()
+                .enumerate()
+                .map(|(i, d)| (i, (d.num_sweeps + 1) as f64 * d.interval))
+                .min_by(|a, b| a.1.partial_cmp(&b.1).expect("finite times"));
+            match next {
+                Some((i, time)) if time <= end + tolerance => {
+                    self.t = time;
+                    self.sweep(i)?;
+                }
+                _ => break,
+            }
+        }
+        self.t = end;
+        Ok(())
+    }
+
+    /// Attempts one hop for every molecule of the `i`-th diffusing species.
+    fn sweep(&mut self, i: usize) -> Result<()> {
+        let diffuser = &mut self.diffusers[i];
+        let species = diffuser.species;
+        let location = encode(self.space.species_cache[species.0].location);
+        for phase in 0..2 {
+            let space = &self.space;
+            let hops = self
+                .rngs
+                .iter_mut()
+                .zip(&diffuser.slabs)
+                .skip(phase)
+                .step_by(2)
+                .collect::<Vec<_>>()
+                .into_par_iter()
+                .map(|(rng, molecules)| walk_slab(space, species, location, molecules, rng))
+                .collect::<Result<Vec<_>>>()?;
+            for (k, hops) in (phase..).step_by(2).zip(hops) {
+                for (j, to) in hops {
+                    let molecule = &mut diffuser.slabs[k][j];
+                    self.space
+                        .apply_hop(species, molecule.entry, molecule.coordinate, to)?;
+                    molecule.coordinate = to;
+                }
+            }
+        }
+
+        let (bounds, space) = (&self.bounds, &self.space);
+        let mut leavers = Vec::new();
+        for (k, molecules) in diffuser.slabs.iter_mut().enumerate() {
+            molecules.retain(|&molecule| {
+                let slab = slab_of(bounds, layer_of(space, molecule.coordinate));
+                if slab != k {
+                    leavers.push((slab, molecule));
+                }
+                slab == k
+            });
+        }
+        for (slab, molecule) in leavers {
+            diffuser.slabs[slab].push(molecule);
+        }
+        diffuser.num_sweeps += 1;
+        Ok(())
+    }
+}
+
+impl HCPLatticeSpace {
+    /// Moves the molecule of a single-voxel `species` at `from` to the
+    /// adjacent `to`, known to be a voxel of its location, `entry` being the
+    /// index of the molecule in the species cache. Same as `move_particle`
+    /// otherwise.
+    fn apply_hop(
+        &mut self,
+        species: SpeciesID,
+        entry: usize,
+        from: Coordinate,
+        to: Coordinate,
+    ) -> Result<()> {
+        let cache = self.get_species_cache_mut(species)?;
+        if let TrackingType::Tracking(entries) = &mut cache.cache {
+            entries.set_coordinate(entry, to);
+        }
+        if let Some(location) = cache.location {
+            self.get_species_cache_mut(location)?.move_to(to, from);
+        }
+        self.voxels.swap(from.0, to.0);
+        if self.images.is_some() && self.periodic {
+            self.track_image(species, from, to)?;
+        }
+        Ok(())
+    }
+}
+
+fn layer_of(space: &HCPLatticeSpace, coordinate: Coordinate) -> usize {
+    coordinate.0 / (space.size.row * space.size.col)
+}
+
+/// Returns the slab holding `layer`.
+fn slab_of(bounds: &[usize], layer: usize) -> usize {
+    bounds.partition_point(|&first| first <= layer) - 1
+}
+
+/// Draws a hop for every molecule of a slab, in order, and returns the
+/// accepted ones as `(index, to)`. Hops are checked against the space
+/// overlaid with the hops accepted before.
+fn walk_slab(
+    space: &HCPLatticeSpace,
+    species: SpeciesID,
+    location: u32,
+    molecules: &[Molecule],
+    rng: &mut Pcg64,
+) -> Result<Vec<(usize, Coordinate)>> {
+    let raw = encode(Some(species));
+    let mut overlay: HashMap<usize, u32> = HashMap::new();
+    let mut hops = Vec::new();
+    for (j, molecule) in molecules.iter().enumerate() {
+        let from = molecule.coordinate;
+        let direction = sample_direction(&[1.0; 12], 12.0, rng);
+        if let Some(to) = space.neighbor(from, direction)? {
+            let current = overlay
+                .get(&to.0)
+                .copied()
+                .unwrap_or_else(|| space.voxels.get(to.0));
+            if current == location {
+                overlay.insert(to.0, raw);
+                overlay.insert(from.0, location);
+                hops.push((j, to));
+            }
+        }
+    }
+    Ok(hops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HCPLatticeSize, MoleculeInfo, ParticleID, Simulator, Species};
+
+    const D: f64 = 1e-12;
+
+    fn space(size: usize, periodic: bool) -> (HCPLatticeSpace, SpeciesID) {
+        let mut space = HCPLatticeSpace::new(1e-8, HCPLatticeSize::new(size, size, size));
+        space.set_periodic(periodic).unwrap();
+        space.set_image_tracking(true);
+        let a = space.register_species(Species::new("A"), None);
+        space
+            .set_molecule_info(
+                a,
+                MoleculeInfo {
+                    radius: 1e-8,
+                    diffusion_coefficient: D,
+                },
+            )
+            .unwrap();
+        (space, a)
+    }
+
+    /// Checks that every molecule is listed exactly once, in its slab.
+    fn check_slabs(sim: &ParallelSimulator) {
+        for diffuser in &sim.diffusers {
+            let mut listed: Vec<usize> = Vec::new();
+            let expected = sim.space.coordinates_of(diffuser.species).unwrap();
+            for (k, molecules) in diffuser.slabs.iter().enumerate() {
+                for molecule in molecules {
+                    let c = molecule.coordinate;
+                    assert_eq!(slab_of(&sim.bounds, layer_of(&sim.space, c)), k);
+                    assert_eq!(expected[molecule.entry], c);
+                    listed.push(c.0);
+                }
+            }
+            let mut expected: Vec<usize> = expected.iter().map(|c| c.0).collect();
+            listed.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(listed, expected);
+        }
+        sim.space.validate().unwrap();
+    }
+
+    fn msd(space: &HCPLatticeSpace, starts: &[(ParticleID, [f64; 3])]) -> f64 {
+        starts
+            .iter()
+            .map(|&(pid, start)| {
+                let end = space.unwrapped_position(pid).unwrap();
+                (0..3).map(|i| (end[i] - start[i]).powi(2)).sum::<f64>()
+            })
+            .sum::<f64>()
+            / starts.len() as f64
+    }
+
+    #[test]
+    fn matches_serial_msd() {
+        let setup = || {
+            let (mut space, a) = space(30, true);
+            let starts: Vec<_> = (0..27000)
+                .step_by(17)
+                .take(1500)
+                .map(|i| {
+                    let pid = space.place_particle(a, Coordinate(i)).unwrap();
+                    (pid, space.unwrapped_position(pid).unwrap())
+                })
+                .collect();
+            (space, a, starts)
+        };
+        let (serial, a, starts) = setup();
+        let mut serial = Simulator::with_seed(serial, 7);
+        let mut parallel = ParallelSimulator::new(setup().0, 4, 7).unwrap();
+        let duration = 60.0 * serial.space().diffusion_interval(a).unwrap();
+        serial.run(duration).unwrap();
+        parallel.run(duration).unwrap();
+        check_slabs(&parallel);
+
+        let serial = msd(serial.space(), &starts);
+        let parallel = msd(parallel.space(), &starts);
+        assert!((serial / (6.0 * D * duration) - 0.95).abs() < 0.05);
+        assert!((parallel - serial).abs() < 0.07 * serial);
+    }
+
+    #[test]
+    fn spreads_across_slabs_like_serial() {
+        let setup = || {
+            let (mut space, a) = space(24, false);
+            for c in space
+                .coordinates()
+                .take(24 * 24 * 6)
+                .step_by(3)
+                .collect::<Vec<_>>()
+            {
+                space.place_particle(a, c).unwrap();
+            }
+            (space, a)
+        };
+        let (serial, a) = setup();
+        let mut serial = Simulator::with_seed(serial, 3);
+        let mut parallel = ParallelSimulator::new(setup().0, 6, 3).unwrap();
+        let duration = 60.0 * serial.space().diffusion_interval(a).unwrap();
+        let beyond = |space: &HCPLatticeSpace| {
+            space
+                .coordinates_of(a)
+                .unwrap()
+                .into_iter()
+                .filter(|&c| layer_of(space, c) >= 6)
+                .count() as f64
+        };
+        serial.run(duration / 2.0).unwrap();
+        parallel.run(duration / 2.0).unwrap();
+        check_slabs(&parallel);
+        serial.run(duration / 2.0).unwrap();
+        parallel.run(duration / 2.0).unwrap();
+        check_slabs(&parallel);
+
+        assert_eq!(parallel.space().num_molecules(a).unwrap(), 1152);
+        let (serial, parallel) = (beyond(serial.space()), beyond(parallel.space()));
+        assert!(serial > 200.0);
+        assert!((parallel - serial).abs() < 0.1 * serial);
+    }
+
+    #[test]
+    fn independent_of_thread_count() {
+        let run = |threads| {
+            let (mut space, a) = space(16, true);
+            for c in space.coordinates().step_by(3).collect::<Vec<_>>() {
+                space.place_particle(a, c).unwrap();
+            }
+            let duration = 10.0 * space.diffusion_interval(a).unwrap();
+            let mut sim = ParallelSimulator::new(space, 8, 1).unwrap();
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| sim.run(duration))
+                .unwrap();
+            check_slabs(&sim);
+            sim.space().occupied().collect::<Vec<_>>()
+        };
+        let reference = run(2);
+        for threads in [4, 8] {
+            assert_eq!(run(threads), reference);
+        }
+    }
+
+    #[test]
+    fn rejects_thin_or_odd_slabs() {
+        let new = |periodic, num_slabs| ParallelSimulator::new(space(8, periodic).0, num_slabs, 0);
+        assert!(new(true, 2).is_ok());
+        assert!(new(true, 1).is_ok());
+        assert!(new(false, 3).is_ok());
+        assert!(matches!(new(true, 3), Err(Error::InvalidDecomposition)));
+        assert!(matches!(new(false, 5), Err(Error::InvalidDecomposition)));
+        assert!(matches!(new(false, 0), Err(Error::InvalidDecomposition)));
+    }
+}
@@ -0,0 +1,283 @@
+//! Reading worlds saved by E-Cell4's `SpatiocyteWorld`.
+//!
+//! E-Cell4 lays the same HCP lattice out along different axes: its layers
+//! are stacked along x, its close-packed rows run along z, and its voxels
+//! are indexed by `(row, layer, col)` with `col` the stacking index. An
+//! E-Cell4 voxel `(row, layer, col)` is this crate's `(layer, row + 1 - s,
+//! col)`, `s` being 1 if both `layer` and `col` are odd and 0 otherwise, so
+//! that its E-Cell4 position `(x, y, z)` is this crate's `(z + 2r, y, x)`.
+//! The extra column keeps every voxel on the lattice.
+//!
+//! The file is expected to hold a `LatticeSpace` group with the attributes
+//! `t`, `voxel_radius` and `edge_lengths` and a `species` group with one
+//! subgroup per species, named by its serial. Each subgroup has the
+//! attributes `radius`, `D` and `location` (empty for vacant voxels) and a
+//! `voxels` dataset of `(lot, serial, coordinate)` records, coordinates
+//! counting the one-voxel border E-Cell4 keeps around the lattice.
+
+use crate::{
+    Coordinate, Error, HCPLatticeSize, HCPLatticeSpace, MoleculeInfo, ParticleID, Result, Species,
+    TrackingType,
+};
+use hdf5::types::{FixedAscii, VarLenUnicode};
+use hdf5::{Group, H5Type};
+use std::path::Path;
+
+#[derive(H5Type, Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+struct H5Voxel {
+    lot: u32,
+    serial: u32,
+    coordinate: u64,
+}
+
+fn to_error(err: hdf5::Error) -> Error {
+    Error::Parse(err.to_string())
+}
+
+fn read_f64(group: &Group, name: &str) -> Result<f64> {
+    group
+        .attr(name)
+        .and_then(|attr| attr.read_scalar())
+        .map_err(to_error)
+}
+
+/// E-Cell4 writes fixed-length strings; variable-length ones are accepted
+/// as well.
+fn read_string(group: &Group, name: &str) -> Result<String> {
+    let attr = group.attr(name).map_err(to_error)?;
+    match attr.read_scalar::<FixedAscii<256>>() {
+        Ok(value) => Ok(value.as_str().to_string()),
+        Err(_) => attr
+            .read_scalar::<VarLenUnicode>()
+            .map(|value| value.as_str().to_string())
+            .map_err(to_error),
+    }
+}
+
+/// Returns the `(row, layer, col)` sizes of the E-Cell4 lattice covering
+/// `edge_lengths`, without the border.
+fn ecell4_shape(voxel_radius: f64, edge_lengths: &[f64]) -> (usize, usize, usize) {
+    let hcp_x = voxel_radius * (8.0f64 / 3.0).sqrt();
+    let hcp_y = voxel_radius * 3.0f64.sqrt();
+    let col = (edge_lengths[0] / hcp_x).round() as usize + 1;
+    let layer = (edge_lengths[1] / hcp_y).round() as usize + 1;
+    let row = (edge_lengths[2] / 2.0 / voxel_radius).round() as usize + 1;
+    (row, layer, col)
+}
+
+impl HCPLatticeSpace {
+    /// Reconstructs a world saved by E-Cell4 and returns it with its time.
+    ///
+    /// Species keep their serials, radii, diffusion coefficients and
+    /// locations, and molecules keep their `ParticleID`s. A species whose
+    /// voxels carry no `ParticleID` (lot and serial both zero), as E-Cell4
+    /// writes structures, is only counted.
+    pub fn from_hdf5(path: &Path) -> Result<(Self, f64)> {
+        let file = hdf5::File::open(path).map_err(to_error)?;
+        let root = file.group("LatticeSpace").map_err(to_error)?;
+        let t = read_f64(&root, "t")?;
+        let voxel_radius = read_f64(&root, "voxel_radius")?;
+        let edge_lengths: Vec<f64> = root
+            .attr("edge_lengths")
+            .and_then(|attr| attr.read_raw())
+            .map_err(to_error)?;
+        if edge_lengths.len() != 3 {
+            return Err(Error::Parse(
+                "edge_lengths must have 3 elements".to_string(),
+            ));
+        }
+
+        let (rows, layers, cols) = ecell4_shape(voxel_radius, &edge_lengths);
+        let mut space = Self::try_new(voxel_radius, HCPLatticeSize::new(layers, rows + 1, cols))?;
+
+        let species_group = root.group("species").map_err(to_error)?;
+        let mut pending = Vec::new();
+        for serial in species_group.member_names().map_err(to_error)? {
+            let group = species_group.group(&serial).map_err(to_error)?;
+            let location = read_string(&group, "location")?;
+            let voxels: Vec<H5Voxel> = group
+                .dataset("voxels")
+                .and_then(|dataset| dataset.read_raw())
+                .map_err(to_error)?;
+            let info = MoleculeInfo {
+                radius: read_f64(&group, "radius")?,
+                diffusion_coefficient: read_f64(&group, "D")?,
+            };
+            pending.push((serial, location, info, voxels));
+        }
+
+        // Locations have to be registered before the species on them, while
+        // the groups come in alphabetical order.
+        while !pending.is_empty() {
+            let ready = pending.iter().position(|(_, location, _, _)| {
+                location.is_empty() || space.find_species(location).is_some()
+            });
+            let (serial, location, info, voxels) = match ready {
+                Some(i) => pending.remove(i),
+                None => return Err(Error::Parse(format!("unknown location {:?}", pending[0].1))),
+            };
+            let location = space.find_species(&location);
+            let id = space.register_species(Species::new(&serial), location);
+            space.set_molecule_info(id, info)?;
+            let tracked = voxels.iter().any(|v| v.lot != 0 || v.serial != 0);
+            if !tracked {
+                space.get_species_cache_mut(id)?.cache = TrackingType::Count(0);
+            }
+
+            for voxel in voxels {
+                let coordinate = space.ecell4_coordinate(voxel.coordinate, rows, layers)?;
+                space.place_particle(id, coordinate)?;
+                if tracked {
+                    let pid = ParticleID(voxel.lot.into(), voxel.serial.into());
+                    if let TrackingType::Tracking(entries) =
+                        &mut space.get_species_cache_mut(id)?.cache
+                    {
+                        entries.set_pid(entries.len() - 1, pid);
+                    }
+                    space.pids.reserve(pid);
+                }
+            }
+        }
+        Ok((space, t))
+    }
+
+    /// Maps a bordered E-Cell4 coordinate on a lattice of `rows` by `layers`
+    /// inner voxels to a coordinate of this space.
+    fn ecell4_coordinate(&self, coordinate: u64, rows: usize, layers: usize) -> Result<Coordinate> {
+        let coordinate = coordinate as usize;
+        let (row, rest) = (coordinate % (rows + 2), coordinate / (rows + 2));
+        let (layer, col) = (rest % (layers + 2), rest / (layers + 2));
+        let out_of_range = || Error::Parse(format!("voxel {} lies on the border", coordinate));
+        let row = row.checked_sub(1).ok_or_else(out_of_range)?;
+        let layer = layer.checked_sub(1).ok_or_else(out_of_range)?;
+        let col = col.checked_sub(1).ok_or_else(out_of_range)?;
+        let shift = (layer % 2 == 1 && col % 2 == 1) as usize;
+        self.global_to_coordinate(layer, row + 1 - shift, col)
+            .map_err(|_| out_of_range())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_f64(group: &Group, name: &str, value: f64) {
+        group
+            .new_attr::<f64>()
+            .create(name)
+            .unwrap()
+            .write_scalar(&value)
+            .unwrap();
+    }
+
+    fn write_species(parent: &Group, serial: &str, location: &str, voxels: &[H5Voxel]) {
+        let group = parent.create_group(serial).unwrap();
+        write_f64(&group, "radius", 5e-9);
+        write_f64(&group, "D", 1e-12);
+        group
+            .new_attr::<FixedAscii<32>>()
+            .create("location")
+            .unwrap()
+            .write_scalar(&FixedAscii::<32>::from_ascii(location).unwrap())
+            .unwrap();
+        group
+            .new_dataset::<H5Voxel>()
+            .shape(voxels.len())
+            .create("voxels")
+            .unwrap()
+            .write_raw(voxels)
+            .unwrap();
+    }
+
+    /// Returns the E-Cell4 position of a bordered coordinate.
+    fn ecell4_position(r: f64, coordinate: u64, rows: u64, layers: u64) -> [f64; 3] {
+        let row = (coordinate % (rows + 2)) as f64 - 1.0;
+        let layer = ((coordinate / (rows + 2)) % (layers + 2)) as f64 - 1.0;
+        let col = (coordinate / (rows + 2) / (layers + 2)) as f64 - 1.0;
+        let odd = ((layer + col) as u64 % 2) as f64;
+        [
+            col * r * (8.0f64 / 3.0).sqrt(),
+            (col as u64 % 2) as f64 * r / 3.0f64.sqrt() + layer * r * 3.0f64.sqrt(),
+            (row * 2.0 + odd) * r,
+        ]
+    }
+
+    #[test]
+    fn read_world() {
+        let r = 5e-9;
+        let path = std::env::temp_dir().join("spatiocyte_ecell4_world.h5");
+        {
+            let file = hdf5::File::create(&path).unwrap();
+            let root = file.create_group("LatticeSpace").unwrap();
+            write_f64(&root, "t", 2.5);
+            write_f64(&root, "voxel_radius", r);
+            let edges = [
+                3.0 * r * (8.0f64 / 3.0).sqrt(),
+                3.0 * r * 3.0f64.sqrt(),
+                6.0 * r,
+            ];
+            root.new_attr::<f64>()
+                .shape(3)
+                .create("edge_lengths")
+                .unwrap()
+                .write_raw(&edges[..])
+                .unwrap();
+            let species = root.create_group("species").unwrap();
+            let membrane = [H5Voxel {
+                lot: 0,
+                serial: 0,
+                coordinate: 43,
+            }];
+            write_species(&species, "M", "", &membrane);
+            let tracked = [
+                H5Voxel {
+                    lot: 1,
+                    serial: 7,
+                    coordinate: 44,
+                },
+                H5Voxel {
+                    lot: 1,
+                    serial: 9,
+                    coordinate: 88,
+                },
+            ];
+            write_species(&species, "A", "", &tracked);
+            let on_membrane = [H5Voxel {
+                lot: 0,
+                serial: 0,
+                coordinate: 43,
+            }];
+            write_species(&species, "B", "M", &on_membrane);
+        }
+
+        let (space, t) = HCPLatticeSpace::from_hdf5(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(t, 2.5);
+        assert_eq!(
+            (space.size.row, space.size.col, space.size.layer),
+            (4, 5, 4)
+        );
+        space.validate().unwrap();
+
+        let a = space.find_species("A").unwrap();
+        let m = space.find_species("M").unwrap();
+        let b = space.find_species("B").unwrap();
+        assert_eq!(space.location_of(b).unwrap(), Some(m));
+        assert_eq!(space.num_molecules(m).unwrap(), 0);
+        assert_eq!(space.num_molecules(b).unwrap(), 1);
+        assert!(matches!(
+            space.species_cache[m.0].cache,
+            TrackingType::Count(0)
+        ));
+        assert_eq!(space.molecule_info(a).unwrap().diffusion_coefficient, 1e-12);
+
+        let (_, at) = space.find_particle(ParticleID(1, 9)).unwrap();
+        let ours = space.coordinate_to_position(at).unwrap();
+        let theirs = ecell4_position(r, 88, 4, 4);
+        let expected = [theirs[2] + 2.0 * r, theirs[1], theirs[0]];
+        for (a, b) in ours.iter().zip(&expected) {
+            assert!((a - b).abs() < 1e-6 * r);
+        }
+    }
+}
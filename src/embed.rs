@@ -0,0 +1,304 @@
+//! Copying the molecules of one space into a box of another, e.g. dropping
+//! an equilibrated organelle into a cell-scale lattice.
+//!
+//! `copy_from` maps the voxel `(row, col, layer)` of the source to `(row +
+//! dr, col + dc, layer + dl)` of the destination. The stagger of the
+//! lattice alternates with the parity of rows and of layers (see the
+//! `neighbors` module), so that an odd `dr` or `dl` would turn the
+//! neighbors of a voxel into other ones: these offsets have to be even,
+//! while `dc` may be any. A molecule located on a structure brings the
+//! structure under it along, which is hidden by the molecule in the
+//! source. Structures that the destination already has on a voxel are
+//! built upon rather than collided with, so that membrane proteins can be
+//! dropped onto an existing membrane.
+//!
+//! Species are matched by name, the missing ones being registered with the
+//! location, `MoleculeInfo`, obstacle flag and tracking of the source. The
+//! molecules get fresh `ParticleID`s. `extract` goes the other way, copying
+//! a box of a space into a new space of that size.
+
+use crate::{Coordinate, Error, HCPLatticeSize, HCPLatticeSpace, Result, SpeciesID};
+
+/// What to do when a source molecule maps onto a voxel the destination
+/// already holds something else on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CopyPolicy {
+    /// Fail with `InvalidLocation(source, destination)`, copying nothing.
+    Error,
+    /// Leave the voxel alone and count it as skipped.
+    Skip,
+    /// Remove what is there first and count it as overwritten.
+    Overwrite,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct CopyReport {
+    /// The number of source voxels copied, overwritten ones included.
+    pub copied: usize,
+    pub skipped: usize,
+    pub overwritten: usize,
+    /// The species registered in the destination by the copy, in
+    /// registration order.
+    pub registered: Vec<SpeciesID>,
+}
+
+impl HCPLatticeSpace {
+    /// Copies every occupied voxel of `other` to `offset` rows, columns and
+    /// layers away in this space, handling collisions by `policy`.
+    ///
+    /// Fails with `VoxelRadiusMismatch` unless both spaces have the same
+    /// voxel radius, with `StaggeredOffset` for an odd row or layer offset,
+    /// with `OutOfRange` if `other` does not fit, and with `InvalidReaction`
+    /// if it holds multi-voxel molecules. The space is left unchanged on
+    /// failure.
+    pub fn copy_from(
+        &mut self,
+        other: &HCPLatticeSpace,
+        offset: (usize, usize, usize),
+        policy: CopyPolicy,
+    ) -> Result<CopyReport> {
+        self.copy_box(other, (0, 0, 0), offset, other.size, policy)
+    }
+
+    /// Returns a new space of `size` holding the molecules of the box of
+    /// this space starting at `offset`, with every species registered in
+    /// the same order so that their IDs agree. The offset has to be even in
+    /// rows and layers as for `copy_from`.
+    pub fn extract(
+        &self,
+        offset: (usize, usize, usize),
+        size: HCPLatticeSize,
+    ) -> Result<HCPLatticeSpace> {
+        let mut space = HCPLatticeSpace::new(self.voxel_radius, size);
+        for i in 0..self.species_cache.len() {
+            space.import_species(self, SpeciesID(i));
+        }
+        space.copy_box(self, offset, (0, 0, 0), size, CopyPolicy::Error)?;
+        Ok(space)
+    }
+
+    fn copy_box(
+        &mut self,
+        other: &HCPLatticeSpace,
+        from: (usize, usize, usize),
+        to: (usize, usize, usize),
+        size: HCPLatticeSize,
+        policy: CopyPolicy,
+    ) -> Result<CopyReport> {
+        if self.voxel_radius != other.voxel_radius {
+            return Err(Error::VoxelRadiusMismatch(
+                self.voxel_radius,
+                other.voxel_radius,
+            ));
+        }
+        let odd = |a: usize, b: usize| (a + b) % 2 == 1;
+        if odd(from.0, to.0) || odd(from.2, to.2) {
+            return Err(Error::StaggeredOffset);
+        }
+        if size.num_voxels() == Some(0) {
+            return Ok(CopyReport::default());
+        }
+        let corner = (size.row - 1, size.col - 1, size.layer - 1);
+        other.global_to_coordinate(from.0 + corner.0, from.1 + corner.1, from.2 + corner.2)?;
+        self.global_to_coordinate(to.0 + corner.0, to.1 + corner.1, to.2 + corner.2)?;
+
+        let mut report = CopyReport::default();
+        let registered = self.species_cache.len();
+        self.transaction(|space| {
+            for (source, species) in other.occupied() {
+                let (row, col, layer) = other.size.global(source.0);
+                let inside = (from.0..from.0 + size.row).contains(&row)
+                    && (from.1..from.1 + size.col).contains(&col)
+                    && (from.2..from.2 + size.layer).contains(&layer);
+                if !inside {
+                    continue;
+                }
+                if other.species_cache[species.0].voxel_count > 1 {
+                    return Err(Error::InvalidReaction);
+                }
+                let target = space.flatten(
+                    row - from.0 + to.0,
+                    col - from.1 + to.1,
+                    layer - from.2 + to.2,
+                );
+                space.copy_voxel(other, species, source, target, policy, &mut report)?;
+            }
+            Ok(())
+        })?;
+        report.registered = (registered..self.species_cache.len())
+            .map(SpeciesID)
+            .collect();
+        Ok(report)
+    }
+
+    /// Places the molecule of `species` at `source` of `other` on `target`,
+    /// with the structures it is located on.
+    fn copy_voxel(
+        &mut self,
+        other: &HCPLatticeSpace,
+        species: SpeciesID,
+        source: Coordinate,
+        target: Coordinate,
+        policy: CopyPolicy,
+        report: &mut CopyReport,
+    ) -> Result<()> {
+        let mut chain = vec![self.import_species(other, species)];
+        while let Some(location) = self.species_cache[chain[0].0].location {
+            chain.insert(0, location);
+        }
+        let base = |space: &Self| match space.voxel(target) {
+            None => Some(0),
+            Some(held) => chain[..chain.len() - 1]
+                .iter()
+                .position(|&id| id == held)
+                .map(|i| i + 1),
+        };
+        let start = match (base(self), policy) {
+            (Some(start), _) => start,
+            (None, CopyPolicy::Error) => return Err(Error::InvalidLocation(source, target)),
+            (None, CopyPolicy::Skip) => {
+                report.skipped += 1;
+                return Ok(());
+            }
+            (None, CopyPolicy::Overwrite) => {
+                report.overwritten += 1;
+                loop {
+                    if let Some(start) = base(self) {
+                        break start;
+                    }
+                    self.remove_at(target)?;
+                }
+            }
+        };
+        for &id in &chain[start..] {
+            self.place_particle(id, target)?;
+        }
+        report.copied += 1;
+        Ok(())
+    }
+
+    /// Returns the species of this space named as `species` of `other`,
+    /// registering it and its location if missing.
+    fn import_species(&mut self, other: &HCPLatticeSpace, species: SpeciesID) -> SpeciesID {
+        let source = &other.species_cache[species.0];
+        if let Some(id) = self.species_id(&source.species) {
+            return id;
+        }
+        let location = source
+            .location
+            .map(|location| self.import_species(other, location));
+        let id = if source.obstacle {
+            self.register_obstacle(source.species.clone())
+        } else {
+            self.register_species(source.species.clone(), location)
+        };
+        let cache = self.get_species_cache_mut(id).expect("just registered");
+        cache.info = source.info;
+        if !other.is_tracking(species) {
+            cache.cache = crate::TrackingType::Count(0);
+        }
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Species;
+
+    /// A membrane patch with molecules on it and in the bulk.
+    fn organelle() -> HCPLatticeSpace {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), Some(membrane));
+        let b = space.register_counted_species(Species::new("B"), None);
+        for i in 0..16 {
+            space.place_particle(membrane, Coordinate(i)).unwrap();
+        }
+        space.place_particle(a, Coordinate(5)).unwrap();
+        space.place_particle(b, Coordinate(30)).unwrap();
+        space.place_particle(b, Coordinate(63)).unwrap();
+        space
+    }
+
+    #[test]
+    fn embed_and_extract() {
+        let small = organelle();
+        let mut cell = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(10, 10, 10));
+        let wall = cell.register_obstacle(Species::new("W"));
+        let report = cell
+            .copy_from(&small, (2, 3, 4), CopyPolicy::Error)
+            .unwrap();
+        assert_eq!(report.copied, 18);
+        assert_eq!(report.registered.len(), 3);
+        cell.validate().unwrap();
+        let a = cell.find_species("A").unwrap();
+        assert_eq!(cell.location_of(a).unwrap(), cell.find_species("M"));
+        assert!(!cell.is_tracking(cell.find_species("B").unwrap()));
+        let (row, col, layer) = small.coordinate_to_global(Coordinate(5)).unwrap();
+        let c = cell
+            .global_to_coordinate(row + 2, col + 3, layer + 4)
+            .unwrap();
+        assert_eq!(cell.species_at(c).unwrap(), Some(a));
+
+        let back = cell
+            .extract((2, 3, 4), HCPLatticeSize::new(4, 4, 4))
+            .unwrap();
+        back.validate().unwrap();
+        let diff = back.diff(&small);
+        assert_eq!(diff.only_in_self, vec![Species::new("W")]);
+        assert_eq!((diff.num_voxels, diff.attributes.len()), (0, 0));
+        assert_eq!(back.get_species(wall), Some(&Species::new("W")));
+        for (i, (_, species, _)) in back.species().enumerate() {
+            assert_eq!(cell.get_species(SpeciesID(i)), Some(species));
+        }
+    }
+
+    #[test]
+    fn collisions_follow_the_policy() {
+        let small = organelle();
+        let mut cell = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 8, 4));
+        let b = cell.register_species(Species::new("B"), None);
+        let membrane = cell.register_species(Species::new("M"), None);
+        let blocked = cell.global_to_coordinate(3, 7, 3).unwrap();
+        cell.place_particle(b, blocked).unwrap();
+        let existing = cell.global_to_coordinate(1, 5, 0).unwrap();
+        cell.place_particle(membrane, existing).unwrap();
+        let before = cell.clone();
+
+        assert!(matches!(
+            cell.copy_from(&small, (0, 4, 0), CopyPolicy::Error),
+            Err(Error::InvalidLocation(Coordinate(63), c)) if c == blocked
+        ));
+        assert_eq!(cell.voxels, before.voxels);
+        assert_eq!(cell.num_species(), 2);
+
+        let report = cell.copy_from(&small, (0, 4, 0), CopyPolicy::Skip).unwrap();
+        assert_eq!((report.copied, report.skipped), (17, 1));
+        assert_eq!(cell.num_molecules(membrane).unwrap(), 15);
+        cell.validate().unwrap();
+
+        let mut cell = before.clone();
+        let report = cell
+            .copy_from(&small, (0, 4, 0), CopyPolicy::Overwrite)
+            .unwrap();
+        assert_eq!((report.copied, report.overwritten), (18, 1));
+        assert_eq!(cell.num_molecules(b).unwrap(), 2);
+        cell.validate().unwrap();
+
+        let mut cell = before;
+        assert!(matches!(
+            cell.copy_from(&small, (1, 0, 0), CopyPolicy::Error),
+            Err(Error::StaggeredOffset)
+        ));
+        assert!(matches!(
+            cell.copy_from(&small, (0, 5, 0), CopyPolicy::Error),
+            Err(Error::OutOfRange(_))
+        ));
+        let other = HCPLatticeSpace::new(2.0, HCPLatticeSize::new(4, 4, 4));
+        assert!(matches!(
+            cell.copy_from(&other, (0, 0, 0), CopyPolicy::Error),
+            Err(Error::VoxelRadiusMismatch(..))
+        ));
+    }
+}
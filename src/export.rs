@@ -0,0 +1,480 @@
+//! Snapshot exporters for visualization tools.
+//!
+//! `write_vtk` produces legacy VTK poly-data readable by ParaView and
+//! `write_xyz` produces an extended-XYZ frame readable by OVITO. Every
+//! molecule is written as a point at its voxel center with the index of its
+//! `SpeciesID` as an integer attribute, which stays stable for the lifetime of
+//! the space and can be used for coloring. With the `json` feature,
+//! `write_json` produces a document `load_model_json` reads back.
+//!
+//! A species may carry a `SpeciesStyle`, a display color and radius that the
+//! exporters write alongside: as `radius` and `color` point data in VTK, as
+//! `radius` and `color` properties in XYZ, which OVITO picks up, and as a
+//! `style` block in JSON. Once a selected species has a style, the others
+//! are drawn gray at their molecule radius. Styles have no effect on the
+//! simulation.
+
+use crate::{Coordinate, HCPLatticeSpace, SpeciesID};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// How a species is drawn: an RGB color and a radius in the length unit of
+/// the space.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SpeciesStyle {
+    pub color: [u8; 3],
+    pub radius: f64,
+}
+
+impl HCPLatticeSpace {
+    /// Sets how `species` is drawn in exports, `None` leaving it unstyled.
+    /// Fails with `UnknownSpecies` for an ID of no species of this space.
+    pub fn set_species_style(
+        &mut self,
+        species: SpeciesID,
+        style: Option<SpeciesStyle>,
+    ) -> crate::Result<()> {
+        self.get_species_cache_mut(species)?.style = style;
+        Ok(())
+    }
+
+    /// Returns the style of `species`, `None` if unstyled or no species of
+    /// this space.
+    pub fn species_style(&self, species: SpeciesID) -> Option<SpeciesStyle> {
+        self.get_species_cache(species).ok()?.style
+    }
+}
+
+/// Which voxels to include in an exported frame.
+///
+/// A structure is a species that serves as the location of another species,
+/// e.g. a membrane. Exporting structures and molecules into separate files
+/// allows rendering the structures translucent.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Selection {
+    All,
+    Molecules,
+    Structures,
+}
+
+impl Selection {
+    fn includes(self, space: &HCPLatticeSpace, species: SpeciesID) -> bool {
+        match self {
+            Selection::All => true,
+            Selection::Molecules => !space.is_structure(species),
+            Selection::Structures => space.is_structure(species),
+        }
+    }
+}
+
+fn collect(space: &HCPLatticeSpace, selection: Selection) -> Vec<(Coordinate, SpeciesID)> {
+    space
+        .occupied()
+        .filter(|&(_, species)| selection.includes(space, species))
+        .collect()
+}
+
+/// Returns the style of each point, `None` if none of their species has one.
+fn styles(
+    space: &HCPLatticeSpace,
+    points: &[(Coordinate, SpeciesID)],
+) -> Option<Vec<SpeciesStyle>> {
+    points
+        .iter()
+        .any(|&(_, species)| space.species_style(species).is_some())
+        .then(|| {
+            points
+                .iter()
+                .map(|&(_, species)| {
+                    space.species_style(species).unwrap_or(SpeciesStyle {
+                        color: [128, 128, 128],
+                        radius: space.species_cache[species.0].info.radius,
+                    })
+                })
+                .collect()
+        })
+}
+
+/// Returns the color of `style` with components from 0 to 1.
+fn unit_color(style: &SpeciesStyle) -> [f64; 3] {
+    style.color.map(|component| f64::from(component) / 255.0)
+}
+
+fn position(space: &HCPLatticeSpace, coordinate: Coordinate) -> [f64; 3] {
+    space
+        .coordinate_to_position(coordinate)
+        .expect("occupied voxels lie inside the lattice")
+}
+
+/// Writes the selected voxels as legacy VTK poly-data with a `species` scalar,
+/// and `radius` and `color` ones if styled.
+pub fn write_vtk<W: Write>(
+    space: &HCPLatticeSpace,
+    selection: Selection,
+    writer: &mut W,
+) -> io::Result<()> {
+    let points = collect(space, selection);
+    writeln!(writer, "# vtk DataFile Version 3.0")?;
+    writeln!(writer, "spatiocyte")?;
+    writeln!(writer, "ASCII")?;
+    writeln!(writer, "DATASET POLYDATA")?;
+    writeln!(writer, "POINTS {} double", points.len())?;
+    for &(coordinate, _) in &points {
+        let [x, y, z] = position(space, coordinate);
+        writeln!(writer, "{:.6} {:.6} {:.6}", x, y, z)?;
+    }
+    writeln!(writer, "VERTICES {} {}", points.len(), 2 * points.len())?;
+    for i in 0..points.len() {
+        writeln!(writer, "1 {}", i)?;
+    }
+    writeln!(writer, "POINT_DATA {}", points.len())?;
+    writeln!(writer, "SCALARS species int 1")?;
+    writeln!(writer, "LOOKUP_TABLE default")?;
+    for &(_, species) in &points {
+        writeln!(writer, "{}", species.0)?;
+    }
+    if let Some(styles) = styles(space, &points) {
+        writeln!(writer, "SCALARS radius double 1")?;
+        writeln!(writer, "LOOKUP_TABLE default")?;
+        for style in &styles {
+            writeln!(writer, "{:.6}", style.radius)?;
+        }
+        writeln!(writer, "COLOR_SCALARS color 3")?;
+        for style in &styles {
+            let [r, g, b] = unit_color(style);
+            writeln!(writer, "{:.4} {:.4} {:.4}", r, g, b)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the selected voxels as one extended-XYZ frame at time `t`.
+///
+/// Frames written one after another into the same writer form a trajectory.
+pub fn write_xyz<W: Write>(
+    space: &HCPLatticeSpace,
+    selection: Selection,
+    t: f64,
+    writer: &mut W,
+) -> io::Result<()> {
+    let points = collect(space, selection);
+    let styles = styles(space, &points);
+    writeln!(writer, "{}", points.len())?;
+    let styled = if styles.is_some() {
+        ":radius:R:1:color:R:3"
+    } else {
+        ""
+    };
+    writeln!(
+        writer,
+        "Properties=species:S:1:pos:R:3:species_id:I:1{} Time={}",
+        styled, t
+    )?;
+    for (i, &(coordinate, species)) in points.iter().enumerate() {
+        let [x, y, z] = position(space, coordinate);
+        write!(
+            writer,
+            "{} {:.6} {:.6} {:.6} {}",
+            space.species_cache[species.0].species.0, x, y, z, species.0
+        )?;
+        if let Some(styles) = &styles {
+            let [r, g, b] = unit_color(&styles[i]);
+            write!(
+                writer,
+                " {:.6} {:.4} {:.4} {:.4}",
+                styles[i].radius, r, g, b
+            )?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Writes every species and the selected voxels at time `t` as a JSON
+/// document in the layout of `HCPLatticeSpace::load_model_json`, positions
+/// in nanometers, with a `style` block for the styled species:
+///
+/// ```json
+/// {
+///   "t": 0.5,
+///   "species": [
+///     {"name": "A", "D": 1e-12, "radius": 5e-9,
+///      "style": {"color": [255, 0, 0], "radius": 5e-9}}
+///   ],
+///   "particles": [{"species": "A", "x": 10.0, "y": 5.0, "z": 0.0}]
+/// }
+/// ```
+///
+/// A structure under a molecule located on it is not written, its voxel
+/// holding the molecule.
+#[cfg(feature = "json")]
+pub fn write_json<W: Write>(
+    space: &HCPLatticeSpace,
+    selection: Selection,
+    t: f64,
+    writer: &mut W,
+) -> io::Result<()> {
+    use crate::import::NANOMETER;
+    use serde_json::{json, Value};
+
+    let species: Vec<Value> = space
+        .species_cache
+        .iter()
+        .map(|cache| {
+            let mut block = json!({
+                "name": cache.species.0,
+                "D": cache.info.diffusion_coefficient,
+                "radius": cache.info.radius,
+            });
+            if let Some(location) = cache.location {
+                block["location"] = json!(space.species_cache[location.0].species.0);
+            }
+            if let Some(style) = cache.style {
+                block["style"] = json!({"color": style.color, "radius": style.radius});
+            }
+            block
+        })
+        .collect();
+    let particles: Vec<Value> = collect(space, selection)
+        .into_iter()
+        .map(|(coordinate, species)| {
+            let [x, y, z] = position(space, coordinate);
+            json!({
+                "species": space.species_cache[species.0].species.0,
+                "x": x / NANOMETER,
+                "y": y / NANOMETER,
+                "z": z / NANOMETER,
+            })
+        })
+        .collect();
+    let document = json!({"t": t, "species": species, "particles": particles});
+    serde_json::to_writer(writer, &document)?;
+    Ok(())
+}
+
+/// Writes one VTK file per frame, named `<prefix>_<index>.vtk` with a
+/// zero-padded index so that ParaView recognizes them as a series.
+pub struct VtkSeries {
+    directory: PathBuf,
+    prefix: String,
+    width: usize,
+    selection: Selection,
+    next_index: usize,
+}
+
+impl VtkSeries {
+    pub fn new<P: AsRef<Path>>(directory: P, prefix: &str, selection: Selection) -> Self {
+        Self {
+            directory: directory.as_ref().to_path_buf(),
+            prefix: prefix.to_string(),
+            width: 5,
+            selection,
+            next_index: 0,
+        }
+    }
+
+    /// Sets the number of digits of the frame index.
+    pub fn with_width(mut self, width: usize) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// Writes the next frame and returns the path of the created file.
+    pub fn write_frame(&mut self, space: &HCPLatticeSpace) -> io::Result<PathBuf> {
+        let path = self.directory.join(format!(
+            "{}_{:0width$}.vtk",
+            self.prefix,
+            self.next_index,
+            width = self.width
+        ));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        write_vtk(space, self.selection, &mut writer)?;
+        writer.flush()?;
+        self.next_index += 1;
+        Ok(path)
+    }
+}
+
+/// Appends extended-XYZ frames to a single writer.
+pub struct XyzTrajectory<W: Write> {
+    writer: W,
+    selection: Selection,
+}
+
+impl<W: Write> XyzTrajectory<W> {
+    pub fn new(writer: W, selection: Selection) -> Self {
+        Self { writer, selection }
+    }
+
+    pub fn write_frame(&mut self, space: &HCPLatticeSpace, t: f64) -> io::Result<()> {
+        write_xyz(space, self.selection, t, &mut self.writer)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HCPLatticeSize, Species};
+
+    fn tiny_space() -> HCPLatticeSpace {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(2, 2, 2));
+        let membrane = space.register_species(Species("M".to_string()), None);
+        let a = space.register_species(Species("A".to_string()), Some(membrane));
+        let b = space.register_species(Species("B".to_string()), None);
+        space.place_particle(membrane, Coordinate(1)).unwrap();
+        space.place_particle(membrane, Coordinate(3)).unwrap();
+        space.place_particle(a, Coordinate(3)).unwrap();
+        space.place_particle(b, Coordinate(6)).unwrap();
+        space
+    }
+
+    const GOLDEN_VTK: &str = "\
+# vtk DataFile Version 3.0
+spatiocyte
+ASCII
+DATASET POLYDATA
+POINTS 3 double
+1.000000 1.732051 0.000000
+3.000000 1.732051 0.000000
+3.000000 0.577350 1.632993
+VERTICES 3 6
+1 0
+1 1
+1 2
+POINT_DATA 3
+SCALARS species int 1
+LOOKUP_TABLE default
+0
+1
+2
+";
+
+    const GOLDEN_XYZ: &str = "\
+2
+Properties=species:S:1:pos:R:3:species_id:I:1 Time=0.5
+A 3.000000 1.732051 0.000000 1
+B 3.000000 0.577350 1.632993 2
+1
+Properties=species:S:1:pos:R:3:species_id:I:1 Time=1
+M 1.000000 1.732051 0.000000 0
+";
+
+    #[test]
+    fn vtk_golden() {
+        let mut buffer = Vec::new();
+        write_vtk(&tiny_space(), Selection::All, &mut buffer).unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(), GOLDEN_VTK);
+    }
+
+    #[test]
+    fn xyz_golden() {
+        let space = tiny_space();
+        let mut molecules = XyzTrajectory::new(Vec::new(), Selection::Molecules);
+        molecules.write_frame(&space, 0.5).unwrap();
+        let mut buffer = molecules.into_inner();
+        write_xyz(&space, Selection::Structures, 1.0, &mut buffer).unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(), GOLDEN_XYZ);
+    }
+
+    #[test]
+    fn styles_in_vtk_and_xyz() {
+        let mut space = tiny_space();
+        let b = space.find_species("B").unwrap();
+        space
+            .set_species_style(
+                b,
+                Some(SpeciesStyle {
+                    color: [255, 0, 51],
+                    radius: 0.5,
+                }),
+            )
+            .unwrap();
+        let mut buffer = Vec::new();
+        write_vtk(&space, Selection::All, &mut buffer).unwrap();
+        let vtk = String::from_utf8(buffer).unwrap();
+        let styled = "\
+SCALARS radius double 1
+LOOKUP_TABLE default
+1.000000
+1.000000
+0.500000
+COLOR_SCALARS color 3
+0.5020 0.5020 0.5020
+0.5020 0.5020 0.5020
+1.0000 0.0000 0.2000
+";
+        assert_eq!(vtk, format!("{}{}", GOLDEN_VTK, styled));
+
+        let mut buffer = Vec::new();
+        write_xyz(&space, Selection::Molecules, 0.5, &mut buffer).unwrap();
+        let xyz = String::from_utf8(buffer).unwrap();
+        assert_eq!(
+            xyz,
+            "\
+2
+Properties=species:S:1:pos:R:3:species_id:I:1:radius:R:1:color:R:3 Time=0.5
+A 3.000000 1.732051 0.000000 1 1.000000 0.5020 0.5020 0.5020
+B 3.000000 0.577350 1.632993 2 0.500000 1.0000 0.0000 0.2000
+"
+        );
+
+        let mut buffer = Vec::new();
+        write_xyz(&space, Selection::Structures, 1.0, &mut buffer).unwrap();
+        assert!(String::from_utf8(buffer)
+            .unwrap()
+            .ends_with("\nM 1.000000 1.732051 0.000000 0\n"));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_round_trip() {
+        use crate::import::{CollisionPolicy, NANOMETER};
+
+        let mut space = HCPLatticeSpace::new(5.0 * NANOMETER, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        let style = SpeciesStyle {
+            color: [10, 200, 30],
+            radius: 2.0 * NANOMETER,
+        };
+        space.set_species_style(a, Some(style)).unwrap();
+        for i in 0..6 {
+            space.place_particle(a, Coordinate(3 * i)).unwrap();
+        }
+        space.place_particle(b, Coordinate(40)).unwrap();
+        let mut buffer = Vec::new();
+        write_json(&space, Selection::All, 0.5, &mut buffer).unwrap();
+
+        let mut copy = HCPLatticeSpace::new(5.0 * NANOMETER, HCPLatticeSize::new(4, 4, 4));
+        let report = copy
+            .load_model_json(buffer.as_slice(), CollisionPolicy::Error)
+            .unwrap();
+        assert_eq!(report.placed, 7);
+        assert!(report.failures.is_empty());
+        assert_eq!(
+            copy.occupied().collect::<Vec<_>>(),
+            space.occupied().collect::<Vec<_>>()
+        );
+        assert_eq!(copy.species_style(a), Some(style));
+        assert_eq!(copy.species_style(b), None);
+    }
+
+    #[test]
+    fn vtk_series_names() {
+        let directory = std::env::temp_dir().join("spatiocyte_vtk_series");
+        std::fs::create_dir_all(&directory).unwrap();
+        let space = tiny_space();
+        let mut series = VtkSeries::new(&directory, "frame", Selection::All).with_width(3);
+        let first = series.write_frame(&space).unwrap();
+        let second = series.write_frame(&space).unwrap();
+        assert_eq!(first.file_name().unwrap(), "frame_000.vtk");
+        assert_eq!(second.file_name().unwrap(), "frame_001.vtk");
+        assert_eq!(std::fs::read_to_string(&second).unwrap(), GOLDEN_VTK);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
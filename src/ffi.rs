@@ -0,0 +1,423 @@
+//! A C interface, for embedding the lattice in simulators written in other
+//! languages. `include/spatiocyte.h` declares it, generated by cbindgen
+//! from this module with `cbindgen.toml`.
+//!
+//! Spaces and simulators are opaque handles created by the `_new` functions
+//! and released by the matching `_free` ones; each function documents which
+//! pointers it borrows and which it takes. Species are identified by their
+//! `SpeciesID::index`. Functions return `SPATIOCYTE_OK`, a positive code
+//! for an `Error` of the engine, as given by `error_code`, or a negative
+//! one for a misuse of the interface itself. No panic crosses the
+//! interface: a panicking call returns `SPATIOCYTE_PANIC`, and the handles
+//! it was given should not be used again but to free them.
+
+use crate::{
+    Coordinate, Error, HCPLatticeSize, HCPLatticeSpace, MoleculeInfo, ReactionRule, Simulator,
+    Species, SpeciesID,
+};
+use rand_pcg::Pcg64;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+
+/// A success.
+pub const SPATIOCYTE_OK: c_int = 0;
+/// A panic inside the engine.
+pub const SPATIOCYTE_PANIC: c_int = -1;
+/// A null handle or pointer where one was required.
+pub const SPATIOCYTE_NULL_POINTER: c_int = -2;
+/// A name that is not valid UTF-8.
+pub const SPATIOCYTE_INVALID_NAME: c_int = -3;
+/// A buffer shorter than what is written into it.
+pub const SPATIOCYTE_BUFFER_TOO_SMALL: c_int = -4;
+
+/// `Error::OutOfRange`.
+pub const SPATIOCYTE_OUT_OF_RANGE: c_int = 1;
+/// `Error::ParticleNotFound`.
+pub const SPATIOCYTE_PARTICLE_NOT_FOUND: c_int = 2;
+/// `Error::InvalidLocation`.
+pub const SPATIOCYTE_INVALID_LOCATION: c_int = 3;
+/// `Error::InvalidWeights`.
+pub const SPATIOCYTE_INVALID_WEIGHTS: c_int = 4;
+/// `Error::NotAdjacent`.
+pub const SPATIOCYTE_NOT_ADJACENT: c_int = 5;
+/// `Error::PositionOutOfRange`.
+pub const SPATIOCYTE_POSITION_OUT_OF_RANGE: c_int = 6;
+/// `Error::SpeciesNotFound`.
+pub const SPATIOCYTE_SPECIES_NOT_FOUND: c_int = 7;
+/// `Error::InvalidReaction`.
+pub const SPATIOCYTE_INVALID_REACTION: c_int = 8;
+/// `Error::InsufficientSpace`.
+pub const SPATIOCYTE_INSUFFICIENT_SPACE: c_int = 9;
+/// `Error::UnknownParticle`.
+pub const SPATIOCYTE_UNKNOWN_PARTICLE: c_int = 10;
+/// `Error::InvalidPeriodicSize`.
+pub const SPATIOCYTE_INVALID_PERIODIC_SIZE: c_int = 11;
+/// `Error::InvalidDecomposition`.
+pub const SPATIOCYTE_INVALID_DECOMPOSITION: c_int = 12;
+/// `Error::SizeOverflow`.
+pub const SPATIOCYTE_SIZE_OVERFLOW: c_int = 13;
+/// `Error::InvalidTransition`.
+pub const SPATIOCYTE_INVALID_TRANSITION: c_int = 14;
+/// `Error::OutOfPlane`.
+pub const SPATIOCYTE_OUT_OF_PLANE: c_int = 15;
+/// `Error::InvalidFraction`.
+pub const SPATIOCYTE_INVALID_FRACTION: c_int = 16;
+/// `Error::NotBulk`.
+pub const SPATIOCYTE_NOT_BULK: c_int = 17;
+/// `Error::TrackingRequired`.
+pub const SPATIOCYTE_TRACKING_REQUIRED: c_int = 18;
+/// `Error::PeriodicBoundary`.
+pub const SPATIOCYTE_PERIODIC_BOUNDARY: c_int = 19;
+/// `Error::DiffusionLimited`.
+pub const SPATIOCYTE_DIFFUSION_LIMITED: c_int = 20;
+/// `Error::StructureInUse`.
+pub const SPATIOCYTE_STRUCTURE_IN_USE: c_int = 21;
+/// `Error::VoxelRadiusMismatch`.
+pub const SPATIOCYTE_VOXEL_RADIUS_MISMATCH: c_int = 22;
+/// `Error::StaggeredOffset`.
+pub const SPATIOCYTE_STAGGERED_OFFSET: c_int = 23;
+/// `Error::UnknownSpecies`.
+pub const SPATIOCYTE_UNKNOWN_SPECIES: c_int = 24;
+/// `Error::Io`.
+pub const SPATIOCYTE_IO: c_int = 25;
+/// `Error::Parse`.
+pub const SPATIOCYTE_PARSE: c_int = 26;
+/// `Error::InvalidLength`.
+pub const SPATIOCYTE_INVALID_LENGTH: c_int = 27;
+/// `Error::LotInUse`.
+pub const SPATIOCYTE_LOT_IN_USE: c_int = 28;
+/// `Error::InvalidDuration`.
+pub const SPATIOCYTE_INVALID_DURATION: c_int = 29;
+
+/// A space without a simulator.
+///
+/// cbindgen:ignore
+#[repr(transparent)]
+pub struct SpatiocyteSpace {
+    space: HCPLatticeSpace,
+}
+
+/// A simulator, owning its space.
+pub struct SpatiocyteSimulator {
+    simulator: Simulator<Pcg64>,
+}
+
+/// Returns the code of `err` at the C interface, from 1 in the order of
+/// the variants of `Error`: one of the positive `SPATIOCYTE_` constants.
+pub fn error_code(err: &Error) -> c_int {
+    match err {
+        Error::OutOfRange(_) => SPATIOCYTE_OUT_OF_RANGE,
+        Error::ParticleNotFound(_) => SPATIOCYTE_PARTICLE_NOT_FOUND,
+        Error::InvalidLocation(..) => SPATIOCYTE_INVALID_LOCATION,
+        Error::InvalidWeights => SPATIOCYTE_INVALID_WEIGHTS,
+        Error::NotAdjacent(..) => SPATIOCYTE_NOT_ADJACENT,
+        Error::PositionOutOfRange(_) => SPATIOCYTE_POSITION_OUT_OF_RANGE,
+        Error::SpeciesNotFound(_) => SPATIOCYTE_SPECIES_NOT_FOUND,
+        Error::InvalidReaction => SPATIOCYTE_INVALID_REACTION,
+        Error::InsufficientSpace(_) => SPATIOCYTE_INSUFFICIENT_SPACE,
+        Error::UnknownParticle(_) => SPATIOCYTE_UNKNOWN_PARTICLE,
+        Error::InvalidPeriodicSize => SPATIOCYTE_INVALID_PERIODIC_SIZE,
+        Error::InvalidDecomposition => SPATIOCYTE_INVALID_DECOMPOSITION,
+        Error::SizeOverflow => SPATIOCYTE_SIZE_OVERFLOW,
+        Error::InvalidTransition => SPATIOCYTE_INVALID_TRANSITION,
+        Error::OutOfPlane => SPATIOCYTE_OUT_OF_PLANE,
+        Error::InvalidFraction(_) => SPATIOCYTE_INVALID_FRACTION,
+        Error::NotBulk(_) => SPATIOCYTE_NOT_BULK,
+        Error::TrackingRequired(_) => SPATIOCYTE_TRACKING_REQUIRED,
+        Error::PeriodicBoundary => SPATIOCYTE_PERIODIC_BOUNDARY,
+        Error::DiffusionLimited(_) => SPATIOCYTE_DIFFUSION_LIMITED,
+        Error::StructureInUse(_) => SPATIOCYTE_STRUCTURE_IN_USE,
+        Error::VoxelRadiusMismatch(..) => SPATIOCYTE_VOXEL_RADIUS_MISMATCH,
+        Error::StaggeredOffset => SPATIOCYTE_STAGGERED_OFFSET,
+        Error::UnknownSpecies(_) => SPATIOCYTE_UNKNOWN_SPECIES,
+        Error::Io(_) => SPATIOCYTE_IO,
+        Error::Parse(_) => SPATIOCYTE_PARSE,
+        Error::InvalidLength(_) => SPATIOCYTE_INVALID_LENGTH,
+        Error::LotInUse(_) => SPATIOCYTE_LOT_IN_USE,
+        Error::InvalidDuration(_) => SPATIOCYTE_INVALID_DURATION,
+    }
+}
+
+/// Runs `f`, turning its error or panic into a status code.
+fn guard<F: FnOnce() -> Result<(), c_int>>(f: F) -> c_int {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => SPATIOCYTE_OK,
+        Ok(Err(code)) => code,
+        Err(_) => SPATIOCYTE_PANIC,
+    }
+}
+
+fn status(err: Error) -> c_int {
+    error_code(&err)
+}
+
+/// Borrows the object behind `pointer`, failing on null.
+unsafe fn borrow_mut<'a, T>(pointer: *mut T) -> Result<&'a mut T, c_int> {
+    pointer.as_mut().ok_or(SPATIOCYTE_NULL_POINTER)
+}
+
+unsafe fn borrow<'a, T>(pointer: *const T) -> Result<&'a T, c_int> {
+    pointer.as_ref().ok_or(SPATIOCYTE_NULL_POINTER)
+}
+
+unsafe fn name<'a>(pointer: *const c_char) -> Result<&'a str, c_int> {
+    if pointer.is_null() {
+        return Err(SPATIOCYTE_NULL_POINTER);
+    }
+    CStr::from_ptr(pointer)
+        .to_str()
+        .map_err(|_| SPATIOCYTE_INVALID_NAME)
+}
+
+unsafe fn names(pointers: *const *const c_char, len: usize) -> Result<Vec<Species>, c_int> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    if pointers.is_null() {
+        return Err(SPATIOCYTE_NULL_POINTER);
+    }
+    std::slice::from_raw_parts(pointers, len)
+        .iter()
+        .map(|&pointer| name(pointer).map(Species::new))
+        .collect()
+}
+
+/// Creates an empty space of `rows × cols × layers` voxels of radius
+/// `radius`, in meters, and returns it, or null if its number of voxels
+/// overflows or a panic occurred. The caller owns the space and frees it
+/// with `spatiocyte_space_free`, unless it passes it on to
+/// `spatiocyte_simulator_new`.
+#[no_mangle]
+pub extern "C" fn spatiocyte_space_new(
+    radius: f64,
+    rows: usize,
+    cols: usize,
+    layers: usize,
+) -> *mut SpatiocyteSpace {
+    panic::catch_unwind(|| {
+        HCPLatticeSpace::try_new(radius, HCPLatticeSize::new(rows, cols, layers))
+            .map_or(std::ptr::null_mut(), |space| {
+                Box::into_raw(Box::new(SpatiocyteSpace { space }))
+            })
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Frees a space from `spatiocyte_space_new`. Null is ignored.
+///
+/// # Safety
+///
+/// `space` must be null or a space from `spatiocyte_space_new` not freed
+/// nor passed to `spatiocyte_simulator_new` yet.
+#[no_mangle]
+pub unsafe extern "C" fn spatiocyte_space_free(space: *mut SpatiocyteSpace) {
+    if !space.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(space))));
+    }
+}
+
+/// Registers a tracked bulk species named `name`, diffusing with
+/// `diffusion_coefficient` in m²/s, and writes its index to `id`. `name`
+/// is borrowed for the call and copied.
+///
+/// # Safety
+///
+/// `space` must be a live space, `name` a NUL-terminated string and `id`
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn spatiocyte_add_species(
+    space: *mut SpatiocyteSpace,
+    name: *const c_char,
+    diffusion_coefficient: f64,
+    id: *mut usize,
+) -> c_int {
+    guard(|| {
+        let space = &mut borrow_mut(space)?.space;
+        let name = self::name(name)?;
+        let id = borrow_mut(id)?;
+        let species = space.register_species(Species::new(name), None);
+        space
+            .set_molecule_info(
+                species,
+                MoleculeInfo {
+                    radius: space.get_voxel_radius(),
+                    diffusion_coefficient,
+                },
+            )
+            .map_err(status)?;
+        *id = species.index();
+        Ok(())
+    })
+}
+
+/// Places a molecule of the species `species` on the voxel `coordinate`,
+/// as `HCPLatticeSpace::place_particle`.
+///
+/// # Safety
+///
+/// `space` must be a live space.
+#[no_mangle]
+pub unsafe extern "C" fn spatiocyte_add_particle(
+    space: *mut SpatiocyteSpace,
+    species: usize,
+    coordinate: usize,
+) -> c_int {
+    guard(|| {
+        let space = &mut borrow_mut(space)?.space;
+        space
+            .place_particle(SpeciesID(species), Coordinate(coordinate))
+            .map(|_| ())
+            .map_err(status)
+    })
+}
+
+/// Returns the number of species of the space, 0 for null.
+///
+/// # Safety
+///
+/// `space` must be null or a live space.
+#[no_mangle]
+pub unsafe extern "C" fn spatiocyte_num_species(space: *const SpatiocyteSpace) -> usize {
+    borrow(space).map_or(0, |space| space.space.num_species())
+}
+
+/// Writes the number of molecules of each species, by index, to the first
+/// `spatiocyte_num_species` entries of `buffer`, which holds `len`. The
+/// buffer stays the caller's.
+///
+/// # Safety
+///
+/// `space` must be a live space and `buffer` writable for `len` entries.
+#[no_mangle]
+pub unsafe extern "C" fn spatiocyte_counts_into(
+    space: *const SpatiocyteSpace,
+    buffer: *mut usize,
+    len: usize,
+) -> c_int {
+    guard(|| {
+        let space = &borrow(space)?.space;
+        if len < space.num_species() {
+            return Err(SPATIOCYTE_BUFFER_TOO_SMALL);
+        }
+        if buffer.is_null() && len > 0 {
+            return Err(SPATIOCYTE_NULL_POINTER);
+        }
+        for (id, _, count) in space.species() {
+            *buffer.add(id.index()) = count;
+        }
+        Ok(())
+    })
+}
+
+/// Creates a simulator of `space`, drawing from a generator seeded with
+/// `seed`, and returns it, or null if `space` is null or a panic occurred.
+/// The simulator takes `space`, which the caller must not use nor free
+/// afterwards, even on failure, and reaches through
+/// `spatiocyte_simulator_space`. The caller owns the simulator and frees it
+/// with `spatiocyte_simulator_free`.
+///
+/// # Safety
+///
+/// `space` must be null or a live space.
+#[no_mangle]
+pub unsafe extern "C" fn spatiocyte_simulator_new(
+    space: *mut SpatiocyteSpace,
+    seed: u64,
+) -> *mut SpatiocyteSimulator {
+    if space.is_null() {
+        return std::ptr::null_mut();
+    }
+    let space = Box::from_raw(space);
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let simulator = Simulator::with_seed(space.space, seed);
+        Box::into_raw(Box::new(SpatiocyteSimulator { simulator }))
+    }))
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Frees a simulator and its space. Null is ignored.
+///
+/// # Safety
+///
+/// `simulator` must be null or a simulator from `spatiocyte_simulator_new`
+/// not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn spatiocyte_simulator_free(simulator: *mut SpatiocyteSimulator) {
+    if !simulator.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(simulator))));
+    }
+}
+
+/// Returns the space of `simulator`, null for null. The space stays the
+/// simulator's: it lives until the simulator is freed, and must not be
+/// freed itself.
+///
+/// # Safety
+///
+/// `simulator` must be null or a live simulator.
+#[no_mangle]
+pub unsafe extern "C" fn spatiocyte_simulator_space(
+    simulator: *const SpatiocyteSimulator,
+) -> *const SpatiocyteSpace {
+    match simulator.as_ref() {
+        // `SpatiocyteSpace` only wraps the space, so that the pointer to
+        // one stands for the other.
+        Some(simulator) => simulator.simulator.space() as *const HCPLatticeSpace as *const _,
+        None => std::ptr::null(),
+    }
+}
+
+/// Adds the reaction of the `num_reactants` species named in `reactants`
+/// into the `num_products` named in `products` with rate `k`, as
+/// `Simulator::add_reaction`. The names are borrowed for the call.
+///
+/// # Safety
+///
+/// `simulator` must be a live simulator and `reactants` and `products`
+/// arrays of as many NUL-terminated strings as given.
+#[no_mangle]
+pub unsafe extern "C" fn spatiocyte_add_reaction(
+    simulator: *mut SpatiocyteSimulator,
+    reactants: *const *const c_char,
+    num_reactants: usize,
+    products: *const *const c_char,
+    num_products: usize,
+    k: f64,
+) -> c_int {
+    guard(|| {
+        let simulator = &mut borrow_mut(simulator)?.simulator;
+        let rule = ReactionRule::new(
+            names(reactants, num_reactants)?,
+            names(products, num_products)?,
+            k,
+        );
+        simulator.add_reaction(rule).map(|_| ()).map_err(status)
+    })
+}
+
+/// Runs `simulator` for `duration` seconds, as `Simulator::run`.
+///
+/// # Safety
+///
+/// `simulator` must be a live simulator.
+#[no_mangle]
+pub unsafe extern "C" fn spatiocyte_run(
+    simulator: *mut SpatiocyteSimulator,
+    duration: f64,
+) -> c_int {
+    guard(|| {
+        let simulator = &mut borrow_mut(simulator)?.simulator;
+        simulator.run(duration).map_err(status)
+    })
+}
+
+/// Returns the time of `simulator`, NaN for null.
+///
+/// # Safety
+///
+/// `simulator` must be null or a live simulator.
+#[no_mangle]
+pub unsafe extern "C" fn spatiocyte_time(simulator: *const SpatiocyteSimulator) -> f64 {
+    borrow(simulator).map_or(f64::NAN, |simulator| simulator.simulator.t())
+}
@@ -0,0 +1,98 @@
+//! Dense occupancy grids, for machine learning on lattice states.
+//!
+//! `to_grid` gives the index of the `SpeciesID` on every voxel, `-1` for
+//! vacant voxels, as a row-major array of shape `[layer, col, row]`: the
+//! row index varies fastest, so that the flat index of a voxel in the grid
+//! is its `Coordinate`. The grid maps onto `ndarray` or NumPy without
+//! copying, and with the `npy` feature `write_npy` writes it as a `.npy`
+//! file that `np.load` reads directly.
+
+use crate::HCPLatticeSpace;
+#[cfg(feature = "npy")]
+use std::io::{self, Write};
+
+impl HCPLatticeSpace {
+    /// Returns the species index of every voxel, `-1` if vacant, with the
+    /// shape of the grid.
+    pub fn to_grid(&self) -> (Vec<i32>, [usize; 3]) {
+        let mut grid = vec![-1; self.num_voxels()];
+        for (coordinate, species) in self.occupied() {
+            grid[coordinate.0] = species.0 as i32;
+        }
+        (grid, [self.size.layer, self.size.col, self.size.row])
+    }
+
+    /// Writes `to_grid` in the `.npy` format, version 1.0, as little-endian
+    /// 32-bit integers.
+    #[cfg(feature = "npy")]
+    pub fn write_npy<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let (grid, [layers, cols, rows]) = self.to_grid();
+        let mut header = format!(
+            "{{'descr': '<i4', 'fortran_order': False, 'shape': ({}, {}, {}), }}",
+            layers, cols, rows
+        );
+        // The magic string, the version and the header length take 10
+        // bytes, and the header is padded with spaces up to a newline
+        // aligning the data on 64 bytes.
+        let len = 10 + header.len() + 1;
+        header.push_str(&" ".repeat((64 - len % 64) % 64));
+        header.push('\n');
+        writer.write_all(b"\x93NUMPY\x01\x00")?;
+        writer.write_all(&(header.len() as u16).to_le_bytes())?;
+        writer.write_all(header.as_bytes())?;
+        for value in grid {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Coordinate, HCPLatticeSize, Species};
+
+    fn populated() -> HCPLatticeSpace {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(3, 4, 2));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        space.place_particle(a, Coordinate(0)).unwrap();
+        let c = space.global_to_coordinate(2, 1, 1).unwrap();
+        space.place_particle(b, c).unwrap();
+        space
+    }
+
+    #[test]
+    fn grid_of_species_indices() {
+        let space = populated();
+        let (grid, shape) = space.to_grid();
+        assert_eq!(shape, [2, 4, 3]);
+        assert_eq!(grid.len(), 24);
+        assert_eq!(grid[0], 0);
+        // Row-major over (layer, col, row).
+        let (layer, col, row) = (1, 1, 2);
+        assert_eq!(grid[(layer * 4 + col) * 3 + row], 1);
+        assert_eq!(grid.iter().filter(|&&v| v == -1).count(), 22);
+    }
+
+    #[cfg(feature = "npy")]
+    #[test]
+    fn npy_layout() {
+        let space = populated();
+        let mut npy = Vec::new();
+        space.write_npy(&mut npy).unwrap();
+        assert_eq!(&npy[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&npy[10..10 + header_len]).unwrap();
+        assert!(
+            header.starts_with("{'descr': '<i4', 'fortran_order': False, 'shape': (2, 4, 3), }")
+        );
+        assert!(header.ends_with(" \n"));
+        let data: Vec<i32> = npy[10 + header_len..]
+            .chunks(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(data, space.to_grid().0);
+    }
+}
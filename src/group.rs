@@ -0,0 +1,125 @@
+//! Families of species counted together, e.g. all the phosphorylated forms
+//! of a protein.
+//!
+//! A species joins a group by being tagged with its name, when registered
+//! with `register_tagged_species` or later with `tag_species`, and may be in
+//! any number of groups. A group exists from the first species tagged with
+//! its name. Groups only name sets of species: nothing in stepping looks at
+//! them.
+
+use crate::{HCPLatticeSpace, Species, SpeciesID};
+
+/// The species tagged with a name.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SpeciesGroup {
+    name: String,
+    members: Vec<SpeciesID>,
+}
+
+impl SpeciesGroup {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the members in the order they were tagged.
+    pub fn members(&self) -> &[SpeciesID] {
+        &self.members
+    }
+
+    pub fn contains(&self, species: SpeciesID) -> bool {
+        self.members.contains(&species)
+    }
+}
+
+impl HCPLatticeSpace {
+    /// Registers a species like `register_species` and tags it with every
+    /// group of `groups`.
+    pub fn register_tagged_species(
+        &mut self,
+        species: Species,
+        location: Option<SpeciesID>,
+        groups: &[&str],
+    ) -> SpeciesID {
+        let id = self.register_species(species, location);
+        for group in groups {
+            self.tag_species(id, group);
+        }
+        id
+    }
+
+    /// Adds `species` to `group`, creating the group if needed. Tagging a
+    /// species twice with a group changes nothing.
+    pub fn tag_species(&mut self, species: SpeciesID, group: &str) {
+        assert!(species.0 < self.species_cache.len(), "unknown species");
+        let index = match self.groups.iter().position(|g| g.name == group) {
+            Some(index) => index,
+            None => {
+                self.groups.push(SpeciesGroup {
+                    name: group.to_string(),
+                    members: Vec::new(),
+                });
+                self.groups.len() - 1
+            }
+        };
+        let members = &mut self.groups[index].members;
+        if !members.contains(&species) {
+            members.push(species);
+        }
+    }
+
+    /// Returns the group named `name`, `None` if no species has been tagged
+    /// with it.
+    pub fn group(&self, name: &str) -> Option<&SpeciesGroup> {
+        self.groups.iter().find(|group| group.name == name)
+    }
+
+    /// Returns every group in the order they were created.
+    pub fn groups(&self) -> &[SpeciesGroup] {
+        &self.groups
+    }
+
+    /// Returns the total number of molecules of the species in `group`,
+    /// zero for an unknown group.
+    pub fn group_count(&self, group: &str) -> usize {
+        self.group(group).map_or(0, |group| {
+            group
+                .members
+                .iter()
+                .map(|&species| self.species_cache[species.0].num_molecules())
+                .sum()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Coordinate, HCPLatticeSize};
+
+    #[test]
+    fn counts_of_families() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species::new("A"), None);
+        let ap = space.register_tagged_species(Species::new("Ap"), None, &["phospho"]);
+        let app = space.register_tagged_species(Species::new("App"), None, &["phospho", "active"]);
+        space.tag_species(a, "kinase");
+        space.tag_species(ap, "kinase");
+        space.tag_species(ap, "kinase");
+        for (i, &species) in [a, a, ap, app, app, app].iter().enumerate() {
+            space.place_particle(species, Coordinate(i)).unwrap();
+        }
+
+        assert_eq!(space.group_count("phospho"), 4);
+        assert_eq!(space.group_count("kinase"), 3);
+        assert_eq!(space.group_count("active"), 3);
+        assert_eq!(space.group_count("none"), 0);
+        let names: Vec<&str> = space.groups().iter().map(|g| g.name()).collect();
+        assert_eq!(names, vec!["phospho", "active", "kinase"]);
+        let kinase = space.group("kinase").unwrap();
+        assert_eq!(kinase.members(), &[a, ap]);
+        assert!(!kinase.contains(app));
+
+        space.remove_at(Coordinate(3)).unwrap();
+        assert_eq!(space.group_count("phospho"), 3);
+    }
+}
@@ -0,0 +1,317 @@
+//! Pluggable acceptance of diffusive hops.
+//!
+//! `walk` draws a direction for every molecule and lets sinks, transitions
+//! and collisions act on the hop first. A hop none of them took is then put
+//! to the space's `HopPolicy`, which accepts or rejects it, and an accepted
+//! hop is made: into a voxel of the species' location by `move_particle`,
+//! or, if another single-voxel molecule of the same location is in the way,
+//! by exchanging the two. Hops into anything else, such as a structure or a
+//! multi-voxel molecule, stay rejected whatever the policy says.
+//!
+//! `ExcludedVolume`, the default, rejects hops into voxels the species
+//! cannot occupy and draws the Metropolis acceptance of the `interaction`
+//! and `potential` modules for the others, which is what `walk` did before
+//! policies. `Unconditional` accepts every hop, so that molecules exchange
+//! places with those in their way and ignore energies.
+
+use crate::voxels::VoxelStore;
+use crate::{Coordinate, HCPLatticeSpace, ParticleID, Result, TrackingType};
+use rand::RngCore;
+use std::sync::Arc;
+
+/// Decides whether a molecule hops.
+pub trait HopPolicy: Send + Sync {
+    /// Returns whether the molecule `pid`, on the voxel `from`, hops to the
+    /// neighbor `target`. `pid` is `ParticleID(u64::MAX, from)` for a
+    /// counted species, whose molecules have no ID; the species is the one
+    /// at `from`.
+    fn accept(
+        &self,
+        space: &HCPLatticeSpace,
+        pid: ParticleID,
+        from: Coordinate,
+        target: Coordinate,
+        rng: &mut dyn RngCore,
+    ) -> bool;
+}
+
+/// Rejects hops into voxels the species cannot occupy, and biases the
+/// others by energy; see the module documentation.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ExcludedVolume;
+
+impl HopPolicy for ExcludedVolume {
+    fn accept(
+        &self,
+        space: &HCPLatticeSpace,
+        _pid: ParticleID,
+        from: Coordinate,
+        target: Coordinate,
+        rng: &mut dyn RngCore,
+    ) -> bool {
+        let species = match space.voxel(from) {
+            Some(species) => species,
+            None => return false,
+        };
+        // Multi-voxel molecules check their whole footprint when moved.
+        (space.species_cache[species.0].voxel_count > 1 || space.can_occupy(species, target))
+            && space.accept_hop(species, from, target, rng)
+    }
+}
+
+/// Accepts every hop.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Unconditional;
+
+impl HopPolicy for Unconditional {
+    fn accept(
+        &self,
+        _space: &HCPLatticeSpace,
+        _pid: ParticleID,
+        _from: Coordinate,
+        _target: Coordinate,
+        _rng: &mut dyn RngCore,
+    ) -> bool {
+        true
+    }
+}
+
+impl HCPLatticeSpace {
+    /// Sets the policy `walk` and `walk_biased` accept hops with, and so
+    /// the `Simulator`; `None` restores `ExcludedVolume`.
+    pub fn set_hop_policy(&mut self, policy: Option<Arc<dyn HopPolicy>>) {
+        self.hop_policy = policy;
+    }
+
+    /// Returns the policy of `set_hop_policy`, `None` for the default.
+    pub fn hop_policy(&self) -> Option<&Arc<dyn HopPolicy>> {
+        self.hop_policy.as_ref()
+    }
+
+    /// Puts the hop of the molecule `pid` from `from` to `to` to the
+    /// policy, and makes it if accepted. Returns whether it was made.
+    pub(crate) fn policy_hop(
+        &mut self,
+        pid: ParticleID,
+        from: Coordinate,
+        to: Coordinate,
+        rng: &mut dyn RngCore,
+    ) -> Result<bool> {
+        let accepted = match &self.hop_policy {
+            Some(policy) => policy.accept(self, pid, from, to, rng),
+            None => ExcludedVolume.accept(self, pid, from, to, rng),
+        };
+        if !accepted {
+            return Ok(false);
+        }
+        match self.move_particle(from, to) {
+            Ok(()) => Ok(true),
+            Err(crate::Error::InvalidLocation(..)) => self.exchange(from, to),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Swaps the molecules at the adjacent `from` and `to` if both are
+    /// single-voxel molecules of the same location that are not obstacles,
+    /// and returns whether it did.
+    fn exchange(&mut self, from: Coordinate, to: Coordinate) -> Result<bool> {
+        let (a, b) = match (self.voxel(from), self.voxel(to)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return Ok(false),
+        };
+        let (cache_a, cache_b) = (&self.species_cache[a.0], &self.species_cache[b.0]);
+        if cache_a.voxel_count > 1
+            || cache_b.voxel_count > 1
+            || cache_a.location != cache_b.location
+            || self.is_obstacle(a)
+            || self.is_obstacle(b)
+        {
+            return Ok(false);
+        }
+
+        if a == b {
+            if let TrackingType::Tracking(entries) = &mut self.get_species_cache_mut(a)?.cache {
+                for i in 0..entries.len() {
+                    if entries[i].1 == from {
+                        entries.set_coordinate(i, to);
+                    } else if entries[i].1 == to {
+                        entries.set_coordinate(i, from);
+                    }
+                }
+            }
+        } else {
+            self.get_species_cache_mut(a)?.move_to(from, to);
+            self.get_species_cache_mut(b)?.move_to(to, from);
+        }
+        if let Some(journal) = &mut self.journal {
+            journal.record_voxel(from.0, self.voxels.get(from.0));
+            journal.record_voxel(to.0, self.voxels.get(to.0));
+        }
+        self.voxels.swap(from.0, to.0);
+        if self.images.is_some() && self.periodic {
+            self.track_image(a, from, to)?;
+            self.track_image(b, to, from)?;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HCPLatticeSize, Species};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// Rejects every hop, counting the calls.
+    struct Frozen(std::sync::atomic::AtomicUsize);
+
+    impl HopPolicy for Frozen {
+        fn accept(
+            &self,
+            space: &HCPLatticeSpace,
+            pid: ParticleID,
+            from: Coordinate,
+            _target: Coordinate,
+            _rng: &mut dyn RngCore,
+        ) -> bool {
+            assert_eq!(space.find_particle(pid).unwrap().1, from);
+            self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            false
+        }
+    }
+
+    /// Accepts every hop as `Unconditional`, recording the molecules.
+    #[derive(Default)]
+    struct Checked(std::sync::Mutex<Vec<ParticleID>>);
+
+    impl HopPolicy for Checked {
+        fn accept(
+            &self,
+            space: &HCPLatticeSpace,
+            pid: ParticleID,
+            from: Coordinate,
+            _target: Coordinate,
+            _rng: &mut dyn RngCore,
+        ) -> bool {
+            assert_eq!(space.find_particle(pid).unwrap().1, from);
+            self.0.lock().unwrap().push(pid);
+            true
+        }
+    }
+
+    #[test]
+    fn default_policy_is_excluded_volume() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let a = space.register_species(Species::new("A"), None);
+        space.set_periodic(true).unwrap();
+        space.set_interaction(a, a, -1.0);
+        for i in (0..216).step_by(2) {
+            space.place_particle(a, Coordinate(i)).unwrap();
+        }
+        let mut explicit = space.clone();
+        explicit.set_hop_policy(Some(Arc::new(ExcludedVolume)));
+        let (mut rng, mut rng2) = (StdRng::seed_from_u64(3), StdRng::seed_from_u64(3));
+        for _ in 0..20 {
+            space.walk(a, &mut rng).unwrap();
+            explicit.walk(a, &mut rng2).unwrap();
+        }
+        assert!(space.diff(&explicit).is_empty());
+        assert_eq!(
+            space.particles().collect::<Vec<_>>(),
+            explicit.particles().collect::<Vec<_>>()
+        );
+
+        let counter = Arc::new(Frozen(Default::default()));
+        let before = space.clone();
+        space.set_hop_policy(Some(counter.clone()));
+        space.walk(a, &mut rng).unwrap();
+        assert!(space.diff(&before).is_empty());
+        assert_eq!(counter.0.load(std::sync::atomic::Ordering::Relaxed), 108);
+    }
+
+    #[test]
+    fn unconditional_hops_exchange_molecules() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_counted_species(Species::new("B"), None);
+        let wall = space.register_obstacle(Species::new("W"));
+        for i in 0..64 {
+            let species = [a, b, wall][i % 3];
+            space.place_particle(species, Coordinate(i)).unwrap();
+        }
+        let pids: Vec<ParticleID> = space
+            .particles()
+            .filter(|&(_, species, _)| species == a)
+            .map(|(pid, _, _)| pid)
+            .collect();
+        let before = space.clone();
+        let mut rng = StdRng::seed_from_u64(1);
+        space.walk(a, &mut rng).unwrap();
+        assert!(space.diff(&before).is_empty());
+
+        space.set_hop_policy(Some(Arc::new(Unconditional)));
+        for _ in 0..20 {
+            space.walk(a, &mut rng).unwrap();
+            space.walk(b, &mut rng).unwrap();
+        }
+        space.validate().unwrap();
+        assert_eq!(space.num_molecules(a).unwrap(), 22);
+        assert_eq!(space.num_molecules(b).unwrap(), 21);
+        for i in (2..64).step_by(3) {
+            assert_eq!(space.species_at(Coordinate(i)).unwrap(), Some(wall));
+        }
+        assert_ne!(space.diff(&before).num_voxels, 0);
+        for pid in pids {
+            let (_, c) = space.find_particle(pid).unwrap();
+            assert_eq!(space.species_at(c).unwrap(), Some(a));
+        }
+    }
+
+    #[test]
+    fn exchanged_molecules_hop_from_their_voxel() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species::new("A"), None);
+        space.set_periodic(true).unwrap();
+        for i in 0..60 {
+            space.place_particle(a, Coordinate(i)).unwrap();
+        }
+        let mut pids = space.particles_of(a).unwrap();
+        let policy = Arc::new(Checked::default());
+        space.set_hop_policy(Some(policy.clone()));
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..10 {
+            let before = space.clone();
+            space.walk(a, &mut rng).unwrap();
+            assert_ne!(space.diff(&before).num_voxels, 0);
+            let mut hopped = std::mem::take(&mut *policy.0.lock().unwrap());
+            hopped.sort();
+            pids.sort();
+            assert_eq!(hopped, pids);
+        }
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn multi_voxel_molecules_hop_once_per_sweep() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(8, 8, 8));
+        space.set_periodic(true).unwrap();
+        let big = space.register_multi_voxel_species(Species::new("R"), None, 4);
+        for (row, layer) in [(2, 2), (6, 6)] {
+            let seed = space.global_to_coordinate(row, 4, layer).unwrap();
+            space.place_particle(big.id(), seed).unwrap();
+        }
+        let mut pids = space.particles_of(big.id()).unwrap();
+        pids.sort();
+        let policy = Arc::new(Checked::default());
+        space.set_hop_policy(Some(policy.clone()));
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..100 {
+            space.walk(big.id(), &mut rng).unwrap();
+            let mut hopped = std::mem::take(&mut *policy.0.lock().unwrap());
+            hopped.sort();
+            assert_eq!(hopped, pids);
+        }
+        space.validate().unwrap();
+    }
+}
@@ -0,0 +1,347 @@
+//! Loading initial conditions from particle lists.
+//!
+//! Positions in the input files are given in nanometers, while the space
+//! works in meters, the unit assumed for the voxel radius.
+
+use crate::{Coordinate, Error, HCPLatticeSpace, Result, Species, SpeciesID};
+use std::io::{BufRead, BufReader, Read};
+
+pub const NANOMETER: f64 = 1e-9;
+
+/// What to do when a particle maps to a voxel that is already taken.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CollisionPolicy {
+    /// Record the particle as a failure.
+    Error,
+    /// Drop the particle and count it as skipped.
+    Skip,
+    /// Place the particle on the vacant neighbor nearest to its position,
+    /// recording a failure if there is none.
+    Nudge,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ImportFailure {
+    /// Line number for CSV input, 1-based index into `particles` for JSON.
+    pub record: usize,
+    pub reason: String,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ImportReport {
+    pub placed: usize,
+    pub skipped: usize,
+    pub nudged: usize,
+    pub failures: Vec<ImportFailure>,
+}
+
+impl ImportReport {
+    fn fail(&mut self, record: usize, reason: &str) {
+        self.failures.push(ImportFailure {
+            record,
+            reason: reason.to_string(),
+        });
+    }
+}
+
+fn parse_row<'a>(fields: &[&'a str]) -> std::result::Result<(&'a str, [f64; 3]), String> {
+    if fields.len() != 4 {
+        return Err(format!("expected 4 fields, found {}", fields.len()));
+    }
+    if fields[0].is_empty() {
+        return Err("empty species name".to_string());
+    }
+    let mut position = [0.0; 3];
+    for (value, field) in position.iter_mut().zip(&fields[1..]) {
+        *value = field
+            .parse()
+            .map_err(|_| format!("invalid number {:?}", field))?;
+    }
+    Ok((fields[0], position))
+}
+
+impl HCPLatticeSpace {
+    /// Places particles listed as `species, x, y, z` rows.
+    ///
+    /// Blank lines, lines starting with `#` and a leading header row are
+    /// ignored. Unseen species are registered in bulk. Rows that cannot be
+    /// placed are reported in `ImportReport::failures` without aborting the
+    /// import; only I/O errors are returned as `Err`.
+    pub fn load_particles_csv(
+        &mut self,
+        reader: impl Read,
+        policy: CollisionPolicy,
+    ) -> Result<ImportReport> {
+        let mut report = ImportReport::default();
+        let mut first = true;
+        for (i, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line.map_err(Error::Io)?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            if first && fields[0].eq_ignore_ascii_case("species") {
+                first = false;
+                continue;
+            }
+            first = false;
+
+            match parse_row(&fields) {
+                Ok((name, position)) => {
+                    self.import_particle(name, position, policy, i + 1, &mut report)
+                }
+                Err(reason) => report.fail(i + 1, &reason),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Loads species attributes and particles from a JSON document:
+    ///
+    /// ```json
+    /// {
+    ///   "species": [
+    ///     {"name": "M"},
+    ///     {"name": "A", "D": 1e-12, "radius": 5e-9, "location": "M"}
+    ///   ],
+    ///   "particles": [{"species": "A", "x": 10.0, "y": 5.0, "z": 0.0}]
+    /// }
+    /// ```
+    ///
+    /// Species are registered in the listed order and a location must be
+    /// listed before the species located on it. A species block may carry a
+    /// `"style": {"color": [r, g, b], "radius": r}` for the exporters. Malformed species blocks
+    /// abort the import with `Error::Parse`; malformed particles are reported
+    /// as failures like in `load_particles_csv`.
+    #[cfg(feature = "json")]
+    pub fn load_model_json(
+        &mut self,
+        reader: impl Read,
+        policy: CollisionPolicy,
+    ) -> Result<ImportReport> {
+        use serde_json::Value;
+
+        let model: Value =
+            serde_json::from_reader(reader).map_err(|err| Error::Parse(err.to_string()))?;
+
+        let species = model.get("species").and_then(Value::as_array);
+        for (i, block) in species.into_iter().flatten().enumerate() {
+            let name = block
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| Error::Parse(format!("species[{}].name is missing", i)))?;
+            let id = match self.find_species(name) {
+                Some(id) => id,
+                None => {
+                    let location = match block.get("location").and_then(Value::as_str) {
+                        Some(location) => Some(self.find_species(location).ok_or_else(|| {
+                            Error::Parse(format!(
+                                "species[{}].location {:?} is not declared before it",
+                                i, location
+                            ))
+                        })?),
+                        None => None,
+                    };
+                    self.register_species(Species::new(name), location)
+                }
+            };
+            let mut info = self.molecule_info(id)?;
+            if let Some(d) = block.get("D").and_then(Value::as_f64) {
+                info.diffusion_coefficient = d;
+            }
+            if let Some(radius) = block.get("radius").and_then(Value::as_f64) {
+                info.radius = radius;
+            }
+            self.set_molecule_info(id, info)?;
+            if let Some(style) = block.get("style") {
+                let invalid = || Error::Parse(format!("species[{}].style is invalid", i));
+                let color = style
+                    .get("color")
+                    .and_then(Value::as_array)
+                    .filter(|color| color.len() == 3)
+                    .ok_or_else(invalid)?;
+                let mut rgb = [0; 3];
+                for (component, value) in rgb.iter_mut().zip(color) {
+                    *component = value
+                        .as_u64()
+                        .filter(|&value| value <= 255)
+                        .map(|value| value as u8)
+                        .ok_or_else(invalid)?;
+                }
+                let radius = style
+                    .get("radius")
+                    .and_then(Value::as_f64)
+                    .ok_or_else(invalid)?;
+                self.set_species_style(id, Some(crate::SpeciesStyle { color: rgb, radius }))?;
+            }
+        }
+
+        let mut report = ImportReport::default();
+        let particles = model.get("particles").and_then(Value::as_array);
+        for (i, particle) in particles.into_iter().flatten().enumerate() {
+            let name = particle.get("species").and_then(Value::as_str);
+            let axis = |key| particle.get(key).and_then(Value::as_f64);
+            match (name, axis("x"), axis("y"), axis("z")) {
+                (Some(name), Some(x), Some(y), Some(z)) => {
+                    self.import_particle(name, [x, y, z], policy, i + 1, &mut report)
+                }
+                _ => report.fail(i + 1, "expected species, x, y and z"),
+            }
+        }
+        Ok(report)
+    }
+
+    fn import_particle(
+        &mut self,
+        name: &str,
+        position: [f64; 3],
+        policy: CollisionPolicy,
+        record: usize,
+        report: &mut ImportReport,
+    ) {
+        let species = match self.find_species(name) {
+            Some(species) => species,
+            None => self.register_species(Species::new(name), None),
+        };
+        let position = [
+            position[0] * NANOMETER,
+            position[1] * NANOMETER,
+            position[2] * NANOMETER,
+        ];
+        let coordinate = match self.position_to_coordinate(position) {
+            Ok(coordinate) => coordinate,
+            Err(_) => return report.fail(record, "position outside the lattice"),
+        };
+
+        match self.place_particle(species, coordinate) {
+            Ok(_) => report.placed += 1,
+            Err(Error::InvalidLocation(..)) => match policy {
+                CollisionPolicy::Error => report.fail(record, "voxel already occupied"),
+                CollisionPolicy::Skip => report.skipped += 1,
+                CollisionPolicy::Nudge => {
+                    match self.nearest_vacant_neighbor(species, coordinate, position) {
+                        Some(neighbor) => {
+                            self.place_particle(species, neighbor)
+                                .expect("the neighbor was checked to be vacant");
+                            report.nudged += 1;
+                        }
+                        None => report.fail(record, "no vacant neighbor to nudge to"),
+                    }
+                }
+            },
+            Err(err) => report.fail(record, &format!("{:?}", err)),
+        }
+    }
+
+    /// Returns the neighbor of `coordinate` nearest to `position` whose voxel
+    /// is the location of `species`.
+    fn nearest_vacant_neighbor(
+        &self,
+        species: SpeciesID,
+        coordinate: Coordinate,
+        position: [f64; 3],
+    ) -> Option<Coordinate> {
+        let location = self.species_cache[species.0].location;
+        let distance = |c: Coordinate| -> f64 {
+            let center = self.coordinate_to_position(c).unwrap();
+            center
+                .iter()
+                .zip(&position)
+                .map(|(a, b)| (a - b).powi(2))
+                .sum()
+        };
+        self.neighbors(coordinate)
+            .ok()?
+            .into_iter()
+            .filter(|&c| self.voxel(c) == location)
+            .min_by(|a, b| distance(*a).partial_cmp(&distance(*b)).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HCPLatticeSize;
+
+    fn new_space() -> HCPLatticeSpace {
+        HCPLatticeSpace::new(5.0 * NANOMETER, HCPLatticeSize::new(4, 4, 4))
+    }
+
+    #[test]
+    fn load_csv() {
+        let csv = "\
+species, x, y, z
+# comment
+A, 0.0, 0.0, 0.0
+A, 10.0, 0.0, 0.0
+B, 0.4, 0.3, 0.1
+A, 1.0, 2.0
+A, 1.0, foo, 2.0
+B, 1000.0, 0.0, 0.0
+B, -9.0, 0.0, 0.0
+";
+        let mut space = new_space();
+        let report = space
+            .load_particles_csv(csv.as_bytes(), CollisionPolicy::Nudge)
+            .unwrap();
+        assert_eq!(report.placed, 2);
+        assert_eq!(report.nudged, 1);
+        assert_eq!(report.skipped, 0);
+        let lines: Vec<usize> = report.failures.iter().map(|f| f.record).collect();
+        assert_eq!(lines, vec![6, 7, 8, 9]);
+
+        let a = space.find_species("A").unwrap();
+        let b = space.find_species("B").unwrap();
+        assert_eq!(space.get_species_id_at(Coordinate(0)).unwrap(), Some(a));
+        assert_eq!(space.coordinates_of(b).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn collision_policies() {
+        let csv = "A, 0, 0, 0\nB, 0, 0, 0\n";
+        let mut space = new_space();
+        let report = space
+            .load_particles_csv(csv.as_bytes(), CollisionPolicy::Skip)
+            .unwrap();
+        assert_eq!((report.placed, report.skipped), (1, 1));
+
+        let mut space = new_space();
+        let report = space
+            .load_particles_csv(csv.as_bytes(), CollisionPolicy::Error)
+            .unwrap();
+        assert_eq!(report.placed, 1);
+        assert_eq!(report.failures[0].record, 2);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn load_json() {
+        let json = r#"{
+            "species": [
+                {"name": "M"},
+                {"name": "A", "D": 1e-12, "radius": 2.5e-9, "location": "M"}
+            ],
+            "particles": [
+                {"species": "M", "x": 0.0, "y": 0.0, "z": 0.0},
+                {"species": "A", "x": 0.0, "y": 0.0, "z": 0.0},
+                {"species": "A", "x": 0.0}
+            ]
+        }"#;
+        let mut space = new_space();
+        let report = space
+            .load_model_json(json.as_bytes(), CollisionPolicy::Error)
+            .unwrap();
+        assert_eq!(report.placed, 2);
+        assert_eq!(report.failures[0].record, 3);
+        let a = space.find_species("A").unwrap();
+        assert_eq!(space.molecule_info(a).unwrap().diffusion_coefficient, 1e-12);
+        assert_eq!(space.get_species_id_at(Coordinate(0)).unwrap(), Some(a));
+
+        let bad = r#"{"species": [{"name": "B", "location": "X"}]}"#;
+        assert!(matches!(
+            space.load_model_json(bad.as_bytes(), CollisionPolicy::Error),
+            Err(Error::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,149 @@
+//! Pair interaction energies biasing diffusion.
+//!
+//! A molecule interacts with the molecules on its 12 neighbor voxels. A hop
+//! into a vacant voxel that changes the energy of the hopping molecule by
+//! `ΔE` is accepted with the Metropolis probability `min(1, exp(-ΔE))`, so
+//! that attracted molecules (negative energies) tend to stay together.
+//! Energies are in units of kT. For species feeling the potential of the
+//! `potential` module, `ΔE` includes its change too.
+
+use crate::{Coordinate, HCPLatticeSpace, SpeciesID};
+use rand::Rng;
+
+impl HCPLatticeSpace {
+    /// Sets the interaction energy between neighboring molecules of `a` and
+    /// `b`, in units of kT.
+    pub fn set_interaction(&mut self, a: SpeciesID, b: SpeciesID, energy: f64) {
+        self.interactions.insert((a, b), energy);
+        self.interactions.insert((b, a), energy);
+    }
+
+    /// Returns the interaction energy between `a` and `b`, zero if unset.
+    pub fn interaction(&self, a: SpeciesID, b: SpeciesID) -> f64 {
+        self.interactions.get(&(a, b)).copied().unwrap_or(0.0)
+    }
+
+    /// Returns the energy of a molecule of `species` at `coordinate` with
+    /// its neighbors, leaving out the voxel `skip`.
+    fn neighborhood_energy(
+        &self,
+        species: SpeciesID,
+        coordinate: Coordinate,
+        skip: Coordinate,
+    ) -> f64 {
+        self.neighbors(coordinate)
+            .expect("hops are between voxels of the lattice")
+            .into_iter()
+            .filter(|&neighbor| neighbor != skip)
+            .filter_map(|neighbor| self.voxel(neighbor))
+            .map(|other| self.interaction(species, other))
+            .sum()
+    }
+
+    /// Decides whether the molecule of `species` at `from` may hop to `to`.
+    ///
+    /// Hops that are blocked anyway, hops of multi-voxel molecules and hops
+    /// that do not raise the energy are accepted without drawing a random
+    /// number, so that zero energies reproduce plain diffusion exactly.
+    pub(crate) fn accept_hop<R: Rng + ?Sized>(
+        &self,
+        species: SpeciesID,
+        from: Coordinate,
+        to: Coordinate,
+        rng: &mut R,
+    ) -> bool {
+        let cache = &self.species_cache[species.0];
+        let biased = cache.metropolis && self.potential.is_some();
+        if (self.interactions.is_empty() && !biased)
+            || cache.voxel_count > 1
+            || !self.can_occupy(species, to)
+        {
+            return true;
+        }
+        let mut delta = self.potential_change(species, from, to);
+        if !self.interactions.is_empty() {
+            delta += self.neighborhood_energy(species, to, from)
+                - self.neighborhood_energy(species, from, to);
+        }
+        delta <= 0.0 || rng.gen::<f64>() < (-delta).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HCPLatticeSize, Species};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn crowded_space() -> (HCPLatticeSpace, SpeciesID, SpeciesID) {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        for i in (0..216).step_by(4) {
+            space.place_particle(a, Coordinate(i)).unwrap();
+        }
+        for i in (2..216).step_by(7) {
+            let _ = space.place_particle(b, Coordinate(i));
+        }
+        (space, a, b)
+    }
+
+    #[test]
+    fn zero_energies_reproduce_walk() {
+        let (mut plain, a, b) = crowded_space();
+        let (mut neutral, _, _) = crowded_space();
+        neutral.set_interaction(a, b, 0.0);
+        neutral.set_interaction(a, a, 0.0);
+        let mut rng1 = StdRng::seed_from_u64(2);
+        let mut rng2 = StdRng::seed_from_u64(2);
+        for _ in 0..20 {
+            plain.walk(a, &mut rng1).unwrap();
+            plain.walk(b, &mut rng1).unwrap();
+            neutral.walk(a, &mut rng2).unwrap();
+            neutral.walk(b, &mut rng2).unwrap();
+        }
+        assert_eq!(plain.voxels, neutral.voxels);
+        assert_eq!(neutral.interaction(b, a), 0.0);
+    }
+
+    /// Fraction of walks after which the A molecule neighbors the B one.
+    fn contact_fraction(energy: f64) -> f64 {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        space.set_periodic(true).unwrap();
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        let center = space.global_to_coordinate(3, 3, 3).unwrap();
+        space.place_particle(b, center).unwrap();
+        space.place_particle(a, Coordinate(0)).unwrap();
+        space.set_interaction(a, b, energy);
+
+        let mut rng = StdRng::seed_from_u64(8);
+        let contacts = space.neighbors(center).unwrap();
+        let steps = 20000;
+        let mut touching = 0;
+        for _ in 0..steps {
+            space.walk(a, &mut rng).unwrap();
+            let at = space.coordinates_of(a).unwrap()[0];
+            if contacts.contains(&at) {
+                touching += 1;
+            }
+        }
+        touching as f64 / steps as f64
+    }
+
+    #[test]
+    fn attraction_favors_contact() {
+        // 12 of the 215 voxels free for A touch B; an energy of -2 weights
+        // each of them by e², raising the expected fraction to about 0.3.
+        let free = contact_fraction(0.0);
+        let attracted = contact_fraction(-2.0);
+        assert!((free - 12.0 / 215.0).abs() < 0.02, "free {}", free);
+        let expected = 12.0 * 2f64.exp() / (12.0 * 2f64.exp() + 203.0);
+        assert!(
+            (attracted - expected).abs() < 0.06,
+            "attracted {}",
+            attracted
+        );
+    }
+}
@@ -0,0 +1,383 @@
+//! The operations shared by lattice geometries.
+//!
+//! `LatticeSpace` is everything the `Simulator` and the observers need from
+//! a space: enumerating voxels and their neighbors, locating them in real
+//! space, looking species up, placing, moving and removing molecules, and
+//! walking a species. The time between two hops depends on the geometry and
+//! is given by `diffusion_interval`.
+//!
+//! `HCPLatticeSpace` implements it by forwarding to its inherent methods,
+//! which keep working without the trait in scope, and `CubicLatticeSpace`
+//! is the simple cubic lattice with 6 neighbors per voxel, so that the same
+//! model runs on either geometry. `StackLattice` is the HCP lattice with its
+//! voxels in an array sized at compile time.
+
+use crate::{Coordinate, HCPLatticeSpace, MoleculeInfo, ParticleID, Result, Species, SpeciesID};
+use rand::Rng;
+
+pub trait LatticeSpace {
+    fn num_voxels(&self) -> usize;
+
+    /// Returns the voxels adjacent to `coordinate`.
+    fn neighbors(&self, coordinate: Coordinate) -> Result<Vec<Coordinate>>;
+
+    /// Returns the center of the voxel in real space.
+    fn position(&self, coordinate: Coordinate) -> Result<[f64; 3]>;
+
+    fn is_periodic(&self) -> bool;
+
+    /// Returns the lengths along x, y and z after which the lattice repeats
+    /// itself when periodic.
+    fn periodic_lengths(&self) -> [f64; 3];
+
+    /// Returns the species occupying `coordinate`, `None` if it is vacant.
+    fn species_at(&self, coordinate: Coordinate) -> Result<Option<SpeciesID>>;
+
+    /// Registers a species whose molecules occupy voxels of `location`, or
+    /// vacant voxels if `None`.
+    fn register_species(&mut self, species: Species, location: Option<SpeciesID>) -> SpeciesID;
+
+    /// Returns the number of registered species, whose IDs are the indices
+    /// below it in registration order.
+    fn num_species(&self) -> usize;
+
+    fn find_species(&self, name: &str) -> Option<SpeciesID>;
+
+    /// Returns the location of `species`, `None` meaning vacant voxels.
+    /// This and the other methods taking a `SpeciesID` fail with
+    /// `UnknownSpecies` for an ID of no species of the space.
+    fn location_of(&self, species: SpeciesID) -> Result<Option<SpeciesID>>;
+
+    /// Returns true if a molecule of `species` may take the voxel at
+    /// `coordinate`: a vacant one for a bulk species, one of its location
+    /// otherwise. False if `coordinate` is outside the lattice or `species`
+    /// no species of the space.
+    fn can_occupy(&self, species: SpeciesID, coordinate: Coordinate) -> bool {
+        match (self.species_at(coordinate), self.location_of(species)) {
+            (Ok(occupant), Ok(location)) => occupant == location,
+            _ => false,
+        }
+    }
+
+    /// Returns true for species that never move nor react.
+    fn is_obstacle(&self, _species: SpeciesID) -> bool {
+        false
+    }
+
+    /// Returns true for species whose molecules have a `ParticleID`.
+    fn is_tracking(&self, species: SpeciesID) -> bool {
+        species.index() < self.num_species()
+    }
+
+    /// Returns the numbers of molecules absorbed by each sink.
+    fn sink_counts(&self) -> Vec<u64> {
+        Vec::new()
+    }
+
+    fn molecule_info(&self, species: SpeciesID) -> Result<MoleculeInfo>;
+
+    fn set_molecule_info(&mut self, species: SpeciesID, info: MoleculeInfo) -> Result<()>;
+
+    /// Returns the time between two hops of a molecule of `species` on this
+    /// geometry, `None` if it does not diffuse or is no species of the
+    /// space.
+    fn diffusion_interval(&self, species: SpeciesID) -> Option<f64>;
+
+    fn num_molecules(&self, species: SpeciesID) -> Result<usize>;
+
+    /// Returns the voxels of the molecules of `species`, in placement order.
+    fn coordinates_of(&self, species: SpeciesID) -> Result<Vec<Coordinate>>;
+
+    /// Returns the `ParticleID`s of the molecules of `species`, in
+    /// placement order, failing with `TrackingRequired` for a species only
+    /// counted.
+    fn particles_of(&self, species: SpeciesID) -> Result<Vec<ParticleID>>;
+
+    fn find_particle(&self, pid: ParticleID) -> Option<(&Species, Coordinate)>;
+
+    /// Returns the voxel of the molecule `pid` if it is one of `species`.
+    fn particle_coordinate(&self, species: SpeciesID, pid: ParticleID) -> Option<Coordinate>;
+
+    /// Places a new molecule of `species` on a voxel of its location.
+    fn place_particle(&mut self, species: SpeciesID, coordinate: Coordinate) -> Result<ParticleID>;
+
+    /// Moves the molecule at `from` into the adjacent voxel `to`, which has
+    /// to be a voxel of its location.
+    fn move_particle(&mut self, from: Coordinate, to: Coordinate) -> Result<()>;
+
+    /// Removes the molecule at `coordinate`, handing its voxel back to its
+    /// location.
+    fn remove_at(&mut self, coordinate: Coordinate) -> Result<(SpeciesID, Option<ParticleID>)>;
+
+    /// Turns the molecule at `coordinate` into a molecule of `into` located
+    /// on the same species, keeping its voxel and its `ParticleID`.
+    fn change_species_at(&mut self, coordinate: Coordinate, into: SpeciesID) -> Result<()>;
+
+    /// Attempts one hop for every molecule of `species` towards a uniformly
+    /// chosen neighbor, in placement order.
+    fn walk<R: Rng>(&mut self, species: SpeciesID, rng: &mut R) -> Result<()>;
+}
+
+impl LatticeSpace for HCPLatticeSpace {
+    fn num_voxels(&self) -> usize {
+        HCPLatticeSpace::num_voxels(self)
+    }
+
+    fn neighbors(&self, coordinate: Coordinate) -> Result<Vec<Coordinate>> {
+        HCPLatticeSpace::neighbors(self, coordinate)
+    }
+
+    fn position(&self, coordinate: Coordinate) -> Result<[f64; 3]> {
+        self.coordinate_to_position(coordinate)
+    }
+
+    fn is_periodic(&self) -> bool {
+        HCPLatticeSpace::is_periodic(self)
+    }
+
+    fn periodic_lengths(&self) -> [f64; 3] {
+        HCPLatticeSpace::periodic_lengths(self)
+    }
+
+    fn species_at(&self, coordinate: Coordinate) -> Result<Option<SpeciesID>> {
+        HCPLatticeSpace::species_at(self, coordinate)
+    }
+
+    fn register_species(&mut self, species: Species, location: Option<SpeciesID>) -> SpeciesID {
+        HCPLatticeSpace::register_species(self, species, location)
+    }
+
+    fn num_species(&self) -> usize {
+        self.species_cache.len()
+    }
+
+    fn find_species(&self, name: &str) -> Option<SpeciesID> {
+        HCPLatticeSpace::find_species(self, name)
+    }
+
+    fn location_of(&self, species: SpeciesID) -> Result<Option<SpeciesID>> {
+        HCPLatticeSpace::location_of(self, species)
+    }
+
+    fn can_occupy(&self, species: SpeciesID, coordinate: Coordinate) -> bool {
+        HCPLatticeSpace::can_occupy(self, species, coordinate)
+    }
+
+    fn is_obstacle(&self, species: SpeciesID) -> bool {
+        HCPLatticeSpace::is_obstacle(self, species)
+    }
+
+    fn is_tracking(&self, species: SpeciesID) -> bool {
+        HCPLatticeSpace::is_tracking(self, species)
+    }
+
+    fn sink_counts(&self) -> Vec<u64> {
+        HCPLatticeSpace::sink_counts(self)
+    }
+
+    fn molecule_info(&self, species: SpeciesID) -> Result<MoleculeInfo> {
+        HCPLatticeSpace::molecule_info(self, species)
+    }
+
+    fn set_molecule_info(&mut self, species: SpeciesID, info: MoleculeInfo) -> Result<()> {
+        HCPLatticeSpace::set_molecule_info(self, species, info)
+    }
+
+    fn diffusion_interval(&self, species: SpeciesID) -> Option<f64> {
+        HCPLatticeSpace::diffusion_interval(self, species)
+    }
+
+    fn num_molecules(&self, species: SpeciesID) -> Result<usize> {
+        HCPLatticeSpace::num_molecules(self, species)
+    }
+
+    fn coordinates_of(&self, species: SpeciesID) -> Result<Vec<Coordinate>> {
+        HCPLatticeSpace::coordinates_of(self, species)
+    }
+
+    fn particles_of(&self, species: SpeciesID) -> Result<Vec<ParticleID>> {
+        HCPLatticeSpace::particles_of(self, species)
+    }
+
+    fn find_particle(&self, pid: ParticleID) -> Option<(&Species, Coordinate)> {
+        HCPLatticeSpace::find_particle(self, pid)
+    }
+
+    fn particle_coordinate(&self, species: SpeciesID, pid: ParticleID) -> Option<Coordinate> {
+        HCPLatticeSpace::particle_coordinate(self, species, pid)
+    }
+
+    fn place_particle(&mut self, species: SpeciesID, coordinate: Coordinate) -> Result<ParticleID> {
+        HCPLatticeSpace::place_particle(self, species, coordinate)
+    }
+
+    fn move_particle(&mut self, from: Coordinate, to: Coordinate) -> Result<()> {
+        HCPLatticeSpace::move_particle(self, from, to)
+    }
+
+    fn remove_at(&mut self, coordinate: Coordinate) -> Result<(SpeciesID, Option<ParticleID>)> {
+        HCPLatticeSpace::remove_at(self, coordinate)
+    }
+
+    fn change_species_at(&mut self, coordinate: Coordinate, into: SpeciesID) -> Result<()> {
+        HCPLatticeSpace::change_species_at(self, coordinate, into)
+    }
+
+    fn walk<R: Rng>(&mut self, species: SpeciesID, rng: &mut R) -> Result<()> {
+        HCPLatticeSpace::walk(self, species, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CubicLatticeSpace, Error, HCPLatticeSize, ReactionRule, Simulator, StackLattice,
+        TrajectoryTarget,
+    };
+
+    /// Counts the molecules of `species` having a vacant neighbor, written
+    /// against the trait only.
+    fn num_exposed<L: LatticeSpace>(space: &L, species: SpeciesID) -> usize {
+        space
+            .coordinates_of(species)
+            .unwrap()
+            .into_iter()
+            .filter(|&c| {
+                space
+                    .neighbors(c)
+                    .unwrap()
+                    .iter()
+                    .any(|&n| space.species_at(n).unwrap().is_none())
+            })
+            .count()
+    }
+
+    /// Checks that the voxels and the molecule lists agree.
+    fn check<L: LatticeSpace>(space: &L) {
+        let mut occupied = 0;
+        for i in 0..space.num_species() {
+            let species = SpeciesID(i);
+            let coordinates = space.coordinates_of(species).unwrap();
+            assert_eq!(coordinates.len(), space.num_molecules(species).unwrap());
+            for c in coordinates {
+                assert_eq!(space.species_at(c).unwrap(), Some(species));
+            }
+            occupied += space.num_molecules(species).unwrap();
+        }
+        let voxels = (0..space.num_voxels())
+            .filter(|&i| space.species_at(Coordinate(i)).unwrap().is_some())
+            .count();
+        assert_eq!(voxels, occupied);
+    }
+
+    /// Places, moves and removes molecules on a membrane.
+    fn bookkeeping<L: LatticeSpace>(mut space: L) {
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), Some(membrane));
+        assert_eq!(space.num_species(), 2);
+        assert_eq!(space.find_species("A"), Some(a));
+        assert_eq!(space.location_of(a).unwrap(), Some(membrane));
+
+        let center = Coordinate(space.num_voxels() / 2);
+        let neighbors = space.neighbors(center).unwrap();
+        assert!(matches!(
+            space.place_particle(a, center),
+            Err(Error::InvalidLocation(..))
+        ));
+        space.place_particle(membrane, center).unwrap();
+        for &n in &neighbors {
+            space.place_particle(membrane, n).unwrap();
+        }
+        let pid = space.place_particle(a, center).unwrap();
+        assert_eq!(num_exposed(&space, a), 0);
+        assert_eq!(num_exposed(&space, membrane), neighbors.len());
+
+        space.move_particle(center, neighbors[0]).unwrap();
+        assert_eq!(space.find_particle(pid).unwrap().1, neighbors[0]);
+        assert_eq!(space.species_at(center).unwrap(), Some(membrane));
+        assert_eq!(space.particles_of(a).unwrap(), vec![pid]);
+        check(&space);
+
+        assert_eq!(space.remove_at(neighbors[0]).unwrap(), (a, Some(pid)));
+        assert_eq!(space.num_molecules(membrane).unwrap(), neighbors.len() + 1);
+        assert!(space.remove_at(center).is_ok());
+        assert!(matches!(
+            space.remove_at(Coordinate(space.num_voxels())),
+            Err(Error::OutOfRange(_))
+        ));
+        check(&space);
+    }
+
+    /// Runs diffusing `A` molecules decaying into `B` and returns the mean
+    /// squared displacement of the survivors at `t` over `6 D t` and the
+    /// fraction of `A` left.
+    fn model<L: LatticeSpace>(mut space: L) -> (f64, f64) {
+        let d = 1e-12;
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        for &id in &[a, b] {
+            space
+                .set_molecule_info(
+                    id,
+                    MoleculeInfo {
+                        radius: 1e-8,
+                        diffusion_coefficient: d,
+                    },
+                )
+                .unwrap();
+        }
+        let num_voxels = space.num_voxels();
+        for i in (0..num_voxels).step_by(num_voxels / 1000) {
+            space.place_particle(a, Coordinate(i)).unwrap();
+        }
+        let interval = space.diffusion_interval(a).unwrap();
+        let t = 50.0 * interval;
+        let k = 0.5 / t;
+
+        let mut sim = Simulator::with_seed(space, 5);
+        let rule = ReactionRule::new(vec![Species::new("A")], vec![Species::new("B")], k);
+        sim.add_reaction(rule).unwrap();
+        let target = TrajectoryTarget::Species(Species::new("A"));
+        let observer = sim.add_trajectory_observer(target, t, true).unwrap();
+        sim.run(t).unwrap();
+        check(sim.space());
+
+        let survivors: Vec<_> = sim
+            .trajectory_observer(observer)
+            .trajectories()
+            .iter()
+            .filter(|trajectory| trajectory.positions.len() == 2)
+            .collect();
+        let msd = survivors
+            .iter()
+            .map(|trajectory| {
+                let [p, q] = [trajectory.positions[0], trajectory.positions[1]];
+                (0..3).map(|i| (q[i] - p[i]).powi(2)).sum::<f64>()
+            })
+            .sum::<f64>()
+            / survivors.len() as f64;
+        let left = sim.space().num_molecules(a).unwrap() as f64 / 1000.0;
+        (msd / (6.0 * d * t), left)
+    }
+
+    #[test]
+    fn bookkeeping_on_every_lattice() {
+        bookkeeping(HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6)));
+        bookkeeping(CubicLatticeSpace::new(2.0, [6, 6, 6]));
+        bookkeeping(StackLattice::<216>::new(1.0, HCPLatticeSize::new(6, 6, 6)));
+    }
+
+    #[test]
+    fn same_model_on_both_lattices() {
+        let mut hcp = HCPLatticeSpace::new(1e-8, HCPLatticeSize::new(24, 24, 24));
+        hcp.set_periodic(true).unwrap();
+        let mut cubic = CubicLatticeSpace::new(2e-8, [26, 26, 26]);
+        cubic.set_periodic(true);
+        for (msd, left) in [model(hcp), model(cubic)] {
+            // Blocked hops slow the walk down a little.
+            assert!((msd - 0.94).abs() < 0.08, "msd {}", msd);
+            assert!((left - (-0.5f64).exp()).abs() < 0.05, "left {}", left);
+        }
+    }
+}
@@ -1,54 +1,604 @@
-// use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+pub mod analysis;
+pub mod anisotropy;
+pub mod boundary;
+pub mod collision;
+pub mod crowding;
+pub mod cubic;
+pub mod diff;
+#[cfg(feature = "rayon")]
+pub mod domain;
+#[cfg(feature = "hdf5")]
+pub mod ecell4;
+pub mod embed;
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod grid;
+pub mod group;
+pub mod hop;
+pub mod import;
+pub mod interaction;
+pub mod lattice;
+#[cfg(feature = "model")]
+pub mod model;
+pub mod multi_voxel;
+pub mod neighbors;
+pub mod nsm;
+pub mod observer;
+pub mod obstacle;
+#[cfg(feature = "rayon")]
+pub mod parallel;
+pub mod potential;
+pub mod property;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod reaction;
+pub mod region;
+pub mod render;
+pub mod resize;
+pub mod simulator;
+pub mod sink;
+pub mod slice;
+pub mod snapshot;
+pub mod stack;
+pub mod surface;
+pub mod synchronous;
+pub mod time_course;
+pub mod transaction;
+pub mod units;
+mod voxels;
+
+pub use analysis::Axis;
+pub use anisotropy::DiffusionTensor;
+pub use boundary::Face;
+pub use cubic::CubicLatticeSpace;
+pub use diff::LatticeDiff;
+#[cfg(feature = "rayon")]
+pub use domain::ParallelSimulator;
+pub use export::SpeciesStyle;
+pub use group::SpeciesGroup;
+pub use hop::HopPolicy;
+pub use lattice::LatticeSpace;
+pub use multi_voxel::MultiVoxelSpecies;
+pub use neighbors::Direction;
+pub use nsm::NsmSimulator;
+pub use observer::{
+    NumberObserver, Observer, RegionObserver, SinkObserver, Trajectory, TrajectoryObserver,
+    TrajectoryTarget,
+};
+pub use property::PropertyHandle;
+pub use reaction::ReactionRule;
+pub use region::{Cuboid, Region, Sphere};
+pub use simulator::Simulator;
+pub use sink::SinkID;
+pub use slice::Slice;
+pub use snapshot::{FrozenLattice, LatticeSnapshot};
+pub use stack::StackLattice;
+pub use time_course::TimeCourse;
+
+use rand::Rng;
+use voxels::{decode, encode, ChunkedVoxels, DenseVoxels, SparseVoxels, VoxelStore, Voxels};
+
+/// The identity of a tracked molecule: a lot and a serial within the lot.
+///
+/// The lot tells apart IDs of different origins, such as runs or spaces
+/// later merged into one, and the serial the molecules of a lot. A space
+/// starts in lot 0, or another lot of `set_lot`, and hands out serials
+/// counting up within its lot, taking a new lot when the serial would
+/// wrap. Each clone of a space takes a new lot as well, and a lot left
+/// and later taken again resumes after the last serial it handed out, so
+/// that a space and every space cloned from it, directly or not, never
+/// hand out the same ID, nor an ID they handed out before, even once its
+/// molecule is gone. IDs order by lot and then by serial.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct ParticleID(u64, u64);
 
+impl ParticleID {
+    pub fn lot(self) -> u64 {
+        self.0
+    }
+
+    pub fn serial(self) -> u64 {
+        self.1
+    }
+}
+
+/// Hands out the `ParticleID`s of a space; see `ParticleID`.
+#[derive(Debug)]
+pub(crate) struct PidAllocator {
+    lot: u64,
+    serial: u64,
+    /// The last lot taken, shared by a space and its clones.
+    lots: Arc<AtomicU64>,
+    /// Where the lots stand, shared by a space and its clones.
+    marks: Arc<Mutex<LotMarks>>,
+}
+
+/// The lots of the allocators of a space and its clones.
+#[derive(Debug, Default)]
+struct LotMarks {
+    /// The lots an allocator hands out serials of.
+    held: HashSet<u64>,
+    /// The next serial of each lot left by its allocator.
+    left: HashMap<u64, u64>,
+}
+
+impl PidAllocator {
+    pub(crate) fn new() -> Self {
+        let marks = LotMarks {
+            held: std::iter::once(0).collect(),
+            left: HashMap::new(),
+        };
+        Self {
+            lot: 0,
+            serial: 0,
+            lots: Arc::new(AtomicU64::new(0)),
+            marks: Arc::new(Mutex::new(marks)),
+        }
+    }
+
+    /// Hands out serials of `lot` from now on, after the last one it
+    /// handed out if any. Fails with `LotInUse` if another allocator holds
+    /// `lot`.
+    pub(crate) fn set_lot(&mut self, lot: u64) -> Result<()> {
+        if lot == self.lot {
+            return Ok(());
+        }
+        let mut marks = lock(&self.marks);
+        if !marks.held.insert(lot) {
+            return Err(Error::LotInUse(lot));
+        }
+        marks.held.remove(&self.lot);
+        marks.left.insert(self.lot, self.serial);
+        self.serial = marks.left.remove(&lot).unwrap_or(0);
+        drop(marks);
+        self.lot = lot;
+        self.lots.fetch_max(lot, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub(crate) fn next(&mut self) -> ParticleID {
+        if self.serial == u64::MAX {
+            let lot = self.take_lot();
+            let mut marks = lock(&self.marks);
+            marks.held.remove(&self.lot);
+            marks.left.insert(self.lot, u64::MAX);
+            marks.held.insert(lot);
+            drop(marks);
+            self.lot = lot;
+            self.serial = 0;
+        }
+        let pid = ParticleID(self.lot, self.serial);
+        self.serial += 1;
+        pid
+    }
+
+    /// Keeps clear of `pid`, given to a molecule from elsewhere, e.g. a
+    /// file, so as not to hand it out again.
+    #[cfg(feature = "hdf5")]
+    pub(crate) fn reserve(&mut self, pid: ParticleID) {
+        let next = pid.1.saturating_add(1);
+        if pid.0 == self.lot {
+            self.serial = self.serial.max(next);
+        } else {
+            let mut marks = lock(&self.marks);
+            let mark = marks.left.entry(pid.0).or_insert(0);
+            *mark = (*mark).max(next);
+        }
+        self.lots.fetch_max(pid.0, Ordering::Relaxed);
+    }
+
+    fn take_lot(&self) -> u64 {
+        self.lots.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+fn lock(marks: &Mutex<LotMarks>) -> std::sync::MutexGuard<'_, LotMarks> {
+    // The marks stay consistent across a panic of another holder.
+    marks.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl Clone for PidAllocator {
+    fn clone(&self) -> Self {
+        let lot = self.take_lot();
+        lock(&self.marks).held.insert(lot);
+        Self {
+            lot,
+            serial: 0,
+            lots: Arc::clone(&self.lots),
+            marks: Arc::clone(&self.marks),
+        }
+    }
+}
+
+impl Drop for PidAllocator {
+    fn drop(&mut self) {
+        let (lot, serial) = (self.lot, self.serial);
+        let mut marks = lock(&self.marks);
+        marks.held.remove(&lot);
+        marks.left.insert(lot, serial);
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Species(String);
 
+impl Species {
+    pub fn new(name: &str) -> Self {
+        Species(name.to_string())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Physical attributes of a species.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MoleculeInfo {
+    pub radius: f64,
+    pub diffusion_coefficient: f64,
+}
+
+/// Everything a space knows about a species besides its molecules, as
+/// returned by `HCPLatticeSpace::species_attributes`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SpeciesAttributes {
+    pub info: MoleculeInfo,
+    /// The species whose voxels it occupies, `None` meaning vacant voxels.
+    pub location: Option<SpeciesID>,
+    /// False if it only counts its molecules.
+    pub tracking: bool,
+    pub obstacle: bool,
+    /// The number of voxels of each molecule.
+    pub voxel_count: usize,
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Coordinate(usize);
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct HCPLatticeSize {
     row: usize,
     col: usize,
     layer: usize,
 }
 
+impl HCPLatticeSize {
+    pub fn new(row: usize, col: usize, layer: usize) -> Self {
+        Self { row, col, layer }
+    }
+
+    /// Returns the number of voxels, `None` if it overflows `usize`.
+    pub fn num_voxels(&self) -> Option<usize> {
+        self.row.checked_mul(self.col)?.checked_mul(self.layer)
+    }
+
+    /// Returns the `(row, col, layer)` of an index known to be in range.
+    fn global(&self, index: usize) -> (usize, usize, usize) {
+        let row = index % self.row;
+        let col = (index / self.row) % self.col;
+        let layer = index / (self.row * self.col);
+        (row, col, layer)
+    }
+}
+
+/// Returns the lengths along x, y and z of a lattice of `size` voxels of
+/// radius `r`; see `HCPLatticeSpace::periodic_lengths`.
+fn lattice_lengths(r: f64, size: HCPLatticeSize) -> [f64; 3] {
+    [
+        2.0 * r * size.col as f64,
+        3f64.sqrt() * r * size.row as f64,
+        (8f64 / 3.0).sqrt() * r * size.layer as f64,
+    ]
+}
+
+/// Returns the center of the voxel at `(row, col, layer)` of a lattice of
+/// voxel radius `r`, which may lie outside the lattice.
+fn center(r: f64, row: isize, col: isize, layer: isize) -> [f64; 3] {
+    let odd_row = row.rem_euclid(2) as f64;
+    let odd_layer = layer.rem_euclid(2) as f64;
+    [
+        r * (2.0 * col as f64 + odd_row + odd_layer),
+        r * (3f64.sqrt() * row as f64 + odd_layer / 3f64.sqrt()),
+        r * (8f64 / 3.0).sqrt() * layer as f64,
+    ]
+}
+
 #[derive(Debug)]
 pub enum Error {
     OutOfRange(Coordinate),
     ParticleNotFound(Coordinate),
     InvalidLocation(Coordinate, Coordinate),
+    InvalidWeights,
+    NotAdjacent(Coordinate, Coordinate),
+    PositionOutOfRange([f64; 3]),
+    SpeciesNotFound(Species),
+    InvalidReaction,
+    InsufficientSpace(Coordinate),
+    UnknownParticle(ParticleID),
+    InvalidPeriodicSize,
+    InvalidDecomposition,
+    SizeOverflow,
+    InvalidTransition,
+    /// A direction or operation out of the plane of a 2D lattice.
+    OutOfPlane,
+    InvalidFraction(f64),
+    /// An operation on bulk molecules given a structure or a species
+    /// located on one.
+    NotBulk(Species),
+    /// Counting the molecules of a species that has to be tracked.
+    TrackingRequired(Species),
+    /// A boundary condition on a face of a periodic lattice, which has none.
+    PeriodicBoundary,
+    /// A reaction faster than collisions on the lattice can carry at its
+    /// voxel radius, with the probability per collision it would need; a
+    /// smaller voxel radius lowers it.
+    DiffusionLimited(f64),
+    /// Clearing a structure still hosting molecules of the species located
+    /// on it.
+    StructureInUse(Vec<Species>),
+    /// Copying between spaces of different voxel radii, this one's first.
+    VoxelRadiusMismatch(f64, f64),
+    /// A row or layer offset of odd parity, which the stagger of the
+    /// lattice does not allow; see the `embed` module.
+    StaggeredOffset,
+    /// A `SpeciesID` no species of the space is registered under, e.g. one
+    /// from another space.
+    UnknownSpecies(SpeciesID),
+    Io(std::io::Error),
+    Parse(String),
+    /// A length of a lattice shorter than a voxel along its axis, or a
+    /// voxel radius that is not positive; see `HCPLatticeSpace::from_lengths`.
+    InvalidLength(f64),
+    /// Taking a lot of `ParticleID`s that another space cloned from the
+    /// same one hands out IDs of; see `HCPLatticeSpace::set_lot`.
+    LotInUse(u64),
+    /// A duration that is negative or not finite, or a time step that is
+    /// not positive; see `HCPLatticeSpace::run`.
+    InvalidDuration(f64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = |species: &[Species]| {
+            species
+                .iter()
+                .map(|species| species.name())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self {
+            Error::OutOfRange(c) => write!(f, "voxel {} is out of the lattice", c.0),
+            Error::ParticleNotFound(c) => write!(f, "no molecule on voxel {}", c.0),
+            Error::InvalidLocation(from, to) if from == to => {
+                write!(f, "voxel {} is not a location of the species", to.0)
+            }
+            Error::InvalidLocation(from, to) => write!(
+                f,
+                "the molecule on voxel {} cannot go to voxel {}",
+                from.0, to.0
+            ),
+            Error::InvalidWeights => write!(f, "invalid hop weights"),
+            Error::NotAdjacent(a, b) => write!(f, "voxels {} and {} are not adjacent", a.0, b.0),
+            Error::PositionOutOfRange(p) => write!(f, "position {:?} is out of the lattice", p),
+            Error::SpeciesNotFound(species) => {
+                write!(f, "species {} is not registered", species.name())
+            }
+            Error::InvalidReaction => write!(f, "invalid reaction"),
+            Error::InsufficientSpace(c) => write!(f, "no room for the molecule at voxel {}", c.0),
+            Error::UnknownParticle(pid) => {
+                write!(f, "no molecule has the ID {}:{}", pid.lot(), pid.serial())
+            }
+            Error::InvalidPeriodicSize => write!(
+                f,
+                "a periodic lattice needs an even number of rows and of layers"
+            ),
+            Error::InvalidDecomposition => write!(f, "invalid domain decomposition"),
+            Error::SizeOverflow => write!(f, "the lattice has too many voxels"),
+            Error::InvalidTransition => write!(f, "invalid transition"),
+            Error::OutOfPlane => write!(f, "out of the plane of a 2D lattice"),
+            Error::InvalidFraction(x) => write!(f, "{} is not a fraction within [0, 1]", x),
+            Error::NotBulk(species) => write!(f, "species {} is not in the bulk", species.name()),
+            Error::TrackingRequired(species) => {
+                write!(f, "species {} has to be tracked", species.name())
+            }
+            Error::PeriodicBoundary => write!(f, "a periodic lattice has no boundary"),
+            Error::DiffusionLimited(p) => write!(
+                f,
+                "the reaction needs a probability per collision of {}, above 1",
+                p
+            ),
+            Error::StructureInUse(species) => write!(
+                f,
+                "the structure still hosts molecules of {}",
+                names(species)
+            ),
+            Error::VoxelRadiusMismatch(a, b) => {
+                write!(f, "voxel radii {} and {} differ", a, b)
+            }
+            Error::StaggeredOffset => write!(f, "row and layer offsets have to be even"),
+            Error::UnknownSpecies(id) => write!(f, "no species has the ID {}", id.0),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Parse(message) => write!(f, "parse error: {}", message),
+            Error::InvalidLength(length) => write!(f, "invalid length {}", length),
+            Error::LotInUse(lot) => write!(f, "lot {} is in use by another space", lot),
+            Error::InvalidDuration(t) => write!(f, "invalid duration {}", t),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct SpeciesID(usize);
 
+impl SpeciesID {
+    /// Returns the registration index of the species, counting from 0.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 enum TrackingType {
-    Tracking(Vec<(ParticleID, Coordinate)>),
+    Tracking(Tracked),
     Count(usize),
 }
 
+/// The `(ParticleID, Coordinate)` entries of a tracked species, one per
+/// voxel in placement order, those of a multi-voxel molecule next to each
+/// other with the anchor first. Also indexes the anchors by `ParticleID`,
+/// so that finding a molecule takes no scan; the entries are read through
+/// `Deref` and changed only by the methods keeping the index.
+#[derive(Clone, PartialEq, Debug, Default)]
+struct Tracked {
+    entries: Vec<(ParticleID, Coordinate)>,
+    anchors: HashMap<ParticleID, Coordinate>,
+}
+
+impl Tracked {
+    /// Returns the anchor of the molecule `pid`, `None` if not of these.
+    fn get(&self, pid: ParticleID) -> Option<Coordinate> {
+        self.anchors.get(&pid).copied()
+    }
+
+    fn push(&mut self, pid: ParticleID, coordinate: Coordinate) {
+        self.anchors.entry(pid).or_insert(coordinate);
+        self.entries.push((pid, coordinate));
+    }
+
+    fn is_anchor(&self, i: usize) -> bool {
+        i == 0 || self.entries[i - 1].0 != self.entries[i].0
+    }
+
+    /// Removes entry `i`, handing the anchor of a multi-voxel molecule on
+    /// to its next voxel.
+    fn remove(&mut self, i: usize) -> (ParticleID, Coordinate) {
+        let anchor = self.is_anchor(i);
+        let (pid, coordinate) = self.entries.remove(i);
+        if anchor {
+            match self.entries.get(i) {
+                Some(&(next, c)) if next == pid => {
+                    self.anchors.insert(pid, c);
+                }
+                _ => {
+                    self.anchors.remove(&pid);
+                }
+            }
+        }
+        (pid, coordinate)
+    }
+
+    /// Removes every entry of the molecule `pid` and returns their voxels.
+    fn remove_particle(&mut self, pid: ParticleID) -> Vec<Coordinate> {
+        let cluster = self
+            .entries
+            .iter()
+            .filter(|(id, _)| *id == pid)
+            .map(|(_, c)| *c)
+            .collect();
+        self.entries.retain(|(id, _)| *id != pid);
+        self.anchors.remove(&pid);
+        cluster
+    }
+
+    fn set_coordinate(&mut self, i: usize, coordinate: Coordinate) {
+        if self.is_anchor(i) {
+            self.anchors.insert(self.entries[i].0, coordinate);
+        }
+        self.entries[i].1 = coordinate;
+    }
+
+    /// Gives the single-voxel molecule of entry `i` the ID `pid`.
+    fn set_pid(&mut self, i: usize, pid: ParticleID) {
+        let (old, coordinate) = self.entries[i];
+        self.anchors.remove(&old);
+        self.anchors.insert(pid, coordinate);
+        self.entries[i].0 = pid;
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+    }
+
+    #[cfg(test)]
+    fn capacity(&self) -> usize {
+        self.entries.capacity()
+    }
+}
+
+impl std::ops::Deref for Tracked {
+    type Target = [(ParticleID, Coordinate)];
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+impl<'a> IntoIterator for &'a Tracked {
+    type Item = &'a (ParticleID, Coordinate);
+    type IntoIter = std::slice::Iter<'a, (ParticleID, Coordinate)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+impl std::iter::FromIterator<(ParticleID, Coordinate)> for Tracked {
+    fn from_iter<I: IntoIterator<Item = (ParticleID, Coordinate)>>(iter: I) -> Self {
+        let mut tracked = Tracked::default();
+        for (pid, coordinate) in iter {
+            tracked.push(pid, coordinate);
+        }
+        tracked
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 struct SpeciesCache {
     species: Species,
     location: Option<SpeciesID>,
+    info: MoleculeInfo,
+    voxel_count: usize,
+    /// The radius of the ball of voxels a multi-voxel molecule occupies
+    /// around the voxel it is placed on, if registered with a footprint.
+    footprint: Option<f64>,
+    /// Whether this is an obstacle, see the `obstacle` module.
+    obstacle: bool,
+    /// The coefficients of anisotropic diffusion, see the `anisotropy`
+    /// module.
+    tensor: Option<anisotropy::DiffusionTensor>,
+    /// Whether its hops feel the potential of the `potential` module.
+    metropolis: bool,
+    /// How exporters draw it, see the `export` module.
+    style: Option<export::SpeciesStyle>,
     cache: TrackingType,
+    /// The species a molecule may turn into by hopping onto their location,
+    /// with the probability per attempt.
+    transitions: Vec<(SpeciesID, f64)>,
 }
 
 impl SpeciesCache {
     fn remove(&mut self, coordinate: Coordinate) {
         match &mut self.cache {
             TrackingType::Tracking(cache) => {
-                for i in 0..cache.len() {
-                    if cache[i].1 == coordinate {
-                        cache.remove(i);
-                        break;
-                    }
+                if let Some(i) = cache.iter().position(|(_, c)| *c == coordinate) {
+                    cache.remove(i);
                 }
             }
             TrackingType::Count(count) => {
@@ -57,10 +607,10 @@ impl SpeciesCache {
         }
     }
 
-    fn add(&mut self, coordinate: Coordinate) {
+    fn add(&mut self, pid: ParticleID, coordinate: Coordinate) {
         match &mut self.cache {
             TrackingType::Tracking(cache) => {
-                cache.push((ParticleID(0,0), coordinate));
+                cache.push(pid, coordinate);
             }
             TrackingType::Count(count) => {
                 *count += 1;
@@ -68,33 +618,187 @@ impl SpeciesCache {
         }
     }
 
+    fn num_molecules(&self) -> usize {
+        match &self.cache {
+            TrackingType::Tracking(entries) => entries.len() / self.voxel_count,
+            TrackingType::Count(count) => *count,
+        }
+    }
+
     fn move_to(&mut self, from: Coordinate, to: Coordinate) {
         if let TrackingType::Tracking(cache) = &mut self.cache {
-            for (_pid, coordinate) in cache {
-                if *coordinate == from {
-                    *coordinate = to;
-                    break;
-                }
+            if let Some(i) = cache.iter().position(|(_, c)| *c == from) {
+                cache.set_coordinate(i, to);
             }
         }
     }
 }
 
+#[derive(Clone)]
 pub struct HCPLatticeSpace {
     voxel_radius: f64,
     size: HCPLatticeSize,
-    voxels: Box<[Option<SpeciesID>]>,
+    voxels: Voxels,
     species_cache: Vec<SpeciesCache>,
+    /// The first species registered under each name.
+    species_ids: HashMap<Species, SpeciesID>,
+    pids: PidAllocator,
+    periodic: bool,
+    /// Whether this is a single layer created by `new_2d`.
+    planar: bool,
+    /// Periodic boundary crossings of the particles that have crossed one,
+    /// if image tracking is enabled.
+    images: Option<HashMap<ParticleID, [i32; 3]>>,
+    /// Pair interaction energies in units of kT, stored under both orders.
+    interactions: HashMap<(SpeciesID, SpeciesID), f64>,
+    sinks: Vec<sink::Sink>,
+    /// The sink of each sink voxel, by coordinate index.
+    sink_voxels: HashMap<usize, usize>,
+    /// The potential of each voxel in units of kT, allocated when first set.
+    potential: Option<Box<[f32]>>,
+    /// The properties of `add_voxel_property`, in the order they were added.
+    properties: Vec<property::VoxelProperty>,
+    /// The groups of the `group` module, in the order they were created.
+    groups: Vec<group::SpeciesGroup>,
+    /// The reactions of the `collision` module.
+    collisions: Vec<collision::Collision>,
+    /// The journal of the innermost running `transaction`, if any.
+    journal: Option<transaction::Journal>,
+    /// The policy of the `hop` module, `ExcludedVolume` if unset.
+    hop_policy: Option<Arc<dyn hop::HopPolicy>>,
+}
+
+/// The most voxels `HCPLatticeSpace::try_new` allocates, 8 GB of them.
+pub const DEFAULT_MAX_VOXELS: usize = 1 << 31;
+
+/// Returns the number of voxels of `size`, panicking if it overflows.
+fn expect_num_voxels(size: &HCPLatticeSize) -> usize {
+    size.num_voxels()
+        .expect("the number of voxels overflows usize")
 }
 
 impl HCPLatticeSpace {
+    /// Panics if the number of voxels overflows `usize`; see `try_new`.
     pub fn new(voxel_radius: f64, size: HCPLatticeSize) -> Self {
-        let num_voxels = size.row * size.col * size.layer;
+        let num_voxels = expect_num_voxels(&size);
+        Self::with_voxels(
+            voxel_radius,
+            size,
+            Voxels::Dense(DenseVoxels::new(num_voxels)),
+        )
+    }
+
+    /// Same as `new`, but fails with `SizeOverflow` instead of allocating
+    /// more than `DEFAULT_MAX_VOXELS` voxels or overflowing their count.
+    pub fn try_new(voxel_radius: f64, size: HCPLatticeSize) -> Result<Self> {
+        Self::try_new_with_limit(voxel_radius, size, DEFAULT_MAX_VOXELS)
+    }
+
+    /// Same as `try_new` with a limit of `max_voxels` voxels.
+    pub fn try_new_with_limit(
+        voxel_radius: f64,
+        size: HCPLatticeSize,
+        max_voxels: usize,
+    ) -> Result<Self> {
+        match size.num_voxels() {
+            Some(num_voxels) if num_voxels <= max_voxels => Ok(Self::with_voxels(
+                voxel_radius,
+                size,
+                Voxels::Dense(DenseVoxels::new(num_voxels)),
+            )),
+            _ => Err(Error::SizeOverflow),
+        }
+    }
+
+    /// Creates a space storing only its occupied voxels, for dilute systems
+    /// too large for the dense store of `new`. See the `voxels` module for
+    /// the trade-off.
+    pub fn new_sparse(voxel_radius: f64, size: HCPLatticeSize) -> Self {
+        let num_voxels = expect_num_voxels(&size);
+        Self::with_voxels(
+            voxel_radius,
+            size,
+            Voxels::Sparse(SparseVoxels::new(num_voxels)),
+        )
+    }
+
+    /// Creates a space allocating its voxels in bricks, only where molecules
+    /// are, for lattices too large for memory whose molecules stay in a
+    /// small region. See the `voxels` module for the trade-off.
+    pub fn new_chunked(voxel_radius: f64, size: HCPLatticeSize) -> Self {
+        let num_voxels = expect_num_voxels(&size);
+        Self::with_voxels(
+            voxel_radius,
+            size,
+            Voxels::Chunked(ChunkedVoxels::new(num_voxels)),
+        )
+    }
+
+    /// Creates a single-layer lattice of `rows × cols` voxels in the z = 0
+    /// plane, where molecules hop between the 6 in-plane neighbors only.
+    ///
+    /// A 3D lattice of one layer is not the same: it lets molecules attempt
+    /// hops out of the plane, which are rejected, and its diffusion interval
+    /// is the 3D one. On a 2D lattice the out-of-plane directions fail with
+    /// `OutOfPlane` and `diffusion_interval` obeys `<Δr²> = 4Dt`.
+    pub fn new_2d(voxel_radius: f64, rows: usize, cols: usize) -> Self {
+        let mut space = Self::new(voxel_radius, HCPLatticeSize::new(rows, cols, 1));
+        space.planar = true;
+        space
+    }
+
+    /// Creates a lattice of voxels of `voxel_radius` covering `lengths`
+    /// along x, y and z, with the fewest columns, rows and layers whose
+    /// `actual_lengths` are no shorter. The lattice spacings are `2r` along
+    /// x, `√3 r` along y and `2r√(2/3)` along z, so each actual length is
+    /// less than a spacing longer than the requested one. A periodic lattice
+    /// also needs even numbers of rows and layers; see `set_periodic`.
+    ///
+    /// Fails with `InvalidLength` of a length shorter than the spacing along
+    /// its axis, or of a voxel radius that is not positive, and with
+    /// `SizeOverflow` as `try_new`.
+    pub fn from_lengths(voxel_radius: f64, lengths: [f64; 3]) -> Result<Self> {
+        if !(voxel_radius > 0.0 && voxel_radius.is_finite()) {
+            return Err(Error::InvalidLength(voxel_radius));
+        }
+        let spacings = lattice_lengths(voxel_radius, HCPLatticeSize::new(1, 1, 1));
+        let mut counts = [0; 3];
+        for axis in 0..3 {
+            let (length, spacing) = (lengths[axis], spacings[axis]);
+            if !(length >= spacing && length.is_finite()) {
+                return Err(Error::InvalidLength(length));
+            }
+            let mut count = (length / spacing).ceil() as usize;
+            // Rounding may leave one spacing more than needed.
+            if count > 1 && (count - 1) as f64 * spacing >= length {
+                count -= 1;
+            }
+            counts[axis] = count;
+        }
+        let [col, row, layer] = counts;
+        Self::try_new(voxel_radius, HCPLatticeSize::new(row, col, layer))
+    }
+
+    fn with_voxels(voxel_radius: f64, size: HCPLatticeSize, voxels: Voxels) -> Self {
         Self {
             voxel_radius,
             size,
-            voxels: vec![None; num_voxels].into_boxed_slice(),
+            voxels,
             species_cache: Vec::new(),
+            species_ids: HashMap::new(),
+            pids: PidAllocator::new(),
+            periodic: false,
+            planar: false,
+            images: None,
+            interactions: HashMap::new(),
+            sinks: Vec::new(),
+            sink_voxels: HashMap::new(),
+            potential: None,
+            properties: Vec::new(),
+            groups: Vec::new(),
+            collisions: Vec::new(),
+            journal: None,
+            hop_policy: None,
         }
     }
 
@@ -102,64 +806,3054 @@ impl HCPLatticeSpace {
         self.voxel_radius
     }
 
-    pub fn find_particle(&self, pid: ParticleID) -> Option<(&Species, Coordinate)> {
-        for species in &self.species_cache {
-            if let TrackingType::Tracking(cache) = &species.cache {
-                for (id, coordinate) in cache {
-                    if *id == pid {
-                        return Some((&species.species, *coordinate));
-                    }
+    /// Enables or disables periodic boundaries on all three axes, or on x
+    /// and y for a 2D lattice.
+    ///
+    /// The stagger of rows and layers only lines up across a periodic seam
+    /// if there are even numbers of rows and layers.
+    pub fn set_periodic(&mut self, periodic: bool) -> Result<()> {
+        let odd_layers = !self.planar && self.size.layer % 2 == 1;
+        if periodic && (self.size.row % 2 == 1 || odd_layers) {
+            return Err(Error::InvalidPeriodicSize);
+        }
+        self.periodic = periodic;
+        Ok(())
+    }
+
+    pub fn is_periodic(&self) -> bool {
+        self.periodic
+    }
+
+    /// Returns true for a lattice created by `new_2d`.
+    pub fn is_2d(&self) -> bool {
+        self.planar
+    }
+
+    /// Enables or disables counting the periodic boundary crossings of each
+    /// particle in `move_particle`, which `unwrapped_position` needs.
+    /// Disabling it forgets the crossings counted so far.
+    pub fn set_image_tracking(&mut self, enabled: bool) {
+        if enabled != self.images.is_some() {
+            self.images = if enabled { Some(HashMap::new()) } else { None };
+        }
+    }
+
+    /// Returns the position of `pid` as if it had never been wrapped around
+    /// a periodic boundary. Without image tracking this is its position.
+    pub fn unwrapped_position(&self, pid: ParticleID) -> Result<[f64; 3]> {
+        let (_, coordinate) = self.find_particle(pid).ok_or(Error::UnknownParticle(pid))?;
+        let mut position = self.coordinate_to_position(coordinate)?;
+        let image = self
+            .images
+            .as_ref()
+            .and_then(|images| images.get(&pid))
+            .copied()
+            .unwrap_or([0; 3]);
+        for ((x, image), length) in position
+            .iter_mut()
+            .zip(&image)
+            .zip(&self.periodic_lengths())
+        {
+            *x += *image as f64 * length;
+        }
+        Ok(position)
+    }
+
+    /// Counts the boundary crossings of the move of the molecule of
+    /// `species` from `from` to the adjacent `to`.
+    fn track_image(&mut self, species: SpeciesID, from: Coordinate, to: Coordinate) -> Result<()> {
+        let pid = match &self.species_cache[species.0].cache {
+            TrackingType::Tracking(entries) => {
+                entries.iter().find(|(_, c)| *c == to).map(|(pid, _)| *pid)
+            }
+            TrackingType::Count(_) => None,
+        };
+        let pid = match pid {
+            Some(pid) => pid,
+            None => return Ok(()),
+        };
+        let p = self.coordinate_to_position(from)?;
+        let q = self.coordinate_to_position(to)?;
+        let lengths = self.periodic_lengths();
+        let mut crossings = [0; 3];
+        for axis in 0..3 {
+            crossings[axis] = -((q[axis] - p[axis]) / lengths[axis]).round() as i32;
+        }
+        if crossings != [0; 3] {
+            if let Some(images) = &mut self.images {
+                let image = images.entry(pid).or_insert([0; 3]);
+                for axis in 0..3 {
+                    image[axis] += crossings[axis];
                 }
             }
         }
-        None
+        Ok(())
     }
 
-    fn get_species_id_at(&self, coordinate: Coordinate) -> Result<Option<SpeciesID>> {
-        self.voxels
-            .get(coordinate.0)
-            .ok_or(Error::OutOfRange(coordinate))
-            .map(|id| *id)
+    /// Returns the lengths along x, y and z after which the lattice repeats
+    /// itself when periodic.
+    pub fn periodic_lengths(&self) -> [f64; 3] {
+        lattice_lengths(self.voxel_radius, self.size)
     }
 
-    // fn get_species_cache(&self, id: SpeciesID) -> &SpeciesCache {
-    //     &self.species_cache[id.0]
-    // }
+    /// An alias of `periodic_lengths`, named for `from_lengths`: the
+    /// lengths along x, y and z the lattice covers, which `from_lengths`
+    /// rounds the requested ones up to. The lattice is taken to cover the
+    /// box it tiles when periodic, not the smaller one spanned by the voxel
+    /// centers or the larger `bounding_box` of the voxel spheres.
+    pub fn actual_lengths(&self) -> [f64; 3] {
+        self.periodic_lengths()
+    }
 
-    fn get_species_cache_mut(&mut self, id: SpeciesID) -> &mut SpeciesCache {
-        &mut self.species_cache[id.0]
+    /// Returns the volume of the lattice in cubic meters: the voxels stand
+    /// for cells of `4√2 r³` each, which tile the box of `periodic_lengths`
+    /// exactly. This is the volume to divide counts by; the voxel spheres
+    /// only fill `π/(3√2)`, about 74%, of it, and `bounding_box` is larger.
+    pub fn volume(&self) -> f64 {
+        self.periodic_lengths().iter().product()
     }
 
-    pub fn move_particle(&mut self, from: Coordinate, to: Coordinate) -> Result<()> {
-        let from_species_id = self
-            .get_species_id_at(from)?
-            .ok_or(Error::ParticleNotFound(from))?;
-        let to_species_id = self.get_species_id_at(to)?;
+    /// Returns the surface area of the box of `periodic_lengths`, whose
+    /// volume is `volume`.
+    pub fn surface_area(&self) -> f64 {
+        let [x, y, z] = self.periodic_lengths();
+        2.0 * (x * y + y * z + z * x)
+    }
 
-        let from_species_cache = self.get_species_cache_mut(from_species_id);
+    /// Returns the lower and upper corners of the smallest box holding
+    /// every voxel sphere, the extent of the voxel centers grown by `r`.
+    /// The staggered rows and layers make it larger than the box of
+    /// `volume`, by up to `r` along x and `r/√3` along y, and by `2r - h`
+    /// along z with `h = 2r√(2/3)` the distance between the layers.
+    pub fn bounding_box(&self) -> ([f64; 3], [f64; 3]) {
+        let r = self.voxel_radius;
+        // The extremes are on the first two and last two indices, which
+        // cover both parities.
+        let ends = |len: usize| {
+            let len = len as isize;
+            [0, 1.min(len - 1), (len - 2).max(0), len - 1]
+        };
+        let mut lower = [f64::INFINITY; 3];
+        let mut upper = [f64::NEG_INFINITY; 3];
+        for &row in &ends(self.size.row) {
+            for &col in &ends(self.size.col) {
+                for &layer in &ends(self.size.layer) {
+                    let center = self.center(row, col, layer);
+                    for axis in 0..3 {
+                        lower[axis] = lower[axis].min(center[axis] - r);
+                        upper[axis] = upper[axis].max(center[axis] + r);
+                    }
+                }
+            }
+        }
+        (lower, upper)
+    }
 
-        if from_species_cache.location != to_species_id {
-            return Err(Error::InvalidLocation(from, to));
+    /// Registers a species whose molecules occupy voxels of `location`, or
+    /// vacant voxels if `None`. Its radius defaults to the voxel radius and
+    /// its diffusion coefficient to zero.
+    ///
+    /// Panics if `u32::MAX - 1` species are already registered, the most a
+    /// voxel can tell apart, or if `location` is an obstacle or no species
+    /// of this space.
+    pub fn register_species(&mut self, species: Species, location: Option<SpeciesID>) -> SpeciesID {
+        assert!(
+            self.species_cache.len() < u32::MAX as usize - 1,
+            "too many species for the voxel encoding"
+        );
+        if let Some(location) = location {
+            assert!(
+                self.contains_species(location),
+                "the location is no species of this space"
+            );
+            assert!(
+                !self.is_obstacle(location),
+                "no species can be located on an obstacle"
+            );
         }
+        let id = SpeciesID(self.species_cache.len());
+        self.species_ids.entry(species.clone()).or_insert(id);
+        self.species_cache.push(SpeciesCache {
+            species,
+            location,
+            info: MoleculeInfo {
+                radius: self.voxel_radius,
+                diffusion_coefficient: 0.0,
+            },
+            voxel_count: 1,
+            footprint: None,
+            obstacle: false,
+            cache: TrackingType::Tracking(Tracked::default()),
+            transitions: Vec::new(),
+            tensor: None,
+            metropolis: false,
+            style: None,
+        });
+        id
+    }
 
-        from_species_cache.move_to(from, to);
+    /// Same as `register_species`, the species only counting its molecules
+    /// instead of tracking them; see `set_tracking`.
+    ///
+    /// The molecules of a counted species still sit on the lattice, where
+    /// they can be placed, removed and react, but have no identity: the
+    /// `ParticleID`s returned on placing them are not kept, `find_particle`
+    /// does not find them and `particles_of` fails. Finding them takes a
+    /// scan of the lattice, which makes walking them slow, so counting
+    /// suits abundant species that mostly stay put, saving the memory and
+    /// the bookkeeping of an entry per molecule.
+    pub fn register_counted_species(
+        &mut self,
+        species: Species,
+        location: Option<SpeciesID>,
+    ) -> SpeciesID {
+        let id = self.register_species(species, location);
+        self.get_species_cache_mut(id)
+            .expect("just registered")
+            .cache = TrackingType::Count(0);
+        id
+    }
 
-        if let Some(to_species_id) = to_species_id {
-            let to_species_cache = self.get_species_cache_mut(to_species_id);
-            to_species_cache.remove(to);
-            to_species_cache.add(from);
+    /// Switches `species` between tracking its molecules, with a
+    /// `ParticleID` each, and only counting them.
+    ///
+    /// A counted species starts being tracked with fresh `ParticleID`s for
+    /// its molecules, assigned in coordinate order. A tracked species forgets
+    /// the `ParticleID`s of its molecules and their periodic images. Fails
+    /// with `TrackingRequired` for a multi-voxel species, which has to be
+    /// tracked.
+    pub fn set_tracking(&mut self, species: SpeciesID, tracking: bool) -> Result<()> {
+        self.check_species(species)?;
+        let cache = &self.species_cache[species.0];
+        match (&cache.cache, tracking) {
+            (TrackingType::Tracking(_), true) | (TrackingType::Count(_), false) => {}
+            (TrackingType::Tracking(entries), false) => {
+                if cache.voxel_count > 1 {
+                    return Err(Error::TrackingRequired(cache.species.clone()));
+                }
+                let count = entries.len();
+                if let Some(images) = &mut self.images {
+                    for (pid, _) in entries {
+                        images.remove(pid);
+                    }
+                }
+                self.get_species_cache_mut(species)?.cache = TrackingType::Count(count);
+            }
+            (TrackingType::Count(_), true) => {
+                let coordinates: Vec<Coordinate> = self
+                    .occupied()
+                    .filter(|&(_, id)| id == species)
+                    .map(|(c, _)| c)
+                    .collect();
+                let entries = coordinates
+                    .into_iter()
+                    .map(|c| (self.next_pid(), c))
+                    .collect();
+                self.get_species_cache_mut(species)?.cache = TrackingType::Tracking(entries);
+            }
         }
+        Ok(())
+    }
 
-        self.voxels.swap(from.0, to.0);
+    /// Stops tracking the molecules of `species`, as `set_tracking`, given
+    /// the species rather than its ID. Counted molecules keep their voxels,
+    /// so that they exclude volume as before: only their `ParticleID`s are
+    /// dropped. Fails with `SpeciesNotFound` for a species not registered.
+    pub fn convert_to_counted(&mut self, species: &Species) -> Result<()> {
+        let id = self
+            .species_id(species)
+            .ok_or_else(|| Error::SpeciesNotFound(species.clone()))?;
+        self.set_tracking(id, false)
+    }
+
+    /// Starts tracking the molecules of `species` where they are, as
+    /// `set_tracking`, and returns their fresh `ParticleID`s in coordinate
+    /// order. A tracked species gets none and returns its current ones.
+    pub fn convert_to_tracked(&mut self, species: &Species) -> Result<Vec<ParticleID>> {
+        let id = self
+            .species_id(species)
+            .ok_or_else(|| Error::SpeciesNotFound(species.clone()))?;
+        self.set_tracking(id, true)?;
+        self.particles_of(id)
+    }
+
+    /// Returns true unless `species` only counts its molecules, false for
+    /// an ID of no species of this space.
+    pub fn is_tracking(&self, species: SpeciesID) -> bool {
+        self.get_species_cache(species)
+            .is_ok_and(|cache| matches!(cache.cache, TrackingType::Tracking(_)))
+    }
 
+    /// Reserves room for at least `additional` more molecules of a tracked
+    /// species, so that placing them does not reallocate. Fails with
+    /// `UnknownSpecies` for an ID of no species of this space.
+    pub fn reserve(&mut self, species: SpeciesID, additional: usize) -> Result<()> {
+        let cache = self.get_species_cache_mut(species)?;
+        if let TrackingType::Tracking(entries) = &mut cache.cache {
+            entries.reserve(additional * cache.voxel_count);
+        }
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+    pub fn molecule_info(&self, species: SpeciesID) -> Result<MoleculeInfo> {
+        Ok(self.get_species_cache(species)?.info)
+    }
+
+    pub fn set_molecule_info(&mut self, species: SpeciesID, info: MoleculeInfo) -> Result<()> {
+        self.get_species_cache_mut(species)?.info = info;
+        Ok(())
+    }
+
+    /// Returns the time between two hops of a molecule of `species`, `None`
+    /// if it does not diffuse, like obstacles, or is no species of this
+    /// space: `(2r)²/(2nD)` in `n` dimensions.
+    pub fn diffusion_interval(&self, species: SpeciesID) -> Option<f64> {
+        let cache = self.get_species_cache(species).ok()?;
+        if cache.obstacle {
+            return None;
+        }
+        if let Some(tensor) = cache.tensor {
+            return self.tensor_interval(tensor);
+        }
+        let d = cache.info.diffusion_coefficient;
+        if d > 0.0 {
+            let r = self.voxel_radius;
+            let dimensions = if self.planar { 2.0 } else { 3.0 };
+            Some(2.0 * r * r / (dimensions * d))
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if a species of this space is registered under `id`.
+    /// IDs are indices handed out in registration order and never reused,
+    /// so that this only tells an ID of another space holding more species
+    /// from one of this space.
+    pub fn contains_species(&self, id: SpeciesID) -> bool {
+        id.0 < self.species_cache.len()
+    }
+
+    /// Fails with `UnknownSpecies` unless `contains_species(id)`.
+    pub(crate) fn check_species(&self, id: SpeciesID) -> Result<()> {
+        if self.contains_species(id) {
+            Ok(())
+        } else {
+            Err(Error::UnknownSpecies(id))
+        }
+    }
+
+    /// Returns the first species registered under `name`.
+    pub fn find_species(&self, name: &str) -> Option<SpeciesID> {
+        self.species_id(&Species::new(name))
+    }
+
+    /// Returns the ID `species` was first registered under, in constant
+    /// time.
+    pub fn species_id(&self, species: &Species) -> Option<SpeciesID> {
+        self.species_ids.get(species).copied()
+    }
+
+    fn flatten(&self, row: usize, col: usize, layer: usize) -> Coordinate {
+        Coordinate(row + self.size.row * (col + self.size.col * layer))
+    }
+
+    pub fn global_to_coordinate(&self, row: usize, col: usize, layer: usize) -> Result<Coordinate> {
+        let coordinate = self.flatten(row, col, layer);
+        if row >= self.size.row || col >= self.size.col || layer >= self.size.layer {
+            return Err(Error::OutOfRange(coordinate));
+        }
+        Ok(coordinate)
+    }
+
+    pub fn coordinate_to_global(&self, coordinate: Coordinate) -> Result<(usize, usize, usize)> {
+        self.check_bounds(coordinate)?;
+        Ok(self.size.global(coordinate.0))
+    }
+
+    /// Returns the center of the voxel in real space.
+    ///
+    /// See the `neighbors` module for the layout of the lattice.
+    pub fn coordinate_to_position(&self, coordinate: Coordinate) -> Result<[f64; 3]> {
+        let (row, col, layer) = self.coordinate_to_global(coordinate)?;
+        Ok(self.center(row as isize, col as isize, layer as isize))
+    }
+
+    /// Returns the center of the voxel at `(row, col, layer)`, which may lie
+    /// outside the lattice.
+    fn center(&self, row: isize, col: isize, layer: isize) -> [f64; 3] {
+        center(self.voxel_radius, row, col, layer)
+    }
+
+    /// Returns the voxel whose center is nearest to `position`.
+    ///
+    /// Fails if that voxel would lie outside the lattice.
+    pub fn position_to_coordinate(&self, position: [f64; 3]) -> Result<Coordinate> {
+        let r = self.voxel_radius;
+        let [x, y, z] = position;
+        let layer = (z / (r * (8f64 / 3.0).sqrt())).round() as isize;
+        let mut nearest = (f64::INFINITY, (0, 0, 0));
+        for layer in layer - 1..=layer + 1 {
+            let odd_layer = layer.rem_euclid(2) as f64;
+            let row = ((y / r - odd_layer / 3f64.sqrt()) / 3f64.sqrt()).round() as isize;
+            for row in row - 1..=row + 1 {
+                let odd_row = row.rem_euclid(2) as f64;
+                let col = ((x / r - odd_row - odd_layer) / 2.0).round() as isize;
+                for col in col - 1..=col + 1 {
+                    let d2: f64 = self
+                        .center(row, col, layer)
+                        .iter()
+                        .zip(&position)
+                        .map(|(a, b)| (a - b).powi(2))
+                        .sum();
+                    if d2 < nearest.0 {
+                        nearest = (d2, (row, col, layer));
+                    }
+                }
+            }
+        }
+
+        let (_, (row, col, layer)) = nearest;
+        if row < 0 || col < 0 || layer < 0 {
+            return Err(Error::PositionOutOfRange(position));
+        }
+        self.global_to_coordinate(row as usize, col as usize, layer as usize)
+            .map_err(|_| Error::PositionOutOfRange(position))
+    }
+
+    /// Returns the neighbor in `direction`, `None` outside a non-periodic
+    /// lattice. Fails with `OutOfPlane` for an inter-layer direction on a 2D
+    /// lattice.
+    pub fn neighbor(
+        &self,
+        coordinate: Coordinate,
+        direction: Direction,
+    ) -> Result<Option<Coordinate>> {
+        let global = self.coordinate_to_global(coordinate)?;
+        if self.planar && !direction.is_in_plane() {
+            return Err(Error::OutOfPlane);
+        }
+        Ok(
+            neighbors::neighbor(&self.size, self.periodic, global, direction)
+                .map(|(row, col, layer)| self.flatten(row, col, layer)),
+        )
+    }
+
+    pub fn neighbors(&self, coordinate: Coordinate) -> Result<Vec<Coordinate>> {
+        let mut neighbors = Vec::with_capacity(Direction::ALL.len());
+        for &direction in self.directions() {
+            if let Some(neighbor) = self.neighbor(coordinate, direction)? {
+                neighbors.push(neighbor);
+            }
+        }
+        Ok(neighbors)
+    }
+
+    /// Counts the species on the neighbors of `coordinate`, indexed by
+    /// `SpeciesID::index`, with the vacant neighbors in a last slot at
+    /// `num_species()`. Neighbors outside a non-periodic lattice are not
+    /// counted, so the counts sum to fewer than 12 on its faces.
+    pub fn neighbor_species_counts(&self, coordinate: Coordinate) -> Result<Vec<usize>> {
+        let vacant = self.species_cache.len();
+        let mut counts = vec![0; vacant + 1];
+        for neighbor in self.neighbors(coordinate)? {
+            counts[self.voxel(neighbor).map_or(vacant, |id| id.0)] += 1;
+        }
+        Ok(counts)
+    }
+
+    /// Returns the voxel nearest to `from`, in hops, where a molecule of
+    /// `species` could be placed, searching up to `max_radius` hops away.
+    /// `from` itself is at distance zero; ties are broken in `Direction`
+    /// order. `None` if there is none, as for an ID of no species of this
+    /// space.
+    pub fn nearest_empty(
+        &self,
+        from: Coordinate,
+        max_radius: usize,
+        species: SpeciesID,
+    ) -> Option<Coordinate> {
+        self.check_bounds(from).ok()?;
+        if self.can_occupy(species, from) {
+            return Some(from);
+        }
+        let mut visited = HashSet::new();
+        visited.insert(from.0);
+        let mut frontier = vec![from];
+        for _ in 0..max_radius {
+            let mut next = Vec::new();
+            for &coordinate in &frontier {
+                for neighbor in self.neighbors(coordinate).ok()? {
+                    if !visited.insert(neighbor.0) {
+                        continue;
+                    }
+                    if self.can_occupy(species, neighbor) {
+                        return Some(neighbor);
+                    }
+                    next.push(neighbor);
+                }
+            }
+            frontier = next;
+        }
+        None
+    }
+
+    /// Returns the distance between the centers of two voxels, the shortest
+    /// over the periodic images if the lattice is periodic.
+    pub fn distance(&self, a: Coordinate, b: Coordinate) -> Result<f64> {
+        let p = self.coordinate_to_position(a)?;
+        let q = self.coordinate_to_position(b)?;
+        let d = self.displacement(p, q);
+        Ok(d.iter().map(|x| x * x).sum::<f64>().sqrt())
+    }
+
+    /// Returns the molecules of tracked species, or of `species` only,
+    /// occupying a voxel whose center lies within `radius` of the center of
+    /// `center`, in coordinate order. Distances are to the nearest periodic
+    /// image. A multi-voxel molecule is listed once per voxel within reach.
+    ///
+    /// Only the voxels in the box of rows, columns and layers around
+    /// `center` that can be within `radius` are looked at.
+    pub fn particles_within(
+        &self,
+        center: Coordinate,
+        radius: f64,
+        species: Option<SpeciesID>,
+    ) -> Result<Vec<(ParticleID, &Species, Coordinate)>> {
+        if let Some(species) = species {
+            self.check_species(species)?;
+        }
+        let (row, col, layer) = self.coordinate_to_global(center)?;
+        let origin = self.coordinate_to_position(center)?;
+        let r = self.voxel_radius;
+        // The half-widths of the box, in rows, columns and layers, with one
+        // to spare for the stagger.
+        let reach = |spacing: f64| (radius / spacing).ceil() as isize + 1;
+        let spans = [
+            (row, self.size.row, reach(3f64.sqrt() * r)),
+            (col, self.size.col, reach(2.0 * r)),
+            (layer, self.size.layer, reach((8f64 / 3.0).sqrt() * r)),
+        ];
+        let axes: Vec<Vec<usize>> = spans
+            .iter()
+            .map(|&(index, len, reach)| {
+                let mut indices: Vec<usize> = (index as isize - reach..=index as isize + reach)
+                    .filter_map(|i| {
+                        if self.periodic {
+                            Some(i.rem_euclid(len as isize) as usize)
+                        } else {
+                            (0..len as isize).contains(&i).then_some(i as usize)
+                        }
+                    })
+                    .collect();
+                indices.sort_unstable();
+                indices.dedup();
+                indices
+            })
+            .collect();
+
+        let mut found = Vec::new();
+        for &layer in &axes[2] {
+            for &col in &axes[1] {
+                for &row in &axes[0] {
+                    let coordinate = self.flatten(row, col, layer);
+                    let id = match self.voxel(coordinate) {
+                        Some(id) if species.is_none_or(|species| species == id) => id,
+                        _ => continue,
+                    };
+                    let p = self.coordinate_to_position(coordinate)?;
+                    let d2: f64 = self.displacement(origin, p).iter().map(|x| x * x).sum();
+                    if d2 > radius * radius {
+                        continue;
+                    }
+                    if let Some(pid) = self.particle_at(coordinate)? {
+                        found.push((pid, &self.species_cache[id.0].species, coordinate));
+                    }
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Iterates over the occupied voxels whose centers lie within `radius`
+    /// of `center`, in coordinate order, with their species. Distances are
+    /// to the nearest periodic image.
+    ///
+    /// Searches outwards from the voxel nearest to `center` through
+    /// `neighbors`, so that the cost grows with the volume of the ball
+    /// rather than with the lattice; a `center` outside the lattice falls
+    /// back to checking every voxel.
+    pub fn within_radius(
+        &self,
+        center: [f64; 3],
+        radius: f64,
+    ) -> impl Iterator<Item = (Coordinate, SpeciesID)> + '_ {
+        let distance2 = |c: Coordinate| -> f64 {
+            let p = self
+                .coordinate_to_position(c)
+                .expect("a voxel of the lattice");
+            self.displacement(center, p).iter().map(|x| x * x).sum()
+        };
+        let mut found = Vec::new();
+        match self.position_to_coordinate(center) {
+            Ok(start) => {
+                // Every voxel within reach is a neighbor of one nearer to
+                // the center by less than a hop.
+                let reach = (radius + 2.0 * self.voxel_radius).powi(2);
+                let mut visited = HashSet::new();
+                visited.insert(start.0);
+                let mut frontier = vec![start];
+                while let Some(c) = frontier.pop() {
+                    let d2 = distance2(c);
+                    if d2 <= radius * radius {
+                        if let Some(id) = self.voxel(c) {
+                            found.push((c, id));
+                        }
+                    }
+                    for neighbor in self.neighbors(c).expect("a voxel of the lattice") {
+                        if visited.insert(neighbor.0) && distance2(neighbor) <= reach {
+                            frontier.push(neighbor);
+                        }
+                    }
+                }
+                found.sort_unstable_by_key(|&(c, _)| c.0);
+            }
+            Err(_) => {
+                found = self
+                    .occupied()
+                    .filter(|&(c, _)| distance2(c) <= radius * radius)
+                    .collect();
+            }
+        }
+        found.into_iter()
+    }
+
+    /// Returns `q - p`, wrapped to the nearest image along each periodic
+    /// axis.
+    fn displacement(&self, p: [f64; 3], q: [f64; 3]) -> [f64; 3] {
+        let mut d = [q[0] - p[0], q[1] - p[1], q[2] - p[2]];
+        if self.periodic {
+            for (x, length) in d.iter_mut().zip(&self.periodic_lengths()) {
+                *x -= (*x / length).round() * length;
+            }
+        }
+        d
+    }
+
+    /// Returns the fewest hops between two voxels, across periodic
+    /// boundaries if any. This is a breadth-first search, taking time
+    /// proportional to the cube of the distance.
+    pub fn lattice_distance(&self, a: Coordinate, b: Coordinate) -> Result<usize> {
+        self.check_bounds(a)?;
+        self.check_bounds(b)?;
+        Ok(self
+            .hop_distance(a, b, &|_| false)
+            .expect("the lattice is connected"))
+    }
+
+    /// Returns the fewest hops from `from` to `to` through voxels not held
+    /// by a species for which `blocked` is true, vacant voxels being always
+    /// passable, or `None` if `to` cannot be reached. `from` itself may be
+    /// blocked, e.g. by the molecule whose way out is being measured, but
+    /// `to` may not.
+    pub fn hop_distance(
+        &self,
+        from: Coordinate,
+        to: Coordinate,
+        blocked: &dyn Fn(SpeciesID) -> bool,
+    ) -> Option<usize> {
+        self.check_bounds(from).ok()?;
+        self.check_bounds(to).ok()?;
+        let passable = |c: Coordinate| self.voxel(c).is_none_or(|id| !blocked(id));
+        if from == to {
+            return Some(0);
+        }
+        if !passable(to) {
+            return None;
+        }
+        let mut visited = HashSet::new();
+        visited.insert(from.0);
+        let mut frontier = vec![from];
+        let mut distance = 0;
+        while !frontier.is_empty() {
+            distance += 1;
+            let mut next = Vec::new();
+            for &coordinate in &frontier {
+                for neighbor in self.neighbors(coordinate).ok()? {
+                    if neighbor == to {
+                        return Some(distance);
+                    }
+                    if passable(neighbor) && visited.insert(neighbor.0) {
+                        next.push(neighbor);
+                    }
+                }
+            }
+            frontier = next;
+        }
+        None
+    }
+
+    pub fn place_particle(
+        &mut self,
+        species: SpeciesID,
+        coordinate: Coordinate,
+    ) -> Result<ParticleID> {
+        self.check_species(species)?;
+        if self.species_cache[species.0].voxel_count > 1 {
+            return self.place_cluster(species, coordinate);
+        }
+        let current = self.get_species_id_at(coordinate)?;
+        if !self.can_occupy(species, coordinate) {
+            return Err(Error::InvalidLocation(coordinate, coordinate));
+        }
+        if let Some(location) = current {
+            self.get_species_cache_mut(location)?.remove(coordinate);
+        }
+
+        let pid = self.next_pid();
+        self.get_species_cache_mut(species)?.add(pid, coordinate);
+        self.set_voxel(coordinate, Some(species));
+        Ok(pid)
+    }
+
+    /// Places every `(species, coordinate)` pair in order and returns their
+    /// `ParticleID`s in the same order. Later pairs may be located on
+    /// molecules placed by earlier ones.
+    ///
+    /// If any placement fails, the space is restored to its state before the
+    /// call, as by `transaction`, and the error of that placement is
+    /// returned.
+    pub fn place_many(
+        &mut self,
+        placements: &[(SpeciesID, Coordinate)],
+    ) -> Result<Vec<ParticleID>> {
+        let mut pids = Vec::with_capacity(placements.len());
+        self.transaction(|space| {
+            for &(species, coordinate) in placements {
+                pids.push(space.place_particle(species, coordinate)?);
+            }
+            Ok(())
+        })?;
+        Ok(pids)
+    }
+
+    /// Mints the `ParticleID`s of molecules placed from now on in `lot`,
+    /// after the last serial this space or a space cloned from the same
+    /// one handed out in it, and takes the lots of later clones and wraps
+    /// past it. Giving each run or each space to be merged its own lot
+    /// keeps their IDs apart.
+    ///
+    /// Fails with `LotInUse` if another of these spaces mints IDs in `lot`.
+    /// Panics on `u64::MAX`, the lot of the IDs standing in for counted
+    /// molecules, e.g. in `HopPolicy::accept`.
+    pub fn set_lot(&mut self, lot: u64) -> Result<()> {
+        assert_ne!(lot, u64::MAX, "the lot u64::MAX is reserved");
+        self.pids.set_lot(lot)
+    }
+
+    fn next_pid(&mut self) -> ParticleID {
+        self.pids.next()
+    }
+
+    /// Removes the molecule at `coordinate`, handing the voxel back to its
+    /// location. Returns the species of the removed molecule.
+    fn vacate(&mut self, coordinate: Coordinate) -> Result<SpeciesID> {
+        let species = self
+            .get_species_id_at(coordinate)?
+            .ok_or(Error::ParticleNotFound(coordinate))?;
+        if self.species_cache[species.0].voxel_count > 1 {
+            return self.vacate_cluster(species, coordinate);
+        }
+        let cache = self.get_species_cache_mut(species)?;
+        cache.remove(coordinate);
+        let location = cache.location;
+        if let Some(location) = location {
+            let pid = self.next_pid();
+            self.get_species_cache_mut(location)?.add(pid, coordinate);
+        }
+        self.set_voxel(coordinate, location);
+        Ok(species)
+    }
+
+    /// Removes the molecule at `coordinate`, handing its voxels back to its
+    /// location, and returns its species with its `ParticleID`, or `None` if
+    /// the species is only counted.
+    pub fn remove_at(&mut self, coordinate: Coordinate) -> Result<(SpeciesID, Option<ParticleID>)> {
+        let species = self
+            .get_species_id_at(coordinate)?
+            .ok_or(Error::ParticleNotFound(coordinate))?;
+        let pid = match &self.species_cache[species.0].cache {
+            TrackingType::Tracking(entries) => entries
+                .iter()
+                .find(|(_, c)| *c == coordinate)
+                .map(|(pid, _)| *pid),
+            TrackingType::Count(_) => None,
+        };
+        self.vacate(coordinate)?;
+        if let (Some(images), Some(pid)) = (&mut self.images, pid) {
+            images.remove(&pid);
+        }
+        Ok((species, pid))
+    }
+
+    /// Removes every molecule of `species`, which stays registered, and
+    /// returns how many there were. Fails with `StructureInUse`, listing
+    /// them, if molecules of species located on it are left.
+    pub fn clear_species(&mut self, species: &Species) -> Result<usize> {
+        let id = self
+            .species_id(species)
+            .ok_or_else(|| Error::SpeciesNotFound(species.clone()))?;
+        let dependents: Vec<Species> = self
+            .species()
+            .filter(|&(other, _, count)| {
+                count > 0 && self.species_cache[other.0].location == Some(id)
+            })
+            .map(|(_, species, _)| species.clone())
+            .collect();
+        if !dependents.is_empty() {
+            return Err(Error::StructureInUse(dependents));
+        }
+        let coordinates = self.coordinates_of(id)?;
+        for &c in &coordinates {
+            self.remove_at(c)?;
+        }
+        Ok(coordinates.len())
+    }
+
+    /// Removes every molecule but those of structures and obstacles, handing
+    /// their voxels back to their locations, to start over without
+    /// registering the species and building the structures again. The
+    /// species keep their attributes, the sinks their voxels with their
+    /// counts back to zero.
+    pub fn clear_particles(&mut self) {
+        let mobile: Vec<bool> = (0..self.species_cache.len())
+            .map(|i| !self.is_structure(SpeciesID(i)) && !self.species_cache[i].obstacle)
+            .collect();
+        self.clear_where(&mobile);
+    }
+
+    /// Removes every molecule, structures and obstacles included, as
+    /// `clear_particles`, leaving the lattice vacant.
+    pub fn reset(&mut self) {
+        self.clear_where(&vec![true; self.species_cache.len()]);
+    }
+
+    /// Removes the molecules of the species flagged in `cleared`, which have
+    /// to include every species located on a cleared one.
+    fn clear_where(&mut self, cleared: &[bool]) {
+        let occupied: Vec<(Coordinate, SpeciesID)> = self
+            .occupied()
+            .filter(|&(_, species)| cleared[species.0])
+            .collect();
+        for (i, _) in cleared.iter().enumerate().filter(|(_, &flag)| flag) {
+            let cache = self
+                .get_species_cache_mut(SpeciesID(i))
+                .expect("a species of this space");
+            cache.cache = match cache.cache {
+                TrackingType::Tracking(_) => TrackingType::Tracking(Tracked::default()),
+                TrackingType::Count(_) => TrackingType::Count(0),
+            };
+        }
+        for (c, species) in occupied {
+            let location = self.species_cache[species.0].location;
+            let location = location.filter(|location| !cleared[location.0]);
+            if let Some(location) = location {
+                let pid = self.next_pid();
+                self.get_species_cache_mut(location)
+                    .expect("a species of this space")
+                    .add(pid, c);
+            }
+            self.set_voxel(c, location);
+        }
+        if let Some(mut images) = self.images.take() {
+            images.retain(|&pid, _| self.find_particle(pid).is_some());
+            self.images = Some(images);
+        }
+        self.reset_sink_counts();
+    }
+
+    /// Turns the molecule at `coordinate` into a molecule of `into`, which
+    /// keeps its voxel and its `ParticleID`. A molecule of a counted species
+    /// gets a new `ParticleID` if `into` is tracked. Fails with
+    /// `InvalidLocation` unless both species share their location, and with
+    /// `InvalidReaction` for multi-voxel species and obstacles.
+    pub fn change_species_at(&mut self, coordinate: Coordinate, into: SpeciesID) -> Result<()> {
+        self.check_species(into)?;
+        let species = self
+            .get_species_id_at(coordinate)?
+            .ok_or(Error::ParticleNotFound(coordinate))?;
+        let (from, to) = (&self.species_cache[species.0], &self.species_cache[into.0]);
+        if from.voxel_count > 1 || to.voxel_count > 1 || from.obstacle || to.obstacle {
+            return Err(Error::InvalidReaction);
+        }
+        if from.location != to.location {
+            return Err(Error::InvalidLocation(coordinate, coordinate));
+        }
+        if species == into {
+            return Ok(());
+        }
+        let pid = match &from.cache {
+            TrackingType::Tracking(entries) => entries
+                .iter()
+                .find(|(_, c)| *c == coordinate)
+                .map(|(pid, _)| *pid),
+            TrackingType::Count(_) => None,
+        };
+        self.get_species_cache_mut(species)?.remove(coordinate);
+        let pid = match pid {
+            Some(pid) => pid,
+            None => self.next_pid(),
+        };
+        self.get_species_cache_mut(into)?.add(pid, coordinate);
+        self.set_voxel(coordinate, Some(into));
+        Ok(())
+    }
+
+    /// Returns the species and the voxel, the anchor of a multi-voxel one,
+    /// of the molecule `pid`, looking it up in each tracked species.
+    pub fn find_particle(&self, pid: ParticleID) -> Option<(&Species, Coordinate)> {
+        self.species_cache
+            .iter()
+            .find_map(|species| match &species.cache {
+                TrackingType::Tracking(cache) => cache.get(pid).map(|c| (&species.species, c)),
+                TrackingType::Count(_) => None,
+            })
+    }
+
+    /// Returns the voxel, the anchor of a multi-voxel one, of the molecule
+    /// `pid` if it is one of `species`, with a single lookup. `None` for a
+    /// molecule gone, of another species or of a counted one.
+    pub fn particle_coordinate(&self, species: SpeciesID, pid: ParticleID) -> Option<Coordinate> {
+        match &self.get_species_cache(species).ok()?.cache {
+            TrackingType::Tracking(cache) => cache.get(pid),
+            TrackingType::Count(_) => None,
+        }
+    }
+
+    /// Iterates over the molecules of the tracked species, in registration
+    /// and then placement order, one item per voxel of multi-voxel ones.
+    pub fn particles(&self) -> impl Iterator<Item = (ParticleID, SpeciesID, Coordinate)> + '_ {
+        self.species_cache
+            .iter()
+            .enumerate()
+            .flat_map(|(i, cache)| {
+                match &cache.cache {
+                    TrackingType::Tracking(entries) => &entries[..],
+                    TrackingType::Count(_) => &[],
+                }
+                .iter()
+                .map(move |&(pid, c)| (pid, SpeciesID(i), c))
+            })
+    }
+
+    /// Returns the `ParticleID`s of the molecules of a tracked species.
+    /// Fails with `TrackingRequired` for a counted species, whose molecules
+    /// have none.
+    pub fn particles_of(&self, species: SpeciesID) -> Result<Vec<ParticleID>> {
+        let entries = self.tracked_entries(species)?;
+        let mut pids: Vec<ParticleID> = Vec::with_capacity(entries.len());
+        for (pid, _) in entries {
+            if pids.last().is_none_or(|last| last != pid) {
+                pids.push(*pid);
+            }
+        }
+        Ok(pids)
+    }
+
+    /// Returns the molecules of a tracked species with their anchor voxels,
+    /// sorted by `ParticleID`: first by lot, then by serial.
+    ///
+    /// `particles`, `particles_of` and `coordinates_of` list molecules in
+    /// placement order, which depends on how the space got to its state: a
+    /// molecule changing species with a transition goes last, and molecules
+    /// read from a file come in file order. This order only depends on which
+    /// molecules are where, so that two spaces holding the same molecules
+    /// list them the same way. `coordinates` and `occupied` are in coordinate
+    /// order and `species` in registration order.
+    ///
+    /// Fails with `TrackingRequired` for a counted species.
+    pub fn particles_sorted(&self, species: SpeciesID) -> Result<Vec<(ParticleID, Coordinate)>> {
+        let entries = self.tracked_entries(species)?;
+        let mut particles: Vec<(ParticleID, Coordinate)> = Vec::with_capacity(entries.len());
+        for &(pid, c) in entries {
+            if particles.last().is_none_or(|&(last, _)| last != pid) {
+                particles.push((pid, c));
+            }
+        }
+        particles.sort_unstable_by_key(|&(pid, _)| pid);
+        Ok(particles)
+    }
+
+    fn tracked_entries(&self, species: SpeciesID) -> Result<&[(ParticleID, Coordinate)]> {
+        self.check_species(species)?;
+        let cache = &self.species_cache[species.0];
+        match &cache.cache {
+            TrackingType::Tracking(entries) => Ok(entries),
+            TrackingType::Count(_) => Err(Error::TrackingRequired(cache.species.clone())),
+        }
+    }
+
+    pub fn num_molecules(&self, species: SpeciesID) -> Result<usize> {
+        Ok(self.get_species_cache(species)?.num_molecules())
+    }
+
+    /// Returns the number of occupied voxels summed over every species,
+    /// which is the number of molecules if none is multi-voxel. It equals
+    /// `occupied().count()` without scanning the lattice.
+    pub fn total_molecules(&self) -> usize {
+        self.species_cache
+            .iter()
+            .map(|cache| match &cache.cache {
+                TrackingType::Tracking(entries) => entries.len(),
+                TrackingType::Count(count) => *count,
+            })
+            .sum()
+    }
+
+    /// Returns the number of registered species. Their IDs are their
+    /// registration indices, from 0 to `num_species() - 1`, and never
+    /// change.
+    pub fn num_species(&self) -> usize {
+        self.species_cache.len()
+    }
+
+    /// Returns the species registered under `id`, `None` for an ID beyond
+    /// `num_species`, e.g. one of another space.
+    pub fn get_species(&self, id: SpeciesID) -> Option<&Species> {
+        self.species_cache.get(id.0).map(|cache| &cache.species)
+    }
+
+    /// Returns the attributes of the species registered under `id`, `None`
+    /// for an ID beyond `num_species`.
+    pub fn species_attributes(&self, id: SpeciesID) -> Option<SpeciesAttributes> {
+        self.species_cache.get(id.0).map(|cache| SpeciesAttributes {
+            info: cache.info,
+            location: cache.location,
+            tracking: matches!(cache.cache, TrackingType::Tracking(_)),
+            obstacle: cache.obstacle,
+            voxel_count: cache.voxel_count,
+        })
+    }
+
+    /// Iterates over the registered species in registration order, with
+    /// their current molecule counts.
+    pub fn species(&self) -> impl Iterator<Item = (SpeciesID, &Species, usize)> + '_ {
+        self.species_cache
+            .iter()
+            .enumerate()
+            .map(|(i, cache)| (SpeciesID(i), &cache.species, cache.num_molecules()))
+    }
+
+    /// Returns the species whose voxels `species` occupies, `None` meaning
+    /// vacant voxels.
+    pub fn location_of(&self, species: SpeciesID) -> Result<Option<SpeciesID>> {
+        Ok(self.get_species_cache(species)?.location)
+    }
+
+    /// Returns true if a molecule of `species` may take the voxel at
+    /// `coordinate`, i.e. if the voxel is occupied by its location: a
+    /// vacant voxel for a bulk species, a molecule of the structure for a
+    /// species located on one. False if `coordinate` is outside the lattice
+    /// or `species` is no species of this space.
+    ///
+    /// Placing, moving and reacting molecules all check their target voxels
+    /// through here.
+    pub fn can_occupy(&self, species: SpeciesID, coordinate: Coordinate) -> bool {
+        self.contains(coordinate)
+            && self
+                .get_species_cache(species)
+                .is_ok_and(|cache| self.voxel(coordinate) == cache.location)
+    }
+
+    pub fn size(&self) -> HCPLatticeSize {
+        self.size
+    }
+
+    pub fn num_voxels(&self) -> usize {
+        self.voxels.len()
+    }
+
+    /// Iterates over every voxel of the lattice in coordinate order.
+    pub fn coordinates(&self) -> impl Iterator<Item = Coordinate> {
+        (0..self.voxels.len()).map(Coordinate)
+    }
+
+    /// Returns true if `coordinate` is a voxel of the lattice.
+    pub fn contains(&self, coordinate: Coordinate) -> bool {
+        coordinate.0 < self.voxels.len()
+    }
+
+    /// Fails with `OutOfRange` unless `coordinate` is a voxel of the
+    /// lattice. Every method taking a coordinate from the caller checks it
+    /// through here before indexing.
+    fn check_bounds(&self, coordinate: Coordinate) -> Result<()> {
+        if self.contains(coordinate) {
+            Ok(())
+        } else {
+            Err(Error::OutOfRange(coordinate))
+        }
+    }
+
+    /// Returns the species occupying `coordinate`, `None` if it is vacant.
+    pub fn species_at(&self, coordinate: Coordinate) -> Result<Option<SpeciesID>> {
+        self.get_species_id_at(coordinate)
+    }
+
+    /// Iterates over the occupied voxels in coordinate order.
+    pub fn occupied(&self) -> impl Iterator<Item = (Coordinate, SpeciesID)> + '_ {
+        self.voxels
+            .occupied()
+            .filter_map(|(i, raw)| decode(raw).map(|id| (Coordinate(i), id)))
+    }
+
+    /// Returns true if `species` is the location of any registered species.
+    pub(crate) fn is_structure(&self, species: SpeciesID) -> bool {
+        self.species_cache
+            .iter()
+            .any(|cache| cache.location == Some(species))
+    }
+
+    fn get_species_id_at(&self, coordinate: Coordinate) -> Result<Option<SpeciesID>> {
+        self.check_bounds(coordinate)?;
+        Ok(self.voxel(coordinate))
+    }
+
+    /// Returns the contents of a voxel known to be on the lattice.
+    fn voxel(&self, coordinate: Coordinate) -> Option<SpeciesID> {
+        decode(self.voxels.get(coordinate.0))
+    }
+
+    fn set_voxel(&mut self, coordinate: Coordinate, voxel: Option<SpeciesID>) {
+        if let Some(journal) = &mut self.journal {
+            journal.record_voxel(coordinate.0, self.voxels.get(coordinate.0));
+        }
+        self.voxels.set(coordinate.0, encode(voxel));
+    }
+
+    fn get_species_cache(&self, id: SpeciesID) -> Result<&SpeciesCache> {
+        self.species_cache
+            .get(id.0)
+            .ok_or(Error::UnknownSpecies(id))
+    }
+
+    fn get_species_cache_mut(&mut self, id: SpeciesID) -> Result<&mut SpeciesCache> {
+        self.check_species(id)?;
+        if let Some(journal) = &mut self.journal {
+            journal.record_cache(id, &self.species_cache[id.0]);
+        }
+        Ok(&mut self.species_cache[id.0])
+    }
+
+    pub fn move_particle(&mut self, from: Coordinate, to: Coordinate) -> Result<()> {
+        let from_species_id = self
+            .get_species_id_at(from)?
+            .ok_or(Error::ParticleNotFound(from))?;
+        if self.species_cache[from_species_id.0].voxel_count > 1 {
+            return self.move_cluster(from_species_id, from, to);
+        }
+        let to_species_id = self.get_species_id_at(to)?;
+        if !self.can_occupy(from_species_id, to) || self.is_obstacle(from_species_id) {
+            return Err(Error::InvalidLocation(from, to));
+        }
+
+        self.get_species_cache_mut(from_species_id)?
+            .move_to(from, to);
+
+        if let Some(to_species_id) = to_species_id {
+            self.get_species_cache_mut(to_species_id)?.move_to(to, from);
+        }
+
+        if let Some(journal) = &mut self.journal {
+            journal.record_voxel(from.0, self.voxels.get(from.0));
+            journal.record_voxel(to.0, self.voxels.get(to.0));
+        }
+        self.voxels.swap(from.0, to.0);
+
+        if self.images.is_some() && self.periodic {
+            self.track_image(from_species_id, from, to)?;
+        }
+        Ok(())
+    }
+
+    /// Consumes the molecules at the adjacent voxels `a` and `b` and places
+    /// a molecule of `product` at `a`.
+    ///
+    /// The product must be located on what the molecule at `a` leaves
+    /// behind; the voxel `b` is handed back to its location. If the product
+    /// cannot be placed, as a multi-voxel one that does not fit, the space
+    /// is left unchanged.
+    pub fn react_bimolecular(
+        &mut self,
+        a: Coordinate,
+        b: Coordinate,
+        product: SpeciesID,
+    ) -> Result<ParticleID> {
+        let species_a = self
+            .get_species_id_at(a)?
+            .ok_or(Error::ParticleNotFound(a))?;
+        let species_b = self
+            .get_species_id_at(b)?
+            .ok_or(Error::ParticleNotFound(b))?;
+        self.check_species(product)?;
+        if !self.neighbors(a)?.contains(&b) {
+            return Err(Error::NotAdjacent(a, b));
+        }
+        if self.is_obstacle(species_a) || self.is_obstacle(species_b) {
+            return Err(Error::InvalidReaction);
+        }
+        if self.species_cache[species_a.0].location != self.species_cache[product.0].location {
+            return Err(Error::InvalidLocation(a, a));
+        }
+
+        let mut pid = None;
+        self.transaction(|space| {
+            space.vacate(b)?;
+            space.vacate(a)?;
+            pid = Some(space.place_particle(product, a)?);
+            Ok(())
+        })?;
+        Ok(pid.expect("set by the transaction"))
+    }
+
+    /// Fires `A -> keep + new_product` on the molecule at `reactant`: the
+    /// molecule turns into a molecule of `keep` on its voxel, as by
+    /// `change_species_at`, and a molecule of `new_product` is placed on a
+    /// random neighbor it can occupy. Returns the `ParticleID`s of both,
+    /// fresh ones for counted species. Fails with `InsufficientSpace` if no
+    /// neighbor is free for `new_product`, leaving the space unchanged then
+    /// as on any other error.
+    pub fn react_with_product_placement<R: Rng>(
+        &mut self,
+        reactant: Coordinate,
+        keep: SpeciesID,
+        new_product: SpeciesID,
+        rng: &mut R,
+    ) -> Result<(ParticleID, ParticleID)> {
+        self.get_species_id_at(reactant)?
+            .ok_or(Error::ParticleNotFound(reactant))?;
+        self.check_species(keep)?;
+        self.check_species(new_product)?;
+        let free: Vec<Coordinate> = self
+            .neighbors(reactant)?
+            .into_iter()
+            .filter(|&c| self.can_occupy(new_product, c))
+            .collect();
+        if free.is_empty() {
+            return Err(Error::InsufficientSpace(reactant));
+        }
+        let target = free[rng.gen_range(0..free.len())];
+        let mut pids = None;
+        self.transaction(|space| {
+            space.change_species_at(reactant, keep)?;
+            let kept = match &space.species_cache[keep.0].cache {
+                TrackingType::Tracking(entries) => entries
+                    .iter()
+                    .find(|(_, c)| *c == reactant)
+                    .map(|(pid, _)| *pid),
+                TrackingType::Count(_) => None,
+            };
+            let kept = kept.unwrap_or_else(|| space.next_pid());
+            pids = Some((kept, space.place_particle(new_product, target)?));
+            Ok(())
+        })?;
+        Ok(pids.expect("set by the transaction"))
+    }
+
+    /// Same as `react_bimolecular`, but returns `None` instead of an error
+    /// when the reaction cannot fire.
+    pub fn try_react_bimolecular(
+        &mut self,
+        a: Coordinate,
+        b: Coordinate,
+        product: SpeciesID,
+    ) -> Option<ParticleID> {
+        self.react_bimolecular(a, b, product).ok()
+    }
+
+    /// Checks that the voxels and the species caches agree, returning every
+    /// violation found. Meant as a post-condition in tests and debugging.
+    pub fn validate(&self) -> std::result::Result<(), Vec<String>> {
+        let mut violations = Vec::new();
+        let mut occupancy = vec![0; self.species_cache.len()];
+        let mut num_occupied = 0;
+        for (i, raw) in self.voxels.occupied() {
+            num_occupied += 1;
+            match decode(raw) {
+                Some(id) if id.0 >= self.species_cache.len() => {
+                    violations.push(format!("voxel {} holds unknown species {}", i, id.0));
+                }
+                Some(id) => occupancy[id.0] += 1,
+                None => {}
+            }
+        }
+
+        let mut pids = Vec::new();
+        for (id, cache) in self.species_cache.iter().enumerate() {
+            let name = &cache.species.0;
+            match cache.location {
+                Some(location) if location.0 >= self.species_cache.len() => {
+                    violations.push(format!(
+                        "{} is located on unknown species {}",
+                        name, location.0
+                    ));
+                }
+                Some(location) if location.0 == id => {
+                    violations.push(format!("{} is located on itself", name));
+                }
+                _ => {}
+            }
+
+            let count = match &cache.cache {
+                TrackingType::Tracking(entries) => {
+                    for (pid, coordinate) in entries {
+                        match self.get_species_id_at(*coordinate).ok() {
+                            Some(Some(held)) if held.0 == id => {}
+                            Some(held) => violations.push(format!(
+                                "{} tracks {:?} at voxel {} holding {:?}",
+                                name, pid, coordinate.0, held
+                            )),
+                            None => violations.push(format!(
+                                "{} tracks {:?} at out-of-range voxel {}",
+                                name, pid, coordinate.0
+                            )),
+                        }
+                        pids.push((*pid, id));
+                    }
+                    let mut coordinates: Vec<usize> = entries.iter().map(|(_, c)| c.0).collect();
+                    coordinates.sort_unstable();
+                    coordinates.dedup();
+                    if coordinates.len() != entries.len() {
+                        violations.push(format!("{} tracks a voxel more than once", name));
+                    }
+                    let mut anchors = HashMap::new();
+                    for &(pid, coordinate) in entries {
+                        anchors.entry(pid).or_insert(coordinate);
+                    }
+                    if anchors != entries.anchors {
+                        violations.push(format!("{} indexes its molecules wrongly", name));
+                    }
+                    entries.len()
+                }
+                TrackingType::Count(count) => *count,
+            };
+            if count != occupancy[id] {
+                violations.push(format!(
+                    "{} counts {} molecules but occupies {} voxels",
+                    name, count, occupancy[id]
+                ));
+            }
+        }
+
+        if self.total_molecules() != num_occupied {
+            violations.push(format!(
+                "{} molecules in total on {} occupied voxels",
+                self.total_molecules(),
+                num_occupied
+            ));
+        }
+
+        // A multi-voxel molecule has one entry per voxel, all sharing its
+        // ParticleID; any other repetition is a violation.
+        pids.sort_unstable_by_key(|(pid, _)| (pid.0, pid.1));
+        for run in pids.chunk_by(|a, b| a.0 == b.0) {
+            let (pid, id) = run[0];
+            let voxel_count = self.species_cache[id].voxel_count;
+            if run.iter().any(|&(_, other)| other != id) || run.len() != voxel_count {
+                violations.push(format!("{:?} is used by {} voxels", pid, run.len()));
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Returns one voxel per molecule of `species`, the anchor of multi-voxel
+    /// ones: in placement order for tracked species, which moves preserve,
+    /// and in coordinate order for counted ones.
+    pub fn coordinates_of(&self, species: SpeciesID) -> Result<Vec<Coordinate>> {
+        let species_cache = self.get_species_cache(species)?;
+        Ok(match &species_cache.cache {
+            TrackingType::Tracking(cache) => {
+                let mut coordinates: Vec<Coordinate> = Vec::with_capacity(cache.len());
+                let mut seen = Vec::new();
+                for (pid, c) in cache {
+                    if species_cache.voxel_count > 1 {
+                        if seen.contains(pid) {
+                            continue;
+                        }
+                        seen.push(*pid);
+                    }
+                    coordinates.push(*c);
+                }
+                coordinates
+            }
+            TrackingType::Count(_) => self
+                .occupied()
+                .filter(|&(_, id)| id == species)
+                .map(|(c, _)| c)
+                .collect(),
+        })
+    }
+
+    /// Attempts one hop for every molecule of `species` towards a uniformly
+    /// chosen neighbor, or one chosen with the weights of its diffusion
+    /// tensor in the `anisotropy` module. Hops leaving the lattice or into
+    /// a voxel other than the species' location are rejected, unless a
+    /// transition of the `surface` module or a reaction of the `collision`
+    /// module applies, or a sink of the `sink` module absorbs the molecule.
+    /// Molecules hop one after another, in the order of placement. The
+    /// remaining hops are accepted by the policy of the `hop` module.
+    pub fn walk<R: Rng>(&mut self, species: SpeciesID, rng: &mut R) -> Result<()> {
+        self.check_species(species)?;
+        self.walk_biased(species, &self.hop_weights(species), rng)
+    }
+
+    /// Same as `walk`, but the hop direction is drawn with probability
+    /// proportional to `weights`, indexed by `Direction::index`.
+    ///
+    /// On a 2D lattice the weights of the inter-layer directions must be
+    /// zero, or this fails with `OutOfPlane`.
+    pub fn walk_biased<R: Rng>(
+        &mut self,
+        species: SpeciesID,
+        weights: &[f64; 12],
+        rng: &mut R,
+    ) -> Result<()> {
+        self.check_species(species)?;
+        let total = self.check_weights(weights)?;
+        // One attempt per molecule, from the anchor of a multi-voxel one.
+        let molecules: Vec<(ParticleID, Coordinate)> = match &self.species_cache[species.0].cache {
+            TrackingType::Tracking(entries) => (0..entries.len())
+                .filter(|&i| entries.is_anchor(i))
+                .map(|i| entries[i])
+                .collect(),
+            TrackingType::Count(_) => self
+                .coordinates_of(species)?
+                .into_iter()
+                .map(|c| (ParticleID(u64::MAX, c.0 as u64), c))
+                .collect(),
+        };
+        let tracked = self.is_tracking(species);
+        for (pid, from) in molecules {
+            // An earlier hop may have exchanged the molecule with another of
+            // its species, or taken it away.
+            let from = if tracked {
+                match self.particle_coordinate(species, pid) {
+                    Some(c) => c,
+                    None => continue,
+                }
+            } else {
+                from
+            };
+            let direction = sample_direction(weights, total, rng);
+            if let Some(to) = self.neighbor(from, direction)? {
+                if self.try_absorb(species, from, to)? {
+                    continue;
+                }
+                if self.try_transition(species, from, to, rng)? {
+                    continue;
+                }
+                if self.try_collision(species, from, to, rng)? {
+                    continue;
+                }
+                self.policy_hop(pid, from, to, rng)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the sum of the hop `weights`, failing as `walk_biased` does
+    /// unless they can be sampled from.
+    fn check_weights(&self, weights: &[f64; 12]) -> Result<f64> {
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+            return Err(Error::InvalidWeights);
+        }
+        if self.planar
+            && Direction::ALL
+                .iter()
+                .any(|d| !d.is_in_plane() && weights[d.index()] > 0.0)
+        {
+            return Err(Error::OutOfPlane);
+        }
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return Err(Error::InvalidWeights);
+        }
+        Ok(total)
+    }
+
+    /// Attempts one hop of the molecule `pid` towards a uniformly chosen
+    /// neighbor, and returns whether it moved. A hop leaving the lattice or
+    /// into a voxel other than the species' location is rejected, leaving
+    /// the molecule where it was; sinks, transitions, collisions and
+    /// interactions do not apply. Fails with `UnknownParticle` if no
+    /// tracked molecule has `pid`, found as by `find_particle`.
+    pub fn hop_or_stay<R: Rng>(&mut self, pid: ParticleID, rng: &mut R) -> Result<bool> {
+        let (_, from) = self.find_particle(pid).ok_or(Error::UnknownParticle(pid))?;
+        let species = self.voxel(from).expect("a tracked molecule on its voxel");
+        let directions = self.directions();
+        let direction = directions[rng.gen_range(0..directions.len())];
+        match self.neighbor(from, direction)? {
+            Some(to) if self.can_occupy(species, to) => match self.move_particle(from, to) {
+                Ok(()) => Ok(true),
+                Err(Error::InvalidLocation(..)) => Ok(false),
+                Err(err) => Err(err),
+            },
+            _ => Ok(false),
+        }
+    }
+
+    /// Returns the directions a molecule can hop in: the 6 in-plane ones on
+    /// a 2D lattice, all 12 otherwise.
+    fn directions(&self) -> &'static [Direction] {
+        if self.planar {
+            &Direction::ALL[..6]
+        } else {
+            &Direction::ALL
+        }
+    }
+
+    /// Returns the weights of an unbiased walk over `directions`.
+    pub(crate) fn uniform_weights(&self) -> [f64; 12] {
+        let mut weights = [0.0; 12];
+        for direction in self.directions() {
+            weights[direction.index()] = 1.0;
+        }
+        weights
+    }
+}
+
+fn sample_direction<R: Rng>(weights: &[f64; 12], total: f64, rng: &mut R) -> Direction {
+    let mut threshold = rng.gen::<f64>() * total;
+    for &direction in &Direction::ALL {
+        let weight = weights[direction.index()];
+        if threshold < weight {
+            return direction;
+        }
+        threshold -= weight;
+    }
+    // Only reachable through rounding; fall back to the last allowed direction.
+    *Direction::ALL
+        .iter()
+        .rev()
+        .find(|d| weights[d.index()] > 0.0)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn it_works() {
+        assert_eq!(2 + 2, 4);
+    }
+
+    fn distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+        a.iter()
+            .zip(&b)
+            .map(|(x, y)| (x - y).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    }
+
+    #[test]
+    fn neighbors_touch() {
+        let space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        for i in 0..64 {
+            let c = Coordinate(i);
+            let p = space.coordinate_to_position(c).unwrap();
+            for &direction in &Direction::ALL {
+                if let Some(n) = space.neighbor(c, direction).unwrap() {
+                    let q = space.coordinate_to_position(n).unwrap();
+                    assert!((distance(p, q) - 2.0).abs() < 1e-9);
+                    assert_eq!(space.neighbor(n, direction.opposite()).unwrap(), Some(c));
+                }
+            }
+        }
+        let center = space.global_to_coordinate(1, 1, 1).unwrap();
+        assert_eq!(space.neighbors(center).unwrap().len(), 12);
+    }
+
+    #[test]
+    fn corner_neighbors_stay_in_bounds() {
+        let space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let neighbors = space.neighbors(Coordinate(0)).unwrap();
+        let expected = vec![
+            space.global_to_coordinate(0, 1, 0).unwrap(),
+            space.global_to_coordinate(1, 0, 0).unwrap(),
+            space.global_to_coordinate(0, 0, 1).unwrap(),
+        ];
+        assert_eq!(neighbors, expected);
+        for corner in 0..8 {
+            let (row, col, layer) = (corner & 1, corner >> 1 & 1, corner >> 2);
+            let c = space
+                .global_to_coordinate(3 * row, 3 * col, 3 * layer)
+                .unwrap();
+            let neighbors = space.neighbors(c).unwrap();
+            assert!(!neighbors.is_empty() && neighbors.len() < 12);
+            for n in neighbors {
+                assert!(space.contains(n));
+                assert!((space.distance(c, n).unwrap() - 2.0).abs() < 1e-9);
+            }
+        }
+        let single = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(1, 1, 1));
+        assert!(single.neighbors(Coordinate(0)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn position_round_trip() {
+        let space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 5, 6));
+        for i in 0..120 {
+            let [x, y, z] = space.coordinate_to_position(Coordinate(i)).unwrap();
+            let jittered = [x + 0.3, y - 0.2, z + 0.1];
+            assert_eq!(
+                space.position_to_coordinate(jittered).unwrap(),
+                Coordinate(i)
+            );
+        }
+        assert!(space.position_to_coordinate([-2.0, 0.0, 0.0]).is_err());
+        assert!(space.position_to_coordinate([0.0, 0.0, 100.0]).is_err());
+    }
+
+    #[test]
+    fn periodic_neighbors() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        assert_eq!(space.neighbors(Coordinate(0)).unwrap().len(), 3);
+        space.set_periodic(true).unwrap();
+        let [lx, ly, lz] = space.periodic_lengths();
+        for i in 0..64 {
+            let c = Coordinate(i);
+            let p = space.coordinate_to_position(c).unwrap();
+            let neighbors = space.neighbors(c).unwrap();
+            assert_eq!(neighbors.len(), 12);
+            for n in neighbors {
+                let q = space.coordinate_to_position(n).unwrap();
+                let mut d = [q[0] - p[0], q[1] - p[1], q[2] - p[2]];
+                for (d, l) in d.iter_mut().zip(&[lx, ly, lz]) {
+                    *d -= l * (*d / l).round();
+                }
+                assert!((distance(d, [0.0; 3]) - 2.0).abs() < 1e-9);
+            }
+        }
+        let mut odd = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(3, 4, 4));
+        assert!(odd.set_periodic(true).is_err());
+    }
+
+    #[test]
+    fn unwrapped_position_sums_steps() {
+        let r = 1.0;
+        let mut space = HCPLatticeSpace::new(r, HCPLatticeSize::new(4, 4, 4));
+        space.set_periodic(true).unwrap();
+        space.set_image_tracking(true);
+        let a = space.register_species(Species::new("A"), None);
+        let pid = space.place_particle(a, Coordinate(0)).unwrap();
+        let start = space.unwrapped_position(pid).unwrap();
+
+        // The displacement table documented on `Direction`.
+        let h = 2.0 * r * (2.0f64 / 3.0).sqrt();
+        let step = |direction: Direction, odd_layer: bool| -> [f64; 3] {
+            let s = if odd_layer { -1.0 } else { 1.0 };
+            let q = r / 3f64.sqrt();
+            let t = 3f64.sqrt() * r;
+            match direction {
+                Direction::East => [2.0 * r, 0.0, 0.0],
+                Direction::West => [-2.0 * r, 0.0, 0.0],
+                Direction::NorthEast => [r, t, 0.0],
+                Direction::NorthWest => [-r, t, 0.0],
+                Direction::SouthEast => [r, -t, 0.0],
+                Direction::SouthWest => [-r, -t, 0.0],
+                Direction::UpEast => [r, s * q, h],
+                Direction::UpWest => [-r, s * q, h],
+                Direction::UpApex => [0.0, -2.0 * s * q, h],
+                Direction::DownEast => [r, s * q, -h],
+                Direction::DownWest => [-r, s * q, -h],
+                Direction::DownApex => [0.0, -2.0 * s * q, -h],
+            }
+        };
+
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut coordinate = Coordinate(0);
+        let mut expected = start;
+        for _ in 0..2000 {
+            let direction = Direction::ALL[rng.gen_range(0..12)];
+            let odd_layer = space.coordinate_to_global(coordinate).unwrap().2 % 2 == 1;
+            let to = space.neighbor(coordinate, direction).unwrap().unwrap();
+            space.move_particle(coordinate, to).unwrap();
+            let delta = step(direction, odd_layer);
+            for axis in 0..3 {
+                expected[axis] += delta[axis];
+            }
+            coordinate = to;
+        }
+        let unwrapped = space.unwrapped_position(pid).unwrap();
+        for axis in 0..3 {
+            assert!((unwrapped[axis] - expected[axis]).abs() < 1e-9);
+        }
+        let [lx, ly, lz] = space.periodic_lengths();
+        assert!(expected[0].abs() > lx || expected[1].abs() > ly || expected[2].abs() > lz);
+
+        space.set_image_tracking(false);
+        let wrapped = space.coordinate_to_position(coordinate).unwrap();
+        assert_eq!(space.unwrapped_position(pid).unwrap(), wrapped);
+    }
+
+    #[test]
+    fn directions_are_stable() {
+        let space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let displacement = |row, col, layer, direction| {
+            let c = space.global_to_coordinate(row, col, layer).unwrap();
+            let n = space.neighbor(c, direction).unwrap().unwrap();
+            let p = space.coordinate_to_position(c).unwrap();
+            let q = space.coordinate_to_position(n).unwrap();
+            [q[0] - p[0], q[1] - p[1], q[2] - p[2]]
+        };
+        for &direction in &Direction::ALL {
+            let even = displacement(2, 2, 2, direction);
+            for &(row, col) in &[(2, 3), (3, 2), (3, 3)] {
+                let other = displacement(row, col, 2, direction);
+                assert!(distance(even, other) < 1e-9);
+            }
+            // Only the inter-layer directions mirror their y component.
+            let odd = displacement(2, 2, 3, direction);
+            let sign = if direction.index() < 6 { 1.0 } else { -1.0 };
+            assert!((even[0] - odd[0]).abs() < 1e-9);
+            assert!((even[1] - sign * odd[1]).abs() < 1e-9);
+            assert!((even[2] - odd[2]).abs() < 1e-9);
+        }
+    }
+
+    fn crowded_space() -> (HCPLatticeSpace, SpeciesID) {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species("A".to_string()), None);
+        for i in (0..64).step_by(3) {
+            space.place_particle(a, Coordinate(i)).unwrap();
+        }
+        (space, a)
+    }
+
+    #[test]
+    fn uniform_weights_reproduce_walk() {
+        let (mut walked, a) = crowded_space();
+        let (mut biased, _) = crowded_space();
+        let mut rng1 = StdRng::seed_from_u64(1);
+        let mut rng2 = StdRng::seed_from_u64(1);
+        for _ in 0..10 {
+            walked.walk(a, &mut rng1).unwrap();
+            biased.walk_biased(a, &[0.5; 12], &mut rng2).unwrap();
+        }
+        assert_eq!(walked.voxels, biased.voxels);
+        walked.validate().unwrap();
+    }
+
+    #[test]
+    fn bimolecular_reaction() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species("A".to_string()), None);
+        let b = space.register_species(Species("B".to_string()), None);
+        let c = space.register_species(Species("C".to_string()), None);
+        let origin = space.global_to_coordinate(1, 1, 1).unwrap();
+        let near = space.neighbor(origin, Direction::East).unwrap().unwrap();
+        let far = space.global_to_coordinate(3, 3, 3).unwrap();
+        space.place_particle(a, origin).unwrap();
+        space.place_particle(b, near).unwrap();
+        space.place_particle(b, far).unwrap();
+
+        assert!(matches!(
+            space.react_bimolecular(origin, far, c),
+            Err(Error::NotAdjacent(..))
+        ));
+        assert_eq!(space.try_react_bimolecular(origin, far, c), None);
+        let empty = space.neighbor(origin, Direction::West).unwrap().unwrap();
+        assert_eq!(space.try_react_bimolecular(origin, empty, c), None);
+
+        let pid = space.try_react_bimolecular(origin, near, c).unwrap();
+        assert_eq!(
+            space.find_particle(pid),
+            Some((&Species("C".to_string()), origin))
+        );
+        assert_eq!(space.get_species_id_at(near).unwrap(), None);
+        assert_eq!(space.try_react_bimolecular(origin, near, c), None);
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn bimolecular_reaction_rolls_back_a_product_that_does_not_fit() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species("A".to_string()), None);
+        let b = space.register_species(Species("B".to_string()), None);
+        let big = space
+            .register_footprint_species(Species("Big".to_string()), None, 1.0)
+            .id();
+        let corner = space.global_to_coordinate(0, 0, 0).unwrap();
+        let near = space.neighbors(corner).unwrap()[0];
+        space.place_particle(a, corner).unwrap();
+        space.place_particle(b, near).unwrap();
+        let before = space.clone();
+
+        assert!(matches!(
+            space.react_bimolecular(corner, near, big),
+            Err(Error::InsufficientSpace(..))
+        ));
+        assert_eq!(space.try_react_bimolecular(corner, near, big), None);
+        assert!(space.diff(&before).is_empty());
+        assert_eq!(space.get_species_id_at(corner).unwrap(), Some(a));
+        assert_eq!(space.get_species_id_at(near).unwrap(), Some(b));
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn biased_walk_follows_weights() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(2, 8, 2));
+        let a = space.register_species(Species("A".to_string()), None);
+        let pid = space.place_particle(a, Coordinate(0)).unwrap();
+        let mut weights = [0.0; 12];
+        weights[Direction::East.index()] = 1.0;
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..10 {
+            space.walk_biased(a, &weights, &mut rng).unwrap();
+        }
+        let (_, coordinate) = space.find_particle(pid).unwrap();
+        assert_eq!(space.coordinate_to_global(coordinate).unwrap(), (0, 7, 0));
+        assert!(space.walk_biased(a, &[0.0; 12], &mut rng).is_err());
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn enumerate_species() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), Some(membrane));
+        for i in 0..5 {
+            space.place_particle(membrane, Coordinate(i)).unwrap();
+        }
+        space.place_particle(a, Coordinate(10)).unwrap();
+        space.place_particle(b, Coordinate(0)).unwrap();
+        space.place_particle(b, Coordinate(1)).unwrap();
+
+        let listed: Vec<(SpeciesID, &str, usize)> = space
+            .species()
+            .map(|(id, species, count)| (id, species.name(), count))
+            .collect();
+        assert_eq!(listed, vec![(membrane, "M", 3), (a, "A", 1), (b, "B", 2)]);
+        assert_eq!(space.location_of(a).unwrap(), None);
+        assert_eq!(space.location_of(b).unwrap(), Some(membrane));
+
+        assert_eq!(space.species_id(&Species::new("B")), Some(b));
+        assert_eq!(space.species_id(&Species::new("X")), None);
+        let again = space.register_species(Species::new("A"), None);
+        assert_ne!(again, a);
+        assert_eq!(space.find_species("A"), Some(a));
+    }
+
+    #[test]
+    fn occupancy_follows_the_location() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), Some(membrane));
+        let wall = space.register_obstacle(Species::new("W"));
+        let (empty, surface, taken) = (Coordinate(0), Coordinate(1), Coordinate(2));
+        space.place_particle(membrane, surface).unwrap();
+        space.place_particle(a, taken).unwrap();
+
+        // Bulk species take vacant voxels, located ones their structure.
+        assert!(space.can_occupy(a, empty));
+        assert!(space.can_occupy(wall, empty));
+        assert!(space.can_occupy(b, surface));
+        assert!(!space.can_occupy(a, surface));
+        assert!(!space.can_occupy(b, empty));
+        assert!(!space.can_occupy(a, taken));
+        assert!(!space.can_occupy(b, taken));
+        assert!(!space.can_occupy(a, Coordinate(64)));
+
+        assert!(matches!(
+            space.place_particle(b, empty),
+            Err(Error::InvalidLocation(_, _))
+        ));
+        assert!(matches!(
+            space.move_particle(taken, surface),
+            Err(Error::InvalidLocation(_, _))
+        ));
+        space.place_particle(b, surface).unwrap();
+        assert!(!space.can_occupy(b, surface));
+        space.move_particle(taken, empty).unwrap();
+        assert!(space.can_occupy(a, taken));
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn nearest_empty_voxel() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let a = space.register_species(Species::new("A"), None);
+        let membrane = space.register_species(Species::new("M"), None);
+        let b = space.register_species(Species::new("B"), Some(membrane));
+        let center = space.global_to_coordinate(2, 2, 2).unwrap();
+        assert_eq!(space.nearest_empty(center, 0, a), Some(center));
+
+        space.place_particle(a, center).unwrap();
+        let shell = space.neighbors(center).unwrap();
+        for &c in &shell {
+            space.place_particle(a, c).unwrap();
+        }
+        assert_eq!(space.nearest_empty(center, 1, a), None);
+        let found = space.nearest_empty(center, 2, a).unwrap();
+        assert!(!shell.contains(&found) && found != center);
+        assert!(shell
+            .iter()
+            .any(|&c| space.neighbors(c).unwrap().contains(&found)));
+
+        let far = space.global_to_coordinate(5, 5, 5).unwrap();
+        space.place_particle(membrane, far).unwrap();
+        assert_eq!(space.nearest_empty(center, 3, b), None);
+        assert_eq!(space.nearest_empty(center, 10, b), Some(far));
+    }
+
+    #[test]
+    fn hop_distance_around_walls() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let wall = space.register_species(Species::new("W"), None);
+        let a = space.register_species(Species::new("A"), None);
+        let from = space.global_to_coordinate(2, 0, 2).unwrap();
+        let to = space.global_to_coordinate(2, 4, 2).unwrap();
+        let blocked = |id: SpeciesID| id == wall;
+        assert_eq!(space.hop_distance(from, from, &blocked), Some(0));
+        assert_eq!(space.hop_distance(from, to, &blocked), Some(4));
+
+        // A wall across column 2 cuts the lattice in two.
+        for c in space.coordinates().collect::<Vec<_>>() {
+            if space.coordinate_to_global(c).unwrap().1 == 2 {
+                space.place_particle(wall, c).unwrap();
+            }
+        }
+        assert_eq!(space.hop_distance(from, to, &blocked), None);
+        assert_eq!(space.hop_distance(from, to, &|_| false), Some(4));
+
+        // A hole makes a detour through it.
+        let hole = space.global_to_coordinate(0, 2, 2).unwrap();
+        space.remove_at(hole).unwrap();
+        let around = space.hop_distance(from, to, &blocked).unwrap();
+        assert!(around > 4);
+        assert_eq!(
+            around,
+            space.hop_distance(from, hole, &blocked).unwrap()
+                + space.hop_distance(hole, to, &blocked).unwrap()
+        );
+
+        space.place_particle(a, to).unwrap();
+        let walls_and_a = |id: SpeciesID| id == wall || id == a;
+        assert_eq!(space.hop_distance(from, to, &walls_and_a), None);
+        assert_eq!(space.hop_distance(to, from, &walls_and_a), Some(around));
+        assert_eq!(space.hop_distance(from, Coordinate(216), &blocked), None);
+    }
+
+    #[test]
+    fn count_neighbor_species() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        let center = space.global_to_coordinate(2, 2, 2).unwrap();
+        let neighbors = space.neighbors(center).unwrap();
+        for (i, &n) in neighbors.iter().enumerate().take(5) {
+            space.place_particle(if i < 3 { a } else { b }, n).unwrap();
+        }
+        space.place_particle(b, center).unwrap();
+        assert_eq!(
+            space.neighbor_species_counts(center).unwrap(),
+            vec![3, 2, 7]
+        );
+        assert_eq!(b.index(), 1);
+
+        let corner = space.neighbor_species_counts(Coordinate(0)).unwrap();
+        assert_eq!(
+            corner.iter().sum::<usize>(),
+            space.neighbors(Coordinate(0)).unwrap().len()
+        );
+        assert!(space.neighbor_species_counts(Coordinate(216)).is_err());
+    }
+
+    #[test]
+    fn lattice_from_lengths() {
+        let r = 5e-9;
+        let spacings = [2.0 * r, 3f64.sqrt() * r, 2.0 * r * (2f64 / 3.0).sqrt()];
+        for lengths in [
+            [1e-6, 1e-6, 4e-6],
+            [1e-7, 3.3e-8, 2.5e-7],
+            spacings,
+            [40.0 * r, 10.0 * spacings[1], 6.0 * spacings[2]],
+        ] {
+            let space = HCPLatticeSpace::from_lengths(r, lengths).unwrap();
+            let actual = space.actual_lengths();
+            for axis in 0..3 {
+                assert!(actual[axis] >= lengths[axis], "{:?} {:?}", actual, lengths);
+                assert!(actual[axis] - lengths[axis] < spacings[axis]);
+            }
+            assert_eq!(actual, space.periodic_lengths());
+        }
+        let cell = HCPLatticeSpace::from_lengths(r, [1e-6, 1e-6, 4e-6]).unwrap();
+        assert_eq!(cell.size(), HCPLatticeSize::new(116, 100, 490));
+        let exact = HCPLatticeSpace::from_lengths(0.5, [4.0, 3f64.sqrt() * 3.0, 1.0]).unwrap();
+        assert_eq!(exact.size(), HCPLatticeSize::new(6, 4, 2));
+
+        for (radius, lengths) in [
+            (r, [1e-6, 1e-6, spacings[2] / 2.0]),
+            (r, [0.0, 1e-6, 1e-6]),
+            (r, [1e-6, f64::NAN, 1e-6]),
+            (r, [1e-6, f64::INFINITY, 1e-6]),
+            (0.0, [1e-6; 3]),
+            (-r, [1e-6; 3]),
+        ] {
+            assert!(matches!(
+                HCPLatticeSpace::from_lengths(radius, lengths),
+                Err(Error::InvalidLength(_))
+            ));
+        }
+        assert!(matches!(
+            HCPLatticeSpace::from_lengths(1e-9, [1.0; 3]),
+            Err(Error::SizeOverflow)
+        ));
+    }
+
+    #[test]
+    fn box_geometry() {
+        let r = 0.5;
+        let space = HCPLatticeSpace::new(r, HCPLatticeSize::new(4, 5, 6));
+        let packed = 120.0 * 4.0 * 2f64.sqrt() * r.powi(3);
+        assert!((space.volume() / packed - 1.0).abs() < 1e-12);
+        assert!((space.volume() * 1e3 / space.volume_in_liters() - 1.0).abs() < 1e-12);
+        let [x, y, z] = space.periodic_lengths();
+        assert!((space.surface_area() - 2.0 * (x * y + y * z + z * x)).abs() < 1e-12);
+
+        let (lower, upper) = space.bounding_box();
+        for c in space.coordinates() {
+            let p = space.coordinate_to_position(c).unwrap();
+            for axis in 0..3 {
+                assert!(lower[axis] <= p[axis] - r + 1e-12);
+                assert!(p[axis] + r <= upper[axis] + 1e-12);
+            }
+        }
+        let h = 2.0 * r * (2f64 / 3.0).sqrt();
+        let expected_upper = [
+            r * (2.0 * 4.0 + 2.0) + r,
+            r * (3f64.sqrt() * 3.0 + 1.0 / 3f64.sqrt()) + r,
+            5.0 * h + r,
+        ];
+        for axis in 0..3 {
+            assert!((lower[axis] + r).abs() < 1e-12, "{:?}", lower);
+            assert!(
+                (upper[axis] - expected_upper[axis]).abs() < 1e-12,
+                "{:?}",
+                upper
+            );
+        }
+
+        let single = HCPLatticeSpace::new(r, HCPLatticeSize::new(1, 1, 1));
+        assert_eq!(single.bounding_box(), ([-r; 3], [r; 3]));
+        let planar = HCPLatticeSpace::new_2d(r, 2, 3);
+        let (lower, upper) = planar.bounding_box();
+        assert_eq!((lower[2], upper[2]), (-r, r));
+        assert!((upper[0] - r * 6.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn distances() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let center = space.global_to_coordinate(2, 3, 2).unwrap();
+        for n in space.neighbors(center).unwrap() {
+            assert!((space.distance(center, n).unwrap() - 2.0).abs() < 1e-9);
+            assert_eq!(space.lattice_distance(center, n).unwrap(), 1);
+        }
+        // Hops are 2r long, so no path is shorter than the straight line,
+        // and the hop count is a metric.
+        let coordinates: Vec<Coordinate> = space.coordinates().step_by(7).collect();
+        for &a in &coordinates {
+            for &b in &coordinates {
+                let hops = space.lattice_distance(a, b).unwrap();
+                assert_eq!(hops, space.lattice_distance(b, a).unwrap());
+                assert!(2.0 * hops as f64 >= space.distance(a, b).unwrap() - 1e-9);
+                assert!(
+                    hops <= space.lattice_distance(a, center).unwrap()
+                        + space.lattice_distance(center, b).unwrap()
+                );
+            }
+        }
+        assert!(space.distance(center, Coordinate(216)).is_err());
+        assert!(space.lattice_distance(Coordinate(216), center).is_err());
+
+        // Across the seam, the nearest image is the one next door.
+        let west = space.global_to_coordinate(2, 0, 2).unwrap();
+        let east = space.global_to_coordinate(2, 5, 2).unwrap();
+        assert!((space.distance(west, east).unwrap() - 10.0).abs() < 1e-9);
+        assert_eq!(space.lattice_distance(west, east).unwrap(), 5);
+        space.set_periodic(true).unwrap();
+        assert!((space.distance(west, east).unwrap() - 2.0).abs() < 1e-9);
+        assert_eq!(space.lattice_distance(west, east).unwrap(), 1);
+        let bottom = space.global_to_coordinate(0, 0, 0).unwrap();
+        let top = space.global_to_coordinate(0, 0, 5).unwrap();
+        assert!(space.distance(bottom, top).unwrap() < 2.5);
+    }
+
+    #[test]
+    fn particles_within_match_a_scan() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(8, 10, 6));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        for c in space.coordinates().collect::<Vec<_>>() {
+            match c.0 % 5 {
+                0 => drop(space.place_particle(a, c).unwrap()),
+                2 => drop(space.place_particle(b, c).unwrap()),
+                _ => {}
+            }
+        }
+        let corner = space.global_to_coordinate(0, 0, 0).unwrap();
+        let middle = space.global_to_coordinate(4, 5, 3).unwrap();
+        let edge = space.global_to_coordinate(7, 9, 2).unwrap();
+        for periodic in [false, true] {
+            space.set_periodic(periodic).unwrap();
+            for &center in &[corner, middle, edge] {
+                for &radius in &[0.0, 2.0, 3.5, 5.0, 40.0] {
+                    for filter in [None, Some(a)] {
+                        let found: Vec<Coordinate> = space
+                            .particles_within(center, radius, filter)
+                            .unwrap()
+                            .into_iter()
+                            .map(|(_, _, c)| c)
+                            .collect();
+                        let scanned: Vec<Coordinate> = space
+                            .occupied()
+                            .filter(|&(_, id)| filter.is_none_or(|f| f == id))
+                            .map(|(c, _)| c)
+                            .filter(|&c| space.distance(center, c).unwrap() <= radius)
+                            .collect();
+                        let mut found_sorted = found.clone();
+                        found_sorted.sort_by_key(|c| c.0);
+                        assert_eq!(
+                            found_sorted, scanned,
+                            "{:?} {} {}",
+                            center, radius, periodic
+                        );
+                    }
+                }
+            }
+        }
+
+        // Across the seam, the corner sees the far edge next door.
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(8, 10, 6));
+        let a = space.register_species(Species::new("A"), None);
+        let far = space.global_to_coordinate(0, 9, 0).unwrap();
+        let pid = space.place_particle(a, far).unwrap();
+        assert!(space
+            .particles_within(corner, 2.1, None)
+            .unwrap()
+            .is_empty());
+        space.set_periodic(true).unwrap();
+        let within = space.particles_within(corner, 2.1, None).unwrap();
+        assert_eq!(within, vec![(pid, &Species::new("A"), far)]);
+    }
+
+    #[test]
+    fn switch_tracking() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        space.set_periodic(true).unwrap();
+        space.set_image_tracking(true);
+        let a = space.register_species(Species::new("A"), None);
+        let big = space.register_multi_voxel_species(Species::new("R"), None, 3);
+        for i in [5, 1, 9, 30] {
+            space.place_particle(a, Coordinate(i)).unwrap();
+        }
+        space.place_particle(big.id(), Coordinate(40)).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20 {
+            space.walk(a, &mut rng).unwrap();
+        }
+        let old = space.particles_of(a).unwrap();
+        let coordinates = space.coordinates_of(a).unwrap();
+
+        space.set_tracking(a, false).unwrap();
+        assert!(!space.is_tracking(a));
+        space.validate().unwrap();
+        assert_eq!(space.num_molecules(a).unwrap(), 4);
+        assert!(matches!(
+            space.particles_of(a),
+            Err(Error::TrackingRequired(_))
+        ));
+        assert!(space.images.as_ref().unwrap().is_empty());
+        space.set_tracking(a, false).unwrap();
+
+        space.set_tracking(a, true).unwrap();
+        space.validate().unwrap();
+        let mut sorted = coordinates.clone();
+        sorted.sort_by_key(|c| c.0);
+        assert_eq!(space.coordinates_of(a).unwrap(), sorted);
+        let fresh = space.particles_of(a).unwrap();
+        assert_eq!(fresh.len(), 4);
+        assert!(fresh.iter().all(|pid| !old.contains(pid)));
+        for (&pid, &c) in fresh.iter().zip(&sorted) {
+            assert_eq!(space.find_particle(pid).unwrap().1, c);
+        }
+
+        assert!(matches!(
+            space.set_tracking(big.id(), false),
+            Err(Error::TrackingRequired(_))
+        ));
+        assert!(space.is_tracking(big.id()));
+    }
+
+    #[test]
+    fn place_many_is_atomic() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), Some(membrane));
+        let pids = space
+            .place_many(&[
+                (membrane, Coordinate(3)),
+                (membrane, Coordinate(1)),
+                (a, Coordinate(1)),
+            ])
+            .unwrap();
+        assert_eq!(pids.len(), 3);
+        assert_eq!(space.find_particle(pids[2]).unwrap().1, Coordinate(1));
+        assert_eq!(space.find_particle(pids[0]).unwrap().1, Coordinate(3));
+
+        let voxels = space.voxels.clone();
+        let species_cache = space.species_cache.clone();
+        for bad in [
+            vec![(membrane, Coordinate(5)), (a, Coordinate(6))],
+            vec![(membrane, Coordinate(5)), (membrane, Coordinate(64))],
+            vec![(membrane, Coordinate(5)), (membrane, Coordinate(5))],
+        ] {
+            assert!(space.place_many(&bad).is_err());
+            assert_eq!(space.voxels, voxels);
+            assert_eq!(space.species_cache, species_cache);
+        }
+        // The IDs of the undone placements are not handed out again.
+        let next = space.place_particle(membrane, Coordinate(7)).unwrap();
+        assert_eq!(next, ParticleID(0, 6));
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn particles_are_looked_up_by_id() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        let pids: Vec<ParticleID> = (0..3)
+            .map(|i| space.place_particle(a, Coordinate(i)).unwrap())
+            .collect();
+        space.move_particle(Coordinate(2), Coordinate(5)).unwrap();
+        space.remove_at(Coordinate(0)).unwrap();
+        assert_eq!(space.particle_coordinate(a, pids[0]), None);
+        assert_eq!(space.particle_coordinate(a, pids[1]), Some(Coordinate(1)));
+        assert_eq!(space.particle_coordinate(a, pids[2]), Some(Coordinate(5)));
+        assert_eq!(space.particle_coordinate(b, pids[2]), None);
+        space.change_species_at(Coordinate(5), b).unwrap();
+        assert_eq!(space.particle_coordinate(a, pids[2]), None);
+        assert_eq!(space.particle_coordinate(b, pids[2]), Some(Coordinate(5)));
+
+        let big = space.register_multi_voxel_species(Species::new("Big"), None, 3);
+        let pid = space.place_particle(big.id(), Coordinate(21)).unwrap();
+        space.move_multi_voxel(pid, Direction::East).unwrap();
+        let anchor = space.voxels_of(pid)[0];
+        assert_ne!(anchor, Coordinate(21));
+        assert_eq!(space.particle_coordinate(big.id(), pid), Some(anchor));
+        assert_eq!(space.find_particle(pid).unwrap().1, anchor);
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn remove_molecules() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), Some(membrane));
+        let b = space.register_species(Species::new("B"), None);
+        space.get_species_cache_mut(b).unwrap().cache = TrackingType::Count(0);
+        space.place_particle(membrane, Coordinate(1)).unwrap();
+        let pid = space.place_particle(a, Coordinate(1)).unwrap();
+        space.place_particle(b, Coordinate(2)).unwrap();
+
+        assert_eq!(space.remove_at(Coordinate(1)).unwrap(), (a, Some(pid)));
+        assert_eq!(space.species_at(Coordinate(1)).unwrap(), Some(membrane));
+        assert!(space.find_particle(pid).is_none());
+        assert_eq!(space.remove_at(Coordinate(2)).unwrap(), (b, None));
+        assert_eq!(space.num_molecules(b).unwrap(), 0);
+        assert!(matches!(
+            space.remove_at(Coordinate(2)),
+            Err(Error::ParticleNotFound(Coordinate(2)))
+        ));
+        assert!(matches!(
+            space.remove_at(Coordinate(64)),
+            Err(Error::OutOfRange(_))
+        ));
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn clear_species_keeps_it_registered() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        space.set_image_tracking(true);
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_counted_species(Species::new("B"), None);
+        let pids: Vec<ParticleID> = (0..10)
+            .map(|i| space.place_particle(a, Coordinate(2 * i)).unwrap())
+            .collect();
+        for i in 0..5 {
+            space.place_particle(b, Coordinate(2 * i + 1)).unwrap();
+        }
+
+        assert_eq!(space.clear_species(&Species::new("A")).unwrap(), 10);
+        assert_eq!(space.num_molecules(a).unwrap(), 0);
+        assert!(pids.iter().all(|&pid| space.find_particle(pid).is_none()));
+        assert!(space.coordinates_of(a).unwrap().is_empty());
+        assert_eq!(space.find_species("A"), Some(a));
+        assert_eq!(space.clear_species(&Species::new("B")).unwrap(), 5);
+        assert_eq!(space.num_molecules(b).unwrap(), 0);
+        assert_eq!(space.occupied().count(), 0);
+        assert_eq!(space.clear_species(&Species::new("B")).unwrap(), 0);
+        assert!(matches!(
+            space.clear_species(&Species::new("C")),
+            Err(Error::SpeciesNotFound(_))
+        ));
+        space.place_particle(a, Coordinate(0)).unwrap();
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn clear_structure_with_dependents() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), Some(membrane));
+        let b = space.register_species(Species::new("B"), Some(membrane));
+        for i in 0..16 {
+            space.place_particle(membrane, Coordinate(i)).unwrap();
+        }
+        space.place_particle(a, Coordinate(3)).unwrap();
+        match space.clear_species(&Species::new("M")) {
+            Err(Error::StructureInUse(dependents)) => {
+                assert_eq!(dependents, vec![Species::new("A")])
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(space.num_molecules(membrane).unwrap(), 15);
+        assert_eq!(space.num_molecules(b).unwrap(), 0);
+
+        space.clear_species(&Species::new("A")).unwrap();
+        assert_eq!(space.species_at(Coordinate(3)).unwrap(), Some(membrane));
+        assert_eq!(space.clear_species(&Species::new("M")).unwrap(), 16);
+        assert_eq!(space.occupied().count(), 0);
+        space.validate().unwrap();
+    }
+
+    /// Builds a membrane layer, scatters molecules on it and in the bulk with
+    /// the seed, walks them and returns the occupied voxels.
+    fn scatter_and_walk(space: &mut HCPLatticeSpace, seed: u64) -> Vec<(Coordinate, SpeciesID)> {
+        use rand::Rng;
+
+        let (membrane, a, a_s) = (SpeciesID(0), SpeciesID(1), SpeciesID(2));
+        if space.num_molecules(membrane).unwrap() == 0 {
+            for c in space.coordinates().take(36).collect::<Vec<_>>() {
+                space.place_particle(membrane, c).unwrap();
+            }
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+        for c in space.coordinates().collect::<Vec<_>>() {
+            if rng.gen::<f64>() < 0.2 {
+                let species = if c.0 < 36 { a_s } else { a };
+                space.place_particle(species, c).unwrap();
+            }
+        }
+        for _ in 0..20 {
+            space.walk(a, &mut rng).unwrap();
+            space.walk(a_s, &mut rng).unwrap();
+        }
+        space.occupied().collect()
+    }
+
+    fn sweep_space() -> HCPLatticeSpace {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        space.set_image_tracking(true);
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), None);
+        let a_s = space.register_species(Species::new("As"), Some(membrane));
+        let info = MoleculeInfo {
+            radius: 1.0,
+            diffusion_coefficient: 1.0,
+        };
+        space.set_molecule_info(a, info).unwrap();
+        space.set_molecule_info(a_s, info).unwrap();
+        space.add_sink(vec![Coordinate(200)], None).unwrap();
+        space
+    }
+
+    #[test]
+    fn cleared_space_runs_like_a_fresh_one() {
+        let mut fresh = sweep_space();
+        let expected = scatter_and_walk(&mut fresh, 1);
+
+        let mut space = sweep_space();
+        scatter_and_walk(&mut space, 7);
+        space.clear_particles();
+        space.validate().unwrap();
+        assert_eq!(space.num_molecules(SpeciesID(0)).unwrap(), 36);
+        assert_eq!(space.occupied().count(), 36);
+        assert_eq!(space.sink_counts(), vec![0]);
+        assert_eq!(
+            space
+                .molecule_info(SpeciesID(1))
+                .unwrap()
+                .diffusion_coefficient,
+            1.0
+        );
+        assert_eq!(scatter_and_walk(&mut space, 1), expected);
+        assert!(space.diff(&fresh).is_empty(), "{}", space.diff(&fresh));
+
+        space.reset();
+        space.validate().unwrap();
+        assert_eq!(space.occupied().count(), 0);
+        assert!(space.species().all(|(_, _, count)| count == 0));
+        assert_eq!(space.num_species(), 3);
+        assert_eq!(scatter_and_walk(&mut space, 1), expected);
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn change_species_in_place() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        let a_s = space.register_species(Species::new("As"), Some(membrane));
+        let wall = space.register_obstacle(Species::new("W"));
+        let pid = space.place_particle(a, Coordinate(5)).unwrap();
+        space.place_particle(a, Coordinate(6)).unwrap();
+        space.place_particle(membrane, Coordinate(7)).unwrap();
+        space.place_particle(a_s, Coordinate(7)).unwrap();
+        space.place_particle(wall, Coordinate(8)).unwrap();
+
+        space.change_species_at(Coordinate(5), b).unwrap();
+        assert_eq!(space.species_at(Coordinate(5)).unwrap(), Some(b));
+        assert_eq!(
+            space.find_particle(pid),
+            Some((&Species::new("B"), Coordinate(5)))
+        );
+        assert_eq!(
+            (
+                space.num_molecules(a).unwrap(),
+                space.num_molecules(b).unwrap()
+            ),
+            (1, 1)
+        );
+        space.set_tracking(b, false).unwrap();
+        space.change_species_at(Coordinate(6), b).unwrap();
+        assert_eq!(space.num_molecules(b).unwrap(), 2);
+        space.change_species_at(Coordinate(5), a).unwrap();
+        assert_eq!(space.particles_of(a).unwrap().len(), 1);
+
+        assert!(matches!(
+            space.change_species_at(Coordinate(7), a),
+            Err(Error::InvalidLocation(..))
+        ));
+        assert!(matches!(
+            space.change_species_at(Coordinate(5), a_s),
+            Err(Error::InvalidLocation(..))
+        ));
+        assert!(matches!(
+            space.change_species_at(Coordinate(8), a),
+            Err(Error::InvalidReaction)
+        ));
+        assert!(matches!(
+            space.change_species_at(Coordinate(9), a),
+            Err(Error::ParticleNotFound(_))
+        ));
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn size_overflow() {
+        // 2^33 voxels overflow a 32-bit usize and exceed the default limit
+        // on 64-bit targets.
+        let size = HCPLatticeSize::new(2048, 2048, 2048);
+        assert!(matches!(
+            HCPLatticeSpace::try_new(1.0, size),
+            Err(Error::SizeOverflow)
+        ));
+        let size = HCPLatticeSize::new(usize::MAX / 2, 3, 1);
+        assert_eq!(size.num_voxels(), None);
+        assert!(matches!(
+            HCPLatticeSpace::try_new_with_limit(1.0, size, usize::MAX),
+            Err(Error::SizeOverflow)
+        ));
+        let size = HCPLatticeSize::new(10, 10, 10);
+        assert!(HCPLatticeSpace::try_new_with_limit(1.0, size, 999).is_err());
+        let size = HCPLatticeSize::new(10, 10, 10);
+        let space = HCPLatticeSpace::try_new_with_limit(1.0, size, 1000).unwrap();
+        assert_eq!(space.num_voxels(), 1000);
+    }
+
+    #[test]
+    fn reserve_tracking() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(10, 10, 10));
+        let a = space.register_species(Species::new("A"), None);
+        space.reserve(a, 500).unwrap();
+        let capacity = match &space.species_cache[a.0].cache {
+            TrackingType::Tracking(entries) => entries.capacity(),
+            TrackingType::Count(_) => unreachable!(),
+        };
+        assert!(capacity >= 500);
+        for i in 0..500 {
+            space.place_particle(a, Coordinate(i)).unwrap();
+        }
+        match &space.species_cache[a.0].cache {
+            TrackingType::Tracking(entries) => assert_eq!(entries.capacity(), capacity),
+            TrackingType::Count(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn many_species() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let ids: Vec<SpeciesID> = (0..70_000)
+            .map(|i| space.register_species(Species::new(&i.to_string()), None))
+            .collect();
+        let last = *ids.last().unwrap();
+        space.place_particle(last, Coordinate(5)).unwrap();
+        space.place_particle(ids[65_536], Coordinate(6)).unwrap();
+        assert_eq!(space.species_at(Coordinate(5)).unwrap(), Some(last));
+        assert_eq!(space.species_at(Coordinate(6)).unwrap(), Some(ids[65_536]));
+        space.move_particle(Coordinate(5), Coordinate(4)).unwrap();
+        assert_eq!(space.species_at(Coordinate(4)).unwrap(), Some(last));
+        assert_eq!(space.species_at(Coordinate(5)).unwrap(), None);
+        assert_eq!(decode(encode(Some(last))), Some(last));
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn planar_lattice() {
+        let mut space = HCPLatticeSpace::new_2d(1.0, 4, 6);
+        assert!(space.is_2d());
+        assert!(!HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 6, 1)).is_2d());
+        space.set_periodic(true).unwrap();
+        let c = space.global_to_coordinate(1, 2, 0).unwrap();
+        let neighbors = space.neighbors(c).unwrap();
+        assert_eq!(neighbors.len(), 6);
+        let center = space.coordinate_to_position(c).unwrap();
+        for n in neighbors {
+            let p = space.coordinate_to_position(n).unwrap();
+            assert_eq!(p[2], 0.0);
+            let d2: f64 = (0..3).map(|i| (p[i] - center[i]).powi(2)).sum();
+            assert!((d2 - 4.0).abs() < 1e-9);
+        }
+        assert!(matches!(
+            space.neighbor(c, Direction::UpApex),
+            Err(Error::OutOfPlane)
+        ));
+
+        let a = space.register_species(Species::new("A"), None);
+        space.place_particle(a, c).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut weights = [0.0; 12];
+        weights[Direction::DownEast.index()] = 1.0;
+        assert!(matches!(
+            space.walk_biased(a, &weights, &mut rng),
+            Err(Error::OutOfPlane)
+        ));
+        for _ in 0..100 {
+            space.walk(a, &mut rng).unwrap();
+        }
+        assert_eq!(space.num_molecules(a).unwrap(), 1);
+    }
+
+    #[test]
+    fn scan_coordinates() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(2, 3, 4));
+        let a = space.register_species(Species::new("A"), None);
+        space.place_particle(a, Coordinate(7)).unwrap();
+        assert_eq!(space.coordinates().count(), 24);
+        assert_eq!(space.coordinates().last(), Some(Coordinate(23)));
+        let scanned: Vec<(Coordinate, SpeciesID)> = space
+            .coordinates()
+            .filter_map(|c| space.species_at(c).unwrap().map(|id| (c, id)))
+            .collect();
+        assert_eq!(scanned, space.occupied().collect::<Vec<_>>());
+        assert_eq!(space.total_molecules(), 1);
+        assert!(space.species_at(Coordinate(24)).is_err());
+        assert!(space.contains(Coordinate(23)));
+        assert!(!space.contains(Coordinate(24)));
+        assert!(matches!(
+            space.coordinate_to_global(Coordinate(24)),
+            Err(Error::OutOfRange(Coordinate(24)))
+        ));
+    }
+
+    #[test]
+    fn validate_reports_all_violations() {
+        let (mut space, a) = crowded_space();
+        space.validate().unwrap();
+        space.set_voxel(Coordinate(1), Some(a));
+        space.set_voxel(Coordinate(0), None);
+        let violations = space.validate().unwrap_err();
+        assert_eq!(violations.len(), 1);
+        if let TrackingType::Tracking(cache) = &mut space.species_cache[a.0].cache {
+            let (pid, coordinate) = cache[1];
+            cache.push(pid, coordinate);
+        }
+        let violations = space.validate().unwrap_err();
+        assert_eq!(violations.len(), 5);
+        assert!(violations.iter().any(|v| v.contains("in total")));
+        if let TrackingType::Tracking(cache) = &mut space.species_cache[a.0].cache {
+            cache.anchors.clear();
+        }
+        let violations = space.validate().unwrap_err();
+        assert_eq!(violations.len(), 6);
+        assert!(violations.iter().any(|v| v.contains("indexes")));
+    }
+
+    #[test]
+    fn catalytic_products_on_free_neighbors() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let wall = space.register_obstacle(Species::new("W"));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        let c = space.register_species(Species::new("C"), None);
+        let origin = space.global_to_coordinate(2, 2, 2).unwrap();
+        let neighbors = space.neighbors(origin).unwrap();
+        for &neighbor in &neighbors[1..] {
+            space.place_particle(wall, neighbor).unwrap();
+        }
+        let pid = space.place_particle(a, origin).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let (kept, placed) = space
+            .react_with_product_placement(origin, a, b, &mut rng)
+            .unwrap();
+        assert_eq!(kept, pid);
+        assert_eq!(space.species_at(neighbors[0]).unwrap(), Some(b));
+        assert_eq!(space.find_particle(placed).unwrap().1, neighbors[0]);
+
+        let before = space.clone();
+        assert!(matches!(
+            space.react_with_product_placement(origin, c, b, &mut rng),
+            Err(Error::InsufficientSpace(c)) if c == origin
+        ));
+        assert_eq!(space.voxels, before.voxels);
+        assert_eq!(space.species_cache, before.species_cache);
+
+        space.remove_at(neighbors[0]).unwrap();
+        let (kept, _) = space
+            .react_with_product_placement(origin, c, b, &mut rng)
+            .unwrap();
+        assert_eq!(kept, pid);
+        assert_eq!(space.species_at(origin).unwrap(), Some(c));
+        assert!(matches!(
+            space.react_with_product_placement(Coordinate(215), a, b, &mut rng),
+            Err(Error::ParticleNotFound(Coordinate(215)))
+        ));
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn counted_species_have_no_identities() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_counted_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        assert!(!space.is_tracking(a));
+        let pids: Vec<ParticleID> = (0..3)
+            .map(|i| space.place_particle(a, Coordinate(i)).unwrap())
+            .collect();
+        space.place_particle(b, Coordinate(10)).unwrap();
+        assert_eq!(space.remove_at(Coordinate(1)).unwrap(), (a, None));
+        assert_eq!(space.num_molecules(a).unwrap(), 2);
+        assert_eq!(
+            space.coordinates_of(a).unwrap(),
+            vec![Coordinate(0), Coordinate(2)]
+        );
+        space.validate().unwrap();
+
+        assert!(matches!(
+            space.particles_of(a),
+            Err(Error::TrackingRequired(ref s)) if s.name() == "A"
+        ));
+        assert!(matches!(
+            space.particles_sorted(a),
+            Err(Error::TrackingRequired(_))
+        ));
+        assert!(pids.iter().all(|&pid| space.find_particle(pid).is_none()));
+        assert!(space.particles().all(|(_, species, _)| species == b));
+        assert_eq!(space.particles_of(b).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn clones_are_independent() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let a = space.register_species(Species::new("A"), None);
+        space
+            .set_molecule_info(
+                a,
+                MoleculeInfo {
+                    radius: 1.0,
+                    diffusion_coefficient: 1.0,
+                },
+            )
+            .unwrap();
+        for c in space.coordinates().step_by(9).collect::<Vec<_>>() {
+            space.place_particle(a, c).unwrap();
+        }
+        let original = space.clone();
+
+        let mut ensemble: Vec<HCPLatticeSpace> = (0..3).map(|_| space.clone()).collect();
+        for (seed, member) in ensemble.iter_mut().enumerate() {
+            let mut rng = StdRng::seed_from_u64(seed as u64);
+            for _ in 0..10 {
+                member.walk(a, &mut rng).unwrap();
+            }
+            member
+                .remove_at(member.coordinates_of(a).unwrap()[0])
+                .unwrap();
+            member.register_species(Species::new("B"), None);
+            member.set_potential(Coordinate(0), 1.0).unwrap();
+            member.validate().unwrap();
+        }
+        assert_ne!(ensemble[0].voxels, ensemble[1].voxels);
+        assert_eq!(space.voxels, original.voxels);
+        assert_eq!(space.species_cache, original.species_cache);
+        assert_eq!(space.num_molecules(a).unwrap(), 24);
+        assert_eq!(space.find_species("B"), None);
+        assert_eq!(space.potential(Coordinate(0)), 0.0);
+    }
+
+    #[test]
+    fn conversions_keep_counts_and_voxels() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(5, 5, 5));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), Some(membrane));
+        for c in space.coordinates().take(25).collect::<Vec<_>>() {
+            space.place_particle(membrane, c).unwrap();
+        }
+        for c in space.coordinates().take(25).step_by(3).collect::<Vec<_>>() {
+            space.place_particle(a, c).unwrap();
+        }
+        let voxels = space.voxels.clone();
+        let (num_a, num_membrane) = (
+            space.num_molecules(a).unwrap(),
+            space.num_molecules(membrane).unwrap(),
+        );
+
+        space.convert_to_counted(&Species::new("A")).unwrap();
+        assert!(!space.is_tracking(a));
+        assert_eq!(space.num_molecules(a).unwrap(), num_a);
+        assert_eq!(space.num_molecules(membrane).unwrap(), num_membrane);
+        assert_eq!(space.voxels, voxels);
+        space.validate().unwrap();
+
+        let pids = space.convert_to_tracked(&Species::new("A")).unwrap();
+        assert_eq!(pids.len(), num_a);
+        assert_eq!(space.num_molecules(a).unwrap(), num_a);
+        assert_eq!(space.voxels, voxels);
+        space.validate().unwrap();
+        assert_eq!(space.convert_to_tracked(&Species::new("A")).unwrap(), pids);
+
+        assert!(matches!(
+            space.convert_to_counted(&Species::new("B")),
+            Err(Error::SpeciesNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn particle_ids_are_never_reused() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species::new("A"), None);
+        let mut seen = HashSet::new();
+        for round in 0..5 {
+            for i in 0..10 {
+                let pid = space.place_particle(a, Coordinate(i)).unwrap();
+                assert!(seen.insert(pid), "{:?} in round {}", pid, round);
+            }
+            for i in 0..10 {
+                space.remove_at(Coordinate(i)).unwrap();
+            }
+        }
+        assert!(seen.iter().all(|pid| pid.lot() == 0));
+
+        space.place_particle(a, Coordinate(0)).unwrap();
+        let mut forks = vec![space.clone(), space.clone()];
+        forks.push(forks[0].clone());
+        let mut lots = HashSet::new();
+        for fork in forks.iter_mut().chain(std::iter::once(&mut space)) {
+            let pid = fork.place_particle(a, Coordinate(1)).unwrap();
+            assert!(seen.insert(pid), "{:?}", pid);
+            assert!(lots.insert(pid.lot()));
+            assert!(fork.find_particle(pid).is_some());
+        }
+        assert_eq!(forks[0].particles_of(a).unwrap()[0].serial(), 50);
+
+        space.pids.serial = u64::MAX;
+        let wrapped = space.place_particle(a, Coordinate(2)).unwrap();
+        assert_eq!(wrapped.serial(), 0);
+        assert!(!lots.contains(&wrapped.lot()));
+        let mut sorted: Vec<ParticleID> = seen.into_iter().collect();
+        sorted.sort();
+        assert!(sorted
+            .windows(2)
+            .all(|w| (w[0].lot(), w[0].serial()) < (w[1].lot(), w[1].serial())));
+    }
+
+    #[test]
+    fn lots_keep_runs_apart() {
+        let run = |lot: u64| {
+            let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+            let a = space.register_species(Species::new("A"), None);
+            space.set_lot(lot).unwrap();
+            let pids: Vec<ParticleID> = (0..5)
+                .map(|i| space.place_particle(a, Coordinate(i)).unwrap())
+                .collect();
+            (space, pids)
+        };
+        let (mut first, pids) = run(7);
+        let (_, others) = run(8);
+        assert_eq!(
+            pids.iter()
+                .map(|pid| (pid.lot(), pid.serial()))
+                .collect::<Vec<_>>(),
+            (0..5).map(|serial| (7, serial)).collect::<Vec<_>>()
+        );
+        assert!(others
+            .iter()
+            .all(|pid| pid.lot() == 8 && !pids.contains(pid)));
+
+        let a = first.find_species("A").unwrap();
+        first.set_lot(7).unwrap();
+        assert_eq!(
+            first.place_particle(a, Coordinate(5)).unwrap(),
+            ParticleID(7, 5)
+        );
+        let mut fork = first.clone();
+        let forked = fork.place_particle(a, Coordinate(6)).unwrap();
+        assert!(forked.lot() > 7);
+        first.set_lot(2).unwrap();
+        assert_eq!(
+            first.place_particle(a, Coordinate(6)).unwrap(),
+            ParticleID(2, 0)
+        );
+        assert!(matches!(
+            first.set_lot(forked.lot()),
+            Err(Error::LotInUse(lot)) if lot == forked.lot()
+        ));
+        fork.set_lot(7).unwrap();
+        assert_eq!(
+            fork.place_particle(a, Coordinate(7)).unwrap(),
+            ParticleID(7, 6)
+        );
+        drop(fork);
+        first.set_lot(forked.lot()).unwrap();
+        assert_eq!(
+            first.place_particle(a, Coordinate(8)).unwrap(),
+            ParticleID(forked.lot(), forked.serial() + 1)
+        );
+        first.validate().unwrap();
+    }
+
+    #[test]
+    fn returning_to_a_lot_never_reissues_ids() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species::new("A"), None);
+        let first = space.place_particle(a, Coordinate(0)).unwrap();
+        space.set_lot(1).unwrap();
+        space.set_lot(0).unwrap();
+        let second = space.place_particle(a, Coordinate(1)).unwrap();
+        assert_ne!(second, first);
+        assert_eq!(second, ParticleID(0, 1));
+    }
+
+    #[test]
+    fn within_radius_agrees_with_a_scan() {
+        for periodic in [false, true] {
+            let r = 0.5;
+            let mut space = HCPLatticeSpace::new(r, HCPLatticeSize::new(12, 10, 8));
+            space.set_periodic(periodic).unwrap();
+            let a = space.register_species(Species::new("A"), None);
+            let b = space.register_species(Species::new("B"), None);
+            for c in space.coordinates().step_by(3).collect::<Vec<_>>() {
+                let species = if c.0 % 2 == 0 { a } else { b };
+                space.place_particle(species, c).unwrap();
+            }
+            for &(center, radius) in &[
+                ([4.0, 4.0, 3.0], 2.0),
+                ([0.2, 0.1, 0.0], 1.7),
+                ([5.3, 2.9, 3.1], 0.4),
+                ([4.0, 4.0, 3.0], 0.0),
+                ([-3.0, 4.0, 3.0], 4.0),
+                ([3.0, 3.0, 2.0], 30.0),
+            ] {
+                let found: Vec<(Coordinate, SpeciesID)> =
+                    space.within_radius(center, radius).collect();
+                let expected: Vec<(Coordinate, SpeciesID)> = space
+                    .occupied()
+                    .filter(|&(c, _)| {
+                        let p = space.coordinate_to_position(c).unwrap();
+                        let d = space.displacement(center, p);
+                        d.iter().map(|x| x * x).sum::<f64>() <= radius * radius
+                    })
+                    .collect();
+                assert_eq!(found, expected, "{:?} {} {}", center, radius, periodic);
+            }
+        }
+    }
+
+    #[test]
+    fn species_table_is_stable() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), Some(membrane));
+        let wall = space.register_obstacle(Species::new("W"));
+        let info = MoleculeInfo {
+            radius: 2.0,
+            diffusion_coefficient: 0.5,
+        };
+        space.set_molecule_info(a, info).unwrap();
+        let names: Vec<String> = space
+            .species()
+            .map(|(_, species, _)| species.name().to_string())
+            .collect();
+        for i in 0..10 {
+            space.register_counted_species(Species::new(&format!("C{}", i)), None);
+        }
+        assert_eq!(space.num_species(), 13);
+        for (id, name) in [membrane, a, wall].iter().zip(&names) {
+            assert_eq!(space.get_species(*id).unwrap().name(), name);
+            assert_eq!(space.find_species(name), Some(*id));
+        }
+        for (id, species, _) in space.species() {
+            assert_eq!(space.get_species(id), Some(species));
+            assert_eq!(space.species_id(species), Some(id));
+        }
+        assert_eq!(space.get_species(SpeciesID(13)), None);
+        assert_eq!(space.species_attributes(SpeciesID(13)), None);
+
+        assert_eq!(
+            space.species_attributes(a),
+            Some(SpeciesAttributes {
+                info,
+                location: Some(membrane),
+                tracking: true,
+                obstacle: false,
+                voxel_count: 1,
+            })
+        );
+        assert!(space.species_attributes(wall).unwrap().obstacle);
+        let counted = space.find_species("C3").unwrap();
+        assert!(!space.species_attributes(counted).unwrap().tracking);
+    }
+
+    #[test]
+    fn errors_read_as_sentences() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let err = space
+            .place_particle(SpeciesID(3), Coordinate(0))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "no species has the ID 3");
+        let a = space.register_species(Species::new("A"), None);
+        let err = space.place_particle(a, Coordinate(64)).unwrap_err();
+        assert_eq!(err.to_string(), "voxel 64 is out of the lattice");
+        let err = space.convert_to_counted(&Species::new("B")).unwrap_err();
+        assert_eq!(err.to_string(), "species B is not registered");
+        let io = Error::Io(std::io::Error::new(std::io::ErrorKind::NotFound, "gone"));
+        assert!(std::error::Error::source(&io).is_some());
+    }
+
+    #[test]
+    fn ids_of_another_space_are_rejected() {
+        let mut big = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        big.register_species(Species::new("A"), None);
+        let stale = big.register_species(Species::new("B"), None);
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species::new("A"), None);
+        space.place_particle(a, Coordinate(0)).unwrap();
+        assert!(space.contains_species(a) && !space.contains_species(stale));
+
+        let unknown =
+            |result: Result<()>| matches!(result, Err(Error::UnknownSpecies(id)) if id == stale);
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(unknown(
+            space.place_particle(stale, Coordinate(1)).map(|_| ())
+        ));
+        assert!(unknown(space.change_species_at(Coordinate(0), stale)));
+        assert!(unknown(space.set_tracking(stale, false)));
+        assert!(unknown(space.particles_of(stale).map(|_| ())));
+        assert!(unknown(space.walk(stale, &mut rng)));
+        assert!(unknown(space.set_metropolis(stale, true)));
+        assert!(unknown(space.reserve(stale, 10)));
+        assert!(unknown(space.set_transition(a, stale, 0.5)));
+        assert!(unknown(space.set_collision(a, stale, None, 0.5)));
+        assert!(unknown(
+            space
+                .particles_within(Coordinate(0), 2.0, Some(stale))
+                .map(|_| ())
+        ));
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn getters_reject_ids_of_another_space() {
+        let mut big = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        big.register_species(Species::new("A"), None);
+        let stale = big.register_species(Species::new("B"), None);
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species::new("A"), None);
+        space.place_particle(a, Coordinate(0)).unwrap();
+
+        let unknown = |err: Error| matches!(err, Error::UnknownSpecies(id) if id == stale);
+        assert!(unknown(space.num_molecules(stale).unwrap_err()));
+        assert!(unknown(space.location_of(stale).unwrap_err()));
+        assert!(unknown(space.molecule_info(stale).unwrap_err()));
+        assert!(unknown(space.coordinates_of(stale).unwrap_err()));
+        let info = space.molecule_info(a).unwrap();
+        assert!(unknown(space.set_molecule_info(stale, info).unwrap_err()));
+        assert!(!space.can_occupy(stale, Coordinate(1)));
+        assert_eq!(space.nearest_empty(Coordinate(0), 3, stale), None);
+        assert!(!space.is_tracking(stale));
+        assert!(!space.is_obstacle(stale));
+        assert!(!space.is_metropolis(stale));
+        assert_eq!(space.diffusion_interval(stale), None);
+        assert_eq!(space.diffusion_tensor(stale), None);
+        assert_eq!(space.species_style(stale), None);
+        assert_eq!(space.transition(stale, a), 0.0);
+        assert_eq!(space.num_molecules(a).unwrap(), 1);
+    }
+
+    #[test]
+    fn blocked_hops_stay_put() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let wall = space.register_obstacle(Species::new("W"));
+        let a = space.register_species(Species::new("A"), None);
+        let caged = space.global_to_coordinate(2, 2, 2).unwrap();
+        for c in space.neighbors(caged).unwrap() {
+            space.place_particle(wall, c).unwrap();
+        }
+        let caged_pid = space.place_particle(a, caged).unwrap();
+        let free = space.global_to_coordinate(5, 5, 5).unwrap();
+        let free_pid = space.place_particle(a, free).unwrap();
+
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut moves = 0;
+        for _ in 0..100 {
+            assert!(!space.hop_or_stay(caged_pid, &mut rng).unwrap());
+            let (_, before) = space.find_particle(free_pid).unwrap();
+            if space.hop_or_stay(free_pid, &mut rng).unwrap() {
+                let (_, after) = space.find_particle(free_pid).unwrap();
+                assert!(space.neighbors(before).unwrap().contains(&after));
+                moves += 1;
+            }
+        }
+        space.validate().unwrap();
+        assert_eq!(space.find_particle(caged_pid).unwrap().1, caged);
+        assert!(moves > 20 && moves < 100, "{}", moves);
+        assert!(matches!(
+            space.hop_or_stay(ParticleID(0, 99), &mut rng),
+            Err(Error::UnknownParticle(ParticleID(0, 99)))
+        ));
     }
 }
@@ -0,0 +1,700 @@
+//! Declarative model files, describing a simulation from the lattice up.
+//!
+//! A model is a TOML document; every key but `lattice` is optional:
+//!
+//! ```toml
+//! seed = 1
+//!
+//! [lattice]
+//! voxel_radius = 5e-9
+//! size = [20, 20, 20]
+//! periodic = false
+//!
+//! [[species]]
+//! name = "M"
+//! obstacle = false
+//!
+//! [[species]]
+//! name = "A"
+//! D = 1e-12
+//! radius = 5e-9
+//! location = "M"
+//! tracked = true
+//!
+//! [[structures]]
+//! species = "M"
+//! shape.type = "cuboid"
+//! shape.lower = [0, 0, 0]
+//! shape.upper = [1e-7, 2e-7, 1e-8]
+//!
+//! [[populations]]
+//! species = "A"
+//! count = 100
+//!
+//! [[populations]]
+//! species = "B"
+//! concentration = 1e-6
+//! region = { type = "sphere", center = [1e-7, 1e-7, 1e-7], radius = 5e-8 }
+//!
+//! [[populations]]
+//! species = "C"
+//! coordinates = [0, 5, 17]
+//!
+//! [[reactions]]
+//! reactants = ["A"]
+//! products = ["B"]
+//! rate = 10.0
+//! exact = false
+//!
+//! [[boundaries]]
+//! face = "West"
+//! species = "B"
+//! target = 10
+//! interval = 1e-3
+//!
+//! [[observers]]
+//! species = ["A", "B"]
+//! interval = 1e-3
+//! ```
+//!
+//! Units are SI: meters, seconds, and moles per liter for concentrations.
+//! Species are registered in the listed order, a location before the
+//! species located on it, with the defaults of `register_species`;
+//! `2d = true` in `[lattice]` makes a lattice of `new_2d`, whose size has a
+//! single layer. A structure fills its shape, or the whole lattice without
+//! one, as `populate_structure`. A population places a count of molecules,
+//! or the count of a concentration over its region or, for a species on a
+//! structure, over the structure, on random free voxels, or molecules on
+//! the listed voxels. Structures and then populations are laid out in the
+//! listed order, drawing from a `Pcg64` seeded with `seed`, 0 by default,
+//! which the simulator then goes on drawing from. Reactions are those of
+//! `add_reaction`, or `add_exact_reaction` if `exact`, boundaries the
+//! sources of `add_source_boundary` and observers number observers.
+//!
+//! A model that does not hold fails with `Error::Parse`, naming the key at
+//! fault and what is wrong with it: `reactions[2].rate must be positive`.
+//!
+//! `write_model` saves the current state of a simulator as the initial one
+//! of a model, every molecule where it is now, so that loading it back gives
+//! the same space. The time, the seed and what the format has no keys for,
+//! such as sinks, potentials, collisions or scheduled events, are not
+//! saved.
+
+use crate::boundary::Face;
+use crate::simulator::Pcg64;
+use crate::{
+    Coordinate, Cuboid, Error, HCPLatticeSize, HCPLatticeSpace, MoleculeInfo, ReactionRule, Region,
+    Result, Simulator, Species, SpeciesID, Sphere,
+};
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+
+/// Returns the key path of `key` in the block at `path`.
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn invalid(path: &str, key: &str, what: &str) -> Error {
+    Error::Parse(format!("{} {}", join(path, key), what))
+}
+
+/// Returns `key` of `block` cast by `cast`, `None` if it is missing or
+/// null, and failing with `what` is wrong with it if it does not cast.
+fn get<'a, T>(
+    block: &'a Value,
+    path: &str,
+    key: &str,
+    what: &str,
+    cast: impl Fn(&'a Value) -> Option<T>,
+) -> Result<Option<T>> {
+    match block.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => cast(value)
+            .map(Some)
+            .ok_or_else(|| invalid(path, key, what)),
+    }
+}
+
+fn require<T>(value: Option<T>, path: &str, key: &str) -> Result<T> {
+    value.ok_or_else(|| invalid(path, key, "is missing"))
+}
+
+fn number(block: &Value, path: &str, key: &str) -> Result<Option<f64>> {
+    get(block, path, key, "must be a number", Value::as_f64)
+}
+
+/// Same as `number`, failing unless the number is positive and finite.
+fn positive(block: &Value, path: &str, key: &str) -> Result<Option<f64>> {
+    match number(block, path, key)? {
+        Some(value) if !(value > 0.0 && value.is_finite()) => {
+            Err(invalid(path, key, "must be positive"))
+        }
+        value => Ok(value),
+    }
+}
+
+fn whole(block: &Value, path: &str, key: &str) -> Result<Option<usize>> {
+    get(block, path, key, "must be a whole number", |value| {
+        value.as_u64().map(|value| value as usize)
+    })
+}
+
+fn boolean(block: &Value, path: &str, key: &str) -> Result<bool> {
+    Ok(get(block, path, key, "must be true or false", Value::as_bool)?.unwrap_or(false))
+}
+
+fn string<'a>(block: &'a Value, path: &str, key: &str) -> Result<Option<&'a str>> {
+    get(block, path, key, "must be a string", Value::as_str)
+}
+
+fn vector(block: &Value, path: &str, key: &str) -> Result<Option<[f64; 3]>> {
+    get(block, path, key, "must be 3 numbers", |value| {
+        let values = value.as_array().filter(|values| values.len() == 3)?;
+        let mut vector = [0.0; 3];
+        for (component, value) in vector.iter_mut().zip(values) {
+            *component = value.as_f64()?;
+        }
+        Some(vector)
+    })
+}
+
+/// Returns the blocks of the list `key` with their key paths.
+fn blocks<'a>(block: &'a Value, path: &str, key: &str) -> Result<Vec<(String, &'a Value)>> {
+    let list = get(block, path, key, "must be a list", Value::as_array)?;
+    Ok(list
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .map(|(i, block)| (format!("{}[{}]", join(path, key), i), block))
+        .collect())
+}
+
+/// Returns the species named by `key`, which has to be declared.
+fn species(space: &HCPLatticeSpace, block: &Value, path: &str, key: &str) -> Result<SpeciesID> {
+    let name = require(string(block, path, key)?, path, key)?;
+    space
+        .find_species(name)
+        .ok_or_else(|| invalid(path, key, &format!("{:?} is not declared", name)))
+}
+
+fn names(block: &Value, path: &str, key: &str) -> Result<Vec<Species>> {
+    let names = get(block, path, key, "must be a list of names", |value| {
+        value
+            .as_array()?
+            .iter()
+            .map(|name| name.as_str().map(Species::new))
+            .collect::<Option<Vec<_>>>()
+    })?;
+    Ok(names.unwrap_or_default())
+}
+
+/// Returns the region of a `shape` block.
+fn shape(block: &Value, path: &str) -> Result<Box<dyn Region>> {
+    match require(string(block, path, "type")?, path, "type")? {
+        "sphere" => {
+            let center = require(vector(block, path, "center")?, path, "center")?;
+            let radius = require(positive(block, path, "radius")?, path, "radius")?;
+            Ok(Box::new(Sphere::new(center, radius)))
+        }
+        "cuboid" => {
+            let lower = require(vector(block, path, "lower")?, path, "lower")?;
+            let upper = require(vector(block, path, "upper")?, path, "upper")?;
+            Ok(Box::new(Cuboid::new(lower, upper)))
+        }
+        _ => Err(invalid(path, "type", "must be \"sphere\" or \"cuboid\"")),
+    }
+}
+
+fn region(block: &Value, path: &str, key: &str) -> Result<Option<Box<dyn Region>>> {
+    match block.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(region) => shape(region, &join(path, key)).map(Some),
+    }
+}
+
+fn face(name: &str) -> Option<Face> {
+    Face::SIDES
+        .iter()
+        .chain(&[Face::All])
+        .find(|face| format!("{:?}", face) == name)
+        .copied()
+}
+
+fn lattice(model: &Value) -> Result<HCPLatticeSpace> {
+    let block = require(model.get("lattice"), "", "lattice")?;
+    let path = "lattice";
+    let radius = require(positive(block, path, "voxel_radius")?, path, "voxel_radius")?;
+    let size = get(block, path, "size", "must be 3 whole numbers", |value| {
+        let values = value.as_array().filter(|values| values.len() == 3)?;
+        let mut size = [0; 3];
+        for (len, value) in size.iter_mut().zip(values) {
+            *len = value.as_u64()? as usize;
+        }
+        Some(size)
+    })?;
+    let [rows, cols, layers] = require(size, path, "size")?;
+    let mut space = if boolean(block, path, "2d")? {
+        if layers != 1 {
+            return Err(invalid(path, "size", "must have 1 layer for a 2D lattice"));
+        }
+        if HCPLatticeSize::new(rows, cols, 1).num_voxels().is_none() {
+            return Err(invalid(path, "size", "is too large"));
+        }
+        HCPLatticeSpace::new_2d(radius, rows, cols)
+    } else {
+        HCPLatticeSpace::try_new(radius, HCPLatticeSize::new(rows, cols, layers))
+            .map_err(|_| invalid(path, "size", "is too large"))?
+    };
+    if boolean(block, path, "periodic")? {
+        space
+            .set_periodic(true)
+            .map_err(|err| invalid(path, "periodic", &format!("is not possible: {}", err)))?;
+    }
+    Ok(space)
+}
+
+fn register(space: &mut HCPLatticeSpace, model: &Value) -> Result<()> {
+    for (path, block) in blocks(model, "", "species")? {
+        let path = &path;
+        let name = require(string(block, path, "name")?, path, "name")?;
+        if space.find_species(name).is_some() {
+            return Err(invalid(path, "name", "is declared twice"));
+        }
+        let location = match string(block, path, "location")? {
+            Some(location) => Some(space.find_species(location).ok_or_else(|| {
+                invalid(
+                    path,
+                    "location",
+                    &format!("{:?} is not declared before it", location),
+                )
+            })?),
+            None => None,
+        };
+        if location.is_some_and(|location| space.is_obstacle(location)) {
+            return Err(invalid(path, "location", "is an obstacle"));
+        }
+        let id = if boolean(block, path, "obstacle")? {
+            if location.is_some() {
+                return Err(invalid(path, "location", "is not allowed for an obstacle"));
+            }
+            space.register_obstacle(Species::new(name))
+        } else {
+            space.register_species(Species::new(name), location)
+        };
+        let tracked = get(
+            block,
+            path,
+            "tracked",
+            "must be true or false",
+            Value::as_bool,
+        )?;
+        space.set_tracking(id, tracked.unwrap_or(true))?;
+        let mut info = space.molecule_info(id)?;
+        if let Some(d) = number(block, path, "D")? {
+            if !(d >= 0.0 && d.is_finite()) {
+                return Err(invalid(path, "D", "must not be negative"));
+            }
+            info.diffusion_coefficient = d;
+        }
+        if let Some(radius) = positive(block, path, "radius")? {
+            info.radius = radius;
+        }
+        space.set_molecule_info(id, info)?;
+    }
+    Ok(())
+}
+
+fn lay_out(space: &mut HCPLatticeSpace, model: &Value, rng: &mut Pcg64) -> Result<()> {
+    for (path, block) in blocks(model, "", "structures")? {
+        let structure = species(space, block, &path, "species")?;
+        let filled = match region(block, &path, "shape")? {
+            Some(region) => space.populate_structure(structure, region.as_ref()),
+            None => space.populate_structure(structure, &|_: [f64; 3]| true),
+        };
+        filled.map_err(|err| invalid(&path, "species", &format!("cannot be laid out: {}", err)))?;
+    }
+
+    for (path, block) in blocks(model, "", "populations")? {
+        let path = &path;
+        let id = species(space, block, path, "species")?;
+        let region = region(block, path, "region")?;
+        let within = |space: &HCPLatticeSpace, c: Coordinate| match &region {
+            Some(region) => region.contains(space.coordinate_to_position(c).expect("a voxel")),
+            None => true,
+        };
+        let count = whole(block, path, "count")?;
+        let concentration = number(block, path, "concentration")?;
+        let coordinates = get(
+            block,
+            path,
+            "coordinates",
+            "must be a list of voxels",
+            |value| {
+                value
+                    .as_array()?
+                    .iter()
+                    .map(|c| c.as_u64().map(|c| Coordinate(c as usize)))
+                    .collect::<Option<Vec<_>>>()
+            },
+        )?;
+        let count = match (count, concentration, coordinates) {
+            (Some(count), None, None) => count,
+            (None, Some(molar), None) => {
+                if !(molar >= 0.0 && molar.is_finite()) {
+                    return Err(invalid(path, "concentration", "must not be negative"));
+                }
+                match (&region, space.location_of(id)?) {
+                    (Some(region), _) => space.concentration_to_count_in(region.as_ref(), molar),
+                    (None, Some(structure)) => space.concentration_to_count_on(structure, molar),
+                    (None, None) => space.concentration_to_count(molar),
+                }
+            }
+            (None, None, Some(coordinates)) => {
+                if region.is_some() {
+                    return Err(invalid(path, "region", "is not allowed with coordinates"));
+                }
+                for (i, &c) in coordinates.iter().enumerate() {
+                    space.place_particle(id, c).map_err(|err| {
+                        let key = format!("coordinates[{}]", i);
+                        invalid(path, &key, &format!("is not possible: {}", err))
+                    })?;
+                }
+                continue;
+            }
+            _ => {
+                return Err(Error::Parse(format!(
+                    "{} needs one of count, concentration or coordinates",
+                    path
+                )))
+            }
+        };
+        let mut free: Vec<Coordinate> = space
+            .coordinates()
+            .filter(|&c| space.can_occupy(id, c) && within(space, c))
+            .collect();
+        if free.len() < count {
+            return Err(Error::Parse(format!(
+                "{} needs {} voxels but only {} are free",
+                path,
+                count,
+                free.len()
+            )));
+        }
+        free.shuffle(rng);
+        for &c in &free[..count] {
+            space.place_particle(id, c)?;
+        }
+    }
+    Ok(())
+}
+
+/// The keys of a table of a model, in the order they are written.
+type Block = Vec<(&'static str, toml::Value)>;
+
+fn whole_numbers(values: &[usize]) -> toml::Value {
+    values
+        .iter()
+        .map(|&value| value as i64)
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// Returns `value` as TOML, floats in the shortest form that reads back
+/// the same, e.g. `5e-9` rather than `0.000000005`.
+fn format_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::Float(x) if x.is_nan() => "nan".to_string(),
+        toml::Value::Float(x) => format!("{:?}", x),
+        toml::Value::Array(values) => {
+            let values: Vec<String> = values.iter().map(format_value).collect();
+            format!("[{}]", values.join(", "))
+        }
+        value => value.to_string(),
+    }
+}
+
+fn write_table(text: &mut String, header: &str, block: Block) {
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    text.push_str(header);
+    text.push('\n');
+    for (key, value) in block {
+        text.push_str(&format!("{} = {}\n", key, format_value(&value)));
+    }
+}
+
+impl Simulator<Pcg64> {
+    /// Builds the simulator of a model read from `reader`; see the module
+    /// documentation.
+    pub fn from_model(mut reader: impl Read) -> Result<Self> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text).map_err(Error::Io)?;
+        let table: toml::Table = text
+            .parse()
+            .map_err(|err: toml::de::Error| Error::Parse(err.to_string()))?;
+        // The keys are checked on the tree of `serde_json`, which TOML maps
+        // onto but for its dates, which no key takes.
+        let model = serde_json::to_value(table).map_err(|err| Error::Parse(err.to_string()))?;
+        let mut space = lattice(&model)?;
+        register(&mut space, &model)?;
+        let seed = get(&model, "", "seed", "must be a whole number", Value::as_u64)?;
+        let mut rng = Pcg64::seed_from_u64(seed.unwrap_or(0));
+        lay_out(&mut space, &model, &mut rng)?;
+        let mut simulator = Simulator::new(space, rng);
+
+        for (path, block) in blocks(&model, "", "reactions")? {
+            let path = &path;
+            let rate = require(positive(block, path, "rate")?, path, "rate")?;
+            let rule = ReactionRule::new(
+                names(block, path, "reactants")?,
+                names(block, path, "products")?,
+                rate,
+            );
+            let added = if boolean(block, path, "exact")? {
+                simulator.add_exact_reaction(rule)
+            } else {
+                simulator.add_reaction(rule)
+            };
+            added.map_err(|err| Error::Parse(format!("{} is not possible: {}", path, err)))?;
+        }
+
+        for (path, block) in blocks(&model, "", "boundaries")? {
+            let path = &path;
+            let name = require(string(block, path, "face")?, path, "face")?;
+            let face = face(name).ok_or_else(|| invalid(path, "face", "is not a face"))?;
+            let id = species(simulator.space(), block, path, "species")?;
+            let species = simulator.space().get_species(id).cloned().expect("found");
+            let target = require(whole(block, path, "target")?, path, "target")?;
+            let interval = require(positive(block, path, "interval")?, path, "interval")?;
+            simulator
+                .add_source_boundary(face, species, target, interval)
+                .map_err(|err| Error::Parse(format!("{} is not possible: {}", path, err)))?;
+        }
+
+        for (path, block) in blocks(&model, "", "observers")? {
+            let path = &path;
+            let species = names(block, path, "species")?;
+            for (i, name) in species.iter().enumerate() {
+                if simulator.space().find_species(name.name()).is_none() {
+                    return Err(Error::Parse(format!(
+                        "{}.species[{}] {:?} is not declared",
+                        path,
+                        i,
+                        name.name()
+                    )));
+                }
+            }
+            let interval = require(positive(block, path, "interval")?, path, "interval")?;
+            simulator.add_number_observer(species, interval)?;
+        }
+        Ok(simulator)
+    }
+
+    /// Same as `from_model`, reading the file at `path`.
+    pub fn from_model_file(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path).map_err(Error::Io)?;
+        Self::from_model(BufReader::new(file))
+    }
+
+    /// Writes the model of the current state to `writer`; see the module
+    /// documentation. Fails with `InvalidReaction` if the space holds
+    /// multi-voxel molecules, which have no keys.
+    pub fn write_model(&self, mut writer: impl Write) -> Result<()> {
+        let space = self.space();
+        let size = space.size();
+        let name = |id: SpeciesID| space.get_species(id).expect("a species").name();
+        let mut text = String::new();
+        write_table(
+            &mut text,
+            "[lattice]",
+            vec![
+                ("voxel_radius", space.get_voxel_radius().into()),
+                ("size", whole_numbers(&[size.row, size.col, size.layer])),
+                ("periodic", space.is_periodic().into()),
+                ("2d", space.is_2d().into()),
+            ],
+        );
+
+        let mut populations = Vec::new();
+        for (id, _, _) in space.species() {
+            if space.species_cache[id.0].voxel_count > 1 {
+                return Err(Error::InvalidReaction);
+            }
+            let MoleculeInfo {
+                radius,
+                diffusion_coefficient,
+            } = space.molecule_info(id)?;
+            let mut block: Block = vec![
+                ("name", name(id).into()),
+                ("D", diffusion_coefficient.into()),
+                ("radius", radius.into()),
+            ];
+            if let Some(location) = space.location_of(id)? {
+                block.push(("location", name(location).into()));
+            }
+            if space.is_obstacle(id) {
+                block.push(("obstacle", true.into()));
+            }
+            if !space.is_tracking(id) {
+                block.push(("tracked", false.into()));
+            }
+            write_table(&mut text, "[[species]]", block);
+
+            // A structure also lies under the molecules located on it.
+            let mut coordinates = space.coordinates_of(id)?;
+            for (other, _, _) in space.species() {
+                let mut location = space.location_of(other)?;
+                while let Some(structure) = location {
+                    if structure == id {
+                        coordinates.extend(space.coordinates_of(other)?);
+                        break;
+                    }
+                    location = space.location_of(structure)?;
+                }
+            }
+            if !coordinates.is_empty() {
+                let coordinates: Vec<usize> = coordinates.iter().map(|c| c.0).collect();
+                populations.push(vec![
+                    ("species", name(id).into()),
+                    ("coordinates", whole_numbers(&coordinates)),
+                ]);
+            }
+        }
+        for block in populations {
+            write_table(&mut text, "[[populations]]", block);
+        }
+
+        let names = |species: &[Species]| -> toml::Value {
+            species.iter().map(Species::name).collect::<Vec<_>>().into()
+        };
+        for reaction in self.reactions() {
+            let rule = self.reaction_rule(reaction);
+            let block = vec![
+                ("reactants", names(rule.reactants())),
+                ("products", names(rule.products())),
+                ("rate", rule.k().into()),
+                ("exact", reaction.is_exact().into()),
+            ];
+            write_table(&mut text, "[[reactions]]", block);
+        }
+        for source in self.sources() {
+            let block = vec![
+                ("face", format!("{:?}", source.face()).into()),
+                ("species", name(source.species()).into()),
+                ("target", (source.target() as i64).into()),
+                ("interval", source.interval().into()),
+            ];
+            write_table(&mut text, "[[boundaries]]", block);
+        }
+        for observer in self.number_observers() {
+            let block = vec![
+                ("species", names(observer.species())),
+                ("interval", observer.interval().into()),
+            ];
+            write_table(&mut text, "[[observers]]", block);
+        }
+        writer.write_all(text.as_bytes()).map_err(Error::Io)?;
+        writer.flush().map_err(Error::Io)
+    }
+
+    /// Same as `write_model`, writing the file at `path`.
+    pub fn to_model_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        let file = File::create(path).map_err(Error::Io)?;
+        self.write_model(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn example() -> String {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/model.toml");
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn load_run_and_save_the_example() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/model.toml");
+        let mut simulator = Simulator::from_model_file(path).unwrap();
+        let space = simulator.space();
+        space.validate().unwrap();
+        let id = |name| space.find_species(name).unwrap();
+        let (membrane, receptor, a, b) = (id("M"), id("R"), id("A"), id("B"));
+        assert_eq!(space.location_of(receptor).unwrap(), Some(membrane));
+        assert_eq!(space.num_molecules(receptor).unwrap(), 50);
+        assert_eq!(space.num_molecules(a).unwrap(), 300);
+        assert_eq!(space.num_molecules(b).unwrap(), 0);
+        assert!(space.num_molecules(membrane).unwrap() + 50 == space.size().row * space.size().col);
+        let saved = {
+            let mut buffer = Vec::new();
+            simulator.write_model(&mut buffer).unwrap();
+            buffer
+        };
+        let copy = Simulator::from_model(saved.as_slice()).unwrap();
+        assert!(copy.space().diff(simulator.space()).is_empty());
+        assert_eq!(copy.reactions().len(), 2);
+        assert_eq!(copy.sources()[0].face(), Face::Up);
+
+        simulator.run(2e-3).unwrap();
+        simulator.space().validate().unwrap();
+        assert!(simulator.space().num_molecules(b).unwrap() > 0);
+        assert_eq!(simulator.space().num_molecules(receptor).unwrap(), 50);
+        assert!(simulator.t() >= 2e-3);
+        assert_eq!(simulator.number_observers()[0].data().len(), 21);
+    }
+
+    #[test]
+    fn errors_name_the_key_path() {
+        let error = |model: &str| match Simulator::from_model(model.as_bytes()) {
+            Err(Error::Parse(message)) => message,
+            Err(err) => panic!("{:?}", err),
+            Ok(_) => panic!("{} loaded", model),
+        };
+        let model: toml::Table = example().parse().unwrap();
+        let model = serde_json::to_value(model).unwrap();
+        let with = |pointer: &str, value: Value| {
+            let mut model = model.clone();
+            *model.pointer_mut(pointer).unwrap() = value;
+            toml::to_string(&model).unwrap()
+        };
+        assert_eq!(
+            error(&with("/reactions/1/rate", json!(-1.0))),
+            "reactions[1].rate must be positive"
+        );
+        assert_eq!(
+            error(&with("/species/1/location", json!("X"))),
+            "species[1].location \"X\" is not declared before it"
+        );
+        assert_eq!(
+            error(&with("/lattice/size", json!([4, 4]))),
+            "lattice.size must be 3 whole numbers"
+        );
+        assert_eq!(
+            error(&with("/populations/0/count", json!(1_000_000))),
+            "populations[0] needs 1000000 voxels but only 100 are free"
+        );
+        assert_eq!(
+            error(&with("/boundaries/0/face", json!("Left"))),
+            "boundaries[0].face is not a face"
+        );
+        assert_eq!(
+            error(&with("/observers/0/species/1", json!("C"))),
+            "observers[0].species[1] \"C\" is not declared"
+        );
+        assert_eq!(error(""), "lattice is missing");
+        assert!(error("[lattice").contains("line 1"));
+        assert!(error(&with("/reactions/0/products", json!(["R"])))
+            .starts_with("reactions[0] is not possible: "));
+    }
+}
@@ -0,0 +1,518 @@
+//! Species whose molecules occupy a connected cluster of voxels.
+//!
+//! A cluster is either grown to a number of voxels from the voxel a molecule
+//! is placed on (`register_multi_voxel_species`), or is the footprint of a
+//! sphere centered on it (`register_footprint_species`): every voxel whose
+//! center lies within the radius, which all have to be free.
+//!
+//! Each voxel of a cluster has its own entry in the species' tracking cache,
+//! all entries of a molecule sharing its `ParticleID`, so that every voxel
+//! maps back to the molecule occupying it (`particle_at`). The first entry of
+//! a molecule is its anchor, the voxel through which it is walked and
+//! reacted: the center of a footprint.
+//!
+//! A cluster moves rigidly: every voxel is translated by the same real-space
+//! vector. In-plane hops are lattice translations and always keep the shape,
+//! whereas inter-layer hops only do so for clusters lying within layers of
+//! the same parity (see the `neighbors` module); other hops are rejected.
+//! Footprints wider than a voxel span both parities and so only hop within
+//! their layer.
+
+use crate::{
+    Coordinate, Direction, Error, HCPLatticeSpace, ParticleID, Result, Species, SpeciesID, Tracked,
+    TrackingType,
+};
+
+/// A species registered with `register_multi_voxel_species`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MultiVoxelSpecies {
+    id: SpeciesID,
+    voxel_count: usize,
+}
+
+impl MultiVoxelSpecies {
+    pub fn id(&self) -> SpeciesID {
+        self.id
+    }
+
+    pub fn voxel_count(&self) -> usize {
+        self.voxel_count
+    }
+}
+
+impl HCPLatticeSpace {
+    /// Registers a tracked species whose molecules occupy `voxel_count`
+    /// connected voxels of `location` each.
+    pub fn register_multi_voxel_species(
+        &mut self,
+        species: Species,
+        location: Option<SpeciesID>,
+        voxel_count: usize,
+    ) -> MultiVoxelSpecies {
+        let id = self.register_species(species, location);
+        let voxel_count = voxel_count.max(1);
+        self.get_species_cache_mut(id)
+            .expect("just registered")
+            .voxel_count = voxel_count;
+        MultiVoxelSpecies { id, voxel_count }
+    }
+
+    /// Registers a tracked species whose molecules occupy every voxel within
+    /// `radius` voxel diameters of the voxel they are placed on: 13 voxels
+    /// for a radius of 1, the center and its 12 neighbors.
+    ///
+    /// Molecules are translated only, never rotated, so the footprint keeps
+    /// its shape. Placing one fails with `InsufficientSpace` if part of the
+    /// footprint is off the lattice, including across a periodic boundary,
+    /// and with `InvalidLocation` if a voxel of it is not of `location`.
+    pub fn register_footprint_species(
+        &mut self,
+        species: Species,
+        location: Option<SpeciesID>,
+        radius: f64,
+    ) -> MultiVoxelSpecies {
+        let radius = 2.0 * self.voxel_radius * radius.max(0.0);
+        let voxel_count = self.footprint_size(radius);
+        let id = self.register_species(species, location);
+        let cache = self.get_species_cache_mut(id).expect("just registered");
+        cache.voxel_count = voxel_count;
+        cache.footprint = Some(radius);
+        MultiVoxelSpecies { id, voxel_count }
+    }
+
+    /// Counts the voxels within `radius` of a voxel of an unbounded lattice,
+    /// which is the same count around every voxel.
+    fn footprint_size(&self, radius: f64) -> usize {
+        let reach = (radius / self.voxel_radius).ceil() as isize + 1;
+        let layers = if self.planar { 0..=0 } else { -reach..=reach };
+        let origin = self.center(0, 0, 0);
+        let mut count = 0;
+        for layer in layers {
+            for row in -reach..=reach {
+                for col in -reach..=reach {
+                    if within(self.center(row, col, layer), origin, radius) {
+                        count += 1;
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    /// Returns the molecule occupying `coordinate`, whichever voxel of its
+    /// cluster it is, or `None` if the voxel is vacant or of a counted
+    /// species.
+    pub fn particle_at(&self, coordinate: Coordinate) -> Result<Option<ParticleID>> {
+        Ok(match self.get_species_id_at(coordinate)? {
+            Some(species) => self.pid_at(species, coordinate).ok(),
+            None => None,
+        })
+    }
+
+    /// Returns every voxel occupied by the molecule `pid`, anchor first.
+    pub fn voxels_of(&self, pid: ParticleID) -> Vec<Coordinate> {
+        self.species_cache
+            .iter()
+            .filter_map(|cache| match &cache.cache {
+                TrackingType::Tracking(entries) => Some(entries),
+                TrackingType::Count(_) => None,
+            })
+            .flatten()
+            .filter(|(id, _)| *id == pid)
+            .map(|(_, coordinate)| *coordinate)
+            .collect()
+    }
+
+    fn entries_mut(&mut self, species: SpeciesID) -> &mut Tracked {
+        let cache = self
+            .get_species_cache_mut(species)
+            .expect("a species of this space");
+        match &mut cache.cache {
+            TrackingType::Tracking(entries) => entries,
+            TrackingType::Count(_) => unreachable!("multi-voxel species are tracked"),
+        }
+    }
+
+    fn pid_at(&self, species: SpeciesID, coordinate: Coordinate) -> Result<ParticleID> {
+        match &self.species_cache[species.0].cache {
+            TrackingType::Tracking(entries) => entries
+                .iter()
+                .find(|(_, c)| *c == coordinate)
+                .map(|(pid, _)| *pid)
+                .ok_or(Error::ParticleNotFound(coordinate)),
+            TrackingType::Count(_) => Err(Error::ParticleNotFound(coordinate)),
+        }
+    }
+
+    /// Claims the footprint around `seed`, or else `voxel_count` voxels by a
+    /// breadth-first search from `seed` over vacant voxels, in `Direction`
+    /// order.
+    pub(crate) fn place_cluster(
+        &mut self,
+        species: SpeciesID,
+        seed: Coordinate,
+    ) -> Result<ParticleID> {
+        let location = self.species_cache[species.0].location;
+        let voxel_count = self.species_cache[species.0].voxel_count;
+        self.check_bounds(seed)?;
+        if !self.can_occupy(species, seed) {
+            return Err(Error::InvalidLocation(seed, seed));
+        }
+
+        let cluster = match self.species_cache[species.0].footprint {
+            Some(radius) => {
+                let footprint = self.ball(seed, radius)?;
+                if footprint.len() < voxel_count {
+                    return Err(Error::InsufficientSpace(seed));
+                }
+                if let Some(&taken) = footprint.iter().find(|&&c| self.voxel(c) != location) {
+                    return Err(Error::InvalidLocation(seed, taken));
+                }
+                footprint
+            }
+            None => self.grow_cluster(seed, voxel_count, location)?,
+        };
+
+        let pid = self.next_pid();
+        for &coordinate in &cluster {
+            if let Some(location) = location {
+                self.get_species_cache_mut(location)?.remove(coordinate);
+            }
+            self.entries_mut(species).push(pid, coordinate);
+            self.set_voxel(coordinate, Some(species));
+        }
+        Ok(pid)
+    }
+
+    /// Returns the voxels within `radius` of `center`, `center` first,
+    /// whatever they hold. Voxels across a periodic boundary are left out.
+    fn ball(&self, center: Coordinate, radius: f64) -> Result<Vec<Coordinate>> {
+        let origin = self.coordinate_to_position(center)?;
+        let mut ball = vec![center];
+        let mut next = 0;
+        while next < ball.len() {
+            for neighbor in self.neighbors(ball[next])? {
+                if !ball.contains(&neighbor)
+                    && within(self.coordinate_to_position(neighbor)?, origin, radius)
+                {
+                    ball.push(neighbor);
+                }
+            }
+            next += 1;
+        }
+        Ok(ball)
+    }
+
+    fn grow_cluster(
+        &self,
+        seed: Coordinate,
+        voxel_count: usize,
+        location: Option<SpeciesID>,
+    ) -> Result<Vec<Coordinate>> {
+        let mut cluster = vec![seed];
+        let mut next = 0;
+        while cluster.len() < voxel_count && next < cluster.len() {
+            for neighbor in self.neighbors(cluster[next])? {
+                if cluster.len() < voxel_count
+                    && self.voxel(neighbor) == location
+                    && !cluster.contains(&neighbor)
+                {
+                    cluster.push(neighbor);
+                }
+            }
+            next += 1;
+        }
+        if cluster.len() < voxel_count {
+            return Err(Error::InsufficientSpace(seed));
+        }
+        Ok(cluster)
+    }
+
+    /// Removes the whole molecule having a voxel at `coordinate`.
+    pub(crate) fn vacate_cluster(
+        &mut self,
+        species: SpeciesID,
+        coordinate: Coordinate,
+    ) -> Result<SpeciesID> {
+        let pid = self.pid_at(species, coordinate)?;
+        let location = self.species_cache[species.0].location;
+        let cluster = self.entries_mut(species).remove_particle(pid);
+        for coordinate in cluster {
+            if let Some(location) = location {
+                let pid = self.next_pid();
+                self.get_species_cache_mut(location)?.add(pid, coordinate);
+            }
+            self.set_voxel(coordinate, location);
+        }
+        Ok(species)
+    }
+
+    /// Translates the molecule having a voxel at `from` by the vector from
+    /// `from` to `to`. Fails if the translated cluster is not on the lattice
+    /// or any newly claimed voxel is not a vacant voxel of its location.
+    pub(crate) fn move_cluster(
+        &mut self,
+        species: SpeciesID,
+        from: Coordinate,
+        to: Coordinate,
+    ) -> Result<()> {
+        let pid = self.pid_at(species, from)?;
+        let origin = self.coordinate_to_position(from)?;
+        let target = self.coordinate_to_position(to)?;
+        let shift = [
+            target[0] - origin[0],
+            target[1] - origin[1],
+            target[2] - origin[2],
+        ];
+
+        let cluster = self.voxels_of(pid);
+        let mut translated = Vec::with_capacity(cluster.len());
+        for &coordinate in &cluster {
+            let p = self.coordinate_to_position(coordinate)?;
+            let q = [p[0] + shift[0], p[1] + shift[1], p[2] + shift[2]];
+            let moved = self
+                .position_to_coordinate(q)
+                .map_err(|_| Error::InvalidLocation(from, to))?;
+            let center = self.coordinate_to_position(moved)?;
+            let error: f64 = center.iter().zip(&q).map(|(a, b)| (a - b).powi(2)).sum();
+            if error.sqrt() > 1e-6 * self.voxel_radius {
+                return Err(Error::InvalidLocation(from, to));
+            }
+            translated.push(moved);
+        }
+
+        let location = self.species_cache[species.0].location;
+        let claimed: Vec<Coordinate> = translated
+            .iter()
+            .filter(|c| !cluster.contains(c))
+            .copied()
+            .collect();
+        if claimed.iter().any(|&c| self.voxel(c) != location) {
+            return Err(Error::InvalidLocation(from, to));
+        }
+        let freed: Vec<Coordinate> = cluster
+            .iter()
+            .filter(|c| !translated.contains(c))
+            .copied()
+            .collect();
+
+        for (&claimed, &freed) in claimed.iter().zip(&freed) {
+            if let Some(location) = location {
+                self.get_species_cache_mut(location)?
+                    .move_to(claimed, freed);
+            }
+            self.set_voxel(claimed, Some(species));
+            self.set_voxel(freed, location);
+        }
+        let mut moves = translated.iter();
+        let entries = self.entries_mut(species);
+        for i in 0..entries.len() {
+            if entries[i].0 == pid {
+                entries.set_coordinate(i, *moves.next().unwrap());
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the molecule `pid` one hop in `direction`.
+    pub fn move_multi_voxel(&mut self, pid: ParticleID, direction: Direction) -> Result<()> {
+        let (_, anchor) = self.find_particle(pid).ok_or(Error::UnknownParticle(pid))?;
+        let to = self
+            .neighbor(anchor, direction)?
+            .ok_or(Error::InvalidLocation(anchor, anchor))?;
+        self.move_particle(anchor, to)
+    }
+}
+
+fn within(p: [f64; 3], q: [f64; 3], radius: f64) -> bool {
+    let d2: f64 = p.iter().zip(&q).map(|(a, b)| (a - b).powi(2)).sum();
+    d2 <= (radius * (1.0 + 1e-9)).powi(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HCPLatticeSize;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn place_connected_cluster() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let big = space.register_multi_voxel_species(Species::new("R"), None, 4);
+        let small = space.register_species(Species::new("A"), None);
+        let seed = space.global_to_coordinate(2, 2, 2).unwrap();
+        let pid = space.place_particle(big.id(), seed).unwrap();
+        let cluster = space.voxels_of(pid);
+        assert_eq!(cluster.len(), 4);
+        assert_eq!(cluster[0], seed);
+        for &c in &cluster[1..] {
+            assert!(space.neighbors(seed).unwrap().contains(&c));
+        }
+        assert_eq!(space.num_molecules(big.id()).unwrap(), 1);
+        assert!(space.place_particle(small, cluster[2]).is_err());
+        space.validate().unwrap();
+
+        space.vacate(cluster[3]).unwrap();
+        assert_eq!(space.num_molecules(big.id()).unwrap(), 0);
+        assert_eq!(space.occupied().count(), 0);
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn cluster_moves_rigidly() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 8, 2));
+        let big = space.register_multi_voxel_species(Species::new("R"), None, 3);
+        let small = space.register_species(Species::new("A"), None);
+        let seed = space.global_to_coordinate(2, 2, 0).unwrap();
+        let pid = space.place_particle(big.id(), seed).unwrap();
+        let before: Vec<[f64; 3]> = space
+            .voxels_of(pid)
+            .iter()
+            .map(|&c| space.coordinate_to_position(c).unwrap())
+            .collect();
+
+        space.move_multi_voxel(pid, Direction::East).unwrap();
+        let after: Vec<[f64; 3]> = space
+            .voxels_of(pid)
+            .iter()
+            .map(|&c| space.coordinate_to_position(c).unwrap())
+            .collect();
+        for (p, q) in before.iter().zip(&after) {
+            assert!((q[0] - p[0] - 2.0).abs() < 1e-9);
+            assert!((q[1] - p[1]).abs() < 1e-9);
+        }
+        space.validate().unwrap();
+
+        let anchor = space.voxels_of(pid)[0];
+        let blocker = space.neighbor(anchor, Direction::West).unwrap().unwrap();
+        let blocker = space.neighbor(blocker, Direction::West).unwrap().unwrap();
+        space.place_particle(small, blocker).unwrap();
+        let cluster = space.voxels_of(pid);
+        assert!(space.move_multi_voxel(pid, Direction::West).is_err());
+        assert_eq!(space.voxels_of(pid), cluster);
+        space.validate().unwrap();
+    }
+
+    #[test]
+    fn crowded_walk_conserves_mass() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(8, 8, 4));
+        let big = space.register_multi_voxel_species(Species::new("R"), None, 4);
+        let small = space.register_species(Species::new("A"), None);
+        let mut placed = 0;
+        for i in (0..256).step_by(9) {
+            if space.place_particle(big.id(), Coordinate(i)).is_ok() {
+                placed += 1;
+            }
+        }
+        for i in (0..256).step_by(5) {
+            let _ = space.place_particle(small, Coordinate(i));
+        }
+        let smalls = space.num_molecules(small).unwrap();
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..50 {
+            space.walk(big.id(), &mut rng).unwrap();
+            space.walk(small, &mut rng).unwrap();
+            space.validate().unwrap();
+        }
+        assert_eq!(space.num_molecules(big.id()).unwrap(), placed);
+        assert_eq!(space.num_molecules(small).unwrap(), smalls);
+    }
+
+    #[test]
+    fn footprint_covers_a_sphere() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(10, 10, 10));
+        let ball = space.register_footprint_species(Species::new("R"), None, 1.0);
+        assert_eq!(ball.voxel_count(), 13);
+        let wide = space.register_footprint_species(Species::new("V"), None, 1.5);
+        assert_eq!(wide.voxel_count(), 19);
+        let small = space.register_species(Species::new("A"), None);
+
+        let center = space.global_to_coordinate(4, 4, 4).unwrap();
+        let pid = space.place_particle(ball.id(), center).unwrap();
+        let body = space.voxels_of(pid);
+        assert_eq!(body[0], center);
+        let mut expected = space.neighbors(center).unwrap();
+        expected.push(center);
+        expected.sort_by_key(|c| c.0);
+        let mut sorted = body.clone();
+        sorted.sort_by_key(|c| c.0);
+        assert_eq!(sorted, expected);
+        for &c in &body {
+            assert_eq!(space.particle_at(c).unwrap(), Some(pid));
+        }
+
+        // Any voxel of the body blocks another footprint or molecule.
+        let near = space.global_to_coordinate(4, 6, 4).unwrap();
+        assert!(matches!(
+            space.place_particle(ball.id(), near),
+            Err(Error::InvalidLocation(..))
+        ));
+        assert!(space.place_particle(small, body[5]).is_err());
+        let edge = space.global_to_coordinate(0, 4, 4).unwrap();
+        assert!(matches!(
+            space.place_particle(ball.id(), edge),
+            Err(Error::InsufficientSpace(_))
+        ));
+        space.validate().unwrap();
+
+        space.remove_at(body[7]).unwrap();
+        assert_eq!(space.particle_at(center).unwrap(), None);
+        assert_eq!(space.occupied().count(), 0);
+    }
+
+    #[test]
+    fn crowded_footprints_never_overlap() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(16, 16, 6));
+        let ball = space.register_footprint_species(Species::new("R"), None, 1.0);
+        let small = space.register_species(Species::new("A"), None);
+        let mut pids = Vec::new();
+        for c in space.coordinates().step_by(7).collect::<Vec<_>>() {
+            if let Ok(pid) = space.place_particle(ball.id(), c) {
+                pids.push(pid);
+            }
+        }
+        for c in space.coordinates().step_by(3).collect::<Vec<_>>() {
+            let _ = space.place_particle(small, c);
+        }
+        let smalls = space.num_molecules(small).unwrap();
+        assert!(pids.len() >= 20);
+        // Over half of the lattice is taken.
+        assert!(space.occupied().count() * 2 > space.num_voxels());
+
+        let reach = 2.0 * (1.0 + 1e-9);
+        let mut rng = StdRng::seed_from_u64(8);
+        let mut moved = 0;
+        for _ in 0..40 {
+            let before = space.coordinates_of(ball.id()).unwrap();
+            space.walk(ball.id(), &mut rng).unwrap();
+            space.walk(small, &mut rng).unwrap();
+            space.validate().unwrap();
+            moved += before
+                .iter()
+                .zip(space.coordinates_of(ball.id()).unwrap())
+                .filter(|(a, b)| *a != b)
+                .count();
+            for &pid in &pids {
+                let body = space.voxels_of(pid);
+                assert_eq!(body.len(), 13);
+                let center = space.coordinate_to_position(body[0]).unwrap();
+                for &c in &body {
+                    assert_eq!(space.particle_at(c).unwrap(), Some(pid));
+                    let p = space.coordinate_to_position(c).unwrap();
+                    let d2: f64 = (0..3).map(|i| (p[i] - center[i]).powi(2)).sum();
+                    assert!(d2.sqrt() <= reach);
+                }
+            }
+        }
+        assert!(moved > 0);
+        assert_eq!(space.num_molecules(ball.id()).unwrap(), pids.len());
+        assert_eq!(space.num_molecules(small).unwrap(), smalls);
+        assert_eq!(
+            space.occupied().count(),
+            13 * pids.len() + smalls,
+            "mass is conserved"
+        );
+        assert_eq!(space.total_molecules(), space.occupied().count());
+    }
+}
@@ -0,0 +1,146 @@
+//! Neighbor enumeration on the HCP lattice.
+//!
+//! Voxels are indexed by `(row, col, layer)`. Within a layer the voxels form
+//! a hexagonal grid: columns are spaced by `2r` along x and rows by `√3 r`
+//! along y, with odd rows shifted by `r` along x. Layers are stacked along z
+//! in the ABAB order, odd (B) layers being shifted by `(r, r/√3)` in the xy
+//! plane.
+//!
+//! Each voxel has 12 neighbors, named by `Direction`. The six in-plane
+//! directions point the same way on every voxel. The six inter-layer
+//! directions keep their x and z components on every voxel, but the sign of
+//! their y component flips with the layer parity, because an A site and a B
+//! site see the adjacent layers as mirrored triangles. For an even layer the
+//! `*East`/`*West` neighbors lie at `+r/√3` along y and the `*Apex` neighbor
+//! at `-2r/√3`; for an odd layer these signs are reversed.
+
+use crate::HCPLatticeSize;
+
+/// One of the 12 directions from a voxel to its nearest neighbors.
+///
+/// The displacement of each direction, with `h = 2r√(2/3)` the layer
+/// spacing and `s = ±1` the layer-parity sign (`+1` for even layers):
+///
+/// | Direction   | dx  | dy         | dz |
+/// |-------------|-----|------------|----|
+/// | `East`      | 2r  | 0          | 0  |
+/// | `West`      | -2r | 0          | 0  |
+/// | `NorthEast` | r   | √3 r       | 0  |
+/// | `NorthWest` | -r  | √3 r       | 0  |
+/// | `SouthEast` | r   | -√3 r      | 0  |
+/// | `SouthWest` | -r  | -√3 r      | 0  |
+/// | `UpEast`    | r   | s r/√3     | h  |
+/// | `UpWest`    | -r  | s r/√3     | h  |
+/// | `UpApex`    | 0   | -2s r/√3   | h  |
+/// | `DownEast`  | r   | s r/√3     | -h |
+/// | `DownWest`  | -r  | s r/√3     | -h |
+/// | `DownApex`  | 0   | -2s r/√3   | -h |
+///
+/// The discriminant of each variant is its index in `Direction::ALL`, which
+/// is also the index used for per-direction weights.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Direction {
+    East = 0,
+    West = 1,
+    NorthEast = 2,
+    NorthWest = 3,
+    SouthEast = 4,
+    SouthWest = 5,
+    UpEast = 6,
+    UpWest = 7,
+    UpApex = 8,
+    DownEast = 9,
+    DownWest = 10,
+    DownApex = 11,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 12] = [
+        Direction::East,
+        Direction::West,
+        Direction::NorthEast,
+        Direction::NorthWest,
+        Direction::SouthEast,
+        Direction::SouthWest,
+        Direction::UpEast,
+        Direction::UpWest,
+        Direction::UpApex,
+        Direction::DownEast,
+        Direction::DownWest,
+        Direction::DownApex,
+    ];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Returns true for the six directions within a layer.
+    pub fn is_in_plane(self) -> bool {
+        self.index() < 6
+    }
+
+    /// Returns the direction pointing back to the origin voxel.
+    pub fn opposite(self) -> Direction {
+        match self {
+            Direction::East => Direction::West,
+            Direction::West => Direction::East,
+            Direction::NorthEast => Direction::SouthWest,
+            Direction::NorthWest => Direction::SouthEast,
+            Direction::SouthEast => Direction::NorthWest,
+            Direction::SouthWest => Direction::NorthEast,
+            Direction::UpEast => Direction::DownWest,
+            Direction::UpWest => Direction::DownEast,
+            Direction::UpApex => Direction::DownApex,
+            Direction::DownEast => Direction::UpWest,
+            Direction::DownWest => Direction::UpEast,
+            Direction::DownApex => Direction::UpApex,
+        }
+    }
+
+    /// Returns the `(row, col, layer)` offset of this direction seen from a
+    /// voxel at the given row and layer parities.
+    pub(crate) fn offset(self, odd_row: bool, odd_layer: bool) -> (isize, isize, isize) {
+        let pr = odd_row as isize;
+        let pl = odd_layer as isize;
+        match self {
+            Direction::East => (0, 1, 0),
+            Direction::West => (0, -1, 0),
+            Direction::NorthEast => (1, pr, 0),
+            Direction::NorthWest => (1, pr - 1, 0),
+            Direction::SouthEast => (-1, pr, 0),
+            Direction::SouthWest => (-1, pr - 1, 0),
+            Direction::UpEast => (0, pl, 1),
+            Direction::UpWest => (0, pl - 1, 1),
+            Direction::UpApex => (2 * pl - 1, pr + pl - 1, 1),
+            Direction::DownEast => (0, pl, -1),
+            Direction::DownWest => (0, pl - 1, -1),
+            Direction::DownApex => (2 * pl - 1, pr + pl - 1, -1),
+        }
+    }
+}
+
+/// Returns the `(row, col, layer)` of the neighbor in `direction`. Outside
+/// the lattice this wraps around if `periodic` and is `None` otherwise.
+pub(crate) fn neighbor(
+    size: &HCPLatticeSize,
+    periodic: bool,
+    (row, col, layer): (usize, usize, usize),
+    direction: Direction,
+) -> Option<(usize, usize, usize)> {
+    let (dr, dc, dl) = direction.offset(row % 2 == 1, layer % 2 == 1);
+    let shift = |index: usize, delta: isize, len: usize| {
+        let shifted = index as isize + delta;
+        if periodic {
+            Some(shifted.rem_euclid(len as isize) as usize)
+        } else if shifted < 0 || shifted as usize >= len {
+            None
+        } else {
+            Some(shifted as usize)
+        }
+    };
+    Some((
+        shift(row, dr, size.row)?,
+        shift(col, dc, size.col)?,
+        shift(layer, dl, size.layer)?,
+    ))
+}
@@ -0,0 +1,807 @@
+//! The next subvolume method, an approximation for large copy numbers.
+//!
+//! `NsmSimulator` treats every voxel of an `HCPLatticeSpace` as a
+//! well-mixed bin holding a count of each species, with no exclusion
+//! between molecules. A molecule hops to each neighboring bin at the rate
+//! of the walks of the space, `w_d / (Σw τ)` in direction `d` with `τ` the
+//! `diffusion_interval` of its species and `w` its direction weights, so
+//! that its mean squared displacement is that of a walk. Hops off a
+//! non-periodic lattice or onto a bin of another location are rejected, as
+//! in a walk. The reactions within a bin are channels of the Gillespie
+//! algorithm with propensities
+//!
+//! - `k` for `∅ -> ...`,
+//! - `k n_A` for `A -> ...`,
+//! - `k n_A n_B` for `A + B -> ...`,
+//! - `k n_A (n_A - 1) / 2` for `A + A -> ...`,
+//!
+//! `k` being a rate per bin, per molecule or per pair in a bin, and the
+//! products being put in the same bin.
+//!
+//! Each bin has the time of its next event, drawn from the sum of its
+//! propensities, in an indexed priority queue. The earliest bin fires a
+//! channel or a hop chosen by propensity, after which the bins it changed
+//! draw new times, which is exact since waiting times are memoryless. The
+//! propensities of every bin are cached, and a dependency graph between the
+//! channels and the counts they read limits what an event recomputes: a
+//! reaction recomputes the channels reading the species it changes the
+//! count of, and a hop those reading the count of the species hopping, in
+//! both bins. `scheduler_stats` counts the recomputations.
+//!
+//! The structures of the space, obstacles and the species others are
+//! located on, stay where they are as the location of their bins; the
+//! molecules of every other species are turned into counts.
+
+use crate::reaction::ReactionRule;
+use crate::{Coordinate, Direction, Error, HCPLatticeSpace, Result, SpeciesID};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64;
+
+/// A binary heap of the bins by the time of their next event, knowing where
+/// each bin sits so that its time can be changed in place.
+#[derive(Clone, Debug)]
+struct IndexedQueue {
+    times: Vec<f64>,
+    heap: Vec<usize>,
+    positions: Vec<usize>,
+}
+
+impl IndexedQueue {
+    fn new(times: Vec<f64>) -> Self {
+        let n = times.len();
+        let mut queue = Self {
+            times,
+            heap: (0..n).collect(),
+            positions: (0..n).collect(),
+        };
+        for i in (0..n / 2).rev() {
+            queue.sift_down(i);
+        }
+        queue
+    }
+
+    /// Returns the bin with the earliest time, and that time.
+    fn first(&self) -> Option<(usize, f64)> {
+        self.heap.first().map(|&bin| (bin, self.times[bin]))
+    }
+
+    fn update(&mut self, bin: usize, time: f64) {
+        let earlier = time < self.times[bin];
+        self.times[bin] = time;
+        if earlier {
+            self.sift_up(self.positions[bin]);
+        } else {
+            self.sift_down(self.positions[bin]);
+        }
+    }
+
+    fn less(&self, i: usize, j: usize) -> bool {
+        self.times[self.heap[i]] < self.times[self.heap[j]]
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        self.heap.swap(i, j);
+        self.positions[self.heap[i]] = i;
+        self.positions[self.heap[j]] = j;
+    }
+
+    fn sift_up(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if !self.less(i, parent) {
+                break;
+            }
+            self.swap(i, parent);
+            i = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut i: usize) {
+        loop {
+            let mut first = i;
+            for child in [2 * i + 1, 2 * i + 2] {
+                if child < self.heap.len() && self.less(child, first) {
+                    first = child;
+                }
+            }
+            if first == i {
+                break;
+            }
+            self.swap(i, first);
+            i = first;
+        }
+    }
+}
+
+/// What the scheduler has done since the simulator was made.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct SchedulerStats {
+    pub events: u64,
+    /// The propensities recomputed after events, of a reaction or of the
+    /// hops of a species, in a bin.
+    pub recomputations: u64,
+}
+
+impl SchedulerStats {
+    /// Returns the mean number of propensities recomputed per event.
+    pub fn per_event(&self) -> f64 {
+        if self.events == 0 {
+            0.0
+        } else {
+            self.recomputations as f64 / self.events as f64
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Channel {
+    rule: ReactionRule,
+    reactants: Vec<SpeciesID>,
+    products: Vec<SpeciesID>,
+    /// The location of the bins it takes place in.
+    location: Option<SpeciesID>,
+}
+
+/// The dependency graph of the channels, telling which propensities of a
+/// bin an event in it may change.
+#[derive(Clone, Default, Debug)]
+struct Dependencies {
+    /// The channels reading the count of each species.
+    readers: Vec<Vec<usize>>,
+    /// The species whose counts each channel changes.
+    changed: Vec<Vec<SpeciesID>>,
+    /// The channels reading these counts, for each channel.
+    affected: Vec<Vec<usize>>,
+}
+
+impl Dependencies {
+    fn new(channels: &[Channel], num_species: usize) -> Self {
+        let mut readers = vec![Vec::new(); num_species];
+        for (i, channel) in channels.iter().enumerate() {
+            for id in &channel.reactants {
+                if readers[id.0].last() != Some(&i) {
+                    readers[id.0].push(i);
+                }
+            }
+        }
+        let changed: Vec<Vec<SpeciesID>> = channels
+            .iter()
+            .map(|channel| {
+                let mut net = vec![0i64; num_species];
+                for id in &channel.reactants {
+                    net[id.0] -= 1;
+                }
+                for id in &channel.products {
+                    net[id.0] += 1;
+                }
+                (0..num_species)
+                    .filter(|&i| net[i] != 0)
+                    .map(SpeciesID)
+                    .collect()
+            })
+            .collect();
+        let affected = changed
+            .iter()
+            .map(|species| {
+                let mut channels: Vec<usize> = species
+                    .iter()
+                    .flat_map(|id| readers[id.0].iter().copied())
+                    .collect();
+                channels.sort_unstable();
+                channels.dedup();
+                channels
+            })
+            .collect();
+        Self {
+            readers,
+            changed,
+            affected,
+        }
+    }
+}
+
+/// What an event changed in a bin.
+#[derive(Clone, Copy, Debug)]
+enum Change {
+    Fired(usize),
+    Hopped(SpeciesID),
+}
+
+pub struct NsmSimulator<R = Pcg64> {
+    /// The space the simulator was made from, holding its structures only.
+    space: HCPLatticeSpace,
+    rng: R,
+    t: f64,
+    num_steps: u64,
+    /// Whether each species is a structure rather than counted.
+    structures: Vec<bool>,
+    /// The rate of each direction of a hop of a molecule, by species.
+    hops: Vec<[f64; 12]>,
+    /// The counts of each species, by species and then by bin.
+    counts: Vec<Vec<u32>>,
+    channels: Vec<Channel>,
+    /// The propensities of the channels in each bin, by bin and then by
+    /// channel, and those of the hops of each species out of each bin, by
+    /// bin and then by species.
+    propensities: Vec<f64>,
+    hop_propensities: Vec<f64>,
+    dependencies: Dependencies,
+    /// The total propensity of each bin, and the queue of their next
+    /// events; `None` until the next step after a change to the model.
+    rates: Vec<f64>,
+    queue: Option<IndexedQueue>,
+    stats: SchedulerStats,
+    /// Whether to recompute every propensity of a bin after each event, as
+    /// a reference for the dependency graph.
+    #[cfg(test)]
+    naive: bool,
+}
+
+impl<R: Rng> NsmSimulator<R> {
+    /// Creates a simulator whose bins are the voxels of `space`, turning
+    /// its molecules into counts of one in their voxels.
+    pub fn from_space(space: &HCPLatticeSpace, rng: R) -> Self {
+        let mut space = space.clone();
+        let n = space.species_cache.len();
+        let structures: Vec<bool> = (0..n)
+            .map(|i| {
+                let id = SpeciesID(i);
+                space.is_obstacle(id) || space.species_cache.iter().any(|c| c.location == Some(id))
+            })
+            .collect();
+        let mut counts = vec![vec![0; space.num_voxels()]; n];
+        for c in space.coordinates().collect::<Vec<_>>() {
+            if let Some(id) = space.voxel(c) {
+                if !structures[id.0] {
+                    counts[id.0][c.0] += 1;
+                    space.remove_at(c).expect("a molecule on the voxel");
+                }
+            }
+        }
+        let hops = (0..n).map(|i| hop_rates(&space, SpeciesID(i))).collect();
+        Self {
+            space,
+            rng,
+            t: 0.0,
+            num_steps: 0,
+            structures,
+            hops,
+            counts,
+            channels: Vec::new(),
+            propensities: Vec::new(),
+            hop_propensities: Vec::new(),
+            dependencies: Dependencies::default(),
+            rates: Vec::new(),
+            queue: None,
+            stats: SchedulerStats::default(),
+            #[cfg(test)]
+            naive: false,
+        }
+    }
+
+    pub fn t(&self) -> f64 {
+        self.t
+    }
+
+    pub fn num_steps(&self) -> u64 {
+        self.num_steps
+    }
+
+    /// Returns the space the simulator was made from, with its structures
+    /// but none of the molecules turned into counts.
+    pub fn space(&self) -> &HCPLatticeSpace {
+        &self.space
+    }
+
+    /// Returns how many events the scheduler processed and how many
+    /// propensities it recomputed after them, for profiling.
+    pub fn scheduler_stats(&self) -> SchedulerStats {
+        self.stats
+    }
+
+    /// Returns the counts of `species` in every bin, indexed by the
+    /// coordinate of the bin.
+    pub fn bin_counts(&self, species: SpeciesID) -> &[u32] {
+        &self.counts[species.0]
+    }
+
+    pub fn num_molecules(&self, species: SpeciesID) -> u64 {
+        self.counts[species.0].iter().map(|&n| u64::from(n)).sum()
+    }
+
+    /// Sets the count of `species` in the bin at `coordinate`. Fails with
+    /// `InvalidLocation` for a structure or a bin of another location.
+    pub fn set_count(
+        &mut self,
+        species: SpeciesID,
+        coordinate: Coordinate,
+        count: u32,
+    ) -> Result<()> {
+        self.space.check_bounds(coordinate)?;
+        if self.structures[species.0] || !self.space.can_occupy(species, coordinate) {
+            return Err(Error::InvalidLocation(coordinate, coordinate));
+        }
+        self.counts[species.0][coordinate.0] = count;
+        self.queue = None;
+        Ok(())
+    }
+
+    /// Adds a reaction with at most two reactants taking place in every bin
+    /// of the location of its species; see the module documentation for its
+    /// propensity. Fails with `SpeciesNotFound` for a species not registered
+    /// in the space, and with `InvalidReaction` for more reactants, a
+    /// negative rate, a structure or species of different locations.
+    pub fn add_reaction(&mut self, rule: ReactionRule) -> Result<()> {
+        if rule.reactants().len() > 2 || rule.k() < 0.0 {
+            return Err(Error::InvalidReaction);
+        }
+        let lookup = |species: &[crate::Species]| -> Result<Vec<SpeciesID>> {
+            species
+                .iter()
+                .map(|s| {
+                    self.space
+                        .species_id(s)
+                        .ok_or_else(|| Error::SpeciesNotFound(s.clone()))
+                })
+                .collect()
+        };
+        let reactants = lookup(rule.reactants())?;
+        let products = lookup(rule.products())?;
+        let mut all = reactants.iter().chain(&products);
+        let location = match all.clone().next() {
+            Some(&first) => self.space.location_of(first)?,
+            None => return Err(Error::InvalidReaction),
+        };
+        if all
+            .any(|&id| self.structures[id.0] || self.space.species_cache[id.0].location != location)
+        {
+            return Err(Error::InvalidReaction);
+        }
+        self.channels.push(Channel {
+            rule,
+            reactants,
+            products,
+            location,
+        });
+        self.queue = None;
+        Ok(())
+    }
+
+    /// Returns the time of the next event, infinite if nothing can happen.
+    pub fn next_time(&mut self) -> f64 {
+        self.prepare();
+        self.queue
+            .as_ref()
+            .and_then(IndexedQueue::first)
+            .map_or(f64::INFINITY, |(_, time)| time)
+    }
+
+    /// Processes the next event, if anything can happen.
+    pub fn step(&mut self) {
+        if !self.next_time().is_finite() {
+            return;
+        }
+        let (bin, time) = self.queue.as_ref().and_then(IndexedQueue::first).unwrap();
+        self.t = time;
+        self.num_steps += 1;
+        self.stats.events += 1;
+        let mut threshold = self.rng.gen::<f64>() * self.rates[bin];
+        let num_channels = self.channels.len();
+        for i in 0..num_channels {
+            let propensity = self.propensities[bin * num_channels + i];
+            if threshold < propensity {
+                self.fire(i, bin);
+                self.refresh(bin, Change::Fired(i));
+                return self.reschedule(bin);
+            }
+            threshold -= propensity;
+        }
+        let num_species = self.counts.len();
+        for i in 0..num_species {
+            let propensity = self.hop_propensities[bin * num_species + i];
+            if threshold < propensity {
+                let species = SpeciesID(i);
+                let n = f64::from(self.counts[i][bin]);
+                let mut to = None;
+                for &direction in Direction::ALL.iter() {
+                    if let Some(target) = self.hop_target(species, bin, direction) {
+                        to = Some(target);
+                        let rate = n * self.hops[i][direction.index()];
+                        if threshold < rate {
+                            break;
+                        }
+                        threshold -= rate;
+                    }
+                }
+                let to = to.expect("a bin to hop to");
+                self.counts[i][bin] -= 1;
+                self.counts[i][to] += 1;
+                self.refresh(bin, Change::Hopped(species));
+                self.refresh(to, Change::Hopped(species));
+                self.reschedule(bin);
+                return self.reschedule(to);
+            }
+            threshold -= propensity;
+        }
+        // Rounding may leave the threshold just beyond the last propensity.
+        self.reschedule(bin);
+    }
+
+    /// Processes every event up to `t + duration` and leaves `t` there.
+    pub fn run(&mut self, duration: f64) {
+        let end = self.t + duration;
+        while self.next_time() <= end {
+            self.step();
+        }
+        self.t = end;
+    }
+
+    /// Same as `run`, calling `observer` with the time and the simulator
+    /// every `interval` from now on, now and `t + duration` included when
+    /// `interval` divides `duration`.
+    pub fn run_with_observer<F>(&mut self, duration: f64, interval: f64, mut observer: F)
+    where
+        F: FnMut(f64, &Self),
+    {
+        let (start, end) = (self.t, self.t + duration);
+        let tolerance = end.abs() * 1e-12;
+        let mut samples = 0;
+        loop {
+            let sample = start + samples as f64 * interval;
+            if sample > end + tolerance {
+                break;
+            }
+            let sample = sample.min(end);
+            while self.next_time() <= sample {
+                self.step();
+            }
+            self.t = sample;
+            observer(sample, self);
+            samples += 1;
+        }
+        self.t = self.t.max(end);
+        self.run(end - self.t);
+    }
+
+    fn prepare(&mut self) {
+        if self.queue.is_some() {
+            return;
+        }
+        let n = self.space.num_voxels();
+        self.dependencies = Dependencies::new(&self.channels, self.counts.len());
+        self.propensities = (0..n)
+            .flat_map(|bin| (0..self.channels.len()).map(move |i| (bin, i)))
+            .map(|(bin, i)| self.propensity(i, bin))
+            .collect();
+        self.hop_propensities = (0..n)
+            .flat_map(|bin| (0..self.counts.len()).map(move |i| (bin, i)))
+            .map(|(bin, i)| self.hop_propensity(SpeciesID(i), bin))
+            .collect();
+        self.rates = (0..n).map(|bin| self.bin_rate(bin)).collect();
+        let times = (0..n)
+            .map(|bin| self.t + self.waiting_time(self.rates[bin]))
+            .collect();
+        self.queue = Some(IndexedQueue::new(times));
+    }
+
+    /// Recomputes the propensities of `bin` that `change` may have changed.
+    fn refresh(&mut self, bin: usize, change: Change) {
+        #[cfg(test)]
+        {
+            if self.naive {
+                for i in 0..self.channels.len() {
+                    self.recompute_channel(bin, i);
+                }
+                for i in 0..self.counts.len() {
+                    self.recompute_hops(bin, SpeciesID(i));
+                }
+                return;
+            }
+        }
+        let dependencies = std::mem::take(&mut self.dependencies);
+        let (channels, species) = match &change {
+            Change::Fired(i) => (&dependencies.affected[*i], &dependencies.changed[*i][..]),
+            Change::Hopped(species) => (
+                &dependencies.readers[species.0],
+                std::slice::from_ref(species),
+            ),
+        };
+        for &i in channels {
+            self.recompute_channel(bin, i);
+        }
+        for &species in species {
+            self.recompute_hops(bin, species);
+        }
+        self.dependencies = dependencies;
+    }
+
+    fn recompute_channel(&mut self, bin: usize, channel: usize) {
+        let propensity = self.propensity(channel, bin);
+        self.propensities[bin * self.channels.len() + channel] = propensity;
+        self.stats.recomputations += 1;
+    }
+
+    fn recompute_hops(&mut self, bin: usize, species: SpeciesID) {
+        let propensity = self.hop_propensity(species, bin);
+        self.hop_propensities[bin * self.counts.len() + species.0] = propensity;
+        self.stats.recomputations += 1;
+    }
+
+    fn reschedule(&mut self, bin: usize) {
+        self.rates[bin] = self.bin_rate(bin);
+        let time = self.t + self.waiting_time(self.rates[bin]);
+        self.queue.as_mut().unwrap().update(bin, time);
+    }
+
+    fn waiting_time(&mut self, rate: f64) -> f64 {
+        if rate > 0.0 {
+            -(1.0 - self.rng.gen::<f64>()).ln() / rate
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    /// Sums the cached propensities of `bin`.
+    fn bin_rate(&self, bin: usize) -> f64 {
+        let (num_channels, num_species) = (self.channels.len(), self.counts.len());
+        let reactions: f64 = self.propensities[bin * num_channels..(bin + 1) * num_channels]
+            .iter()
+            .sum();
+        let hops: f64 = self.hop_propensities[bin * num_species..(bin + 1) * num_species]
+            .iter()
+            .sum();
+        reactions + hops
+    }
+
+    /// Returns the rate of the hops of the molecules of `species` out of
+    /// `bin`.
+    fn hop_propensity(&self, species: SpeciesID, bin: usize) -> f64 {
+        let n = f64::from(self.counts[species.0][bin]);
+        if n == 0.0 {
+            return 0.0;
+        }
+        Direction::ALL
+            .iter()
+            .filter(|&&direction| self.hop_target(species, bin, direction).is_some())
+            .map(|direction| n * self.hops[species.0][direction.index()])
+            .sum()
+    }
+
+    fn propensity(&self, channel: usize, bin: usize) -> f64 {
+        let channel = &self.channels[channel];
+        if self.space.voxel(Coordinate(bin)) != channel.location {
+            return 0.0;
+        }
+        let count = |id: SpeciesID| f64::from(self.counts[id.0][bin]);
+        let k = channel.rule.k();
+        match channel.reactants[..] {
+            [] => k,
+            [a] => k * count(a),
+            [a, b] if a == b => k * count(a) * (count(a) - 1.0) / 2.0,
+            [a, b] => k * count(a) * count(b),
+            _ => unreachable!("at most two reactants"),
+        }
+    }
+
+    fn fire(&mut self, channel: usize, bin: usize) {
+        let Channel {
+            reactants,
+            products,
+            ..
+        } = &self.channels[channel];
+        for id in reactants {
+            self.counts[id.0][bin] -= 1;
+        }
+        for id in products {
+            self.counts[id.0][bin] += 1;
+        }
+    }
+
+    /// Returns the bin a molecule of `species` in `bin` hops to in
+    /// `direction`, `None` if the hop is rejected.
+    fn hop_target(&self, species: SpeciesID, bin: usize, direction: Direction) -> Option<usize> {
+        if self.hops[species.0][direction.index()] == 0.0 {
+            return None;
+        }
+        let to = self.space.neighbor(Coordinate(bin), direction).ok()??;
+        if self.space.can_occupy(species, to) {
+            Some(to.0)
+        } else {
+            None
+        }
+    }
+}
+
+impl NsmSimulator<Pcg64> {
+    /// Creates a simulator drawing from a `Pcg64` seeded with `seed`.
+    pub fn with_seed(space: &HCPLatticeSpace, seed: u64) -> Self {
+        Self::from_space(space, Pcg64::seed_from_u64(seed))
+    }
+}
+
+/// Returns the rate of a hop of a molecule of `species` in each direction.
+fn hop_rates(space: &HCPLatticeSpace, species: SpeciesID) -> [f64; 12] {
+    let mut rates = [0.0; 12];
+    if let Some(interval) = space.diffusion_interval(species) {
+        let weights = space.hop_weights(species);
+        let total: f64 = weights.iter().sum();
+        for (rate, weight) in rates.iter_mut().zip(&weights) {
+            *rate = weight / (total * interval);
+        }
+    }
+    rates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HCPLatticeSize, MoleculeInfo, Species};
+
+    fn birth_death_space() -> (HCPLatticeSpace, SpeciesID, SpeciesID) {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let wall = space.register_obstacle(Species::new("W"));
+        let a = space.register_species(Species::new("A"), None);
+        space
+            .set_molecule_info(
+                a,
+                MoleculeInfo {
+                    radius: 1.0,
+                    diffusion_coefficient: 2.0 / 3.0,
+                },
+            )
+            .unwrap();
+        for c in space.coordinates().step_by(7).collect::<Vec<_>>() {
+            space.place_particle(a, c).unwrap();
+        }
+        for c in space.coordinates().skip(3).step_by(49).collect::<Vec<_>>() {
+            space.place_particle(wall, c).unwrap();
+        }
+        (space, wall, a)
+    }
+
+    /// The mean of the total count of `∅ -> A` at `kb` per bin and
+    /// `A -> ∅` at `kd` follows `dN/dt = B kb - kd N` over the `B` bins
+    /// free of walls, its distribution being Poisson at every time when
+    /// it starts so.
+    #[test]
+    fn birth_death_follows_the_ode() {
+        let (space, wall, a) = birth_death_space();
+        let (kb, kd) = (0.5, 1.0);
+        let bins = space.num_voxels() - space.num_molecules(wall).unwrap();
+        let n0 = space.num_molecules(a).unwrap() as f64;
+        let runs = 40;
+        let interval = 0.5;
+        let mut sums = [0.0; 9];
+        for seed in 0..runs {
+            let mut nsm = NsmSimulator::with_seed(&space, seed);
+            assert_eq!(nsm.num_molecules(a), n0 as u64);
+            assert_eq!(nsm.space().num_molecules(a).unwrap(), 0);
+            nsm.add_reaction(ReactionRule::new(vec![], vec![Species::new("A")], kb))
+                .unwrap();
+            nsm.add_reaction(ReactionRule::new(vec![Species::new("A")], vec![], kd))
+                .unwrap();
+            nsm.run_with_observer(4.0, interval, |t, nsm| {
+                sums[(t / interval).round() as usize] += nsm.num_molecules(a) as f64;
+            });
+            assert_eq!(nsm.t(), 4.0);
+            for c in nsm.space().coordinates_of(wall).unwrap() {
+                assert_eq!(nsm.bin_counts(a)[c.0], 0);
+            }
+        }
+        let steady = bins as f64 * kb / kd;
+        for (i, sum) in sums.iter().enumerate() {
+            let t = i as f64 * interval;
+            let expected = steady + (n0 - steady) * (-kd * t).exp();
+            let mean = sum / runs as f64;
+            let sd = (expected / runs as f64).sqrt();
+            assert!(
+                (mean - expected).abs() < 4.0 * sd,
+                "t {} mean {} expected {}",
+                t,
+                mean,
+                expected
+            );
+        }
+    }
+
+    /// A ring of `n` species converting into the next one, fed by a
+    /// source of the first species.
+    fn ring(n: usize, seed: u64) -> (NsmSimulator, Vec<SpeciesID>) {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let species: Vec<SpeciesID> = (0..n)
+            .map(|i| space.register_species(Species::new(&format!("S{}", i)), None))
+            .collect();
+        space
+            .set_molecule_info(
+                species[0],
+                MoleculeInfo {
+                    radius: 1.0,
+                    diffusion_coefficient: 0.1,
+                },
+            )
+            .unwrap();
+        let mut nsm = NsmSimulator::with_seed(&space, seed);
+        let name = |i: usize| Species::new(&format!("S{}", i % n));
+        nsm.add_reaction(ReactionRule::new(vec![], vec![name(0)], 0.2))
+            .unwrap();
+        for i in 0..n {
+            nsm.add_reaction(ReactionRule::new(vec![name(i)], vec![name(i + 1)], 1.0))
+                .unwrap();
+        }
+        nsm.add_reaction(ReactionRule::new(
+            vec![name(0), name(1)],
+            vec![name(2)],
+            0.5,
+        ))
+        .unwrap();
+        nsm.add_reaction(ReactionRule::new(vec![name(0), name(0)], vec![], 0.5))
+            .unwrap();
+        (nsm, species)
+    }
+
+    /// Recomputing only the propensities the dependency graph points to
+    /// leaves the cache as recomputing all of them would, so that both
+    /// schedulers draw the same trajectory from the same seed.
+    #[test]
+    fn dependency_graph_matches_the_naive_scheduler() {
+        let (mut cached, species) = ring(6, 9);
+        let (mut naive, _) = ring(6, 9);
+        naive.naive = true;
+        cached.run(30.0);
+        naive.run(30.0);
+        assert!(cached.num_steps() > 1000);
+        assert_eq!(cached.num_steps(), naive.num_steps());
+        for &id in &species {
+            assert_eq!(cached.bin_counts(id), naive.bin_counts(id));
+        }
+        let (cached, naive) = (cached.scheduler_stats(), naive.scheduler_stats());
+        assert_eq!(cached.events, naive.events);
+        assert!(cached.recomputations < naive.recomputations);
+    }
+
+    #[test]
+    fn hundred_channels_recompute_a_few() {
+        let (mut nsm, _) = ring(100, 1);
+        nsm.run(20.0);
+        let stats = nsm.scheduler_stats();
+        assert!(stats.events > 1000);
+        assert!(stats.per_event() < 6.0, "{:?}", stats);
+
+        let (mut naive, _) = ring(100, 1);
+        naive.naive = true;
+        naive.run(20.0);
+        assert!(naive.scheduler_stats().per_event() > 200.0);
+    }
+
+    #[test]
+    fn hops_spread_counts_within_their_location() {
+        let (space, wall, a) = birth_death_space();
+        let mut nsm = NsmSimulator::with_seed(&space, 3);
+        let start = space.global_to_coordinate(3, 3, 3).unwrap();
+        nsm.set_count(a, start, 1000).unwrap();
+        let wall_voxel = space.coordinates_of(wall).unwrap()[0];
+        assert!(matches!(
+            nsm.set_count(a, wall_voxel, 1),
+            Err(Error::InvalidLocation(_, _))
+        ));
+        assert!(matches!(
+            nsm.set_count(wall, start, 1),
+            Err(Error::InvalidLocation(_, _))
+        ));
+        assert!(matches!(
+            nsm.add_reaction(ReactionRule::new(vec![Species::new("B")], vec![], 1.0)),
+            Err(Error::SpeciesNotFound(_))
+        ));
+        assert!(matches!(
+            nsm.add_reaction(ReactionRule::new(vec![Species::new("W")], vec![], 1.0)),
+            Err(Error::InvalidReaction)
+        ));
+        let total = nsm.num_molecules(a);
+        nsm.run(20.0);
+        assert_eq!(nsm.num_molecules(a), total);
+        assert!(nsm.num_steps() > 0);
+        assert_eq!(nsm.bin_counts(a)[wall_voxel.0], 0);
+        let mean = total as f64 / (216 - nsm.space().num_molecules(wall).unwrap()) as f64;
+        assert!(f64::from(nsm.bin_counts(a)[start.0]) < 3.0 * mean);
+    }
+}
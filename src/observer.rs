@@ -0,0 +1,355 @@
+//! Observers recording the state of a simulation.
+
+use crate::lattice::LatticeSpace;
+use crate::{HCPLatticeSpace, ParticleID, Region, Species};
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// A probe called by the simulator at the times it asks for.
+///
+/// The simulator first calls `next_time` when the observer is added and then
+/// again after every `fire`; returning `None` retires the observer. Observers
+/// due at the same time fire in the order they were added, between events,
+/// so each sees the space as left by every event scheduled before it.
+///
+/// `S` is the space observed; the built-in observers observe any
+/// `LatticeSpace`.
+pub trait Observer<S = HCPLatticeSpace> {
+    fn next_time(&self) -> Option<f64>;
+    fn fire(&mut self, t: f64, space: &S);
+}
+
+/// Records the molecule counts of a list of species every `interval` of
+/// simulated time, starting at the time it was added to the simulator.
+#[derive(Clone, PartialEq, Debug)]
+pub struct NumberObserver {
+    species: Vec<Species>,
+    interval: f64,
+    start: f64,
+    data: Vec<(f64, Vec<usize>)>,
+}
+
+impl NumberObserver {
+    pub(crate) fn new(species: Vec<Species>, interval: f64, start: f64) -> Self {
+        Self {
+            species,
+            interval,
+            start,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn species(&self) -> &[Species] {
+        &self.species
+    }
+
+    pub fn interval(&self) -> f64 {
+        self.interval
+    }
+
+    /// Returns the recorded `(t, counts)` rows, counts being in the order of
+    /// `species`.
+    pub fn data(&self) -> &[(f64, Vec<usize>)] {
+        &self.data
+    }
+
+    /// Writes the recorded rows as CSV with a `t,<species>...` header.
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "t")?;
+        for species in &self.species {
+            write!(writer, ",{}", species.name())?;
+        }
+        writeln!(writer)?;
+        for (t, counts) in &self.data {
+            write!(writer, "{}", t)?;
+            for count in counts {
+                write!(writer, ",{}", count)?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: LatticeSpace> Observer<S> for NumberObserver {
+    /// The sample times are computed from the sample index rather than
+    /// accumulated, so that they do not drift.
+    fn next_time(&self) -> Option<f64> {
+        Some(self.start + self.data.len() as f64 * self.interval)
+    }
+
+    fn fire(&mut self, t: f64, space: &S) {
+        let counts = self
+            .species
+            .iter()
+            .map(|species| {
+                space
+                    .find_species(species.name())
+                    .and_then(|id| space.num_molecules(id).ok())
+                    .unwrap_or(0)
+            })
+            .collect();
+        self.data.push((t, counts));
+    }
+}
+
+/// Records the molecule counts of a list of species within a region every
+/// `interval`, starting at the time it was added, e.g. the recovery of a
+/// photobleached region. A molecule is within the region if the center of
+/// its voxel is.
+#[derive(Clone)]
+pub struct RegionObserver {
+    species: Vec<Species>,
+    region: Arc<dyn Region>,
+    interval: f64,
+    start: f64,
+    data: Vec<(f64, Vec<usize>)>,
+}
+
+impl RegionObserver {
+    pub(crate) fn new(
+        species: Vec<Species>,
+        region: Arc<dyn Region>,
+        interval: f64,
+        start: f64,
+    ) -> Self {
+        Self {
+            species,
+            region,
+            interval,
+            start,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn species(&self) -> &[Species] {
+        &self.species
+    }
+
+    /// Returns the recorded `(t, counts)` rows, counts being in the order of
+    /// `species`.
+    pub fn data(&self) -> &[(f64, Vec<usize>)] {
+        &self.data
+    }
+}
+
+impl fmt::Debug for RegionObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegionObserver")
+            .field("species", &self.species)
+            .field("interval", &self.interval)
+            .field("start", &self.start)
+            .field("data", &self.data)
+            .finish()
+    }
+}
+
+impl<S: LatticeSpace> Observer<S> for RegionObserver {
+    fn next_time(&self) -> Option<f64> {
+        Some(self.start + self.data.len() as f64 * self.interval)
+    }
+
+    fn fire(&mut self, t: f64, space: &S) {
+        let region = &self.region;
+        let counts = self
+            .species
+            .iter()
+            .map(|species| match space.find_species(species.name()) {
+                Some(id) => space
+                    .coordinates_of(id)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|&c| space.position(c).is_ok_and(|p| region.contains(p)))
+                    .count(),
+                None => 0,
+            })
+            .collect();
+        self.data.push((t, counts));
+    }
+}
+
+/// Records the numbers of molecules absorbed so far by every sink of the
+/// `sink` module every `interval`, starting at the time it was added.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SinkObserver {
+    interval: f64,
+    start: f64,
+    data: Vec<(f64, Vec<u64>)>,
+}
+
+impl SinkObserver {
+    pub(crate) fn new(interval: f64, start: f64) -> Self {
+        Self {
+            interval,
+            start,
+            data: Vec::new(),
+        }
+    }
+
+    /// Returns the recorded `(t, counts)` rows, counts being in the order
+    /// the sinks were added and cumulative.
+    pub fn data(&self) -> &[(f64, Vec<u64>)] {
+        &self.data
+    }
+}
+
+impl<S: LatticeSpace> Observer<S> for SinkObserver {
+    fn next_time(&self) -> Option<f64> {
+        Some(self.start + self.data.len() as f64 * self.interval)
+    }
+
+    fn fire(&mut self, t: f64, space: &S) {
+        self.data.push((t, space.sink_counts()));
+    }
+}
+
+/// Which particles a `TrajectoryObserver` follows.
+#[derive(Clone, PartialEq, Debug)]
+pub enum TrajectoryTarget {
+    Particles(Vec<ParticleID>),
+    /// Every particle of the species present at the first sample.
+    Species(Species),
+}
+
+/// The sampled positions of one particle.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Trajectory {
+    pub pid: ParticleID,
+    pub species: Option<Species>,
+    pub times: Vec<f64>,
+    pub positions: Vec<[f64; 3]>,
+    /// The first sample time at which the particle no longer existed.
+    pub death: Option<f64>,
+    last_wrapped: Option<[f64; 3]>,
+}
+
+impl Trajectory {
+    fn new(pid: ParticleID) -> Self {
+        Self {
+            pid,
+            species: None,
+            times: Vec::new(),
+            positions: Vec::new(),
+            death: None,
+            last_wrapped: None,
+        }
+    }
+}
+
+/// Records the real-space positions of selected particles every `interval`.
+///
+/// With `unwrapped`, a displacement across a periodic boundary is replaced
+/// by its minimum image, so that the recorded positions are continuous. This
+/// assumes that a particle moves less than half the lattice between two
+/// samples.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TrajectoryObserver {
+    target: TrajectoryTarget,
+    interval: f64,
+    start: f64,
+    unwrapped: bool,
+    num_samples: usize,
+    trajectories: Vec<Trajectory>,
+}
+
+impl TrajectoryObserver {
+    pub(crate) fn new(
+        target: TrajectoryTarget,
+        interval: f64,
+        start: f64,
+        unwrapped: bool,
+    ) -> Self {
+        let trajectories = match &target {
+            TrajectoryTarget::Particles(pids) => {
+                pids.iter().map(|&pid| Trajectory::new(pid)).collect()
+            }
+            TrajectoryTarget::Species(_) => Vec::new(),
+        };
+        Self {
+            target,
+            interval,
+            start,
+            unwrapped,
+            num_samples: 0,
+            trajectories,
+        }
+    }
+
+    pub fn trajectories(&self) -> &[Trajectory] {
+        &self.trajectories
+    }
+
+    /// Writes one `lot,serial,species,t,x,y,z` row per sample.
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "lot,serial,species,t,x,y,z")?;
+        for trajectory in &self.trajectories {
+            let name = trajectory.species.as_ref().map_or("", |s| s.name());
+            for (t, [x, y, z]) in trajectory.times.iter().zip(&trajectory.positions) {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{}",
+                    trajectory.pid.0, trajectory.pid.1, name, t, x, y, z
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: LatticeSpace> Observer<S> for TrajectoryObserver {
+    fn next_time(&self) -> Option<f64> {
+        Some(self.start + self.num_samples as f64 * self.interval)
+    }
+
+    fn fire(&mut self, t: f64, space: &S) {
+        if self.num_samples == 0 {
+            if let TrajectoryTarget::Species(species) = &self.target {
+                if let Some(id) = space.find_species(species.name()) {
+                    self.trajectories = space
+                        .particles_of(id)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(Trajectory::new)
+                        .collect();
+                }
+            }
+        }
+        self.num_samples += 1;
+
+        let periods = space.periodic_lengths();
+        let unwrap = self.unwrapped && space.is_periodic();
+        for trajectory in &mut self.trajectories {
+            if trajectory.death.is_some() {
+                continue;
+            }
+            let (species, coordinate) = match space.find_particle(trajectory.pid) {
+                Some(found) => found,
+                None => {
+                    trajectory.death = Some(t);
+                    continue;
+                }
+            };
+            if trajectory.species.is_none() {
+                trajectory.species = Some(species.clone());
+            }
+            let wrapped = space
+                .position(coordinate)
+                .expect("tracked particles lie inside the lattice");
+            let position = match (trajectory.last_wrapped, trajectory.positions.last()) {
+                (Some(last), Some(previous)) if unwrap => {
+                    let mut position = *previous;
+                    for axis in 0..3 {
+                        let delta = wrapped[axis] - last[axis];
+                        position[axis] += delta - periods[axis] * (delta / periods[axis]).round();
+                    }
+                    position
+                }
+                _ => wrapped,
+            };
+            trajectory.last_wrapped = Some(wrapped);
+            trajectory.times.push(t);
+            trajectory.positions.push(position);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Obstacles: immobile species excluding volume.
+//!
+//! An obstacle molecule never moves and is never consumed by a reaction, and
+//! no species can be located on an obstacle, so no molecule ever enters its
+//! voxel either. Hops into it are rejected like hops into any other occupied
+//! voxel. Obstacles are placed like any other species, or a region at a time
+//! with `populate_structure`.
+
+use crate::region::Region;
+use crate::{Coordinate, HCPLatticeSpace, Result, Species, SpeciesID};
+
+impl HCPLatticeSpace {
+    /// Registers an obstacle species on vacant voxels.
+    pub fn register_obstacle(&mut self, species: Species) -> SpeciesID {
+        let id = self.register_species(species, None);
+        self.get_species_cache_mut(id)
+            .expect("just registered")
+            .obstacle = true;
+        id
+    }
+
+    /// Returns true if `species` is an obstacle, false for an ID of no
+    /// species of this space.
+    pub fn is_obstacle(&self, species: SpeciesID) -> bool {
+        self.get_species_cache(species)
+            .is_ok_and(|cache| cache.obstacle)
+    }
+
+    /// Places a molecule of `structure`, an obstacle or any other species,
+    /// on every voxel of its location centered in `region`, in coordinate
+    /// order, and returns how many it placed. Voxels holding anything else
+    /// are left alone.
+    pub fn populate_structure(
+        &mut self,
+        structure: SpeciesID,
+        region: &dyn Region,
+    ) -> Result<usize> {
+        let location = self.location_of(structure)?;
+        let targets: Vec<Coordinate> = self
+            .coordinates_in(region)
+            .filter(|&c| self.voxel(c) == location)
+            .collect();
+        for &coordinate in &targets {
+            self.place_particle(structure, coordinate)?;
+        }
+        Ok(targets.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::region::Cuboid;
+    use crate::{Error, HCPLatticeSize, MoleculeInfo, ReactionRule, Simulator};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// A wall of obstacles filling `7.5 <= x <= 10.5`, thicker than a hop,
+    /// with `A` molecules on its west side.
+    fn walled_space() -> (HCPLatticeSpace, SpeciesID, SpeciesID) {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(8, 10, 8));
+        let wall = space.register_obstacle(Species::new("W"));
+        let a = space.register_species(Species::new("A"), None);
+        let [_, ly, lz] = space.periodic_lengths();
+        let slab = Cuboid::new([7.5, -1.0, -1.0], [10.5, ly, lz]);
+        let num_walls = space.coordinates_in(&slab).count();
+        assert_eq!(space.populate_structure(wall, &slab).unwrap(), num_walls);
+        assert_eq!(space.populate_structure(wall, &slab).unwrap(), 0);
+        for c in space.coordinates().collect::<Vec<_>>() {
+            if space.coordinate_to_global(c).unwrap().1 < 3 && c.0 % 3 == 0 {
+                space.place_particle(a, c).unwrap();
+            }
+        }
+        (space, wall, a)
+    }
+
+    #[test]
+    fn walls_block_diffusion() {
+        let (mut space, wall, a) = walled_space();
+        let walls = space.coordinates_of(wall).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..2000 {
+            space.walk(a, &mut rng).unwrap();
+            space.walk(wall, &mut rng).unwrap();
+        }
+        space.validate().unwrap();
+        assert_eq!(space.coordinates_of(wall).unwrap(), walls);
+        let x_wall = 7.5;
+        assert!(
+            space
+                .coordinates_of(a)
+                .unwrap()
+                .iter()
+                .all(|&c| space.coordinate_to_position(c).unwrap()[0] < x_wall),
+            "{}",
+            space.render_layer(0).unwrap()
+        );
+        // They do reach the wall.
+        assert!(space
+            .coordinates_of(a)
+            .unwrap()
+            .iter()
+            .any(|&c| space.coordinate_to_position(c).unwrap()[0] > x_wall - 3.0));
+
+        let brick = walls[0];
+        let free = space.nearest_empty(brick, 3, a).unwrap();
+        assert!(space.move_particle(brick, free).is_err());
+        assert_eq!(space.nearest_empty(brick, 0, a), None);
+    }
+
+    #[test]
+    fn obstacles_do_not_react() {
+        let (mut space, wall, a) = walled_space();
+        space
+            .set_molecule_info(
+                wall,
+                MoleculeInfo {
+                    radius: 1.0,
+                    diffusion_coefficient: 1.0,
+                },
+            )
+            .unwrap();
+        assert_eq!(space.diffusion_interval(wall), None);
+        let b = space.register_species(Species::new("B"), None);
+        let brick = space.coordinates_of(wall).unwrap()[0];
+        let contact = space
+            .neighbors(brick)
+            .unwrap()
+            .into_iter()
+            .find(|&c| space.species_at(c).unwrap().is_none())
+            .unwrap();
+        space.place_particle(a, contact).unwrap();
+        assert!(matches!(
+            space.react_bimolecular(contact, brick, b),
+            Err(Error::InvalidReaction)
+        ));
+        assert!(matches!(
+            space.react_bimolecular(brick, contact, b),
+            Err(Error::InvalidReaction)
+        ));
+
+        let mut sim = Simulator::new(space, StdRng::seed_from_u64(1));
+        let decay = ReactionRule::new(vec![Species::new("W")], vec![], 1.0);
+        assert!(matches!(
+            sim.add_reaction(decay),
+            Err(Error::InvalidReaction)
+        ));
+    }
+}
@@ -0,0 +1,141 @@
+//! A diffusion sweep drawing its hops in parallel, behind the `rayon`
+//! feature.
+//!
+//! `walk_parallel` cuts the molecules of a species, in placement order, into
+//! chunks of `CHUNK_LEN` and draws the hops of each chunk on the thread pool
+//! from its own `Pcg64` stream, all streams being seeded by one number drawn
+//! from the caller's generator. The hops are then applied one after another
+//! in placement order, a hop into a voxel taken by an earlier one being
+//! rejected as in `walk`.
+//!
+//! The chunks do not depend on the number of threads, so that a sweep is
+//! reproducible given the seed on any thread pool. It is not the sweep `walk`
+//! makes from the same seed, which draws every hop from the caller's
+//! generator, but it has the same statistics in the dilute limit.
+
+use crate::{sample_direction, Coordinate, Error, HCPLatticeSpace, Result, SpeciesID};
+use rand::Rng;
+use rand_pcg::Pcg64;
+use rayon::prelude::*;
+
+/// The number of molecules whose hops are drawn from one stream.
+const CHUNK_LEN: usize = 1024;
+
+impl HCPLatticeSpace {
+    /// Same as `walk`, with the hops drawn in parallel. See the module
+    /// documentation for how its random numbers differ from those of `walk`.
+    pub fn walk_parallel<R: Rng>(&mut self, species: SpeciesID, rng: &mut R) -> Result<()> {
+        self.check_species(species)?;
+        let seed: u64 = rng.gen();
+        let weights = self.hop_weights(species);
+        let total: f64 = weights.iter().sum();
+        let froms = self.coordinates_of(species)?;
+        let space = &*self;
+        let mut chunks = froms
+            .par_chunks(CHUNK_LEN)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut rng = Pcg64::new(seed.into(), i as u128);
+                let hops = chunk
+                    .iter()
+                    .map(|&from| {
+                        let direction = sample_direction(&weights, total, &mut rng);
+                        Ok((from, space.neighbor(from, direction)?))
+                    })
+                    .collect::<Result<Vec<(Coordinate, Option<Coordinate>)>>>()?;
+                Ok((rng, hops))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for (rng, hops) in &mut chunks {
+            for &(from, to) in hops.iter() {
+                if let Some(to) = to {
+                    if self.try_absorb(species, from, to)? {
+                        continue;
+                    }
+                    if self.try_transition(species, from, to, rng)? {
+                        continue;
+                    }
+                    if self.try_collision(species, from, to, rng)? {
+                        continue;
+                    }
+                    if !self.accept_hop(species, from, to, rng) {
+                        continue;
+                    }
+                    match self.move_particle(from, to) {
+                        Ok(()) | Err(Error::InvalidLocation(..)) => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HCPLatticeSize, ParticleID, Species};
+    use rand::SeedableRng;
+
+    /// Walks 2000 molecules on a periodic lattice for 40 sweeps and returns
+    /// their mean squared displacement and final voxels.
+    fn sweep(parallel: bool) -> (f64, Vec<Coordinate>) {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(30, 30, 30));
+        space.set_periodic(true).unwrap();
+        space.set_image_tracking(true);
+        let a = space.register_species(Species::new("A"), None);
+        let pids: Vec<ParticleID> = (0..27000)
+            .step_by(13)
+            .take(2000)
+            .map(|i| space.place_particle(a, Coordinate(i)).unwrap())
+            .collect();
+        let starts: Vec<[f64; 3]> = pids
+            .iter()
+            .map(|&pid| space.unwrapped_position(pid).unwrap())
+            .collect();
+
+        let mut rng = Pcg64::seed_from_u64(5);
+        for _ in 0..40 {
+            if parallel {
+                space.walk_parallel(a, &mut rng).unwrap();
+            } else {
+                space.walk(a, &mut rng).unwrap();
+            }
+        }
+        space.validate().unwrap();
+
+        let msd = pids
+            .iter()
+            .zip(&starts)
+            .map(|(&pid, start)| {
+                let end = space.unwrapped_position(pid).unwrap();
+                (0..3).map(|i| (end[i] - start[i]).powi(2)).sum::<f64>()
+            })
+            .sum::<f64>()
+            / pids.len() as f64;
+        (msd, space.coordinates_of(a).unwrap())
+    }
+
+    #[test]
+    fn matches_serial_msd() {
+        let (serial, _) = sweep(false);
+        let (parallel, _) = sweep(true);
+        // 40 hops of length 2r, slightly fewer for the blocked ones.
+        assert!((serial / 160.0 - 0.95).abs() < 0.05);
+        assert!((parallel - serial).abs() < 0.1 * serial);
+    }
+
+    #[test]
+    fn independent_of_thread_count() {
+        let run = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| sweep(true))
+        };
+        assert_eq!(run(1), run(4));
+    }
+}
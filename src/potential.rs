@@ -0,0 +1,173 @@
+//! A scalar potential over the voxels biasing diffusion, e.g. the
+//! electrostatic attraction of a membrane or a chemotactic gradient.
+//!
+//! The potential `U` of each voxel is in units of kT and only species set
+//! with `set_metropolis` feel it: their hops in `walk`, `walk_biased` or
+//! `walk_parallel` from voxel `i` to voxel `j` are accepted with the
+//! probability `min(1, exp(-(U_j - U_i)))`, which brings their density to
+//! the Boltzmann distribution `exp(-U)` at equilibrium. The other species
+//! hop as if there was no potential. Nothing is allocated until a potential
+//! is first set, every voxel then starting at zero.
+
+use crate::{Coordinate, HCPLatticeSpace, Result, SpeciesID};
+
+impl HCPLatticeSpace {
+    /// Sets the potential of the voxel at `coordinate` to `value`.
+    pub fn set_potential(&mut self, coordinate: Coordinate, value: f32) -> Result<()> {
+        self.check_bounds(coordinate)?;
+        self.potential_mut()[coordinate.0] = value;
+        Ok(())
+    }
+
+    /// Sets the potential of every voxel to `potential` of its center.
+    pub fn set_potential_with<F>(&mut self, potential: F)
+    where
+        F: Fn([f64; 3]) -> f32,
+    {
+        let values: Vec<f32> = self
+            .coordinates()
+            .map(|c| potential(self.coordinate_to_position(c).unwrap()))
+            .collect();
+        self.potential = Some(values.into_boxed_slice());
+    }
+
+    /// Returns the potential of the voxel at `coordinate`, zero if none has
+    /// been set or if it is outside the lattice.
+    pub fn potential(&self, coordinate: Coordinate) -> f32 {
+        self.potential
+            .as_ref()
+            .and_then(|potential| potential.get(coordinate.0).copied())
+            .unwrap_or(0.0)
+    }
+
+    /// Makes the hops of `species` feel the potential or not. Fails with
+    /// `UnknownSpecies` for an ID of no species of this space.
+    pub fn set_metropolis(&mut self, species: SpeciesID, metropolis: bool) -> Result<()> {
+        self.get_species_cache_mut(species)?.metropolis = metropolis;
+        Ok(())
+    }
+
+    /// Returns true if the hops of `species` feel the potential, false for
+    /// an ID of no species of this space.
+    pub fn is_metropolis(&self, species: SpeciesID) -> bool {
+        self.get_species_cache(species)
+            .is_ok_and(|cache| cache.metropolis)
+    }
+
+    /// Returns the change in potential of a molecule of `species` hopping
+    /// from `from` to `to`, zero if it does not feel the potential.
+    pub(crate) fn potential_change(
+        &self,
+        species: SpeciesID,
+        from: Coordinate,
+        to: Coordinate,
+    ) -> f64 {
+        match &self.potential {
+            Some(potential) if self.species_cache[species.0].metropolis => {
+                f64::from(potential[to.0]) - f64::from(potential[from.0])
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn potential_mut(&mut self) -> &mut [f32] {
+        let n = self.num_voxels();
+        self.potential
+            .get_or_insert_with(|| vec![0.0; n].into_boxed_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, HCPLatticeSize, MoleculeInfo, Species};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn allocated_when_first_set() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        assert!(space.potential.is_none());
+        assert_eq!(space.potential(Coordinate(5)), 0.0);
+        assert!(matches!(
+            space.set_potential(Coordinate(64), 1.0),
+            Err(Error::OutOfRange(_))
+        ));
+        assert!(space.potential.is_none());
+        space.set_potential(Coordinate(5), 1.5).unwrap();
+        assert_eq!(space.potential.as_ref().unwrap().len(), 64);
+        assert_eq!(space.potential(Coordinate(5)), 1.5);
+        assert_eq!(space.potential(Coordinate(6)), 0.0);
+        assert_eq!(space.potential(Coordinate(64)), 0.0);
+    }
+
+    /// Returns the mean number of molecules on each layer of a lattice in
+    /// the potential `g z`, `flagged` saying whether they feel it.
+    fn layer_occupancy(g: f64, flagged: bool) -> (HCPLatticeSpace, Vec<f64>) {
+        let (rows, cols, layers) = (10, 10, 8);
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(rows, cols, layers));
+        let a = space.register_species(Species::new("A"), None);
+        space
+            .set_molecule_info(
+                a,
+                MoleculeInfo {
+                    radius: 1.0,
+                    diffusion_coefficient: 1.0,
+                },
+            )
+            .unwrap();
+        space.set_metropolis(a, flagged).unwrap();
+        space.set_potential_with(|p| (g * p[2]) as f32);
+        for c in space.coordinates().step_by(20).collect::<Vec<_>>() {
+            space.place_particle(a, c).unwrap();
+        }
+        let mut rng = StdRng::seed_from_u64(5);
+        let (burn_in, sweeps) = (500, 8000);
+        let mut sums = vec![0.0; layers];
+        for sweep in 0..burn_in + sweeps {
+            space.walk(a, &mut rng).unwrap();
+            if sweep >= burn_in {
+                for c in space.coordinates_of(a).unwrap() {
+                    let (_, _, layer) = space.coordinate_to_global(c).unwrap();
+                    sums[layer] += 1.0;
+                }
+            }
+        }
+        let occupancy = sums
+            .iter()
+            .map(|sum| sum / (sweeps * rows * cols) as f64)
+            .collect();
+        (space, occupancy)
+    }
+
+    /// The molecules exclude each other, so that the occupancy `φ` of a
+    /// layer follows the Fermi distribution: `ln(φ/(1 - φ))` is linear in
+    /// the potential with a slope of -1.
+    #[test]
+    fn boltzmann_profile() {
+        let g = 0.3;
+        let (space, occupancy) = layer_occupancy(g, true);
+        space.validate().unwrap();
+        let points: Vec<(f64, f64)> = occupancy
+            .iter()
+            .enumerate()
+            .map(|(layer, &phi)| {
+                let c = space.global_to_coordinate(0, 0, layer).unwrap();
+                (f64::from(space.potential(c)), (phi / (1.0 - phi)).ln())
+            })
+            .collect();
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+        let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+        let sxy: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+        let sxx: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+        let slope = sxy / sxx;
+        assert!((slope + 1.0).abs() < 0.1, "{} {:?}", slope, occupancy);
+
+        let (_, occupancy) = layer_occupancy(g, false);
+        let mean = occupancy.iter().sum::<f64>() / occupancy.len() as f64;
+        for phi in &occupancy {
+            assert!((phi / mean - 1.0).abs() < 0.2, "{:?}", occupancy);
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! User-defined data attached to voxels, e.g. a region label, a local rate
+//! modifier or a flag marking damaged sites.
+//!
+//! `add_voxel_property` adds a property of any type with a default value
+//! and returns a `PropertyHandle` to read and write it by coordinate. A
+//! property holds a value per voxel, but nothing is allocated until a value
+//! is first set, every voxel reading the default until then. Reactions of
+//! the `Simulator` can read properties through `set_reaction_modifier`.
+//!
+//! A handle is only meaningful for the space that returned it, or for its
+//! clones; methods given a handle of another space may panic.
+
+use crate::{Coordinate, HCPLatticeSpace, Result};
+use std::any::Any;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// The name of a property of type `T` of a space.
+pub struct PropertyHandle<T> {
+    index: usize,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for PropertyHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for PropertyHandle<T> {}
+
+impl<T> PartialEq for PropertyHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for PropertyHandle<T> {}
+
+impl<T> fmt::Debug for PropertyHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PropertyHandle").field(&self.index).finish()
+    }
+}
+
+/// The values of a property, whatever their type.
+trait Values: Send + Sync {
+    fn clone_box(&self) -> Box<dyn Values>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    /// Moves the value of each voxel `i` to `map(i)` of `len` voxels, the
+    /// voxels mapped to `None` being dropped.
+    fn remap(&mut self, len: usize, map: &dyn Fn(usize) -> Option<usize>);
+}
+
+struct Dense<T> {
+    default: T,
+    values: Option<Box<[T]>>,
+}
+
+impl<T: Clone + Default + Send + Sync + 'static> Values for Dense<T> {
+    fn clone_box(&self) -> Box<dyn Values> {
+        Box::new(Dense {
+            default: self.default.clone(),
+            values: self.values.clone(),
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn remap(&mut self, len: usize, map: &dyn Fn(usize) -> Option<usize>) {
+        if let Some(values) = &mut self.values {
+            let mut remapped = vec![self.default.clone(); len].into_boxed_slice();
+            for (i, value) in values.iter().enumerate() {
+                if let Some(j) = map(i) {
+                    remapped[j] = value.clone();
+                }
+            }
+            *values = remapped;
+        }
+    }
+}
+
+pub(crate) struct VoxelProperty {
+    name: String,
+    values: Box<dyn Values>,
+}
+
+impl VoxelProperty {
+    /// Moves the values as `HCPLatticeSpace::resize` moves the voxels.
+    pub(crate) fn remap(&mut self, len: usize, map: &dyn Fn(usize) -> Option<usize>) {
+        self.values.remap(len, map);
+    }
+}
+
+impl Clone for VoxelProperty {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            values: self.values.clone_box(),
+        }
+    }
+}
+
+impl HCPLatticeSpace {
+    /// Adds a property named `name` holding `T::default()` on every voxel.
+    /// A name may be added again, `find_voxel_property` then finding the
+    /// last one.
+    pub fn add_voxel_property<T>(&mut self, name: &str) -> PropertyHandle<T>
+    where
+        T: Clone + Default + Send + Sync + 'static,
+    {
+        self.properties.push(VoxelProperty {
+            name: name.to_string(),
+            values: Box::new(Dense::<T> {
+                default: T::default(),
+                values: None,
+            }),
+        });
+        PropertyHandle {
+            index: self.properties.len() - 1,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the last property named `name` holding values of type `T`.
+    pub fn find_voxel_property<T: 'static>(&self, name: &str) -> Option<PropertyHandle<T>> {
+        self.properties
+            .iter()
+            .rposition(|property| {
+                property.name == name && property.values.as_any().is::<Dense<T>>()
+            })
+            .map(|index| PropertyHandle {
+                index,
+                marker: PhantomData,
+            })
+    }
+
+    /// Returns the value of `property` at `coordinate`.
+    pub fn voxel_property<T: 'static>(
+        &self,
+        property: PropertyHandle<T>,
+        coordinate: Coordinate,
+    ) -> Result<&T> {
+        self.check_bounds(coordinate)?;
+        let dense = self.dense(property);
+        Ok(dense
+            .values
+            .as_ref()
+            .map_or(&dense.default, |values| &values[coordinate.0]))
+    }
+
+    /// Sets the value of `property` at `coordinate`.
+    pub fn set_voxel_property<T: Clone + 'static>(
+        &mut self,
+        property: PropertyHandle<T>,
+        coordinate: Coordinate,
+        value: T,
+    ) -> Result<()> {
+        self.check_bounds(coordinate)?;
+        let n = self.num_voxels();
+        let dense = self.properties[property.index]
+            .values
+            .as_any_mut()
+            .downcast_mut::<Dense<T>>()
+            .expect("a handle of this space");
+        let Dense { default, values } = dense;
+        let values = values.get_or_insert_with(|| vec![default.clone(); n].into_boxed_slice());
+        values[coordinate.0] = value;
+        Ok(())
+    }
+
+    /// Iterates over the voxels where `property` differs from its default,
+    /// in coordinate order, with their values.
+    pub fn voxels_with_property<T: PartialEq + 'static>(
+        &self,
+        property: PropertyHandle<T>,
+    ) -> impl Iterator<Item = (Coordinate, &T)> {
+        let dense = self.dense(property);
+        dense
+            .values
+            .iter()
+            .flat_map(|values| values.iter().enumerate())
+            .filter(move |(_, value)| **value != dense.default)
+            .map(|(i, value)| (Coordinate(i), value))
+    }
+
+    fn dense<T: 'static>(&self, property: PropertyHandle<T>) -> &Dense<T> {
+        self.properties[property.index]
+            .values
+            .as_any()
+            .downcast_ref::<Dense<T>>()
+            .expect("a handle of this space")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, HCPLatticeSize};
+
+    #[test]
+    fn properties_of_different_types() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let label = space.add_voxel_property::<u8>("label");
+        let damaged = space.add_voxel_property::<bool>("damaged");
+        assert_eq!(space.voxel_property(label, Coordinate(3)).unwrap(), &0);
+        assert_eq!(space.voxels_with_property(damaged).count(), 0);
+
+        space.set_voxel_property(label, Coordinate(3), 7).unwrap();
+        space.set_voxel_property(label, Coordinate(9), 2).unwrap();
+        space
+            .set_voxel_property(damaged, Coordinate(9), true)
+            .unwrap();
+        assert_eq!(space.voxel_property(label, Coordinate(3)).unwrap(), &7);
+        assert_eq!(
+            space.voxel_property(damaged, Coordinate(3)).unwrap(),
+            &false
+        );
+        let labelled: Vec<(Coordinate, u8)> = space
+            .voxels_with_property(label)
+            .map(|(c, &value)| (c, value))
+            .collect();
+        assert_eq!(labelled, vec![(Coordinate(3), 7), (Coordinate(9), 2)]);
+        let flagged: Vec<Coordinate> = space
+            .voxels_with_property(damaged)
+            .map(|(c, _)| c)
+            .collect();
+        assert_eq!(flagged, vec![Coordinate(9)]);
+
+        let copy = space.clone();
+        space.set_voxel_property(label, Coordinate(3), 0).unwrap();
+        assert_eq!(copy.voxel_property(label, Coordinate(3)).unwrap(), &7);
+        assert_eq!(space.voxels_with_property(label).count(), 1);
+
+        assert_eq!(space.find_voxel_property::<u8>("label"), Some(label));
+        assert_eq!(space.find_voxel_property::<f64>("label"), None);
+        assert_eq!(space.find_voxel_property::<bool>("damaged"), Some(damaged));
+        assert_eq!(space.find_voxel_property::<bool>("rate"), None);
+    }
+
+    #[test]
+    fn out_of_range_coordinates() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(2, 2, 2));
+        let rate = space.add_voxel_property::<f64>("rate");
+        assert!(matches!(
+            space.voxel_property(rate, Coordinate(8)),
+            Err(Error::OutOfRange(Coordinate(8)))
+        ));
+        assert!(matches!(
+            space.set_voxel_property(rate, Coordinate(8), 0.5),
+            Err(Error::OutOfRange(Coordinate(8)))
+        ));
+        assert_eq!(space.voxels_with_property(rate).count(), 0);
+    }
+}
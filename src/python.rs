@@ -0,0 +1,508 @@
+//! Python bindings, for setting up models and running them from Python.
+//! `pyproject.toml` builds them with maturin into a `spatiocyte` extension
+//! module; `tests/python` holds its pytest suite.
+//!
+//! Species are passed as `Species` or by name. Spaces and simulators own
+//! their state: a `Simulator` takes a copy of the space it is given, and
+//! its `space` is a copy of the current one. Errors of the engine raise
+//! `SpatiocyteError` with the `Display` message of the `Error`.
+//!
+//! `Simulator.run` releases the GIL while the simulation steps, and takes
+//! it back every `every` steps to check for signals and to call `progress`
+//! if given, so that other Python threads run meanwhile and Ctrl-C stops
+//! the run with a `KeyboardInterrupt`. Either way the simulator is left at
+//! the last event processed, from which `run` picks up again.
+
+// PyO3's macros convert every result into a `PyResult`, even one already.
+#![allow(clippy::useless_conversion)]
+
+use crate::simulator::{ModelEvent, ReactionID};
+use crate::{
+    Coordinate, Error, HCPLatticeSize, HCPLatticeSpace, MoleculeInfo, ReactionRule, Simulator,
+    Species, SpeciesID,
+};
+use numpy::ndarray::Array2;
+use numpy::{IntoPyArray, PyArray1, PyArray2};
+use pyo3::exceptions::{PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use rand::SeedableRng;
+use rand_pcg::Pcg64;
+use std::num::NonZeroU64;
+use std::ops::ControlFlow;
+
+// The macro checks a feature of PyO3's own, unknown to this crate.
+#[allow(unexpected_cfgs)]
+mod exception {
+    use pyo3::create_exception;
+    use pyo3::exceptions::PyException;
+
+    create_exception!(
+        spatiocyte,
+        SpatiocyteError,
+        PyException,
+        "An error of the engine."
+    );
+}
+
+use exception::SpatiocyteError;
+
+impl From<Error> for PyErr {
+    fn from(err: Error) -> Self {
+        SpatiocyteError::new_err(err.to_string())
+    }
+}
+
+/// A species, identified by its name.
+#[pyclass(name = "Species", module = "spatiocyte", frozen)]
+#[derive(Clone)]
+pub struct PySpecies {
+    species: Species,
+}
+
+#[pymethods]
+impl PySpecies {
+    #[new]
+    fn new(name: &str) -> Self {
+        Self {
+            species: Species::new(name),
+        }
+    }
+
+    #[getter]
+    fn name(&self) -> &str {
+        self.species.name()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Species({:?})", self.species.name())
+    }
+
+    fn __eq__(&self, other: &Self) -> bool {
+        self.species == other.species
+    }
+
+    fn __hash__(&self) -> u64 {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.species.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// A species argument, a `Species` or its name.
+#[derive(FromPyObject)]
+enum SpeciesArg {
+    Species(PySpecies),
+    Name(String),
+}
+
+impl SpeciesArg {
+    fn into_species(self) -> Species {
+        match self {
+            SpeciesArg::Species(species) => species.species,
+            SpeciesArg::Name(name) => Species::new(&name),
+        }
+    }
+}
+
+fn species_id(space: &HCPLatticeSpace, species: SpeciesArg) -> PyResult<SpeciesID> {
+    let species = species.into_species();
+    match space.find_species(species.name()) {
+        Some(id) => Ok(id),
+        None => Err(Error::SpeciesNotFound(species).into()),
+    }
+}
+
+/// Returns the numbers of molecules of every species by name.
+fn counts<'py>(py: Python<'py>, space: &HCPLatticeSpace) -> PyResult<Bound<'py, PyDict>> {
+    let counts = PyDict::new_bound(py);
+    for (_, species, count) in space.species() {
+        counts.set_item(species.name(), count)?;
+    }
+    Ok(counts)
+}
+
+/// Returns the positions of the voxels of `species` as an `N × 3` array.
+fn positions_of<'py>(
+    py: Python<'py>,
+    space: &HCPLatticeSpace,
+    species: SpeciesArg,
+) -> PyResult<Bound<'py, PyArray2<f64>>> {
+    let id = species_id(space, species)?;
+    let coordinates = space.coordinates_of(id)?;
+    let mut positions = Vec::with_capacity(3 * coordinates.len());
+    for c in coordinates {
+        positions.extend(space.coordinate_to_position(c)?);
+    }
+    let positions = Array2::from_shape_vec((positions.len() / 3, 3), positions)
+        .expect("three coordinates per position");
+    Ok(positions.into_pyarray_bound(py))
+}
+
+/// An HCP lattice of voxels; see `HCPLatticeSpace`.
+#[pyclass(name = "HCPLatticeSpace", module = "spatiocyte", unsendable)]
+pub struct PySpace {
+    space: HCPLatticeSpace,
+}
+
+#[pymethods]
+impl PySpace {
+    /// A lattice of `rows × cols × layers` voxels of `voxel_radius`.
+    #[new]
+    fn new(voxel_radius: f64, rows: usize, cols: usize, layers: usize) -> PyResult<Self> {
+        let size = HCPLatticeSize::new(rows, cols, layers);
+        Ok(Self {
+            space: HCPLatticeSpace::try_new(voxel_radius, size)?,
+        })
+    }
+
+    /// A lattice covering `lengths` along x, y and z; see
+    /// `HCPLatticeSpace::from_lengths`.
+    #[staticmethod]
+    fn from_lengths(voxel_radius: f64, lengths: [f64; 3]) -> PyResult<Self> {
+        Ok(Self {
+            space: HCPLatticeSpace::from_lengths(voxel_radius, lengths)?,
+        })
+    }
+
+    #[getter]
+    fn voxel_radius(&self) -> f64 {
+        self.space.voxel_radius
+    }
+
+    #[getter]
+    fn num_voxels(&self) -> usize {
+        self.space.num_voxels()
+    }
+
+    /// The numbers of rows, columns and layers.
+    #[getter]
+    fn size(&self) -> (usize, usize, usize) {
+        let size = self.space.size();
+        (size.row, size.col, size.layer)
+    }
+
+    #[getter]
+    fn volume(&self) -> f64 {
+        self.space.volume()
+    }
+
+    fn set_periodic(&mut self, periodic: bool) -> PyResult<()> {
+        Ok(self.space.set_periodic(periodic)?)
+    }
+
+    /// Registers `species` on the voxels of `location`, or vacant ones, with
+    /// a radius defaulting to the voxel radius and a diffusion coefficient
+    /// to zero.
+    #[pyo3(signature = (species, location = None, radius = None, diffusion_coefficient = 0.0))]
+    fn register_species(
+        &mut self,
+        species: SpeciesArg,
+        location: Option<SpeciesArg>,
+        radius: Option<f64>,
+        diffusion_coefficient: f64,
+    ) -> PyResult<PySpecies> {
+        let location = match location {
+            Some(location) => Some(species_id(&self.space, location)?),
+            None => None,
+        };
+        if location.is_some_and(|location| self.space.is_obstacle(location)) {
+            return Err(PyValueError::new_err(
+                "no species can be located on an obstacle",
+            ));
+        }
+        let species = species.into_species();
+        let id = self.space.register_species(species.clone(), location);
+        let info = MoleculeInfo {
+            radius: radius.unwrap_or(self.space.voxel_radius),
+            diffusion_coefficient,
+        };
+        self.space.set_molecule_info(id, info)?;
+        Ok(PySpecies { species })
+    }
+
+    /// Registers `species` as an obstacle, which never moves and bars the
+    /// voxels it is on.
+    fn register_obstacle(&mut self, species: SpeciesArg) -> PySpecies {
+        let species = species.into_species();
+        self.space.register_obstacle(species.clone());
+        PySpecies { species }
+    }
+
+    /// Places a molecule of `species` at the voxel `coordinate` and returns
+    /// the lot and serial of its `ParticleID`.
+    fn place(&mut self, species: SpeciesArg, coordinate: usize) -> PyResult<(u64, u64)> {
+        let id = species_id(&self.space, species)?;
+        let pid = self.space.place_particle(id, Coordinate(coordinate))?;
+        Ok((pid.lot(), pid.serial()))
+    }
+
+    /// Places `structure` on every voxel of its location and returns how
+    /// many it placed.
+    fn populate_structure(&mut self, structure: SpeciesArg) -> PyResult<usize> {
+        let id = species_id(&self.space, structure)?;
+        Ok(self.space.populate_structure(id, &|_: [f64; 3]| true)?)
+    }
+
+    /// Fills the bulk up to a fraction `phi` of its voxels with `species`,
+    /// drawing the voxels from `seed`, and returns how many it placed.
+    #[pyo3(signature = (species, phi, seed = 0))]
+    fn fill_to_fraction(&mut self, species: SpeciesArg, phi: f64, seed: u64) -> PyResult<usize> {
+        let species = species.into_species();
+        let mut rng = Pcg64::seed_from_u64(seed);
+        Ok(self.space.fill_to_fraction(&species, phi, &mut rng)?)
+    }
+
+    /// The number of molecules at a concentration of `molar` over the
+    /// whole lattice.
+    fn concentration_to_count(&self, molar: f64) -> usize {
+        self.space.concentration_to_count(molar)
+    }
+
+    fn num_molecules(&self, species: SpeciesArg) -> PyResult<usize> {
+        let id = species_id(&self.space, species)?;
+        Ok(self.space.num_molecules(id)?)
+    }
+
+    /// The numbers of molecules of every species, by name.
+    fn counts<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        counts(py, &self.space)
+    }
+
+    fn coordinates_of(&self, species: SpeciesArg) -> PyResult<Vec<usize>> {
+        let id = species_id(&self.space, species)?;
+        Ok(self.space.coordinates_of(id)?.iter().map(|c| c.0).collect())
+    }
+
+    /// The positions of the voxels of `species`, an `N × 3` array.
+    fn positions_of<'py>(
+        &self,
+        py: Python<'py>,
+        species: SpeciesArg,
+    ) -> PyResult<Bound<'py, PyArray2<f64>>> {
+        positions_of(py, &self.space, species)
+    }
+}
+
+/// A reaction of a `Simulator`.
+#[pyclass(name = "Reaction", module = "spatiocyte", frozen)]
+#[derive(Clone, Copy)]
+pub struct PyReaction {
+    reaction: ReactionID,
+}
+
+/// Lets `allow_threads` take an unsendable value along. The closure it
+/// runs stays on the calling thread, and the `unsendable` classes raise
+/// rather than lend their state to other threads, so nothing of the value
+/// is shared while the GIL is released.
+struct StaysOnThread<T>(T);
+
+unsafe impl<T> Send for StaysOnThread<T> {}
+
+/// A simulator owning its space; see `Simulator`.
+#[pyclass(name = "Simulator", module = "spatiocyte", unsendable)]
+pub struct PySimulator {
+    simulator: Simulator<Pcg64>,
+}
+
+#[pymethods]
+impl PySimulator {
+    /// A simulator of a copy of `space`, drawing from `seed`.
+    #[new]
+    #[pyo3(signature = (space, seed = 0))]
+    fn new(space: &PySpace, seed: u64) -> Self {
+        Self {
+            simulator: Simulator::with_seed(space.space.clone(), seed),
+        }
+    }
+
+    /// Adds the reaction of `reactants` into `products` at the rate `k`,
+    /// fired on exact times of its own if `exact`.
+    #[pyo3(signature = (reactants, products, k, exact = false))]
+    fn add_reaction(
+        &mut self,
+        reactants: Vec<SpeciesArg>,
+        products: Vec<SpeciesArg>,
+        k: f64,
+        exact: bool,
+    ) -> PyResult<PyReaction> {
+        let rule = ReactionRule::new(
+            reactants
+                .into_iter()
+                .map(SpeciesArg::into_species)
+                .collect(),
+            products.into_iter().map(SpeciesArg::into_species).collect(),
+            k,
+        );
+        let reaction = if exact {
+            self.simulator.add_exact_reaction(rule)?
+        } else {
+            self.simulator.add_reaction(rule)?
+        };
+        Ok(PyReaction { reaction })
+    }
+
+    /// Makes the molecules of `a` and `b` react into `products` on collision
+    /// at the macroscopic rate `ka`, in M⁻¹s⁻¹ for bulk species, and returns
+    /// the probability per collision; see the `collision` module.
+    #[pyo3(signature = (a, b, products, ka))]
+    fn add_collision_reaction(
+        &mut self,
+        a: SpeciesArg,
+        b: SpeciesArg,
+        products: Vec<SpeciesArg>,
+        ka: f64,
+    ) -> PyResult<f64> {
+        let space = self.simulator.space_mut();
+        let (a, b) = (a.into_species(), b.into_species());
+        let rule = ReactionRule::from_macroscopic(ka, &a, &b, space)?
+            .with_products(products.into_iter().map(SpeciesArg::into_species).collect());
+        space.add_collision_reaction(&rule)?;
+        Ok(rule.k())
+    }
+
+    /// How many times `reaction` fired.
+    fn num_firings(&self, reaction: PyReaction) -> u64 {
+        self.simulator.num_firings(reaction.reaction)
+    }
+
+    /// Places `count` molecules of `species` on random voxels of its
+    /// location now, or as many as there are such voxels if fewer.
+    fn add_molecules(&mut self, species: SpeciesArg, count: usize) -> PyResult<()> {
+        Ok(self.simulator.apply(ModelEvent::AddMolecules {
+            species: species.into_species(),
+            count,
+            region: None,
+        })?)
+    }
+
+    /// Records the counts of `species` every `interval`, starting now, and
+    /// returns the index of the observer for `number_observer`.
+    fn add_number_observer(&mut self, species: Vec<SpeciesArg>, interval: f64) -> PyResult<usize> {
+        let species = species.into_iter().map(SpeciesArg::into_species).collect();
+        self.simulator.add_number_observer(species, interval)?;
+        Ok(self.simulator.number_observers().len() - 1)
+    }
+
+    /// The times and counts an observer of `add_number_observer` recorded,
+    /// an array of `T` times and one of `T × S` counts.
+    #[allow(clippy::type_complexity)]
+    fn number_observer<'py>(
+        &self,
+        py: Python<'py>,
+        index: usize,
+    ) -> PyResult<(Bound<'py, PyArray1<f64>>, Bound<'py, PyArray2<usize>>)> {
+        let observer = self
+            .simulator
+            .number_observers()
+            .get(index)
+            .ok_or_else(|| PyIndexError::new_err("no number observer has this index"))?;
+        let data = observer.data();
+        let times: Vec<f64> = data.iter().map(|&(t, _)| t).collect();
+        let counts = data.iter().flat_map(|(_, counts)| counts.iter().copied());
+        let counts =
+            Array2::from_shape_vec((data.len(), observer.species().len()), counts.collect())
+                .expect("a count of every species at every time");
+        Ok((times.into_pyarray_bound(py), counts.into_pyarray_bound(py)))
+    }
+
+    /// Runs for `duration`, calling `progress(num_steps, t)` every `every`
+    /// steps if given. Returns `False` if `progress` returned `False`,
+    /// which stops the run there, and `True` otherwise.
+    #[pyo3(signature = (duration, progress = None, every = 1000))]
+    fn run(
+        &mut self,
+        py: Python<'_>,
+        duration: f64,
+        progress: Option<PyObject>,
+        every: u64,
+    ) -> PyResult<bool> {
+        if !(duration >= 0.0 && duration.is_finite()) {
+            return Err(PyValueError::new_err(
+                "duration must be finite and not negative",
+            ));
+        }
+        let every = NonZeroU64::new(every)
+            .ok_or_else(|| PyValueError::new_err("every must be positive"))?;
+        let mut failure = None;
+        let simulator = StaysOnThread(&mut self.simulator);
+        let flow = py.allow_threads(|| {
+            let StaysOnThread(simulator) = simulator;
+            simulator.run_with_progress(duration, every, |num_steps, t| {
+                Python::with_gil(|py| {
+                    py.check_signals()?;
+                    match &progress {
+                        Some(progress) => {
+                            let result = progress.call1(py, (num_steps, t))?;
+                            Ok(!matches!(result.extract::<bool>(py), Ok(false)))
+                        }
+                        None => Ok(true),
+                    }
+                })
+                .map_or_else(
+                    |err| {
+                        failure = Some(err);
+                        ControlFlow::Break(())
+                    },
+                    |go_on| {
+                        if go_on {
+                            ControlFlow::Continue(())
+                        } else {
+                            ControlFlow::Break(())
+                        }
+                    },
+                )
+            })
+        })?;
+        match failure {
+            Some(err) => Err(err),
+            None => Ok(flow.is_continue()),
+        }
+    }
+
+    #[getter]
+    fn t(&self) -> f64 {
+        self.simulator.t()
+    }
+
+    #[getter]
+    fn num_steps(&self) -> u64 {
+        self.simulator.num_steps()
+    }
+
+    /// A copy of the current space.
+    #[getter]
+    fn space(&self) -> PySpace {
+        PySpace {
+            space: self.simulator.space().clone(),
+        }
+    }
+
+    /// The numbers of molecules of every species, by name.
+    fn counts<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        counts(py, self.simulator.space())
+    }
+
+    /// The positions of the voxels of `species`, an `N × 3` array.
+    fn positions_of<'py>(
+        &self,
+        py: Python<'py>,
+        species: SpeciesArg,
+    ) -> PyResult<Bound<'py, PyArray2<f64>>> {
+        positions_of(py, self.simulator.space(), species)
+    }
+}
+
+#[pymodule]
+fn spatiocyte(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PySpecies>()?;
+    m.add_class::<PySpace>()?;
+    m.add_class::<PyReaction>()?;
+    m.add_class::<PySimulator>()?;
+    m.add(
+        "SpatiocyteError",
+        m.py().get_type_bound::<SpatiocyteError>(),
+    )?;
+    Ok(())
+}
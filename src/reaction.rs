@@ -0,0 +1,43 @@
+//! Reaction rules.
+
+use crate::Species;
+
+/// A reaction `reactants -> products` with rate constant `k`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ReactionRule {
+    reactants: Vec<Species>,
+    products: Vec<Species>,
+    k: f64,
+}
+
+impl ReactionRule {
+    pub fn new(reactants: Vec<Species>, products: Vec<Species>, k: f64) -> Self {
+        Self {
+            reactants,
+            products,
+            k,
+        }
+    }
+
+    pub fn reactants(&self) -> &[Species] {
+        &self.reactants
+    }
+
+    pub fn products(&self) -> &[Species] {
+        &self.products
+    }
+
+    pub fn k(&self) -> f64 {
+        self.k
+    }
+
+    pub fn set_k(&mut self, k: f64) {
+        self.k = k;
+    }
+
+    /// Returns the same rule with `products` instead of its products.
+    pub fn with_products(mut self, products: Vec<Species>) -> Self {
+        self.products = products;
+        self
+    }
+}
@@ -0,0 +1,178 @@
+//! Regions of real space, to measure or act on part of a lattice.
+//!
+//! A voxel is in a region if its center is, as given by
+//! `coordinate_to_position`. `Sphere` and `Cuboid` cover the usual shapes;
+//! any closure taking a position and returning whether it is inside is a
+//! region too.
+
+use crate::{Coordinate, HCPLatticeSpace, Result, SpeciesID};
+
+pub trait Region {
+    /// Returns true if `position` lies inside the region.
+    fn contains(&self, position: [f64; 3]) -> bool;
+}
+
+impl<F: Fn([f64; 3]) -> bool> Region for F {
+    fn contains(&self, position: [f64; 3]) -> bool {
+        self(position)
+    }
+}
+
+/// The points within `radius` of `center`, boundary included. On a 2D
+/// lattice, whose voxels lie at z = 0, a sphere centered in the plane is a
+/// disk.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Sphere {
+    center: [f64; 3],
+    radius: f64,
+}
+
+impl Sphere {
+    pub fn new(center: [f64; 3], radius: f64) -> Self {
+        Self { center, radius }
+    }
+
+    pub fn center(&self) -> [f64; 3] {
+        self.center
+    }
+
+    pub fn radius(&self) -> f64 {
+        self.radius
+    }
+}
+
+impl Region for Sphere {
+    fn contains(&self, position: [f64; 3]) -> bool {
+        let d2: f64 = position
+            .iter()
+            .zip(&self.center)
+            .map(|(a, b)| (a - b).powi(2))
+            .sum();
+        d2 <= self.radius * self.radius
+    }
+}
+
+/// The axis-aligned box from `lower` to `upper`, boundary included.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Cuboid {
+    lower: [f64; 3],
+    upper: [f64; 3],
+}
+
+impl Cuboid {
+    pub fn new(lower: [f64; 3], upper: [f64; 3]) -> Self {
+        Self { lower, upper }
+    }
+
+    pub fn lower(&self) -> [f64; 3] {
+        self.lower
+    }
+
+    pub fn upper(&self) -> [f64; 3] {
+        self.upper
+    }
+}
+
+impl Region for Cuboid {
+    fn contains(&self, position: [f64; 3]) -> bool {
+        (0..3).all(|i| self.lower[i] <= position[i] && position[i] <= self.upper[i])
+    }
+}
+
+impl HCPLatticeSpace {
+    /// Iterates over the voxels whose centers lie in `region`, in
+    /// coordinate order.
+    pub fn coordinates_in<'a>(
+        &'a self,
+        region: &'a dyn Region,
+    ) -> impl Iterator<Item = Coordinate> + 'a {
+        self.coordinates().filter(move |&c| {
+            region.contains(
+                self.coordinate_to_position(c)
+                    .expect("iterating over the lattice"),
+            )
+        })
+    }
+
+    /// Returns the number of voxels of tracked molecules of `species`
+    /// centered in `region`, the number of its molecules there for a
+    /// single-voxel species. Unlike `count_in_region`, this only goes
+    /// through the tracked molecules, not over the lattice, and fails with
+    /// `TrackingRequired` for a counted species.
+    pub fn occupied_in_region(&self, species: SpeciesID, region: &dyn Region) -> Result<usize> {
+        Ok(self
+            .tracked_entries(species)?
+            .iter()
+            .filter(|(_, c)| {
+                region.contains(
+                    self.coordinate_to_position(*c)
+                        .expect("a molecule on the lattice"),
+                )
+            })
+            .count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, HCPLatticeSize, Species};
+
+    #[test]
+    fn voxels_in_regions() {
+        let space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(8, 8, 8));
+        let center = space.global_to_coordinate(4, 4, 4).unwrap();
+        let sphere = Sphere::new(space.coordinate_to_position(center).unwrap(), 2.1);
+        let mut inside: Vec<Coordinate> = space.coordinates_in(&sphere).collect();
+        let mut expected = space.neighbors(center).unwrap();
+        expected.push(center);
+        inside.sort_by_key(|c| c.0);
+        expected.sort_by_key(|c| c.0);
+        assert_eq!(inside, expected);
+
+        let [lx, ly, lz] = space.periodic_lengths();
+        let everything = Cuboid::new([-1.0; 3], [lx, ly, lz]);
+        assert_eq!(space.coordinates_in(&everything).count(), 512);
+        let lower_half = |p: [f64; 3]| p[2] < lz / 2.0;
+        assert_eq!(space.coordinates_in(&lower_half).count(), 256);
+    }
+
+    #[test]
+    fn occupied_voxels_in_a_region() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(8, 8, 8));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_counted_species(Species::new("B"), None);
+        let cluster = space.register_multi_voxel_species(Species::new("C"), None, 2);
+        for i in (0..512).step_by(4) {
+            space.place_particle(a, Coordinate(i)).unwrap();
+        }
+        space.place_particle(b, Coordinate(1)).unwrap();
+        let lz = space.periodic_lengths()[2];
+        let lower_half = |p: [f64; 3]| p[2] < lz / 2.0;
+        assert_eq!(space.occupied_in_region(a, &lower_half).unwrap(), 64);
+        assert_eq!(
+            space.occupied_in_region(a, &lower_half).unwrap(),
+            space
+                .count_in_region(&Species::new("A"), &lower_half)
+                .unwrap()
+        );
+        assert!(matches!(
+            space.occupied_in_region(b, &lower_half),
+            Err(Error::TrackingRequired(_))
+        ));
+
+        let anchor = space.global_to_coordinate(3, 3, 3).unwrap();
+        space.place_particle(cluster.id(), anchor).unwrap();
+        let everything = |_: [f64; 3]| true;
+        assert_eq!(
+            space.occupied_in_region(cluster.id(), &everything).unwrap(),
+            2
+        );
+        assert_eq!(
+            space
+                .occupied_in_region(cluster.id(), &|_: [f64; 3]| false)
+                .unwrap(),
+            0
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Text pictures of a layer, for debugging.
+//!
+//! `render_layer` draws one character per voxel, separated by spaces, the
+//! last row on top so that y grows upwards. Rows whose voxel centers are
+//! shifted by `r` along x, odd rows on even layers and even rows on odd
+//! layers, start with one more space, which draws the hexagonal stagger.
+//!
+//! By default vacant voxels are `.`, structures and obstacles `#`, and the
+//! other species letters in registration order: `A` to `Z`, then `a` to
+//! `z`, then `?` for all the others.
+
+use crate::{HCPLatticeSpace, Result, Species, SpeciesID};
+
+impl HCPLatticeSpace {
+    /// Draws layer `layer` with the default glyphs.
+    pub fn render_layer(&self, layer: usize) -> Result<String> {
+        let mut glyphs = Vec::with_capacity(self.species_cache.len());
+        let mut letters = ('A'..='Z').chain('a'..='z');
+        for (i, cache) in self.species_cache.iter().enumerate() {
+            glyphs.push(if cache.obstacle || self.is_structure(SpeciesID(i)) {
+                '#'
+            } else {
+                letters.next().unwrap_or('?')
+            });
+        }
+        self.render(layer, None, &|voxel| voxel.map_or('.', |id| glyphs[id.0]))
+    }
+
+    /// Draws layer `layer` with `glyph` of the species on each voxel,
+    /// `None` meaning vacant, cutting lines after `max_width` characters.
+    pub fn render_layer_with<F>(
+        &self,
+        layer: usize,
+        max_width: Option<usize>,
+        glyph: F,
+    ) -> Result<String>
+    where
+        F: Fn(Option<&Species>) -> char,
+    {
+        self.render(layer, max_width, &|voxel| {
+            glyph(voxel.map(|id| &self.species_cache[id.0].species))
+        })
+    }
+
+    fn render(
+        &self,
+        layer: usize,
+        max_width: Option<usize>,
+        glyph: &dyn Fn(Option<SpeciesID>) -> char,
+    ) -> Result<String> {
+        self.global_to_coordinate(0, 0, layer)?;
+        let width = max_width.unwrap_or(usize::MAX);
+        let mut picture = String::new();
+        for row in (0..self.size.row).rev() {
+            let mut line = String::from(if (row + layer) % 2 == 1 { " " } else { "" });
+            for col in 0..self.size.col {
+                if col > 0 {
+                    line.push(' ');
+                }
+                line.push(glyph(self.voxel(self.flatten(row, col, layer))));
+            }
+            picture.extend(line.chars().take(width));
+            picture.push('\n');
+        }
+        Ok(picture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Coordinate, HCPLatticeSize};
+
+    #[test]
+    fn draw_a_layer() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(3, 4, 2));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), Some(membrane));
+        for col in 0..4 {
+            let c = space.global_to_coordinate(0, col, 0).unwrap();
+            space.place_particle(membrane, c).unwrap();
+        }
+        space.place_particle(b, Coordinate(3)).unwrap();
+        space.place_particle(a, Coordinate(5)).unwrap();
+
+        assert_eq!(
+            space.render_layer(0).unwrap(),
+            ". A . .\n . . . .\n# B # #\n"
+        );
+        assert_eq!(
+            space.render_layer(1).unwrap(),
+            " . . . .\n. . . .\n . . . .\n"
+        );
+        assert_eq!(
+            space
+                .render_layer_with(0, Some(4), |species| match species {
+                    Some(species) => species.name().chars().next().unwrap(),
+                    None => '_',
+                })
+                .unwrap(),
+            "_ A \n _ _\nM B \n"
+        );
+        assert!(space.render_layer(2).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Growing or shrinking a lattice in place, keeping what it holds.
+//!
+//! `resize` keeps every voxel at its row, column and layer, hence at its
+//! position, and moves it to the flat index of these in the new size. The
+//! voxels added are vacant. The molecules keep their `ParticleID`s, and the
+//! sinks, the potential and the voxel properties follow their voxels. The
+//! lattice stays periodic if it was, so that the voxels near its far faces
+//! get new neighbors across the seam.
+
+use crate::voxels::VoxelStore;
+use crate::{Coordinate, Error, HCPLatticeSize, HCPLatticeSpace, Result, TrackingType};
+
+impl HCPLatticeSpace {
+    /// Changes the size of the lattice to `size`, and returns the number of
+    /// molecules dropped. A molecule with a voxel outside the new size
+    /// fails with `OutOfRange` of that voxel, leaving the space unchanged,
+    /// unless `truncate` is set, in which case it is removed, whole for a
+    /// multi-voxel molecule, along with whatever it was located on there.
+    ///
+    /// Fails with `SizeOverflow` if the number of voxels overflows,
+    /// `InvalidPeriodicSize` for odd rows or layers on a periodic lattice,
+    /// and `OutOfPlane` for more than one layer on a 2D lattice. Panics
+    /// inside a `transaction`, which could not undo it.
+    pub fn resize(&mut self, size: HCPLatticeSize, truncate: bool) -> Result<usize> {
+        assert!(self.journal.is_none(), "resizing inside a transaction");
+        let len = size.num_voxels().ok_or(Error::SizeOverflow)?;
+        if self.periodic && (size.row % 2 == 1 || size.layer % 2 == 1) {
+            return Err(Error::InvalidPeriodicSize);
+        }
+        if self.planar && size.layer != 1 {
+            return Err(Error::OutOfPlane);
+        }
+        let old = self.size;
+        let map = move |index: usize| {
+            let (row, col, layer) = old.global(index);
+            (row < size.row && col < size.col && layer < size.layer)
+                .then(|| row + size.row * (col + size.col * layer))
+        };
+
+        let outside: Vec<Coordinate> = self
+            .occupied()
+            .map(|(c, _)| c)
+            .filter(|c| map(c.0).is_none())
+            .collect();
+        if let (Some(&first), false) = (outside.first(), truncate) {
+            return Err(Error::OutOfRange(first));
+        }
+        let mut dropped = 0;
+        for c in outside {
+            while self.voxel(c).is_some() {
+                self.remove_at(c)?;
+                dropped += 1;
+            }
+        }
+
+        let mut voxels = self.voxels.empty_like(len);
+        for (index, raw) in self.voxels.occupied() {
+            voxels.set(map(index).expect("inside after truncation"), raw);
+        }
+        self.voxels = voxels;
+        for cache in &mut self.species_cache {
+            if let TrackingType::Tracking(entries) = &mut cache.cache {
+                for i in 0..entries.len() {
+                    let c = Coordinate(map(entries[i].1 .0).expect("inside after truncation"));
+                    entries.set_coordinate(i, c);
+                }
+            }
+        }
+        self.sink_voxels = self
+            .sink_voxels
+            .iter()
+            .filter_map(|(&index, &sink)| map(index).map(|index| (index, sink)))
+            .collect();
+        if let Some(potential) = &mut self.potential {
+            let mut remapped = vec![0.0; len].into_boxed_slice();
+            for (i, &value) in potential.iter().enumerate() {
+                if let Some(j) = map(i) {
+                    remapped[j] = value;
+                }
+            }
+            *potential = remapped;
+        }
+        for property in &mut self.properties {
+            property.remap(len, &map);
+        }
+        self.size = size;
+        Ok(dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParticleID, Species};
+
+    #[test]
+    fn grow_and_shrink_back() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), Some(membrane));
+        let b = space.register_counted_species(Species::new("B"), None);
+        for i in 0..16 {
+            space.place_particle(membrane, Coordinate(i)).unwrap();
+        }
+        let on_membrane = space.place_particle(a, Coordinate(5)).unwrap();
+        space.place_particle(b, Coordinate(63)).unwrap();
+        let far = space.global_to_coordinate(3, 3, 3).unwrap();
+        space.set_potential(far, 2.5).unwrap();
+        let label = space.add_voxel_property::<u8>("label");
+        space.set_voxel_property(label, far, 7).unwrap();
+        space.add_sink(vec![far], None).unwrap();
+        let original = space.clone();
+        let position = |space: &HCPLatticeSpace, pid: ParticleID| {
+            let (_, c) = space.find_particle(pid).unwrap();
+            space.coordinate_to_position(c).unwrap()
+        };
+
+        assert_eq!(
+            space.resize(HCPLatticeSize::new(6, 8, 6), false).unwrap(),
+            0
+        );
+        space.validate().unwrap();
+        assert_eq!(space.num_voxels(), 288);
+        assert_eq!(
+            position(&space, on_membrane),
+            position(&original, on_membrane)
+        );
+        let far = space.global_to_coordinate(3, 3, 3).unwrap();
+        assert_eq!(space.species_at(far).unwrap(), Some(b));
+        assert_eq!(space.potential(far), 2.5);
+        assert_eq!(space.voxel_property(label, far).unwrap(), &7);
+        assert_eq!(space.sink_voxels.keys().collect::<Vec<_>>(), vec![&far.0]);
+
+        assert_eq!(
+            space.resize(HCPLatticeSize::new(4, 4, 4), false).unwrap(),
+            0
+        );
+        assert!(
+            space.diff(&original).is_empty(),
+            "{}",
+            space.diff(&original)
+        );
+        assert_eq!(space.find_particle(on_membrane).unwrap().1, Coordinate(5));
+        assert_eq!(space.voxels_with_property(label).count(), 1);
+    }
+
+    #[test]
+    fn molecules_outside_fail_or_are_dropped() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), Some(membrane));
+        let cluster = space.register_multi_voxel_species(Species::new("C"), None, 2);
+        let far = space.global_to_coordinate(3, 3, 3).unwrap();
+        space.place_particle(membrane, far).unwrap();
+        space.place_particle(a, far).unwrap();
+        let anchor = space.global_to_coordinate(1, 1, 1).unwrap();
+        let pid = space.place_particle(cluster.id(), anchor).unwrap();
+        // Cut between the two voxels of the cluster, which leaves one on
+        // each side, and the membrane outside.
+        let ends: Vec<(usize, usize, usize)> = space
+            .voxels_of(pid)
+            .iter()
+            .map(|&c| space.coordinate_to_global(c).unwrap())
+            .collect();
+        let (p, q) = (ends[0], ends[1]);
+        let size = if p.0 != q.0 {
+            HCPLatticeSize::new(p.0.min(q.0) + 1, 4, 4)
+        } else if p.1 != q.1 {
+            HCPLatticeSize::new(4, p.1.min(q.1) + 1, 4)
+        } else {
+            HCPLatticeSize::new(4, 4, p.2.min(q.2) + 1)
+        };
+        let before = space.clone();
+
+        assert!(matches!(
+            space.resize(size, false),
+            Err(Error::OutOfRange(_))
+        ));
+        assert_eq!(space.voxels, before.voxels);
+        assert_eq!(space.size, before.size);
+
+        assert_eq!(space.resize(size, true).unwrap(), 3);
+        space.validate().unwrap();
+        assert_eq!(space.num_voxels(), size.num_voxels().unwrap());
+        for id in [membrane, a, cluster.id()] {
+            assert_eq!(space.num_molecules(id).unwrap(), 0);
+        }
+        assert_eq!(space.occupied().count(), 0);
+
+        let mut periodic = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        periodic.set_periodic(true).unwrap();
+        assert!(matches!(
+            periodic.resize(HCPLatticeSize::new(3, 4, 4), false),
+            Err(Error::InvalidPeriodicSize)
+        ));
+        let mut planar = HCPLatticeSpace::new_2d(1.0, 4, 4);
+        assert!(matches!(
+            planar.resize(HCPLatticeSize::new(4, 4, 2), false),
+            Err(Error::OutOfPlane)
+        ));
+        assert_eq!(
+            planar.resize(HCPLatticeSize::new(6, 6, 1), false).unwrap(),
+            0
+        );
+    }
+}
@@ -0,0 +1,2542 @@
+//! Event-driven simulation on an `HCPLatticeSpace`, or on any other
+//! `LatticeSpace`.
+//!
+//! Every process is an event in a single queue ordered by time, ties being
+//! broken by scheduling order. Each species with a positive diffusion
+//! coefficient `D` hops every `diffusion_interval` of the space, `2r²/(3D)`
+//! on the HCP lattice with voxel radius `r`. A
+//! first-order reaction with rate `k` fires every `0.1/k`, converting each
+//! reactant with probability `1 - exp(-k dt)`. Observers are events too, at
+//! the times they ask for.
+//!
+//! Homodimerization is stepped the same way. `A + A -> A2` binds every
+//! adjacent pair of `A` once per step, with probability `1 - exp(-k dt)`,
+//! into an `A2` on either voxel of the pair. `A2 -> A + A` picks one of the
+//! `z` neighbors of each `A2` at random and, if `A` can take it, splits the
+//! dimer into an `A` on each voxel with probability `1 - exp(-k dt)`. This
+//! keeps detailed balance, so that at equilibrium a dimer weighs
+//! `z k_on / (2 k_off)` against a pair of monomers, `z` being 12 inside an
+//! HCP lattice. The simulator records
+//! the `ParticleID`s of the monomers forming each tracked dimer, see
+//! `dimer_lineage`.
+//!
+//! A first-order reaction added with `add_exact_reaction` has no time step:
+//! following the next reaction method, each reactant molecule gets its own
+//! event at an exponentially distributed waiting time, keyed by its
+//! `ParticleID`, and fires exactly then. A molecule consumed or changed by
+//! another process before its time leaves a stale event, which is dropped
+//! when it comes up. New reactant molecules, placed by reactions, by
+//! transitions while walking or directly through `space_mut`, get their
+//! times before the next event is processed.
+//!
+//! A source boundary keeps the number of molecules of a species on a face
+//! of the lattice at a target, topping it up on random free voxels of the
+//! face, or removing random molecules in excess, every interval.
+//!
+//! `schedule_event` changes the model once at a given time, as a protocol
+//! would: adding a ligand, washing it out or switching a rate constant. A
+//! rate change takes over from the next step of a stepped reaction, which
+//! restarts its interval then, and draws new waiting times for every
+//! reactant molecule of an exact reaction, which is exact since waiting
+//! times are memoryless.
+//!
+//! `set_reaction_modifier` makes the rate of a reaction depend on where
+//! each molecule is, through a function of the space and its voxel, e.g.
+//! reading a property of the `property` module. The function gives a
+//! factor `f` in `[0, 1]` scaling the probability of each step of a stepped
+//! reaction, and the probability of an exact reaction accepting a firing,
+//! drawing a new waiting time otherwise, which thins its rate to `f k`.
+//!
+//! `photobleach`, directly or as a scheduled event, turns the molecules of a
+//! species within a region into a bleached species in place, for FRAP; a
+//! region observer then records the recovery of the unbleached ones.
+//!
+//! `set_event_listener` calls a function with a `SimEvent` for every
+//! reaction firing, diffusion sweep, sink absorption, periodic boundary
+//! crossing and model event, for debugging why a model does what it does.
+//! Collecting what a sweep did takes a copy of the voxels of the species
+//! before it, which is only made while a listener is set, and the listener
+//! draws nothing from the RNG, so that a run is the same with or without.
+//!
+//! All randomness is drawn from the simulator's RNG, and everything the
+//! simulator iterates over has a fixed order: species in registration
+//! order, the molecules of a species in the order they were placed (moves
+//! keep that order), reactions and observers in the order they were added.
+//! The space keeps its lookup tables in hash maps but never iterates them.
+//! Two runs of the same model from the same RNG state therefore produce the
+//! same results.
+
+use crate::boundary::Face;
+use crate::lattice::LatticeSpace;
+use crate::observer::{
+    NumberObserver, Observer, RegionObserver, SinkObserver, TrajectoryObserver, TrajectoryTarget,
+};
+use crate::{
+    Coordinate, Error, HCPLatticeSpace, ParticleID, ReactionRule, Region, Result, Species,
+    SpeciesID, TimeCourse,
+};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+pub use rand_pcg::Pcg64;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fmt;
+use std::num::NonZeroU64;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug)]
+enum EventKind {
+    Diffusion(SpeciesID),
+    Reaction(usize),
+    Firing(usize, SpeciesID, ParticleID),
+    Source(usize),
+    Model(usize),
+    Observer(ObserverSlot),
+}
+
+/// Where the simulator keeps an observer. The built-in observers are stored
+/// by type so that they can be handed back to the caller.
+#[derive(Clone, Copy, Debug)]
+enum ObserverSlot {
+    Number(usize),
+    Trajectory(usize),
+    Region(usize),
+    Sink(usize),
+    Custom(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ScheduledEvent {
+    time: f64,
+    seq: u64,
+    kind: EventKind,
+}
+
+impl PartialEq for ScheduledEvent {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ScheduledEvent {}
+
+impl PartialOrd for ScheduledEvent {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ScheduledEvent {
+    /// Reversed so that `BinaryHeap` pops the earliest event first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .time
+            .partial_cmp(&self.time)
+            .unwrap_or(Ordering::Equal)
+            .then(other.seq.cmp(&self.seq))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NumberObserverID(usize);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TrajectoryObserverID(usize);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RegionObserverID(usize);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SinkObserverID(usize);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ObserverID(usize);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SourceID(usize);
+
+/// A reaction of a simulator, stepped or exact.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ReactionID {
+    exact: bool,
+    index: usize,
+}
+
+impl ReactionID {
+    /// Returns true for a reaction of `add_exact_reaction`.
+    pub fn is_exact(self) -> bool {
+        self.exact
+    }
+}
+
+/// A change to the model at a given time; see `Simulator::schedule_event`.
+#[derive(Clone)]
+pub enum ModelEvent {
+    /// Places `count` molecules of `species` on random voxels of its
+    /// location, within `region` if any, or as many as there are such
+    /// voxels if fewer.
+    AddMolecules {
+        species: Species,
+        count: usize,
+        region: Option<Arc<dyn Region>>,
+    },
+    /// Removes every molecule of `species`, or those within `region`.
+    RemoveMolecules {
+        species: Species,
+        region: Option<Arc<dyn Region>>,
+    },
+    /// Sets the rate constant of `reaction` to `k`.
+    SetRate { reaction: ReactionID, k: f64 },
+    /// Photobleaches `species` within `region` into `bleached`; see
+    /// `Simulator::photobleach`.
+    Photobleach {
+        species: Species,
+        bleached: Species,
+        region: Arc<dyn Region>,
+    },
+}
+
+impl fmt::Debug for ModelEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let region = |region: &Option<Arc<dyn Region>>| region.as_ref().map(|_| "..");
+        match self {
+            ModelEvent::AddMolecules {
+                species,
+                count,
+                region: within,
+            } => f
+                .debug_struct("AddMolecules")
+                .field("species", species)
+                .field("count", count)
+                .field("region", &region(within))
+                .finish(),
+            ModelEvent::RemoveMolecules {
+                species,
+                region: within,
+            } => f
+                .debug_struct("RemoveMolecules")
+                .field("species", species)
+                .field("region", &region(within))
+                .finish(),
+            ModelEvent::SetRate { reaction, k } => f
+                .debug_struct("SetRate")
+                .field("reaction", reaction)
+                .field("k", k)
+                .finish(),
+            ModelEvent::Photobleach {
+                species, bleached, ..
+            } => f
+                .debug_struct("Photobleach")
+                .field("species", species)
+                .field("bleached", bleached)
+                .field("region", &"..")
+                .finish(),
+        }
+    }
+}
+
+/// What a listener of `Simulator::set_event_listener` is told of, at the
+/// simulation time `t` of each.
+#[derive(Clone, Debug)]
+pub enum SimEvent {
+    /// `reaction` fired on the molecule of `reactant` at `voxel`, leaving a
+    /// molecule of `product` there if any. A binding is reported at the
+    /// voxel of the dimer, a dissociation at that of the first monomer.
+    ReactionFired {
+        t: f64,
+        reaction: ReactionID,
+        voxel: Coordinate,
+        reactant: SpeciesID,
+        product: Option<SpeciesID>,
+    },
+    /// A sweep of `species` tried to hop its `attempted` molecules, of
+    /// which `rejected` stayed on their voxels. For a counted species, a
+    /// molecule hopping into the voxel another one left counts as staying.
+    WalkCompleted {
+        t: f64,
+        species: SpeciesID,
+        attempted: usize,
+        rejected: usize,
+    },
+    /// The sink at `sink`, its index in `sink_counts`, absorbed `count`
+    /// molecules of `species` during a sweep.
+    Absorbed {
+        t: f64,
+        species: SpeciesID,
+        sink: usize,
+        count: u64,
+    },
+    /// The tracked molecule `pid` hopped across a periodic boundary.
+    BoundaryCrossed {
+        t: f64,
+        pid: ParticleID,
+        from: Coordinate,
+        to: Coordinate,
+    },
+    /// An event of `schedule_event` was applied.
+    ModelEventApplied { t: f64, event: ModelEvent },
+}
+
+impl SimEvent {
+    pub fn t(&self) -> f64 {
+        match *self {
+            SimEvent::ReactionFired { t, .. }
+            | SimEvent::WalkCompleted { t, .. }
+            | SimEvent::Absorbed { t, .. }
+            | SimEvent::BoundaryCrossed { t, .. }
+            | SimEvent::ModelEventApplied { t, .. } => t,
+        }
+    }
+}
+
+/// The molecules of a species before a sweep, for its `SimEvent`s.
+struct SweepStart {
+    /// The voxel of each tracked molecule, for single-voxel species.
+    particles: Option<Vec<(ParticleID, Coordinate)>>,
+    coordinates: Vec<Coordinate>,
+    sink_counts: Vec<u64>,
+}
+
+/// A face of the lattice kept at a number of molecules of a species.
+#[derive(Clone, Debug)]
+pub struct SourceBoundary {
+    face: Face,
+    species: SpeciesID,
+    coordinates: Vec<Coordinate>,
+    target: usize,
+    interval: f64,
+    deficits: Vec<(f64, usize)>,
+}
+
+impl SourceBoundary {
+    pub fn face(&self) -> Face {
+        self.face
+    }
+
+    pub fn species(&self) -> SpeciesID {
+        self.species
+    }
+
+    pub fn target(&self) -> usize {
+        self.target
+    }
+
+    pub fn interval(&self) -> f64 {
+        self.interval
+    }
+
+    /// Returns the `(t, missing)` of every top-up that found too few free
+    /// voxels on the face to reach the target.
+    pub fn deficits(&self) -> &[(f64, usize)] {
+        &self.deficits
+    }
+}
+
+/// What a stepped reaction does to its reactant molecules.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Shape {
+    /// `A -> B` or `A -> ∅`.
+    Convert,
+    /// `A + A -> A2` on adjacent pairs.
+    Bind,
+    /// `A2 -> A + A`, the second monomer on a neighbor.
+    Split,
+}
+
+#[derive(Clone, Debug)]
+struct SteppedReaction {
+    rule: ReactionRule,
+    shape: Shape,
+    reactant: SpeciesID,
+    product: Option<SpeciesID>,
+    interval: f64,
+    /// The sequence number of its event in the queue, if any.
+    pending: Option<u64>,
+    /// The number of reaction events so far.
+    fired: u64,
+}
+
+/// A factor of the rate of a reaction at a voxel.
+type Modifier<S> = Box<dyn Fn(&S, Coordinate) -> f64>;
+
+#[derive(Clone, Debug)]
+struct ExactReaction {
+    rule: ReactionRule,
+    reactant: SpeciesID,
+    product: Option<SpeciesID>,
+    /// The molecules with a firing event in the queue, with the sequence
+    /// number of that event.
+    scheduled: HashMap<ParticleID, u64>,
+    fired: u64,
+}
+
+pub struct Simulator<R, S = HCPLatticeSpace> {
+    space: S,
+    rng: R,
+    t: f64,
+    seq: u64,
+    num_steps: u64,
+    queue: BinaryHeap<ScheduledEvent>,
+    initialized: bool,
+    reactions: Vec<SteppedReaction>,
+    exact_reactions: Vec<ExactReaction>,
+    modifiers: HashMap<ReactionID, Modifier<S>>,
+    /// The monomers forming each dimer bound by a reaction, while it lasts.
+    lineage: HashMap<ParticleID, (ParticleID, ParticleID)>,
+    sources: Vec<SourceBoundary>,
+    /// The events of `schedule_event`, taken out as they fire.
+    model_events: Vec<(f64, Option<ModelEvent>)>,
+    /// Whether reactant molecules of exact reactions may lack an event.
+    unscheduled: bool,
+    number_observers: Vec<NumberObserver>,
+    trajectory_observers: Vec<TrajectoryObserver>,
+    region_observers: Vec<RegionObserver>,
+    sink_observers: Vec<SinkObserver>,
+    observers: Vec<Box<dyn Observer<S>>>,
+    listener: Option<Box<dyn FnMut(SimEvent)>>,
+}
+
+impl<R: Rng, S: LatticeSpace> Simulator<R, S> {
+    pub fn new(space: S, rng: R) -> Self {
+        Self {
+            space,
+            rng,
+            t: 0.0,
+            seq: 0,
+            num_steps: 0,
+            queue: BinaryHeap::new(),
+            initialized: false,
+            reactions: Vec::new(),
+            exact_reactions: Vec::new(),
+            modifiers: HashMap::new(),
+            lineage: HashMap::new(),
+            sources: Vec::new(),
+            model_events: Vec::new(),
+            unscheduled: false,
+            number_observers: Vec::new(),
+            trajectory_observers: Vec::new(),
+            region_observers: Vec::new(),
+            sink_observers: Vec::new(),
+            observers: Vec::new(),
+            listener: None,
+        }
+    }
+
+    /// Calls `listener` with every `SimEvent` from now on, replacing the
+    /// previous listener if any.
+    pub fn set_event_listener(&mut self, listener: Box<dyn FnMut(SimEvent)>) {
+        self.listener = Some(listener);
+    }
+
+    /// Removes the listener of `set_event_listener` and returns it.
+    pub fn remove_event_listener(&mut self) -> Option<Box<dyn FnMut(SimEvent)>> {
+        self.listener.take()
+    }
+
+    /// Tells the listener of the event `event` makes, only making it if
+    /// there is one.
+    fn emit(&mut self, event: impl FnOnce() -> SimEvent) {
+        if let Some(listener) = &mut self.listener {
+            listener(event());
+        }
+    }
+
+    /// Returns the RNG, whose clone is a checkpoint of the random stream.
+    pub fn rng(&self) -> &R {
+        &self.rng
+    }
+
+    /// Replaces the RNG, e.g. to resume from a checkpoint.
+    pub fn set_rng(&mut self, rng: R) {
+        self.rng = rng;
+    }
+
+    pub fn t(&self) -> f64 {
+        self.t
+    }
+
+    /// Returns the number of events processed so far.
+    pub fn num_steps(&self) -> u64 {
+        self.num_steps
+    }
+
+    pub fn space(&self) -> &S {
+        &self.space
+    }
+
+    /// Species registered after the first step do not get diffusion events.
+    pub fn space_mut(&mut self) -> &mut S {
+        self.unscheduled = true;
+        &mut self.space
+    }
+
+    fn schedule(&mut self, time: f64, kind: EventKind) {
+        self.queue.push(ScheduledEvent {
+            time,
+            seq: self.seq,
+            kind,
+        });
+        self.seq += 1;
+    }
+
+    fn initialize(&mut self) {
+        for i in 0..self.space.num_species() {
+            if let Some(interval) = self.space.diffusion_interval(SpeciesID(i)) {
+                self.schedule(self.t + interval, EventKind::Diffusion(SpeciesID(i)));
+            }
+        }
+        self.initialized = true;
+    }
+
+    /// Schedules every reactant molecule of an exact reaction lacking an
+    /// event, in reaction and then placement order.
+    fn schedule_firings(&mut self) {
+        if !self.unscheduled {
+            return;
+        }
+        for i in 0..self.exact_reactions.len() {
+            let reactant = self.exact_reactions[i].reactant;
+            for pid in self.space.particles_of(reactant).unwrap_or_default() {
+                self.schedule_firing(i, pid);
+            }
+        }
+        self.unscheduled = false;
+    }
+
+    /// Schedules the firing of `pid` in exact reaction `i` at an
+    /// exponentially distributed waiting time, unless it is scheduled or
+    /// the reaction is off.
+    fn schedule_firing(&mut self, i: usize, pid: ParticleID) {
+        let reaction = &self.exact_reactions[i];
+        let (k, reactant) = (reaction.rule.k(), reaction.reactant);
+        if reaction.scheduled.contains_key(&pid) || k <= 0.0 {
+            return;
+        }
+        let waiting_time = -(1.0 - self.rng.gen::<f64>()).ln() / k;
+        if waiting_time.is_finite() {
+            self.exact_reactions[i].scheduled.insert(pid, self.seq);
+            let kind = EventKind::Firing(i, reactant, pid);
+            self.schedule(self.t + waiting_time, kind);
+        }
+    }
+
+    /// Adds a first-order reaction `A -> B` or `A -> ∅`, a homodimerization
+    /// `A + A -> A2` or a dissociation `A2 -> A + A`; see the module
+    /// documentation. The species must already be registered and share the
+    /// same location.
+    pub fn add_reaction(&mut self, rule: ReactionRule) -> Result<ReactionID> {
+        let (shape, reactant, product) = self.stepped_species(&rule)?;
+        let interval = 0.1 / rule.k();
+        self.reactions.push(SteppedReaction {
+            rule,
+            shape,
+            reactant,
+            product,
+            interval,
+            pending: None,
+            fired: 0,
+        });
+        let index = self.reactions.len() - 1;
+        self.schedule_reaction(index);
+        Ok(ReactionID {
+            exact: false,
+            index,
+        })
+    }
+
+    /// Schedules the next step of stepped reaction `i` an interval from
+    /// now, replacing the one in the queue if any.
+    fn schedule_reaction(&mut self, i: usize) {
+        let interval = self.reactions[i].interval;
+        if interval.is_finite() {
+            self.reactions[i].pending = Some(self.seq);
+            self.schedule(self.t + interval, EventKind::Reaction(i));
+        } else {
+            self.reactions[i].pending = None;
+        }
+    }
+
+    /// Same as `add_reaction`, each molecule reacting at its own exact
+    /// time; see the module documentation. The reactant must be tracked.
+    pub fn add_exact_reaction(&mut self, rule: ReactionRule) -> Result<ReactionID> {
+        let (reactant, product) = self.first_order_species(&rule)?;
+        if !self.space.is_tracking(reactant) {
+            return Err(Error::TrackingRequired(rule.reactants()[0].clone()));
+        }
+        self.exact_reactions.push(ExactReaction {
+            rule,
+            reactant,
+            product,
+            scheduled: HashMap::new(),
+            fired: 0,
+        });
+        self.unscheduled = true;
+        Ok(ReactionID {
+            exact: true,
+            index: self.exact_reactions.len() - 1,
+        })
+    }
+
+    /// Returns the reactions added so far: the stepped ones in the order
+    /// they were added, then the exact ones.
+    pub fn reactions(&self) -> Vec<ReactionID> {
+        let stepped = (0..self.reactions.len()).map(|index| ReactionID {
+            exact: false,
+            index,
+        });
+        let exact = (0..self.exact_reactions.len()).map(|index| ReactionID { exact: true, index });
+        stepped.chain(exact).collect()
+    }
+
+    /// Returns the rule of `reaction`, at its current rate. Panics for a
+    /// reaction of another simulator.
+    pub fn reaction_rule(&self, reaction: ReactionID) -> &ReactionRule {
+        if reaction.exact {
+            &self.exact_reactions[reaction.index].rule
+        } else {
+            &self.reactions[reaction.index].rule
+        }
+    }
+
+    /// Returns the number of times `reaction` has fired: molecules
+    /// converted, pairs bound or dimers split. Panics for a reaction of
+    /// another simulator.
+    pub fn num_firings(&self, reaction: ReactionID) -> u64 {
+        if reaction.exact {
+            self.exact_reactions[reaction.index].fired
+        } else {
+            self.reactions[reaction.index].fired
+        }
+    }
+
+    /// Checks a first-order reaction and returns its reactant and product.
+    /// Scales the rate of `reaction` on each molecule by `modifier` of the
+    /// space and the voxel of the molecule, clamped to `[0, 1]`; see the
+    /// module documentation. Replaces the previous modifier of the reaction,
+    /// if any. Fails with `InvalidReaction` for a reaction of another
+    /// simulator.
+    pub fn set_reaction_modifier<F>(&mut self, reaction: ReactionID, modifier: F) -> Result<()>
+    where
+        F: Fn(&S, Coordinate) -> f64 + 'static,
+    {
+        let count = if reaction.exact {
+            self.exact_reactions.len()
+        } else {
+            self.reactions.len()
+        };
+        if reaction.index >= count {
+            return Err(Error::InvalidReaction);
+        }
+        self.modifiers.insert(reaction, Box::new(modifier));
+        Ok(())
+    }
+
+    /// Returns the factor of the rate of `reaction` at `coordinate`.
+    fn rate_factor(&self, reaction: ReactionID, coordinate: Coordinate) -> f64 {
+        self.modifiers.get(&reaction).map_or(1.0, |modifier| {
+            modifier(&self.space, coordinate).clamp(0.0, 1.0)
+        })
+    }
+
+    /// Returns the monomer and the dimer of a homodimerization, the other
+    /// way round for a dissociation, and the reactant and the product of a
+    /// first-order reaction.
+    fn stepped_species(
+        &self,
+        rule: &ReactionRule,
+    ) -> Result<(Shape, SpeciesID, Option<SpeciesID>)> {
+        if rule.k() < 0.0 {
+            return Err(Error::InvalidReaction);
+        }
+        let (shape, reactant, product) = match (rule.reactants(), rule.products()) {
+            ([a, b], [dimer]) if a == b => (Shape::Bind, a, Some(dimer)),
+            ([dimer], [a, b]) if a == b => (Shape::Split, dimer, Some(a)),
+            _ => {
+                let (reactant, product) = self.first_order_species(rule)?;
+                return Ok((Shape::Convert, reactant, product));
+            }
+        };
+        let (reactant, product) = self.reactant_and_product(reactant, product)?;
+        Ok((shape, reactant, product))
+    }
+
+    fn first_order_species(&self, rule: &ReactionRule) -> Result<(SpeciesID, Option<SpeciesID>)> {
+        if rule.reactants().len() != 1 || rule.products().len() > 1 || rule.k() < 0.0 {
+            return Err(Error::InvalidReaction);
+        }
+        self.reactant_and_product(&rule.reactants()[0], rule.products().first())
+    }
+
+    /// Looks both species up, failing with `InvalidReaction` for an obstacle
+    /// reactant or a product on another location.
+    fn reactant_and_product(
+        &self,
+        reactant: &Species,
+        product: Option<&Species>,
+    ) -> Result<(SpeciesID, Option<SpeciesID>)> {
+        let reactant = self.species_id(reactant)?;
+        let product = product
+            .map(|product| self.species_id(product))
+            .transpose()?;
+        if self.space.is_obstacle(reactant) {
+            return Err(Error::InvalidReaction);
+        }
+        if let Some(product) = product {
+            if self.space.location_of(product)? != self.space.location_of(reactant)? {
+                return Err(Error::InvalidReaction);
+            }
+        }
+        Ok((reactant, product))
+    }
+
+    /// Keeps `target` molecules of `species` on the `coordinates` of `face`,
+    /// topping them up every `interval` starting now.
+    fn add_source(
+        &mut self,
+        face: Face,
+        coordinates: Vec<Coordinate>,
+        species: Species,
+        target: usize,
+        interval: f64,
+    ) -> Result<SourceID> {
+        check_interval(interval)?;
+        let species = self
+            .space
+            .find_species(species.name())
+            .ok_or(Error::SpeciesNotFound(species))?;
+        self.sources.push(SourceBoundary {
+            face,
+            species,
+            coordinates,
+            target,
+            interval,
+            deficits: Vec::new(),
+        });
+        let i = self.sources.len() - 1;
+        self.schedule(self.t, EventKind::Source(i));
+        Ok(SourceID(i))
+    }
+
+    pub fn source(&self, id: SourceID) -> &SourceBoundary {
+        &self.sources[id.0]
+    }
+
+    /// Returns the sources in the order they were added.
+    pub fn sources(&self) -> &[SourceBoundary] {
+        &self.sources
+    }
+
+    /// Brings the molecules of source `i` on its voxels to its target.
+    fn top_up(&mut self, i: usize) -> Result<()> {
+        let species = self.sources[i].species;
+        let mut present = Vec::new();
+        let mut free = Vec::new();
+        for &c in &self.sources[i].coordinates {
+            match self.space.species_at(c)? {
+                Some(id) if id == species => present.push(c),
+                _ if self.space.can_occupy(species, c) => free.push(c),
+                _ => {}
+            }
+        }
+        let target = self.sources[i].target;
+        if present.len() < target {
+            let missing = target - present.len();
+            free.shuffle(&mut self.rng);
+            for &c in free.iter().take(missing) {
+                self.space.place_particle(species, c)?;
+            }
+            if free.len() < missing {
+                let t = self.t;
+                self.sources[i].deficits.push((t, missing - free.len()));
+            }
+            self.unscheduled = true;
+        } else {
+            present.shuffle(&mut self.rng);
+            for &c in &present[target..] {
+                self.space.remove_at(c)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies `event` at `t`, after the events already scheduled then.
+    /// Fails with `SpeciesNotFound` for an unregistered species, and with
+    /// `InvalidReaction` for a negative rate or a reaction of another
+    /// simulator.
+    ///
+    /// Panics if `t` is in the past.
+    pub fn schedule_event(&mut self, t: f64, event: ModelEvent) -> Result<()> {
+        assert!(t >= self.t, "an event cannot be scheduled in the past");
+        match &event {
+            ModelEvent::AddMolecules { species, .. }
+            | ModelEvent::RemoveMolecules { species, .. } => {
+                self.species_id(species)?;
+            }
+            ModelEvent::Photobleach {
+                species, bleached, ..
+            } => {
+                self.species_id(species)?;
+                self.species_id(bleached)?;
+            }
+            &ModelEvent::SetRate { reaction, k } => {
+                let len = if reaction.exact {
+                    self.exact_reactions.len()
+                } else {
+                    self.reactions.len()
+                };
+                if reaction.index >= len || k < 0.0 {
+                    return Err(Error::InvalidReaction);
+                }
+            }
+        }
+        self.model_events.push((t, Some(event)));
+        self.schedule(t, EventKind::Model(self.model_events.len() - 1));
+        Ok(())
+    }
+
+    /// Returns the events of `schedule_event` yet to fire, in the order
+    /// they will. Along with the space and the RNG, they are the state to
+    /// resume a run from, scheduling them again on the new simulator.
+    pub fn pending_events(&self) -> Vec<(f64, &ModelEvent)> {
+        let mut pending: Vec<(f64, &ModelEvent)> = self
+            .model_events
+            .iter()
+            .filter_map(|(t, event)| event.as_ref().map(|event| (*t, event)))
+            .collect();
+        pending.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        pending
+    }
+
+    fn species_id(&self, species: &Species) -> Result<SpeciesID> {
+        self.space
+            .find_species(species.name())
+            .ok_or_else(|| Error::SpeciesNotFound(species.clone()))
+    }
+
+    /// Returns true if `region` is `None` or contains the voxel.
+    fn within(&self, region: &Option<Arc<dyn Region>>, coordinate: Coordinate) -> Result<bool> {
+        match region {
+            Some(region) => Ok(region.contains(self.space.position(coordinate)?)),
+            None => Ok(true),
+        }
+    }
+
+    pub(crate) fn apply(&mut self, event: ModelEvent) -> Result<()> {
+        match event {
+            ModelEvent::AddMolecules {
+                species,
+                count,
+                region,
+            } => {
+                let species = self.species_id(&species)?;
+                let mut free = Vec::new();
+                for c in (0..self.space.num_voxels()).map(Coordinate) {
+                    if self.space.can_occupy(species, c) && self.within(&region, c)? {
+                        free.push(c);
+                    }
+                }
+                free.shuffle(&mut self.rng);
+                for &c in free.iter().take(count) {
+                    self.space.place_particle(species, c)?;
+                }
+                self.unscheduled = true;
+            }
+            ModelEvent::RemoveMolecules { species, region } => {
+                let species = self.species_id(&species)?;
+                for c in self.space.coordinates_of(species)? {
+                    if self.space.species_at(c)? == Some(species) && self.within(&region, c)? {
+                        self.space.remove_at(c)?;
+                    }
+                }
+            }
+            ModelEvent::SetRate { reaction, k } => {
+                if reaction.exact {
+                    let exact = &mut self.exact_reactions[reaction.index];
+                    exact.rule.set_k(k);
+                    exact.scheduled.clear();
+                    self.unscheduled = true;
+                } else {
+                    let stepped = &mut self.reactions[reaction.index];
+                    stepped.rule.set_k(k);
+                    stepped.interval = 0.1 / k;
+                    self.schedule_reaction(reaction.index);
+                }
+            }
+            ModelEvent::Photobleach {
+                species,
+                bleached,
+                region,
+            } => {
+                self.photobleach(&species, &bleached, region.as_ref())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Turns every molecule of `species` within `region` into a molecule of
+    /// `bleached` on the same voxel with the same `ParticleID`, and returns
+    /// how many it turned. `bleached` should be registered with the same
+    /// location and diffusion coefficient. Fails like `change_species_at`
+    /// if they do not share their location.
+    pub fn photobleach(
+        &mut self,
+        species: &Species,
+        bleached: &Species,
+        region: &dyn Region,
+    ) -> Result<usize> {
+        let species = self.species_id(species)?;
+        let bleached = self.species_id(bleached)?;
+        let mut count = 0;
+        for c in self.space.coordinates_of(species)? {
+            if region.contains(self.space.position(c)?) {
+                self.space.change_species_at(c, bleached)?;
+                count += 1;
+            }
+        }
+        self.unscheduled = true;
+        Ok(count)
+    }
+
+    /// Records the counts of `species` every `interval`, starting now.
+    /// Fails with `InvalidDuration` unless `interval` is finite and
+    /// positive, as do the other built-in observers.
+    pub fn add_number_observer(
+        &mut self,
+        species: Vec<Species>,
+        interval: f64,
+    ) -> Result<NumberObserverID> {
+        check_interval(interval)?;
+        let slot = ObserverSlot::Number(self.number_observers.len());
+        self.number_observers
+            .push(NumberObserver::new(species, interval, self.t));
+        self.schedule_observer(slot);
+        Ok(NumberObserverID(self.number_observers.len() - 1))
+    }
+
+    pub fn number_observer(&self, id: NumberObserverID) -> &NumberObserver {
+        &self.number_observers[id.0]
+    }
+
+    /// Returns the number observers in the order they were added.
+    pub fn number_observers(&self) -> &[NumberObserver] {
+        &self.number_observers
+    }
+
+    /// Records the positions of `target` every `interval`, starting now.
+    /// With `unwrapped`, positions are unwrapped across periodic boundaries.
+    pub fn add_trajectory_observer(
+        &mut self,
+        target: TrajectoryTarget,
+        interval: f64,
+        unwrapped: bool,
+    ) -> Result<TrajectoryObserverID> {
+        check_interval(interval)?;
+        let slot = ObserverSlot::Trajectory(self.trajectory_observers.len());
+        self.trajectory_observers
+            .push(TrajectoryObserver::new(target, interval, self.t, unwrapped));
+        self.schedule_observer(slot);
+        Ok(TrajectoryObserverID(self.trajectory_observers.len() - 1))
+    }
+
+    pub fn trajectory_observer(&self, id: TrajectoryObserverID) -> &TrajectoryObserver {
+        &self.trajectory_observers[id.0]
+    }
+
+    /// Records the counts of `species` within `region` every `interval`,
+    /// starting now.
+    pub fn add_region_observer(
+        &mut self,
+        species: Vec<Species>,
+        region: Arc<dyn Region>,
+        interval: f64,
+    ) -> Result<RegionObserverID> {
+        check_interval(interval)?;
+        let slot = ObserverSlot::Region(self.region_observers.len());
+        self.region_observers
+            .push(RegionObserver::new(species, region, interval, self.t));
+        self.schedule_observer(slot);
+        Ok(RegionObserverID(self.region_observers.len() - 1))
+    }
+
+    pub fn region_observer(&self, id: RegionObserverID) -> &RegionObserver {
+        &self.region_observers[id.0]
+    }
+
+    /// Records the numbers of molecules absorbed by the sinks every
+    /// `interval`, starting now.
+    pub fn add_sink_observer(&mut self, interval: f64) -> Result<SinkObserverID> {
+        check_interval(interval)?;
+        let slot = ObserverSlot::Sink(self.sink_observers.len());
+        self.sink_observers
+            .push(SinkObserver::new(interval, self.t));
+        self.schedule_observer(slot);
+        Ok(SinkObserverID(self.sink_observers.len() - 1))
+    }
+
+    pub fn sink_observer(&self, id: SinkObserverID) -> &SinkObserver {
+        &self.sink_observers[id.0]
+    }
+
+    /// Adds a user-defined observer. Its first sample is at whatever time
+    /// its `next_time` returns now, which should not be in the past.
+    pub fn add_observer(&mut self, observer: Box<dyn Observer<S>>) -> ObserverID {
+        let slot = ObserverSlot::Custom(self.observers.len());
+        self.observers.push(observer);
+        self.schedule_observer(slot);
+        ObserverID(self.observers.len() - 1)
+    }
+
+    fn observer_mut(&mut self, slot: ObserverSlot) -> &mut dyn Observer<S> {
+        match slot {
+            ObserverSlot::Number(i) => &mut self.number_observers[i],
+            ObserverSlot::Trajectory(i) => &mut self.trajectory_observers[i],
+            ObserverSlot::Region(i) => &mut self.region_observers[i],
+            ObserverSlot::Sink(i) => &mut self.sink_observers[i],
+            ObserverSlot::Custom(i) => self.observers[i].as_mut(),
+        }
+    }
+
+    /// Schedules the next sample of the observer, or drops it from the
+    /// queue for good if it has retired.
+    fn schedule_observer(&mut self, slot: ObserverSlot) {
+        if let Some(time) = self.observer_mut(slot).next_time() {
+            self.schedule(time, EventKind::Observer(slot));
+        }
+    }
+
+    /// Processes the next event, if any.
+    pub fn step(&mut self) -> Result<()> {
+        self.prepare();
+        let event = match self.queue.pop() {
+            Some(event) => event,
+            None => return Ok(()),
+        };
+        self.t = event.time;
+        self.num_steps += 1;
+
+        match event.kind {
+            EventKind::Diffusion(species) => {
+                let start = match self.listener {
+                    Some(_) => Some(self.sweep_start(species)?),
+                    None => None,
+                };
+                self.space.walk(species, &mut self.rng)?;
+                if let Some(start) = start {
+                    self.report_sweep(species, start)?;
+                }
+                self.unscheduled = true;
+                if let Some(interval) = self.space.diffusion_interval(species) {
+                    self.schedule(self.t + interval, event.kind);
+                }
+            }
+            EventKind::Reaction(i) => {
+                if self.reactions[i].pending == Some(event.seq) {
+                    match self.reactions[i].shape {
+                        Shape::Convert => self.fire_first_order(i)?,
+                        Shape::Bind => self.fire_binding(i)?,
+                        Shape::Split => self.fire_splitting(i)?,
+                    }
+                    self.schedule_reaction(i);
+                    self.unscheduled = true;
+                }
+            }
+            EventKind::Firing(i, reactant, pid) => self.fire_exact(i, reactant, pid, event.seq)?,
+            EventKind::Source(i) => {
+                self.top_up(i)?;
+                self.schedule(self.t + self.sources[i].interval, event.kind);
+            }
+            EventKind::Model(i) => {
+                if let Some(model_event) = self.model_events[i].1.take() {
+                    let event = self.listener.as_ref().map(|_| model_event.clone());
+                    self.apply(model_event)?;
+                    if let Some(event) = event {
+                        let t = self.t;
+                        self.emit(|| SimEvent::ModelEventApplied { t, event });
+                    }
+                }
+            }
+            EventKind::Observer(slot) => {
+                let t = self.t;
+                let space = &self.space;
+                let observer: &mut dyn Observer<S> = match slot {
+                    ObserverSlot::Number(i) => &mut self.number_observers[i],
+                    ObserverSlot::Trajectory(i) => &mut self.trajectory_observers[i],
+                    ObserverSlot::Region(i) => &mut self.region_observers[i],
+                    ObserverSlot::Sink(i) => &mut self.sink_observers[i],
+                    ObserverSlot::Custom(i) => self.observers[i].as_mut(),
+                };
+                observer.fire(t, space);
+                self.schedule_observer(slot);
+            }
+        }
+        Ok(())
+    }
+
+    fn fire_first_order(&mut self, i: usize) -> Result<()> {
+        let reaction = &self.reactions[i];
+        let (reactant, product) = (reaction.reactant, reaction.product);
+        let probability = 1.0 - (-reaction.rule.k() * reaction.interval).exp();
+        let id = ReactionID {
+            exact: false,
+            index: i,
+        };
+        for coordinate in self.space.coordinates_of(reactant)? {
+            let probability = probability * self.rate_factor(id, coordinate);
+            if self.rng.gen::<f64>() < probability {
+                self.space.remove_at(coordinate)?;
+                if let Some(product) = product {
+                    self.space.place_particle(product, coordinate)?;
+                }
+                self.reactions[i].fired += 1;
+                let t = self.t;
+                self.emit(|| SimEvent::ReactionFired {
+                    t,
+                    reaction: id,
+                    voxel: coordinate,
+                    reactant,
+                    product,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Binds every adjacent pair of monomers of stepped reaction `i` with
+    /// its probability, each pair being tried once from its lower
+    /// coordinate.
+    fn fire_binding(&mut self, i: usize) -> Result<()> {
+        let reaction = &self.reactions[i];
+        let (monomer, dimer) = (reaction.reactant, reaction.product.expect("a dimer"));
+        let probability = 1.0 - (-reaction.rule.k() * reaction.interval).exp();
+        let id = ReactionID {
+            exact: false,
+            index: i,
+        };
+        for a in self.space.coordinates_of(monomer)? {
+            for b in self.space.neighbors(a)? {
+                if self.space.species_at(a)? != Some(monomer) {
+                    break;
+                }
+                if b.0 <= a.0 || self.space.species_at(b)? != Some(monomer) {
+                    continue;
+                }
+                let probability = probability * self.rate_factor(id, a);
+                if self.rng.gen::<f64>() < probability {
+                    let (_, first) = self.space.remove_at(a)?;
+                    let (_, second) = self.space.remove_at(b)?;
+                    let at = if self.rng.gen::<bool>() { a } else { b };
+                    let pid = self.space.place_particle(dimer, at)?;
+                    self.reactions[i].fired += 1;
+                    let t = self.t;
+                    self.emit(|| SimEvent::ReactionFired {
+                        t,
+                        reaction: id,
+                        voxel: at,
+                        reactant: monomer,
+                        product: Some(dimer),
+                    });
+                    if let (Some(first), Some(second)) = (first, second) {
+                        if self.space.is_tracking(dimer) {
+                            self.lineage.insert(pid, (first, second));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits every dimer of stepped reaction `i` with its probability onto
+    /// a random neighbor, if the monomer can take it.
+    fn fire_splitting(&mut self, i: usize) -> Result<()> {
+        let reaction = &self.reactions[i];
+        let (dimer, monomer) = (reaction.reactant, reaction.product.expect("a monomer"));
+        let probability = 1.0 - (-reaction.rule.k() * reaction.interval).exp();
+        let id = ReactionID {
+            exact: false,
+            index: i,
+        };
+        for a in self.space.coordinates_of(dimer)? {
+            let neighbors = self.space.neighbors(a)?;
+            let b = match neighbors.choose(&mut self.rng) {
+                Some(&b) => b,
+                None => continue,
+            };
+            if !self.space.can_occupy(monomer, b) {
+                continue;
+            }
+            let probability = probability * self.rate_factor(id, a);
+            if self.rng.gen::<f64>() < probability {
+                let (_, pid) = self.space.remove_at(a)?;
+                if let Some(pid) = pid {
+                    self.lineage.remove(&pid);
+                }
+                self.space.place_particle(monomer, a)?;
+                self.space.place_particle(monomer, b)?;
+                self.reactions[i].fired += 1;
+                let t = self.t;
+                self.emit(|| SimEvent::ReactionFired {
+                    t,
+                    reaction: id,
+                    voxel: a,
+                    reactant: dimer,
+                    product: Some(monomer),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the `ParticleID`s of the two monomers that bound into the
+    /// dimer `pid`, if a homodimerization formed it from tracked monomers.
+    /// The record is dropped when a dissociation splits the dimer.
+    pub fn dimer_lineage(&self, pid: ParticleID) -> Option<(ParticleID, ParticleID)> {
+        self.lineage.get(&pid).copied()
+    }
+
+    /// Fires exact reaction `i` on `pid` of `reactant`, unless the molecule
+    /// has gone or changed species since it was scheduled, or the event
+    /// numbered `seq` has been replaced by another one.
+    fn fire_exact(
+        &mut self,
+        i: usize,
+        reactant: SpeciesID,
+        pid: ParticleID,
+        seq: u64,
+    ) -> Result<()> {
+        let reaction = &mut self.exact_reactions[i];
+        if reaction.scheduled.get(&pid) != Some(&seq) {
+            return Ok(());
+        }
+        reaction.scheduled.remove(&pid);
+        let product = reaction.product;
+        let coordinate = match self.space.particle_coordinate(reactant, pid) {
+            Some(coordinate) => coordinate,
+            None => return Ok(()),
+        };
+        let id = ReactionID {
+            exact: true,
+            index: i,
+        };
+        let factor = self.rate_factor(id, coordinate);
+        if factor < 1.0 && self.rng.gen::<f64>() >= factor {
+            self.schedule_firing(i, pid);
+            return Ok(());
+        }
+        self.exact_reactions[i].fired += 1;
+        let t = self.t;
+        self.emit(|| SimEvent::ReactionFired {
+            t,
+            reaction: id,
+            voxel: coordinate,
+            reactant,
+            product,
+        });
+        self.space.remove_at(coordinate)?;
+        if let Some(product) = product {
+            let placed = self.space.place_particle(product, coordinate)?;
+            for j in 0..self.exact_reactions.len() {
+                if self.exact_reactions[j].reactant == product {
+                    self.schedule_firing(j, placed);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn sweep_start(&self, species: SpeciesID) -> Result<SweepStart> {
+        let coordinates = self.space.coordinates_of(species)?;
+        let particles = match self.space.particles_of(species) {
+            Ok(pids) if pids.len() == coordinates.len() => {
+                Some(pids.into_iter().zip(coordinates.iter().copied()).collect())
+            }
+            _ => None,
+        };
+        Ok(SweepStart {
+            particles,
+            coordinates,
+            sink_counts: self.space.sink_counts(),
+        })
+    }
+
+    /// Tells the listener what the sweep of `species` since `start` did.
+    fn report_sweep(&mut self, species: SpeciesID, start: SweepStart) -> Result<()> {
+        let t = self.t;
+        let after = self.sweep_start(species)?;
+        let mut events = Vec::new();
+        let rejected = match (&start.particles, &after.particles) {
+            (Some(before), Some(now)) => {
+                let now: HashMap<ParticleID, Coordinate> = now.iter().copied().collect();
+                let lengths = self.space.periodic_lengths();
+                let mut rejected = 0;
+                for &(pid, from) in before {
+                    let to = match now.get(&pid) {
+                        Some(&to) => to,
+                        None => continue,
+                    };
+                    if to == from {
+                        rejected += 1;
+                    } else if self.space.is_periodic() {
+                        let (p, q) = (self.space.position(from)?, self.space.position(to)?);
+                        if (0..3).any(|axis| (q[axis] - p[axis]).abs() > lengths[axis] / 2.0) {
+                            events.push(SimEvent::BoundaryCrossed { t, pid, from, to });
+                        }
+                    }
+                }
+                rejected
+            }
+            _ => {
+                let now: HashSet<usize> = after.coordinates.iter().map(|c| c.0).collect();
+                start
+                    .coordinates
+                    .iter()
+                    .filter(|c| now.contains(&c.0))
+                    .count()
+            }
+        };
+        events.insert(
+            0,
+            SimEvent::WalkCompleted {
+                t,
+                species,
+                attempted: start.coordinates.len(),
+                rejected,
+            },
+        );
+        for (sink, (&now, &before)) in after.sink_counts.iter().zip(&start.sink_counts).enumerate()
+        {
+            if now > before {
+                events.push(SimEvent::Absorbed {
+                    t,
+                    species,
+                    sink,
+                    count: now - before,
+                });
+            }
+        }
+        for event in events {
+            self.emit(|| event);
+        }
+        Ok(())
+    }
+
+    /// Initializes the queue on the first call and schedules the firings
+    /// missing since the last one.
+    fn prepare(&mut self) {
+        if !self.initialized {
+            self.initialize();
+        }
+        self.schedule_firings();
+    }
+
+    /// Processes every event up to and including `t + duration`.
+    ///
+    /// Events scheduled within a relative rounding error of the end time
+    /// count as being at the end time, so that an observer whose interval
+    /// divides `duration` samples the final state. Fails with
+    /// `InvalidDuration` for a negative or non-finite `duration`, leaving
+    /// the simulator untouched.
+    pub fn run(&mut self, duration: f64) -> Result<()> {
+        self.run_with_progress(duration, NonZeroU64::MAX, |_, _| ControlFlow::Continue(()))
+            .map(|_| ())
+    }
+
+    /// Same as `run`, calling `progress` with `num_steps` and `t` after
+    /// every `every` steps. If it returns `Break`, the run stops right there
+    /// and returns `Break`, leaving `t` at the last event processed and the
+    /// observers with what they recorded so far; `run` can pick it up again.
+    pub fn run_with_progress<F>(
+        &mut self,
+        duration: f64,
+        every: NonZeroU64,
+        mut progress: F,
+    ) -> Result<ControlFlow<()>>
+    where
+        F: FnMut(u64, f64) -> ControlFlow<()>,
+    {
+        if !(duration >= 0.0 && duration.is_finite()) {
+            return Err(Error::InvalidDuration(duration));
+        }
+        let end = self.t + duration;
+        let tolerance = end.abs() * 1e-12;
+        loop {
+            self.prepare();
+            match self.queue.peek() {
+                Some(event) if event.time <= end + tolerance => {}
+                _ => break,
+            }
+            self.step()?;
+            if self.num_steps.is_multiple_of(every.get())
+                && progress(self.num_steps, self.t).is_break()
+            {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        self.t = end;
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+impl<R: Rng, S: LatticeSpace> Simulator<R, S> {
+    /// Runs for `duration`, sampling every `sample_every` the counts of
+    /// every species and the flux of every reaction, its firings since the
+    /// previous sample divided by `sample_every`.
+    ///
+    /// The `n`-th sample is taken at `n * sample_every` from now, once every
+    /// event up to then is processed as by `run`, so that each firing counts
+    /// in the interval its time falls in. The first sample is the initial
+    /// state, with zero fluxes; samples due after `duration` are not taken,
+    /// though the run goes on to its end.
+    ///
+    /// Fails with `InvalidDuration` unless `duration` is finite and not
+    /// negative and `sample_every` is finite and positive, and with
+    /// `SizeOverflow` if the samples would not fit in memory.
+    pub fn run_time_course(&mut self, duration: f64, sample_every: f64) -> Result<TimeCourse> {
+        if !(duration >= 0.0 && duration.is_finite()) {
+            return Err(Error::InvalidDuration(duration));
+        }
+        if !(sample_every > 0.0 && sample_every.is_finite()) {
+            return Err(Error::InvalidDuration(sample_every));
+        }
+        let start = self.t;
+        let num_species = self.space.num_species();
+        let num_samples = ((duration / sample_every + 1e-9).floor() as usize)
+            .checked_add(1)
+            .ok_or(Error::SizeOverflow)?;
+        let num_counts = num_samples
+            .checked_mul(num_species)
+            .ok_or(Error::SizeOverflow)?;
+        let reactions = self.reactions();
+        let mut course = TimeCourse {
+            times: Vec::with_capacity(num_samples),
+            counts: Vec::with_capacity(num_counts),
+            num_species,
+            reaction_flux: vec![Vec::with_capacity(num_samples); reactions.len()],
+            reactions,
+        };
+        let mut last: Vec<u64> = course
+            .reactions
+            .iter()
+            .map(|&id| self.num_firings(id))
+            .collect();
+        for n in 0..num_samples {
+            if n > 0 {
+                self.run(start + n as f64 * sample_every - self.t)?;
+            }
+            course.times.push(self.t);
+            for i in 0..num_species {
+                course.counts.push(self.space.num_molecules(SpeciesID(i))?);
+            }
+            for (r, &id) in course.reactions.iter().enumerate() {
+                let fired = self.num_firings(id);
+                let flux = if n > 0 {
+                    (fired - last[r]) as f64 / sample_every
+                } else {
+                    0.0
+                };
+                course.reaction_flux[r].push(flux);
+                last[r] = fired;
+            }
+        }
+        // The last sample may be due a rounding error after the end.
+        self.run((start + duration - self.t).max(0.0))?;
+        Ok(course)
+    }
+}
+
+impl<R: Rng> Simulator<R, HCPLatticeSpace> {
+    /// Keeps `target` molecules of `species`, which should be single-voxel,
+    /// on the voxels of `face`, topping them up every `interval` starting
+    /// now. A face without enough free voxels is filled and the shortfall
+    /// recorded in `SourceBoundary::deficits`. Fails with
+    /// `PeriodicBoundary` on a periodic lattice, which has no faces to keep,
+    /// and with `InvalidDuration` unless `interval` is finite and positive.
+    pub fn add_source_boundary(
+        &mut self,
+        face: Face,
+        species: Species,
+        target: usize,
+        interval: f64,
+    ) -> Result<SourceID> {
+        if self.space.is_periodic() {
+            return Err(Error::PeriodicBoundary);
+        }
+        let coordinates = self.space.boundary_coordinates(face).collect();
+        self.add_source(face, coordinates, species, target, interval)
+    }
+}
+
+impl<S: LatticeSpace> Simulator<Pcg64, S> {
+    /// Creates a simulator drawing from a `Pcg64` seeded with `seed`.
+    pub fn with_seed(space: S, seed: u64) -> Self {
+        Self::new(space, Pcg64::seed_from_u64(seed))
+    }
+}
+
+/// Fails with `InvalidDuration` unless `interval` is finite and positive, so
+/// that something recurring every `interval` moves time forward.
+fn check_interval(interval: f64) -> Result<()> {
+    if interval > 0.0 && interval.is_finite() {
+        Ok(())
+    } else {
+        Err(Error::InvalidDuration(interval))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HCPLatticeSize, MoleculeInfo, Sphere};
+    use rand::rngs::StdRng;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn decay_simulator(seed: u64) -> Simulator<StdRng> {
+        let mut space = HCPLatticeSpace::new(1e-8, HCPLatticeSize::new(10, 10, 20));
+        let a = space.register_species(Species::new("A"), None);
+        space.register_species(Species::new("B"), None);
+        space
+            .set_molecule_info(
+                a,
+                MoleculeInfo {
+                    radius: 1e-8,
+                    diffusion_coefficient: 1e-14,
+                },
+            )
+            .unwrap();
+        for i in 0..1000 {
+            space.place_particle(a, Coordinate(i)).unwrap();
+        }
+        Simulator::new(space, StdRng::seed_from_u64(seed))
+    }
+
+    #[test]
+    fn number_of_samples() {
+        let mut sim = decay_simulator(0);
+        let every_tenth = sim
+            .add_number_observer(vec![Species::new("A")], 0.1)
+            .unwrap();
+        let off_grid = sim
+            .add_number_observer(vec![Species::new("A")], 0.3)
+            .unwrap();
+        sim.run(1.0).unwrap();
+        assert_eq!(sim.number_observer(every_tenth).data().len(), 11);
+        assert_eq!(sim.number_observer(off_grid).data().len(), 4);
+        let last = sim.number_observer(every_tenth).data().last().unwrap().0;
+        assert!((last - 1.0).abs() < 1e-9);
+        assert_eq!(sim.number_observer(off_grid).data()[0], (0.0, vec![1000]));
+    }
+
+    #[test]
+    fn counts_follow_reactions() {
+        let mut sim = decay_simulator(1);
+        let observer = sim
+            .add_number_observer(vec![Species::new("A"), Species::new("B")], 0.5)
+            .unwrap();
+        let rule = ReactionRule::new(vec![Species::new("A")], vec![Species::new("B")], 1.0);
+        sim.add_reaction(rule).unwrap();
+        sim.run(1.0).unwrap();
+        sim.space().validate().unwrap();
+
+        let data = sim.number_observer(observer).data();
+        assert_eq!(data[0].1, vec![1000, 0]);
+        for (_, counts) in data {
+            assert_eq!(counts[0] + counts[1], 1000);
+        }
+        let expected = 1000.0 * (-1.0f64).exp();
+        assert!((data[2].1[0] as f64 - expected).abs() < 80.0);
+    }
+
+    #[test]
+    fn sampling_does_not_perturb() {
+        let mut observed = decay_simulator(2);
+        let mut plain = decay_simulator(2);
+        for sim in [&mut observed, &mut plain].iter_mut() {
+            let rule = ReactionRule::new(vec![Species::new("A")], vec![], 2.0);
+            sim.add_reaction(rule).unwrap();
+        }
+        observed
+            .add_number_observer(vec![Species::new("A")], 0.01)
+            .unwrap();
+        observed.run(0.5).unwrap();
+        plain.run(0.5).unwrap();
+        assert_eq!(observed.space().voxels, plain.space().voxels);
+    }
+
+    #[test]
+    fn exact_reactions_chain() {
+        let mut sim = decay_simulator(3);
+        let c = sim.space_mut().register_species(Species::new("C"), None);
+        let observer = sim
+            .add_number_observer(vec![Species::new("A"), Species::new("B")], 0.5)
+            .unwrap();
+        let a_to_b = ReactionRule::new(vec![Species::new("A")], vec![Species::new("B")], 1.0);
+        let b_to_c = ReactionRule::new(vec![Species::new("B")], vec![Species::new("C")], 1.0);
+        sim.add_exact_reaction(a_to_b).unwrap();
+        sim.add_exact_reaction(b_to_c).unwrap();
+        sim.run(1.0).unwrap();
+        sim.space().validate().unwrap();
+        let counts = &sim.number_observer(observer).data()[2].1;
+        // A decays as exp(-t) and B, fed by A, as t exp(-t).
+        let expected = 1000.0 * (-1.0f64).exp();
+        assert!((counts[0] as f64 - expected).abs() < 60.0, "{:?}", counts);
+        assert!((counts[1] as f64 - expected).abs() < 60.0, "{:?}", counts);
+
+        // Molecules placed by hand get their times too.
+        let free = (0..2000)
+            .map(Coordinate)
+            .find(|&c| sim.space().species_at(c).unwrap().is_none())
+            .unwrap();
+        let a = sim.space().find_species("A").unwrap();
+        sim.space_mut().place_particle(a, free).unwrap();
+        sim.run(30.0).unwrap();
+        assert_eq!(sim.space().num_molecules(c).unwrap(), 1001);
+    }
+
+    #[test]
+    fn flux_per_sampling_interval() {
+        let mut sim = decay_simulator(5);
+        sim.space_mut().register_species(Species::new("C"), None);
+        let a_to_b = ReactionRule::new(vec![Species::new("A")], vec![Species::new("B")], 1.0);
+        let b_to_c = ReactionRule::new(vec![Species::new("B")], vec![Species::new("C")], 0.5);
+        let exact = sim.add_exact_reaction(a_to_b).unwrap();
+        let stepped = sim.add_reaction(b_to_c).unwrap();
+        assert_eq!(sim.reactions(), vec![stepped, exact]);
+        let dt = 0.1;
+        let course = sim.run_time_course(1.05, dt).unwrap();
+        assert!((sim.t() - 1.05).abs() < 1e-12);
+        assert_eq!(course.num_samples(), 11);
+        assert_eq!(course.reaction_flux.len(), 2);
+        assert_eq!(course.flux(exact, 0), 0.0);
+        let (a, c) = (SpeciesID(0), SpeciesID(2));
+        for n in 1..course.num_samples() {
+            assert!((course.times[n] - n as f64 * dt).abs() < 1e-12);
+            let consumed = course.count(a, n - 1) - course.count(a, n);
+            let produced = course.count(c, n) - course.count(c, n - 1);
+            assert!((course.flux(exact, n) * dt - consumed as f64).abs() < 1e-9);
+            assert!((course.flux(stepped, n) * dt - produced as f64).abs() < 1e-9);
+        }
+        assert_eq!(
+            sim.num_firings(exact),
+            1000 - sim.space().num_molecules(a).unwrap() as u64
+        );
+        // The first interval sees about k A(0) dt (1 - k dt/2) firings.
+        let expected = 1000.0 * (1.0 - (-dt).exp()) / dt;
+        let sigma = (expected / dt).sqrt();
+        assert!((course.flux(exact, 1) - expected).abs() < 4.0 * sigma);
+
+        let t = sim.t();
+        for (duration, sample_every) in [
+            (1.0, 0.0),
+            (1.0, -0.1),
+            (1.0, f64::NAN),
+            (1.0, f64::INFINITY),
+            (-1.0, 0.1),
+            (f64::NAN, 0.1),
+            (f64::INFINITY, 0.1),
+        ] {
+            assert!(matches!(
+                sim.run_time_course(duration, sample_every),
+                Err(Error::InvalidDuration(_))
+            ));
+        }
+        assert!(matches!(
+            sim.run_time_course(1e300, 1e-300),
+            Err(Error::SizeOverflow)
+        ));
+        assert_eq!(sim.t(), t);
+    }
+
+    #[test]
+    fn exact_and_stepped_reactions_compete() {
+        let mut sim = decay_simulator(4);
+        sim.space_mut().register_species(Species::new("C"), None);
+        let exact = ReactionRule::new(vec![Species::new("A")], vec![Species::new("B")], 1.0);
+        let stepped = ReactionRule::new(vec![Species::new("A")], vec![Species::new("C")], 1.0);
+        sim.add_exact_reaction(exact).unwrap();
+        sim.add_reaction(stepped).unwrap();
+        sim.run(20.0).unwrap();
+        sim.space().validate().unwrap();
+        let count = |name| {
+            let id = sim.space().find_species(name).unwrap();
+            sim.space().num_molecules(id).unwrap()
+        };
+        assert_eq!(count("A"), 0);
+        assert_eq!(count("B") + count("C"), 1000);
+        assert!(count("B") > 420 && count("B") < 580, "{}", count("B"));
+    }
+
+    #[test]
+    fn rates_modified_by_voxel_properties() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(10, 10, 10));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        let damaged = space.add_voxel_property::<bool>("damaged");
+        let rate = space.add_voxel_property::<f64>("rate");
+        for i in 0..1000 {
+            let c = Coordinate(i);
+            if i % 2 == 0 {
+                space.place_particle(a, c).unwrap();
+                space.set_voxel_property(damaged, c, i % 8 == 0).unwrap();
+            } else {
+                space.place_particle(b, c).unwrap();
+                space.set_voxel_property(rate, c, 0.5).unwrap();
+            }
+        }
+        let mut sim = Simulator::new(space, StdRng::seed_from_u64(9));
+        let stepped = ReactionRule::new(vec![Species::new("A")], vec![], 20.0);
+        let stepped = sim.add_reaction(stepped).unwrap();
+        let exact = ReactionRule::new(vec![Species::new("B")], vec![], 1.0);
+        let exact = sim.add_exact_reaction(exact).unwrap();
+        sim.set_reaction_modifier(stepped, move |space: &HCPLatticeSpace, c| {
+            if *space.voxel_property(damaged, c).unwrap() {
+                0.0
+            } else {
+                1.0
+            }
+        })
+        .unwrap();
+        sim.set_reaction_modifier(exact, move |space: &HCPLatticeSpace, c| {
+            *space.voxel_property(rate, c).unwrap()
+        })
+        .unwrap();
+        sim.run(1.0).unwrap();
+        sim.space().validate().unwrap();
+
+        // Only the A on damaged voxels are left, and B decays at half its
+        // rate.
+        let survivors: Vec<Coordinate> = sim.space().coordinates_of(a).unwrap();
+        let flagged: Vec<Coordinate> = sim
+            .space()
+            .voxels_with_property(damaged)
+            .map(|(c, _)| c)
+            .collect();
+        assert_eq!(survivors, flagged);
+        let expected = 500.0 * (-0.5f64).exp();
+        let left = sim.space().num_molecules(b).unwrap() as f64;
+        assert!((left - expected).abs() < 40.0, "{}", left);
+
+        let unknown = ReactionID {
+            exact: true,
+            index: 1,
+        };
+        assert!(matches!(
+            sim.set_reaction_modifier(unknown, |_, _| 1.0),
+            Err(Error::InvalidReaction)
+        ));
+    }
+
+    /// Counts dimers and checks that the monomer equivalents are conserved
+    /// at every step.
+    #[test]
+    fn homodimer_equilibrium() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(10, 10, 10));
+        space.set_periodic(true).unwrap();
+        let a = space.register_species(Species::new("A"), None);
+        let a2 = space.register_species(Species::new("A2"), None);
+        for &species in &[a, a2] {
+            space
+                .set_molecule_info(
+                    species,
+                    MoleculeInfo {
+                        radius: 1.0,
+                        diffusion_coefficient: 1.0,
+                    },
+                )
+                .unwrap();
+        }
+        let total = 200;
+        for i in 0..total {
+            space.place_particle(a, Coordinate(5 * i)).unwrap();
+        }
+        let monomers = space.particles_of(a).unwrap();
+        let mut sim = Simulator::new(space, StdRng::seed_from_u64(12));
+        let (k_on, k_off) = (0.7, 1.0);
+        let bind = ReactionRule::new(
+            vec![Species::new("A"), Species::new("A")],
+            vec![Species::new("A2")],
+            k_on,
+        );
+        let split = ReactionRule::new(
+            vec![Species::new("A2")],
+            vec![Species::new("A"), Species::new("A")],
+            k_off,
+        );
+        sim.add_reaction(bind).unwrap();
+        sim.add_reaction(split).unwrap();
+        assert!(matches!(
+            sim.add_exact_reaction(ReactionRule::new(
+                vec![Species::new("A"), Species::new("A")],
+                vec![Species::new("A2")],
+                k_on,
+            )),
+            Err(Error::InvalidReaction)
+        ));
+
+        let (burn_in, duration) = (20.0, 300.0);
+        let mut sum = 0.0;
+        let mut samples = 0;
+        let mut next = burn_in;
+        while sim.t() < burn_in + duration {
+            sim.step().unwrap();
+            let (na, na2) = (
+                sim.space().num_molecules(a).unwrap(),
+                sim.space().num_molecules(a2).unwrap(),
+            );
+            assert_eq!(na + 2 * na2, total);
+            if sim.t() >= next {
+                sum += na2 as f64;
+                samples += 1;
+                next += 0.5;
+            }
+        }
+        sim.space().validate().unwrap();
+        let measured = sum / samples as f64;
+
+        // Configurations with `n` dimers among `V` voxels weigh
+        // `w^n V!/(N_A! n! (V - N_A - n)!)`, `N_A = total - 2n`.
+        let volume = 1000.0;
+        let w = 12.0 * k_on / (2.0 * k_off);
+        let mut log_weight = 0.0;
+        let mut weights = vec![1.0];
+        for n in 0..total / 2 {
+            let na = (total - 2 * n) as f64;
+            let n = n as f64;
+            log_weight += (w * na * (na - 1.0) / ((n + 1.0) * (volume - na - n + 1.0))).ln();
+            weights.push(log_weight.exp());
+        }
+        let z: f64 = weights.iter().sum();
+        let expected: f64 = weights
+            .iter()
+            .enumerate()
+            .map(|(n, w)| n as f64 * w)
+            .sum::<f64>()
+            / z;
+        assert!(
+            (measured / expected - 1.0).abs() < 0.05,
+            "measured {} expected {}",
+            measured,
+            expected
+        );
+
+        let dimer = sim.space().particles_of(a2).unwrap()[0];
+        let (first, second) = sim.dimer_lineage(dimer).unwrap();
+        assert_ne!(first, second);
+        assert!(sim.dimer_lineage(monomers[0]).is_none());
+        let pids = sim.space().particles_of(a).unwrap();
+        assert!(!pids.contains(&first) && !pids.contains(&second));
+    }
+
+    #[test]
+    fn exact_reactions_need_tracking() {
+        let mut sim = decay_simulator(0);
+        let a = sim.space().find_species("A").unwrap();
+        sim.space_mut().set_tracking(a, false).unwrap();
+        let decay = ReactionRule::new(vec![Species::new("A")], vec![], 1.0);
+        assert!(matches!(
+            sim.add_exact_reaction(decay),
+            Err(Error::TrackingRequired(_))
+        ));
+    }
+
+    #[test]
+    fn sink_time_series() {
+        let mut sim = decay_simulator(5);
+        let coordinates: Vec<Coordinate> = (1500..2000).map(Coordinate).collect();
+        let sink = sim.space_mut().add_sink(coordinates, None).unwrap();
+        let observer = sim.add_sink_observer(0.1).unwrap();
+        sim.run(1.0).unwrap();
+        let data = sim.sink_observer(observer).data();
+        assert_eq!(data.len(), 11);
+        assert_eq!(data[0].1, vec![0]);
+        assert!(data.windows(2).all(|w| w[0].1[0] <= w[1].1[0]));
+        let absorbed = sim.space().absorbed(sink);
+        assert!(data[10].1[0] > 0 && data[10].1[0] <= absorbed);
+        let a = sim.space().find_species("A").unwrap();
+        assert_eq!(
+            sim.space().num_molecules(a).unwrap() as u64 + absorbed,
+            1000
+        );
+    }
+
+    #[test]
+    fn ligand_steps_in_and_out() {
+        let mut sim = decay_simulator(6);
+        let observer = sim
+            .add_number_observer(vec![Species::new("B")], 0.1)
+            .unwrap();
+        let add = ModelEvent::AddMolecules {
+            species: Species::new("B"),
+            count: 100,
+            region: None,
+        };
+        sim.schedule_event(0.5, add).unwrap();
+        let lower_half: Arc<dyn Region> = Arc::new(|p: [f64; 3]| p[2] < 1.6e-7);
+        let wash = ModelEvent::RemoveMolecules {
+            species: Species::new("B"),
+            region: Some(Arc::clone(&lower_half)),
+        };
+        sim.schedule_event(0.8, wash).unwrap();
+        assert_eq!(sim.pending_events().len(), 2);
+        assert!(matches!(
+            sim.schedule_event(
+                0.9,
+                ModelEvent::RemoveMolecules {
+                    species: Species::new("X"),
+                    region: None,
+                }
+            ),
+            Err(Error::SpeciesNotFound(_))
+        ));
+        sim.run(1.0).unwrap();
+        sim.space().validate().unwrap();
+        assert!(sim.pending_events().is_empty());
+
+        let data = sim.number_observer(observer).data();
+        assert_eq!(data[4].1, vec![0]);
+        assert_eq!(data[5].1, vec![100]);
+        assert_eq!(data[7].1, vec![100]);
+        let b = sim.space().find_species("B").unwrap();
+        let left = sim.space().coordinates_of(b).unwrap();
+        assert_eq!(data[8].1, vec![left.len()]);
+        assert!(left.len() < 100);
+        for c in left {
+            assert!(!lower_half.contains(sim.space().position(c).unwrap()));
+        }
+    }
+
+    #[test]
+    fn rate_changes_take_over() {
+        let mut stepped = decay_simulator(7);
+        let observer = stepped
+            .add_number_observer(vec![Species::new("A")], 0.5)
+            .unwrap();
+        let rule = ReactionRule::new(vec![Species::new("A")], vec![Species::new("B")], 0.0);
+        let reaction = stepped.add_reaction(rule).unwrap();
+        let switch_on = ModelEvent::SetRate { reaction, k: 1.0 };
+        stepped.schedule_event(1.0, switch_on).unwrap();
+        assert!(matches!(
+            stepped.schedule_event(1.0, ModelEvent::SetRate { reaction, k: -1.0 }),
+            Err(Error::InvalidReaction)
+        ));
+        stepped.run(2.0).unwrap();
+        let data = stepped.number_observer(observer).data();
+        assert!(data[..3].iter().all(|(_, counts)| counts[0] == 1000));
+        let expected = 1000.0 * (-1.0f64).exp();
+        assert!((data[4].1[0] as f64 - expected).abs() < 80.0, "{:?}", data);
+
+        let mut exact = decay_simulator(8);
+        let observer = exact
+            .add_number_observer(vec![Species::new("A")], 0.5)
+            .unwrap();
+        let rule = ReactionRule::new(vec![Species::new("A")], vec![Species::new("B")], 1.0);
+        let reaction = exact.add_exact_reaction(rule).unwrap();
+        exact
+            .schedule_event(0.5, ModelEvent::SetRate { reaction, k: 0.0 })
+            .unwrap();
+        exact
+            .schedule_event(1.0, ModelEvent::SetRate { reaction, k: 2.0 })
+            .unwrap();
+        exact.run(1.5).unwrap();
+        exact.space().validate().unwrap();
+        let data = exact.number_observer(observer).data();
+        let half = 1000.0 * (-0.5f64).exp();
+        assert!((data[1].1[0] as f64 - half).abs() < 60.0, "{:?}", data);
+        assert_eq!(data[2].1, data[1].1);
+        let expected = data[2].1[0] as f64 * (-1.0f64).exp();
+        assert!((data[3].1[0] as f64 - expected).abs() < 60.0, "{:?}", data);
+    }
+
+    /// Bleaches a sphere of radius `a` and compares the recovery half-time
+    /// with the `0.1002 a²/D` at which half of the molecules starting
+    /// uniformly in a sphere have left it by free diffusion.
+    #[test]
+    fn frap_recovery_half_time() {
+        let (r, d, a) = (0.25, 1.0, 4.0);
+        let (bleach, interval) = (0.5, 0.1);
+        let info = MoleculeInfo {
+            radius: r,
+            diffusion_coefficient: d,
+        };
+        let spot: Arc<dyn Region> = Arc::new(Sphere::new([10.0, 8.66, 8.16], a));
+        let names = vec![Species::new("A"), Species::new("A*")];
+        let mut recovered: Vec<usize> = Vec::new();
+        let mut plateau = 0.0;
+        for seed in 0..16 {
+            let mut space = HCPLatticeSpace::new(r, HCPLatticeSize::new(40, 40, 40));
+            space.set_periodic(true).unwrap();
+            let unbleached = space.register_species(names[0].clone(), None);
+            let bleached = space.register_species(names[1].clone(), None);
+            space.set_molecule_info(unbleached, info).unwrap();
+            space.set_molecule_info(bleached, info).unwrap();
+            // Counting rather than tracking keeps the hops cheap.
+            space.set_tracking(unbleached, false).unwrap();
+            space.set_tracking(bleached, false).unwrap();
+            let mut rng = StdRng::seed_from_u64(seed);
+            for c in space.coordinates().collect::<Vec<_>>() {
+                if rng.gen::<f64>() < 0.03 {
+                    space.place_particle(unbleached, c).unwrap();
+                }
+            }
+            let mut sim = Simulator::new(space, rng);
+            let observer = sim
+                .add_region_observer(names.clone(), Arc::clone(&spot), interval)
+                .unwrap();
+            let event = ModelEvent::Photobleach {
+                species: names[0].clone(),
+                bleached: names[1].clone(),
+                region: Arc::clone(&spot),
+            };
+            sim.schedule_event(bleach, event).unwrap();
+            sim.run(3.5).unwrap();
+            let space = sim.space();
+            space.validate().unwrap();
+            let data = sim.region_observer(observer).data();
+            let first = (bleach / interval).round() as usize;
+            assert!(data[..first].iter().all(|(_, sample)| sample[1] == 0));
+            assert_eq!(
+                data[first].1,
+                vec![0, space.num_molecules(bleached).unwrap()]
+            );
+            recovered.resize(data.len(), 0);
+            for (total, (_, sample)) in recovered.iter_mut().zip(data) {
+                *total += sample[0];
+            }
+            // The spot recovers the density outside, which unlike the count
+            // it had does not depend on how many happened to be there.
+            let inside = space.coordinates_in(spot.as_ref()).count();
+            let outside = space.num_molecules(unbleached).unwrap() - data[first].1[0];
+            plateau += outside as f64 * inside as f64 / (space.num_voxels() - inside) as f64;
+        }
+
+        // The recovery grows about linearly in log t around the half-time.
+        let first = (bleach / interval).round() as usize;
+        let samples: Vec<(f64, f64)> = (first + 1..recovered.len())
+            .map(|i| {
+                let fraction = recovered[i] as f64 / plateau;
+                ((i as f64 * interval - bleach).ln(), fraction)
+            })
+            .filter(|&(_, fraction)| (0.35..=0.65).contains(&fraction))
+            .collect();
+        let n = samples.len() as f64;
+        let (mean_x, mean_y) = samples
+            .iter()
+            .fold((0.0, 0.0), |(x, y), &(xi, yi)| (x + xi / n, y + yi / n));
+        let slope = samples
+            .iter()
+            .map(|&(x, y)| (x - mean_x) * (y - mean_y))
+            .sum::<f64>()
+            / samples
+                .iter()
+                .map(|&(x, _)| (x - mean_x).powi(2))
+                .sum::<f64>();
+        let half_time = (mean_x + (0.5 - mean_y) / slope).exp();
+        let expected = 0.1002 * a * a / d;
+        assert!(
+            (half_time / expected - 1.0).abs() < 0.2,
+            "half-time {} expected {}",
+            half_time,
+            expected
+        );
+    }
+
+    #[test]
+    fn invalid_reactions() {
+        let mut sim = decay_simulator(0);
+        let unknown = ReactionRule::new(vec![Species::new("X")], vec![], 1.0);
+        assert!(matches!(
+            sim.add_reaction(unknown),
+            Err(Error::SpeciesNotFound(_))
+        ));
+        let second_order =
+            ReactionRule::new(vec![Species::new("A"), Species::new("B")], vec![], 1.0);
+        assert!(matches!(
+            sim.add_reaction(second_order),
+            Err(Error::InvalidReaction)
+        ));
+    }
+
+    #[test]
+    fn trajectory_stops_at_death() {
+        let mut sim = decay_simulator(4);
+        let a = sim.space().find_species("A").unwrap();
+        let pids = sim.space().particles_of(a).unwrap();
+        let observer = sim
+            .add_trajectory_observer(TrajectoryTarget::Particles(pids[..5].to_vec()), 0.1, false)
+            .unwrap();
+        let everyone = sim
+            .add_trajectory_observer(TrajectoryTarget::Species(Species::new("A")), 0.1, false)
+            .unwrap();
+        let rule = ReactionRule::new(vec![Species::new("A")], vec![], 5.0);
+        sim.add_reaction(rule).unwrap();
+        sim.run(3.0).unwrap();
+
+        assert_eq!(sim.trajectory_observer(everyone).trajectories().len(), 1000);
+        for trajectory in sim.trajectory_observer(observer).trajectories() {
+            let death = trajectory.death.expect("every particle decays");
+            assert!(trajectory.times.iter().all(|&t| t < death));
+            assert_eq!(trajectory.times.len(), trajectory.positions.len());
+            assert!(!trajectory.times.is_empty());
+        }
+        let mut buffer = Vec::new();
+        sim.trajectory_observer(observer)
+            .write_csv(&mut buffer)
+            .unwrap();
+        let rows: usize = sim
+            .trajectory_observer(observer)
+            .trajectories()
+            .iter()
+            .map(|t| t.times.len())
+            .sum();
+        assert_eq!(String::from_utf8(buffer).unwrap().lines().count(), rows + 1);
+    }
+
+    #[test]
+    fn unwrapped_trajectory_is_continuous() {
+        let r = 1e-8;
+        let mut space = HCPLatticeSpace::new(r, HCPLatticeSize::new(4, 4, 4));
+        space.set_periodic(true).unwrap();
+        let a = space.register_species(Species::new("A"), None);
+        let d = 1e-12;
+        space
+            .set_molecule_info(
+                a,
+                MoleculeInfo {
+                    radius: r,
+                    diffusion_coefficient: d,
+                },
+            )
+            .unwrap();
+        let pid = space.place_particle(a, Coordinate(0)).unwrap();
+        let mut sim = Simulator::new(space, StdRng::seed_from_u64(5));
+        let dt = 2.0 * r * r / (3.0 * d);
+        let unwrapped = sim
+            .add_trajectory_observer(TrajectoryTarget::Particles(vec![pid]), dt, true)
+            .unwrap();
+        let wrapped = sim
+            .add_trajectory_observer(TrajectoryTarget::Particles(vec![pid]), dt, false)
+            .unwrap();
+        sim.run(2000.0 * dt).unwrap();
+
+        let [lx, ly, lz] = sim.space().periodic_lengths();
+        let positions = &sim.trajectory_observer(unwrapped).trajectories()[0].positions;
+        for pair in positions.windows(2) {
+            let step: f64 = (0..3).map(|i| (pair[1][i] - pair[0][i]).powi(2)).sum();
+            assert!(step.sqrt() <= 2.0 * r * (1.0 + 1e-9));
+        }
+        let last = positions.last().unwrap();
+        let raw = sim.trajectory_observer(wrapped).trajectories()[0]
+            .positions
+            .last()
+            .unwrap();
+        for (i, l) in [lx, ly, lz].iter().enumerate() {
+            let images = (last[i] - raw[i]) / l;
+            assert!((images - images.round()).abs() < 1e-6);
+        }
+        let escaped = positions
+            .iter()
+            .any(|p| p[0].abs() > lx || p[1].abs() > ly || p[2].abs() > lz);
+        assert!(escaped);
+    }
+
+    type ProbeLog = Rc<RefCell<Vec<(&'static str, f64, usize)>>>;
+
+    /// Logs the count of A at fixed times, checking that it is called at
+    /// exactly those times with a consistent space.
+    struct Probe {
+        name: &'static str,
+        times: Vec<f64>,
+        log: ProbeLog,
+    }
+
+    impl Observer for Probe {
+        fn next_time(&self) -> Option<f64> {
+            self.times.first().copied()
+        }
+
+        fn fire(&mut self, t: f64, space: &HCPLatticeSpace) {
+            assert_eq!(t, self.times.remove(0));
+            space.validate().unwrap();
+            let count = space
+                .num_molecules(space.find_species("A").unwrap())
+                .unwrap();
+            self.log.borrow_mut().push((self.name, t, count));
+        }
+    }
+
+    /// Sums the occupancy of species A per column from `start` on, every
+    /// `interval`.
+    struct Profile {
+        next: f64,
+        interval: f64,
+        sums: Rc<RefCell<(usize, Vec<f64>)>>,
+    }
+
+    impl Observer for Profile {
+        fn next_time(&self) -> Option<f64> {
+            Some(self.next)
+        }
+
+        fn fire(&mut self, _: f64, space: &HCPLatticeSpace) {
+            let a = space.find_species("A").unwrap();
+            let mut sums = self.sums.borrow_mut();
+            sums.0 += 1;
+            for c in space.coordinates_of(a).unwrap() {
+                let (_, col, _) = space.coordinate_to_global(c).unwrap();
+                sums.1[col] += 1.0;
+            }
+            self.next += self.interval;
+        }
+    }
+
+    #[test]
+    fn source_and_sink_make_a_linear_profile() {
+        let (rows, cols, layers) = (8, 16, 8);
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(rows, cols, layers));
+        let a = space.register_species(Species::new("A"), None);
+        space
+            .set_molecule_info(
+                a,
+                MoleculeInfo {
+                    radius: 1.0,
+                    diffusion_coefficient: 1.0,
+                },
+            )
+            .unwrap();
+        let dt = space.diffusion_interval(a).unwrap();
+        let east: Vec<Coordinate> = space.boundary_coordinates(Face::East).collect();
+        space.add_sink(east, None).unwrap();
+        let mut sim = Simulator::new(space, StdRng::seed_from_u64(11));
+        let face = rows * layers;
+        let source = sim
+            .add_source_boundary(Face::West, Species::new("A"), face / 2, dt)
+            .unwrap();
+        let sums = Rc::new(RefCell::new((0, vec![0.0; cols])));
+        sim.add_observer(Box::new(Profile {
+            next: 600.0 * dt,
+            interval: dt,
+            sums: Rc::clone(&sums),
+        }));
+        sim.run(2100.0 * dt).unwrap();
+        sim.space().validate().unwrap();
+        assert!(sim.source(source).deficits().is_empty());
+
+        let (samples, sums) = &*sums.borrow();
+        let density: Vec<f64> = sums
+            .iter()
+            .map(|sum| sum / (*samples * face) as f64)
+            .collect();
+        // Least squares over the columns off the faces.
+        let points: Vec<(f64, f64)> = (1..cols - 1)
+            .map(|col| (col as f64, density[col]))
+            .collect();
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+        let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+        let sxy: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+        let sxx: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+        let slope = sxy / sxx;
+        let intercept = mean_y - slope * mean_x;
+        let residual = points
+            .iter()
+            .map(|p| (p.1 - intercept - slope * p.0).abs())
+            .fold(0.0, f64::max);
+        assert!(slope < 0.0, "{:?}", density);
+        assert!(residual < 0.02, "{:?}", density);
+        // The line runs from about the source's occupancy to about zero.
+        assert!((intercept - 0.5).abs() < 0.1, "{:?}", density);
+        assert!(
+            (intercept + slope * (cols - 1) as f64).abs() < 0.1,
+            "{:?}",
+            density
+        );
+
+        let mut periodic = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        periodic.set_periodic(true).unwrap();
+        periodic.register_species(Species::new("A"), None);
+        let mut sim = Simulator::new(periodic, StdRng::seed_from_u64(0));
+        assert!(matches!(
+            sim.add_source_boundary(Face::West, Species::new("A"), 1, 1.0),
+            Err(Error::PeriodicBoundary)
+        ));
+    }
+
+    #[test]
+    fn crowded_source_records_deficits() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let wall = space.register_obstacle(Species::new("W"));
+        space.register_species(Species::new("A"), None);
+        for c in space
+            .boundary_coordinates(Face::Down)
+            .step_by(2)
+            .collect::<Vec<_>>()
+        {
+            space.place_particle(wall, c).unwrap();
+        }
+        let mut sim = Simulator::new(space, StdRng::seed_from_u64(0));
+        let source = sim
+            .add_source_boundary(Face::Down, Species::new("A"), 12, 1.0)
+            .unwrap();
+        sim.run(2.0).unwrap();
+        let a = sim.space().find_species("A").unwrap();
+        assert_eq!(sim.space().num_molecules(a).unwrap(), 8);
+        assert_eq!(
+            sim.source(source).deficits(),
+            &[(0.0, 4), (1.0, 4), (2.0, 4)]
+        );
+
+        let mut sim = Simulator::new(sim.space().clone(), StdRng::seed_from_u64(0));
+        let excess = sim
+            .add_source_boundary(Face::Down, Species::new("A"), 3, 1.0)
+            .unwrap();
+        sim.run(0.5).unwrap();
+        assert_eq!(sim.source(excess).target(), 3);
+        assert_eq!(sim.space().num_molecules(a).unwrap(), 3);
+
+        for interval in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                sim.add_source_boundary(Face::Down, Species::new("A"), 3, interval),
+                Err(Error::InvalidDuration(_))
+            ));
+        }
+        assert_eq!(sim.sources().len(), 1);
+    }
+
+    #[test]
+    fn observers_need_a_positive_interval() {
+        let mut sim = decay_simulator(6);
+        let spot: Arc<dyn Region> = Arc::new(Sphere::new([0.0; 3], 1e-8));
+        let species = || vec![Species::new("A")];
+        let target = || TrajectoryTarget::Species(Species::new("A"));
+        for interval in [0.0, -0.1, f64::NAN, f64::INFINITY] {
+            let invalid = |result: Result<()>| matches!(result, Err(Error::InvalidDuration(_)));
+            assert!(invalid(
+                sim.add_number_observer(species(), interval).map(|_| ())
+            ));
+            assert!(invalid(
+                sim.add_trajectory_observer(target(), interval, false)
+                    .map(|_| ())
+            ));
+            assert!(invalid(
+                sim.add_region_observer(species(), Arc::clone(&spot), interval)
+                    .map(|_| ())
+            ));
+            assert!(invalid(sim.add_sink_observer(interval).map(|_| ())));
+        }
+        assert!(sim.number_observers().is_empty());
+        sim.run(1.0).unwrap();
+        assert!((sim.t() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn custom_observers() {
+        let mut sim = decay_simulator(6);
+        let rule = ReactionRule::new(vec![Species::new("A")], vec![], 1.0);
+        sim.add_reaction(rule).unwrap();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let times = vec![0.05, 0.25, 0.3];
+        for name in ["first", "second"] {
+            sim.add_observer(Box::new(Probe {
+                name,
+                times: times.clone(),
+                log: Rc::clone(&log),
+            }));
+        }
+        let counts = sim
+            .add_number_observer(vec![Species::new("A")], 0.05)
+            .unwrap();
+        sim.run(1.0).unwrap();
+
+        let log = log.borrow();
+        assert_eq!(log.len(), 6);
+        for (pair, &t) in log.chunks(2).zip(&times) {
+            assert_eq!((pair[0].0, pair[0].1), ("first", t));
+            assert_eq!((pair[1].0, pair[1].1), ("second", t));
+            assert_eq!(pair[0].2, pair[1].2);
+        }
+        let data = sim.number_observer(counts).data();
+        assert_eq!(data[1].1[0], log[0].2);
+        assert_eq!(data[5].1[0], log[2].2);
+    }
+
+    #[test]
+    fn rng_checkpoint() {
+        let new_space = || {
+            let mut space = HCPLatticeSpace::new(1e-8, HCPLatticeSize::new(4, 4, 4));
+            let a = space.register_species(Species::new("A"), None);
+            space.place_particle(a, Coordinate(0)).unwrap();
+            space
+        };
+        let mut sim = Simulator::with_seed(new_space(), 9);
+        let checkpoint = sim.rng().clone();
+        let rule = ReactionRule::new(vec![Species::new("A")], vec![Species::new("B")], 1.0);
+        sim.space_mut().register_species(Species::new("B"), None);
+        sim.add_reaction(rule).unwrap();
+        sim.run(1.0).unwrap();
+        assert!(*sim.rng() != checkpoint);
+        sim.set_rng(checkpoint.clone());
+        assert!(*sim.rng() == checkpoint);
+        assert!(*Simulator::with_seed(new_space(), 9).rng() == checkpoint);
+    }
+
+    #[test]
+    fn stop_from_progress() {
+        let mut sim = decay_simulator(7);
+        let mut calls = Vec::new();
+        let flow = sim
+            .run_with_progress(1.0, NonZeroU64::new(10).unwrap(), |step, t| {
+                calls.push((step, t));
+                if t < 0.5 {
+                    ControlFlow::Continue(())
+                } else {
+                    ControlFlow::Break(())
+                }
+            })
+            .unwrap();
+        assert_eq!(flow, ControlFlow::Break(()));
+        assert!(calls
+            .iter()
+            .enumerate()
+            .all(|(i, &(step, _))| step == 10 * (i as u64 + 1)));
+        let &(step, t) = calls.last().unwrap();
+        assert_eq!((sim.num_steps(), sim.t()), (step, t));
+        assert!((0.5..1.0).contains(&t));
+
+        let flow = sim
+            .run_with_progress(1.0 - t, NonZeroU64::MIN, |_, _| ControlFlow::Continue(()))
+            .unwrap();
+        assert_eq!(flow, ControlFlow::Continue(()));
+        let mut plain = decay_simulator(7);
+        plain.run(1.0).unwrap();
+        assert_eq!(sim.num_steps(), plain.num_steps());
+        assert_eq!(sim.space().voxels, plain.space().voxels);
+    }
+
+    #[test]
+    fn invalid_durations_leave_time_alone() {
+        let mut sim = decay_simulator(8);
+        sim.run(1.0).unwrap();
+        let (t, num_steps) = (sim.t(), sim.num_steps());
+        for duration in [-5.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(matches!(sim.run(duration), Err(Error::InvalidDuration(_))));
+        }
+        assert_eq!((sim.t(), sim.num_steps()), (t, num_steps));
+        sim.run(0.0).unwrap();
+        assert_eq!(sim.t(), t);
+        sim.run(1.0).unwrap();
+        assert!((sim.t() - 2.0).abs() < 1e-12);
+    }
+
+    fn listened_simulator(seed: u64) -> (Simulator<StdRng>, ReactionID) {
+        let mut space = HCPLatticeSpace::new(1e-8, HCPLatticeSize::new(8, 8, 8));
+        space.set_periodic(true).unwrap();
+        let a = space.register_species(Species::new("A"), None);
+        space.register_species(Species::new("B"), None);
+        space
+            .set_molecule_info(
+                a,
+                MoleculeInfo {
+                    radius: 1e-8,
+                    diffusion_coefficient: 1e-14,
+                },
+            )
+            .unwrap();
+        for i in (0..512).step_by(4) {
+            space.place_particle(a, Coordinate(i)).unwrap();
+        }
+        space.add_sink((0..8).map(Coordinate), None).unwrap();
+        let mut sim = Simulator::new(space, StdRng::seed_from_u64(seed));
+        let rule = ReactionRule::new(vec![Species::new("A")], vec![Species::new("B")], 1.0);
+        let reaction = sim.add_reaction(rule).unwrap();
+        sim.schedule_event(
+            0.25,
+            ModelEvent::RemoveMolecules {
+                species: Species::new("B"),
+                region: None,
+            },
+        )
+        .unwrap();
+        (sim, reaction)
+    }
+
+    #[test]
+    fn event_listener() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let (mut sim, reaction) = listened_simulator(5);
+        let sink = events.clone();
+        sim.set_event_listener(Box::new(move |event| sink.borrow_mut().push(event)));
+        sim.run(0.5).unwrap();
+        let events = events.borrow();
+
+        assert!(events.windows(2).all(|w| w[0].t() <= w[1].t()));
+        let fired = events
+            .iter()
+            .filter(|e| matches!(e, SimEvent::ReactionFired { reaction: r, .. } if *r == reaction))
+            .count();
+        assert_eq!(fired as u64, sim.num_firings(reaction));
+        let absorbed: u64 = events
+            .iter()
+            .map(|e| match e {
+                SimEvent::Absorbed { count, .. } => *count,
+                _ => 0,
+            })
+            .sum();
+        assert!(absorbed > 0);
+        assert_eq!(absorbed, sim.space().sink_counts()[0]);
+        let scheduled: Vec<f64> = events
+            .iter()
+            .filter(|e| matches!(e, SimEvent::ModelEventApplied { .. }))
+            .map(SimEvent::t)
+            .collect();
+        assert_eq!(scheduled, vec![0.25]);
+        match events
+            .iter()
+            .find(|e| matches!(e, SimEvent::WalkCompleted { .. }))
+        {
+            Some(SimEvent::WalkCompleted { attempted, .. }) => assert_eq!(*attempted, 128),
+            other => panic!("{:?}", other),
+        }
+        assert!(events.iter().all(|e| match e {
+            SimEvent::WalkCompleted {
+                attempted,
+                rejected,
+                ..
+            } => rejected <= attempted,
+            _ => true,
+        }));
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, SimEvent::BoundaryCrossed { .. })));
+
+        // The same seed gives the same events, and the listener changes
+        // nothing of the run.
+        let again = Rc::new(RefCell::new(Vec::new()));
+        let (mut replay, _) = listened_simulator(5);
+        let sink = again.clone();
+        replay.set_event_listener(Box::new(move |event| sink.borrow_mut().push(event)));
+        replay.run(0.5).unwrap();
+        assert_eq!(format!("{:?}", again.borrow()), format!("{:?}", *events));
+        assert!(replay.remove_event_listener().is_some());
+        let (mut plain, _) = listened_simulator(5);
+        plain.run(0.5).unwrap();
+        assert!(plain.space().diff(sim.space()).is_empty());
+    }
+
+    #[test]
+    fn write_csv() {
+        let mut sim = decay_simulator(0);
+        let observer = sim
+            .add_number_observer(vec![Species::new("A"), Species::new("B")], 1.0)
+            .unwrap();
+        sim.run(1.0).unwrap();
+        let mut buffer = Vec::new();
+        sim.number_observer(observer)
+            .write_csv(&mut buffer)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "t,A,B\n0,1000,0\n1,1000,0\n"
+        );
+    }
+}
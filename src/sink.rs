@@ -0,0 +1,213 @@
+//! Absorbing sinks, e.g. a proteasome degrading what it catches or a pore
+//! exporting what reaches it.
+//!
+//! A sink is a set of voxels. A molecule attempting to hop into one of them
+//! in `walk`, `walk_biased` or `walk_parallel` is removed instead, as if it
+//! had hopped out of the lattice, and counted by the sink; its voxel is
+//! handed back to its location. This happens whatever is on the sink voxel,
+//! so that a sink can sit on a structure: a pore in a membrane absorbs the
+//! bulk molecules hopping into it as well as the membrane molecules. A sink
+//! absorbing a list of species lets the others hop onto its voxels as if it
+//! was not there. `move_particle` and reactions ignore sinks.
+
+use crate::{Coordinate, Error, HCPLatticeSpace, Result, Species, SpeciesID};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SinkID(usize);
+
+#[derive(Clone, Debug)]
+pub(crate) struct Sink {
+    /// The species absorbed, `None` meaning all of them.
+    absorbs: Option<Vec<Species>>,
+    absorbed: u64,
+}
+
+impl HCPLatticeSpace {
+    /// Makes `coordinates` a sink absorbing `absorbs`, or every species if
+    /// `None`. A voxel already in a sink stays in the first one.
+    pub fn add_sink<I>(&mut self, coordinates: I, absorbs: Option<Vec<Species>>) -> Result<SinkID>
+    where
+        I: IntoIterator<Item = Coordinate>,
+    {
+        let coordinates: Vec<Coordinate> = coordinates.into_iter().collect();
+        if let Some(&outside) = coordinates.iter().find(|&&c| !self.contains(c)) {
+            return Err(Error::OutOfRange(outside));
+        }
+        let id = SinkID(self.sinks.len());
+        self.sinks.push(Sink {
+            absorbs,
+            absorbed: 0,
+        });
+        for c in coordinates {
+            self.sink_voxels.entry(c.0).or_insert(id.0);
+        }
+        Ok(id)
+    }
+
+    /// Returns the number of molecules `sink` has absorbed so far.
+    pub fn absorbed(&self, sink: SinkID) -> u64 {
+        self.sinks[sink.0].absorbed
+    }
+
+    /// Returns the numbers of molecules absorbed by every sink, in the order
+    /// they were added.
+    pub fn sink_counts(&self) -> Vec<u64> {
+        self.sinks.iter().map(|sink| sink.absorbed).collect()
+    }
+
+    pub(crate) fn reset_sink_counts(&mut self) {
+        for sink in &mut self.sinks {
+            sink.absorbed = 0;
+        }
+    }
+
+    /// Removes the molecule of `species` at `from` if `to` is a voxel of a
+    /// sink absorbing it, and returns whether it did.
+    pub(crate) fn try_absorb(
+        &mut self,
+        species: SpeciesID,
+        from: Coordinate,
+        to: Coordinate,
+    ) -> Result<bool> {
+        let sink = match self.sink_voxels.get(&to.0) {
+            Some(&sink) => sink,
+            None => return Ok(false),
+        };
+        let name = &self.species_cache[species.0].species;
+        if let Some(absorbs) = &self.sinks[sink].absorbs {
+            if !absorbs.contains(name) {
+                return Ok(false);
+            }
+        }
+        self.remove_at(from)?;
+        self.sinks[sink].absorbed += 1;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::region::Sphere;
+    use crate::{HCPLatticeSize, MoleculeInfo};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::f64::consts::PI;
+
+    #[test]
+    fn sinks_on_a_membrane() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        let a_s = space.register_species(Species::new("As"), Some(membrane));
+        let layer: Vec<Coordinate> = space.coordinates().take(36).collect();
+        for &c in &layer {
+            space.place_particle(membrane, c).unwrap();
+        }
+        for c in space.coordinates().skip(36).step_by(4).collect::<Vec<_>>() {
+            let species = if c.0 % 8 == 0 { a } else { b };
+            space.place_particle(species, c).unwrap();
+        }
+        for &c in layer.iter().skip(1).step_by(5) {
+            space.place_particle(a_s, c).unwrap();
+        }
+        let (num_a, num_b, num_a_s) = (
+            space.num_molecules(a).unwrap(),
+            space.num_molecules(b).unwrap(),
+            space.num_molecules(a_s).unwrap(),
+        );
+        let pore = space
+            .add_sink(
+                layer.iter().copied().step_by(5),
+                Some(vec![Species::new("A"), Species::new("As")]),
+            )
+            .unwrap();
+        assert!(matches!(
+            space.add_sink(vec![Coordinate(216)], None),
+            Err(Error::OutOfRange(_))
+        ));
+
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..3000 {
+            for &species in &[a, b, a_s] {
+                space.walk(species, &mut rng).unwrap();
+            }
+        }
+        space.validate().unwrap();
+        assert_eq!(space.num_molecules(a).unwrap(), 0);
+        assert_eq!(space.num_molecules(a_s).unwrap(), 0);
+        assert_eq!(space.num_molecules(b).unwrap(), num_b);
+        assert_eq!(space.absorbed(pore), (num_a + num_a_s) as u64);
+        assert_eq!(space.num_molecules(membrane).unwrap(), 36);
+        assert_eq!(space.sink_counts(), vec![(num_a + num_a_s) as u64]);
+    }
+
+    /// Keeps a shell of voxels just beyond `outer` from a spherical sink of
+    /// radius `inner` at occupancy `phi`, walled off from the outside, and
+    /// compares the absorption rate with the steady flux
+    /// `4πDc R_in R_out/(R_out - R_in)` between the spheres.
+    #[test]
+    fn diffusion_limited_flux() {
+        let r = 0.5;
+        let mut space = HCPLatticeSpace::new(r, HCPLatticeSize::new(24, 21, 26));
+        let wall = space.register_obstacle(Species::new("W"));
+        let a = space.register_species(Species::new("A"), None);
+        let d = 1.0;
+        space
+            .set_molecule_info(
+                a,
+                MoleculeInfo {
+                    radius: r,
+                    diffusion_coefficient: d,
+                },
+            )
+            .unwrap();
+        let dt = space.diffusion_interval(a).unwrap();
+        let center = [10.0, 10.0, 10.0];
+        let (inner, outer, phi) = (2.0, 8.0, 0.1);
+        let sink: Vec<Coordinate> = space.coordinates_in(&Sphere::new(center, inner)).collect();
+        let mut kept = Vec::new();
+        for c in space.coordinates().collect::<Vec<_>>() {
+            let p = space.coordinate_to_position(c).unwrap();
+            let distance = (0..3)
+                .map(|i| (p[i] - center[i]).powi(2))
+                .sum::<f64>()
+                .sqrt();
+            if distance > outer + 1.5 {
+                space.place_particle(wall, c).unwrap();
+            } else if distance > outer {
+                kept.push(c);
+            }
+        }
+        let sink = space.add_sink(sink, None).unwrap();
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let (burn_in, sweeps) = (200, 800);
+        for sweep in 0..burn_in + sweeps {
+            if sweep == burn_in {
+                space.sinks[sink.0].absorbed = 0;
+            }
+            for &c in &kept {
+                let occupied = space.species_at(c).unwrap().is_some();
+                if rng.gen::<f64>() < phi {
+                    if !occupied {
+                        space.place_particle(a, c).unwrap();
+                    }
+                } else if occupied {
+                    space.remove_at(c).unwrap();
+                }
+            }
+            space.walk(a, &mut rng).unwrap();
+        }
+        let measured = space.absorbed(sink) as f64 / (sweeps as f64 * dt);
+        let c = phi / (4.0 * 2f64.sqrt() * r.powi(3));
+        let expected = 4.0 * PI * d * c * inner * outer / (outer - inner);
+        assert!(
+            (measured / expected - 1.0).abs() < 0.25,
+            "measured {} expected {}",
+            measured,
+            expected
+        );
+    }
+}
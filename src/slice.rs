@@ -0,0 +1,157 @@
+//! Planes of the lattice as 2D arrays, for plotting.
+//!
+//! `slice_layer` cuts the lattice at a layer, `slice_row` at a row and
+//! `slice_col` at a column. A `Slice` is indexed by `(i, j)`, `i` being the
+//! slower of the two remaining lattice indices in the flattening: the row
+//! for a layer, and the layer for a row or a column. The cells of a slice
+//! are not on a square grid, since HCP rows and layers are staggered, so
+//! `Slice::position` gives the real center of each one in the plane of the
+//! slice; plotting cells at these positions keeps the lattice faithful.
+
+use crate::{HCPLatticeSpace, Result, Species};
+use std::io::{self, Write};
+
+/// The species on each voxel of a plane of the lattice.
+#[derive(Clone, Debug)]
+pub struct Slice<'a> {
+    shape: (usize, usize),
+    cells: Vec<Option<&'a Species>>,
+    positions: Vec<[f64; 2]>,
+}
+
+impl<'a> Slice<'a> {
+    /// Returns the number of cells along `i` and along `j`.
+    pub fn shape(&self) -> (usize, usize) {
+        self.shape
+    }
+
+    /// Returns the species at `(i, j)`, `None` if the voxel is vacant or
+    /// outside the slice.
+    pub fn get(&self, i: usize, j: usize) -> Option<&'a Species> {
+        self.index(i, j).and_then(|index| self.cells[index])
+    }
+
+    /// Returns the center of the voxel at `(i, j)` in the plane of the
+    /// slice: `(x, y)` for a layer, `(x, z)` for a row and `(y, z)` for a
+    /// column.
+    pub fn position(&self, i: usize, j: usize) -> Option<[f64; 2]> {
+        self.index(i, j).map(|index| self.positions[index])
+    }
+
+    /// Writes the slice as CSV, one line per `i` holding the species name
+    /// of each cell, empty for vacant voxels.
+    pub fn to_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for i in 0..self.shape.0 {
+            let line: Vec<&str> = (0..self.shape.1)
+                .map(|j| self.get(i, j).map_or("", |species| species.name()))
+                .collect();
+            writeln!(writer, "{}", line.join(","))?;
+        }
+        Ok(())
+    }
+
+    fn index(&self, i: usize, j: usize) -> Option<usize> {
+        if i < self.shape.0 && j < self.shape.1 {
+            Some(i * self.shape.1 + j)
+        } else {
+            None
+        }
+    }
+}
+
+impl HCPLatticeSpace {
+    /// Returns layer `layer`, indexed by `(row, col)`.
+    pub fn slice_layer(&self, layer: usize) -> Result<Slice<'_>> {
+        self.global_to_coordinate(0, 0, layer)?;
+        Ok(self.slice((self.size.row, self.size.col), |row, col| {
+            ((row, col, layer), [0, 1])
+        }))
+    }
+
+    /// Returns row `row`, indexed by `(layer, col)`.
+    pub fn slice_row(&self, row: usize) -> Result<Slice<'_>> {
+        self.global_to_coordinate(row, 0, 0)?;
+        Ok(self.slice((self.size.layer, self.size.col), |layer, col| {
+            ((row, col, layer), [0, 2])
+        }))
+    }
+
+    /// Returns column `col`, indexed by `(layer, row)`.
+    pub fn slice_col(&self, col: usize) -> Result<Slice<'_>> {
+        self.global_to_coordinate(0, col, 0)?;
+        Ok(self.slice((self.size.layer, self.size.row), |layer, row| {
+            ((row, col, layer), [1, 2])
+        }))
+    }
+
+    /// Builds a slice of `shape` from the `(row, col, layer)` of each cell
+    /// and the two axes of real space spanning its plane.
+    fn slice<F>(&self, shape: (usize, usize), cell: F) -> Slice<'_>
+    where
+        F: Fn(usize, usize) -> ((usize, usize, usize), [usize; 2]),
+    {
+        let mut cells = Vec::with_capacity(shape.0 * shape.1);
+        let mut positions = Vec::with_capacity(shape.0 * shape.1);
+        for i in 0..shape.0 {
+            for j in 0..shape.1 {
+                let ((row, col, layer), axes) = cell(i, j);
+                let coordinate = self.flatten(row, col, layer);
+                cells.push(
+                    self.voxel(coordinate)
+                        .map(|id| &self.species_cache[id.0].species),
+                );
+                let center = self.center(row as isize, col as isize, layer as isize);
+                positions.push([center[axes[0]], center[axes[1]]]);
+            }
+        }
+        Slice {
+            shape,
+            cells,
+            positions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, HCPLatticeSize};
+
+    #[test]
+    fn slices_of_a_populated_lattice() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(3, 4, 2));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        for &((row, col, layer), species) in &[((0, 0, 0), a), ((2, 1, 0), b), ((1, 3, 1), a)] {
+            let c = space.global_to_coordinate(row, col, layer).unwrap();
+            space.place_particle(species, c).unwrap();
+        }
+
+        let layer = space.slice_layer(0).unwrap();
+        assert_eq!(layer.shape(), (3, 4));
+        assert_eq!(layer.get(0, 0), Some(&Species::new("A")));
+        assert_eq!(layer.get(2, 1), Some(&Species::new("B")));
+        assert_eq!(layer.get(1, 3), None);
+        assert_eq!(layer.get(3, 0), None);
+        let [x, y] = layer.position(1, 2).unwrap();
+        assert!((x - 5.0).abs() < 1e-12 && (y - 3f64.sqrt()).abs() < 1e-12);
+
+        let row = space.slice_row(1).unwrap();
+        assert_eq!(row.shape(), (2, 4));
+        assert_eq!(row.get(1, 3), Some(&Species::new("A")));
+        let col = space.slice_col(1).unwrap();
+        assert_eq!(col.shape(), (2, 3));
+        assert_eq!(col.get(0, 2), Some(&Species::new("B")));
+        let [y, z] = col.position(1, 0).unwrap();
+        assert!((y - 1.0 / 3f64.sqrt()).abs() < 1e-12);
+        assert!((z - (8f64 / 3.0).sqrt()).abs() < 1e-12);
+
+        let mut csv = Vec::new();
+        layer.to_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "A,,,\n,,,\n,B,,\n");
+
+        assert!(matches!(space.slice_layer(2), Err(Error::OutOfRange(_))));
+        assert!(space.slice_row(3).is_err());
+        assert!(space.slice_col(4).is_err());
+    }
+}
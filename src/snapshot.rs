@@ -0,0 +1,226 @@
+//! Read-only copies of a space for other threads.
+//!
+//! `HCPLatticeSpace` and the data recorded by the observers hold no interior
+//! mutability and are `Send + Sync`, so that a `&HCPLatticeSpace` can be
+//! read from a scoped thread while the simulation waits between steps.
+//! `freeze` copies the space into a `LatticeSnapshot` behind an `Arc`, which
+//! analysis threads can keep while the simulation goes on. Keep anything
+//! added to the space free of `Cell`s and `Rc`s; the tests below check that
+//! these types stay `Send + Sync`.
+//!
+//! `snapshot_view` is the cheap alternative when the voxels are all the
+//! analysis needs: a `FrozenLattice` shares the voxel store of the space,
+//! which copies it on its next write instead (see the `voxels` module), and
+//! only copies the species names and counts. It knows nothing of
+//! `ParticleID`s.
+
+use crate::voxels::{decode, VoxelStore, Voxels};
+use crate::{
+    center, Coordinate, Error, HCPLatticeSize, HCPLatticeSpace, ParticleID, Result, Species,
+    SpeciesID,
+};
+use std::sync::Arc;
+
+/// An immutable copy of a space at some point of a simulation.
+#[derive(Clone)]
+pub struct LatticeSnapshot {
+    space: HCPLatticeSpace,
+}
+
+impl LatticeSnapshot {
+    /// Returns the frozen space, whose `&self` methods are all available.
+    pub fn space(&self) -> &HCPLatticeSpace {
+        &self.space
+    }
+
+    /// Iterates over the tracked molecules; see `HCPLatticeSpace::particles`.
+    pub fn particles(&self) -> impl Iterator<Item = (ParticleID, SpeciesID, Coordinate)> + '_ {
+        self.space.particles()
+    }
+}
+
+/// The voxels of a space at some point of a simulation, read-only.
+#[derive(Clone, Debug)]
+pub struct FrozenLattice {
+    voxel_radius: f64,
+    size: HCPLatticeSize,
+    voxels: Voxels,
+    species: Vec<Species>,
+    counts: Vec<usize>,
+}
+
+impl FrozenLattice {
+    pub fn num_voxels(&self) -> usize {
+        self.voxels.len()
+    }
+
+    /// Returns the species occupying `coordinate`, `None` if it is vacant.
+    pub fn species_at(&self, coordinate: Coordinate) -> Result<Option<SpeciesID>> {
+        if coordinate.0 < self.voxels.len() {
+            Ok(decode(self.voxels.get(coordinate.0)))
+        } else {
+            Err(Error::OutOfRange(coordinate))
+        }
+    }
+
+    /// Returns the center of the voxel in real space.
+    pub fn position(&self, coordinate: Coordinate) -> Result<[f64; 3]> {
+        if coordinate.0 >= self.voxels.len() {
+            return Err(Error::OutOfRange(coordinate));
+        }
+        let (row, col, layer) = self.size.global(coordinate.0);
+        Ok(center(
+            self.voxel_radius,
+            row as isize,
+            col as isize,
+            layer as isize,
+        ))
+    }
+
+    /// Returns the species registered as `species`; panics if it was not.
+    pub fn species(&self, species: SpeciesID) -> &Species {
+        &self.species[species.0]
+    }
+
+    pub fn num_molecules(&self, species: SpeciesID) -> usize {
+        self.counts[species.0]
+    }
+
+    /// Iterates over the occupied voxels in coordinate order.
+    pub fn occupied(&self) -> impl Iterator<Item = (Coordinate, SpeciesID)> + '_ {
+        self.voxels
+            .occupied()
+            .filter_map(|(i, raw)| decode(raw).map(|id| (Coordinate(i), id)))
+    }
+}
+
+impl HCPLatticeSpace {
+    /// Copies the space into a snapshot to be shared across threads.
+    pub fn freeze(&self) -> Arc<LatticeSnapshot> {
+        Arc::new(LatticeSnapshot {
+            space: self.clone(),
+        })
+    }
+
+    /// Returns a view of the voxels sharing their storage with the space.
+    pub fn snapshot_view(&self) -> FrozenLattice {
+        let species = self
+            .species_cache
+            .iter()
+            .map(|cache| cache.species.clone())
+            .collect();
+        let counts = self
+            .species_cache
+            .iter()
+            .map(|cache| cache.num_molecules())
+            .collect();
+        FrozenLattice {
+            voxel_radius: self.voxel_radius,
+            size: self.size,
+            voxels: self.voxels.clone(),
+            species,
+            counts,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::{NumberObserver, Trajectory, TrajectoryObserver};
+    use crate::{Species, TimeCourse};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn send_sync() {
+        assert_send_sync::<HCPLatticeSpace>();
+        assert_send_sync::<LatticeSnapshot>();
+        assert_send_sync::<Arc<LatticeSnapshot>>();
+        assert_send_sync::<FrozenLattice>();
+        assert_send_sync::<NumberObserver>();
+        assert_send_sync::<TrajectoryObserver>();
+        assert_send_sync::<Trajectory>();
+        assert_send_sync::<TimeCourse>();
+    }
+
+    #[test]
+    fn threads_read_a_snapshot() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(8, 8, 8));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        for c in space.coordinates().step_by(3).collect::<Vec<_>>() {
+            space
+                .place_particle(if c.0 % 2 == 0 { a } else { b }, c)
+                .unwrap();
+        }
+        let snapshot = space.freeze();
+        space.remove_at(Coordinate(0)).unwrap();
+
+        let handles: Vec<_> = [a, b]
+            .iter()
+            .map(|&species| {
+                let snapshot = Arc::clone(&snapshot);
+                thread::spawn(move || {
+                    snapshot
+                        .particles()
+                        .filter(|&(_, id, c)| {
+                            id == species && snapshot.space().species_at(c).unwrap() == Some(id)
+                        })
+                        .count()
+                })
+            })
+            .collect();
+        let counts: Vec<usize> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(counts, vec![86, 85]);
+        assert_eq!(snapshot.space().num_molecules(a).unwrap(), 86);
+        assert_eq!(space.num_molecules(a).unwrap(), 85);
+    }
+
+    #[test]
+    fn view_survives_the_simulation() {
+        let size = HCPLatticeSize::new(6, 7, 8);
+        for mut space in [
+            HCPLatticeSpace::new(1.0, size),
+            HCPLatticeSpace::new_sparse(1.0, size),
+            HCPLatticeSpace::new_chunked(1.0, size),
+        ] {
+            let a = space.register_species(Species::new("A"), None);
+            for c in space.coordinates().step_by(5).collect::<Vec<_>>() {
+                space.place_particle(a, c).unwrap();
+            }
+            let before: Vec<(Coordinate, SpeciesID)> = space.occupied().collect();
+            let view = space.snapshot_view();
+            let reader = {
+                let view = view.clone();
+                thread::spawn(move || view.occupied().collect::<Vec<_>>())
+            };
+            let mut rng = StdRng::seed_from_u64(1);
+            for _ in 0..10 {
+                space.walk(a, &mut rng).unwrap();
+            }
+            space
+                .remove_at(space.coordinates_of(a).unwrap()[0])
+                .unwrap();
+
+            assert_eq!(reader.join().unwrap(), before);
+            assert_eq!(view.occupied().collect::<Vec<_>>(), before);
+            assert_ne!(space.occupied().collect::<Vec<_>>(), before);
+            assert_eq!(view.num_molecules(a), 68);
+            assert_eq!(space.num_molecules(a).unwrap(), 67);
+            assert_eq!(view.species(a), &Species::new("A"));
+            let c = Coordinate(100);
+            assert_eq!(
+                view.position(c).unwrap(),
+                space.coordinate_to_position(c).unwrap()
+            );
+            assert!(matches!(
+                view.species_at(Coordinate(336)),
+                Err(Error::OutOfRange(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,358 @@
+//! An HCP lattice whose voxels sit in an array sized at compile time, for
+//! micro-benchmarks and embedded-style use on tiny lattices.
+//!
+//! `StackLattice<N>` has the geometry of `HCPLatticeSpace`, with the same
+//! coordinates, positions and neighbor order, and keeps its `N` voxels
+//! inline as encoded `u32`s (see the `voxels` module) instead of behind a
+//! shared heap allocation, the whole space living wherever it is put. The
+//! bookkeeping is that of `CubicLatticeSpace`: a species is located on
+//! vacant voxels or on the molecules of another species, and every molecule
+//! has a `ParticleID`. It only implements `LatticeSpace`, so that code
+//! written against the trait runs on it unchanged; multi-voxel species,
+//! interactions, sinks and the other extensions are `HCPLatticeSpace` only.
+
+use crate::lattice::LatticeSpace;
+use crate::neighbors::{self, Direction};
+use crate::voxels::{decode, encode};
+use crate::{
+    Coordinate, Error, HCPLatticeSize, MoleculeInfo, ParticleID, PidAllocator, Result, Species,
+    SpeciesID,
+};
+use rand::Rng;
+
+#[derive(Clone, Debug)]
+struct StackSpecies {
+    species: Species,
+    location: Option<SpeciesID>,
+    info: MoleculeInfo,
+    molecules: Vec<(ParticleID, Coordinate)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct StackLattice<const N: usize> {
+    voxel_radius: f64,
+    size: HCPLatticeSize,
+    voxels: [u32; N],
+    species: Vec<StackSpecies>,
+    pids: PidAllocator,
+    periodic: bool,
+}
+
+impl<const N: usize> StackLattice<N> {
+    /// Creates a lattice of vacant voxels. Panics unless `size` holds
+    /// exactly `N` voxels.
+    pub fn new(voxel_radius: f64, size: HCPLatticeSize) -> Self {
+        assert_eq!(size.num_voxels(), Some(N), "the size has to hold N voxels");
+        Self {
+            voxel_radius,
+            size,
+            voxels: [0; N],
+            species: Vec::new(),
+            pids: PidAllocator::new(),
+            periodic: false,
+        }
+    }
+
+    pub fn voxel_radius(&self) -> f64 {
+        self.voxel_radius
+    }
+
+    /// Makes the lattice wrap around, which needs an even number of rows
+    /// and of layers as for `HCPLatticeSpace::set_periodic`.
+    pub fn set_periodic(&mut self, periodic: bool) -> Result<()> {
+        if periodic && (self.size.row % 2 == 1 || self.size.layer % 2 == 1) {
+            return Err(Error::InvalidPeriodicSize);
+        }
+        self.periodic = periodic;
+        Ok(())
+    }
+
+    pub fn coordinate_to_global(&self, coordinate: Coordinate) -> Result<(usize, usize, usize)> {
+        if coordinate.0 >= N {
+            return Err(Error::OutOfRange(coordinate));
+        }
+        Ok(self.size.global(coordinate.0))
+    }
+
+    fn neighbor(&self, coordinate: Coordinate, direction: Direction) -> Result<Option<Coordinate>> {
+        let global = self.coordinate_to_global(coordinate)?;
+        Ok(
+            neighbors::neighbor(&self.size, self.periodic, global, direction).map(
+                |(row, col, layer)| Coordinate(row + self.size.row * (col + self.size.col * layer)),
+            ),
+        )
+    }
+
+    fn voxel(&self, coordinate: Coordinate) -> Result<Option<SpeciesID>> {
+        self.voxels
+            .get(coordinate.0)
+            .map(|&raw| decode(raw))
+            .ok_or(Error::OutOfRange(coordinate))
+    }
+
+    fn next_pid(&mut self) -> ParticleID {
+        self.pids.next()
+    }
+
+    fn registered(&self, species: SpeciesID) -> Result<&StackSpecies> {
+        self.species
+            .get(species.0)
+            .ok_or(Error::UnknownSpecies(species))
+    }
+
+    fn entry(&self, species: SpeciesID, coordinate: Coordinate) -> Option<usize> {
+        self.species[species.0]
+            .molecules
+            .iter()
+            .position(|&(_, c)| c == coordinate)
+    }
+}
+
+impl<const N: usize> LatticeSpace for StackLattice<N> {
+    fn num_voxels(&self) -> usize {
+        N
+    }
+
+    fn neighbors(&self, coordinate: Coordinate) -> Result<Vec<Coordinate>> {
+        let mut neighbors = Vec::with_capacity(Direction::ALL.len());
+        for &direction in &Direction::ALL {
+            if let Some(neighbor) = self.neighbor(coordinate, direction)? {
+                neighbors.push(neighbor);
+            }
+        }
+        Ok(neighbors)
+    }
+
+    fn position(&self, coordinate: Coordinate) -> Result<[f64; 3]> {
+        let (row, col, layer) = self.coordinate_to_global(coordinate)?;
+        Ok(crate::center(
+            self.voxel_radius,
+            row as isize,
+            col as isize,
+            layer as isize,
+        ))
+    }
+
+    fn is_periodic(&self) -> bool {
+        self.periodic
+    }
+
+    fn periodic_lengths(&self) -> [f64; 3] {
+        let r = self.voxel_radius;
+        [
+            2.0 * r * self.size.col as f64,
+            3f64.sqrt() * r * self.size.row as f64,
+            (8f64 / 3.0).sqrt() * r * self.size.layer as f64,
+        ]
+    }
+
+    fn species_at(&self, coordinate: Coordinate) -> Result<Option<SpeciesID>> {
+        self.voxel(coordinate)
+    }
+
+    fn register_species(&mut self, species: Species, location: Option<SpeciesID>) -> SpeciesID {
+        self.species.push(StackSpecies {
+            species,
+            location,
+            info: MoleculeInfo {
+                radius: self.voxel_radius,
+                diffusion_coefficient: 0.0,
+            },
+            molecules: Vec::new(),
+        });
+        SpeciesID(self.species.len() - 1)
+    }
+
+    fn num_species(&self) -> usize {
+        self.species.len()
+    }
+
+    fn find_species(&self, name: &str) -> Option<SpeciesID> {
+        self.species
+            .iter()
+            .position(|species| species.species.name() == name)
+            .map(SpeciesID)
+    }
+
+    fn location_of(&self, species: SpeciesID) -> Result<Option<SpeciesID>> {
+        Ok(self.registered(species)?.location)
+    }
+
+    fn molecule_info(&self, species: SpeciesID) -> Result<MoleculeInfo> {
+        Ok(self.registered(species)?.info)
+    }
+
+    fn set_molecule_info(&mut self, species: SpeciesID, info: MoleculeInfo) -> Result<()> {
+        self.registered(species)?;
+        self.species[species.0].info = info;
+        Ok(())
+    }
+
+    fn diffusion_interval(&self, species: SpeciesID) -> Option<f64> {
+        let d = self.registered(species).ok()?.info.diffusion_coefficient;
+        if d > 0.0 {
+            let r = self.voxel_radius;
+            Some(2.0 * r * r / (3.0 * d))
+        } else {
+            None
+        }
+    }
+
+    fn num_molecules(&self, species: SpeciesID) -> Result<usize> {
+        Ok(self.registered(species)?.molecules.len())
+    }
+
+    fn coordinates_of(&self, species: SpeciesID) -> Result<Vec<Coordinate>> {
+        Ok(self
+            .registered(species)?
+            .molecules
+            .iter()
+            .map(|&(_, c)| c)
+            .collect())
+    }
+
+    fn particles_of(&self, species: SpeciesID) -> Result<Vec<ParticleID>> {
+        Ok(self
+            .registered(species)?
+            .molecules
+            .iter()
+            .map(|&(pid, _)| pid)
+            .collect())
+    }
+
+    fn find_particle(&self, pid: ParticleID) -> Option<(&Species, Coordinate)> {
+        self.species.iter().find_map(|species| {
+            species
+                .molecules
+                .iter()
+                .find(|&&(id, _)| id == pid)
+                .map(|&(_, c)| (&species.species, c))
+        })
+    }
+
+    fn particle_coordinate(&self, species: SpeciesID, pid: ParticleID) -> Option<Coordinate> {
+        self.registered(species)
+            .ok()?
+            .molecules
+            .iter()
+            .find(|&&(id, _)| id == pid)
+            .map(|&(_, c)| c)
+    }
+
+    fn place_particle(&mut self, species: SpeciesID, coordinate: Coordinate) -> Result<ParticleID> {
+        self.registered(species)?;
+        let current = self.voxel(coordinate)?;
+        if !self.can_occupy(species, coordinate) {
+            return Err(Error::InvalidLocation(coordinate, coordinate));
+        }
+        if let Some(location) = current {
+            let entry = self.entry(location, coordinate).expect("listed");
+            self.species[location.0].molecules.remove(entry);
+        }
+        let pid = self.next_pid();
+        self.species[species.0].molecules.push((pid, coordinate));
+        self.voxels[coordinate.0] = encode(Some(species));
+        Ok(pid)
+    }
+
+    fn move_particle(&mut self, from: Coordinate, to: Coordinate) -> Result<()> {
+        let species = self.voxel(from)?.ok_or(Error::ParticleNotFound(from))?;
+        let target = self.voxel(to)?;
+        if !self.can_occupy(species, to) {
+            return Err(Error::InvalidLocation(from, to));
+        }
+        let entry = self.entry(species, from).expect("listed");
+        self.species[species.0].molecules[entry].1 = to;
+        if let Some(location) = target {
+            let entry = self.entry(location, to).expect("listed");
+            self.species[location.0].molecules[entry].1 = from;
+        }
+        self.voxels.swap(from.0, to.0);
+        Ok(())
+    }
+
+    fn remove_at(&mut self, coordinate: Coordinate) -> Result<(SpeciesID, Option<ParticleID>)> {
+        let species = self
+            .voxel(coordinate)?
+            .ok_or(Error::ParticleNotFound(coordinate))?;
+        let entry = self.entry(species, coordinate).expect("listed");
+        let (pid, _) = self.species[species.0].molecules.remove(entry);
+        let location = self.species[species.0].location;
+        if let Some(location) = location {
+            let pid = self.next_pid();
+            self.species[location.0].molecules.push((pid, coordinate));
+        }
+        self.voxels[coordinate.0] = encode(location);
+        Ok((species, Some(pid)))
+    }
+
+    fn change_species_at(&mut self, coordinate: Coordinate, into: SpeciesID) -> Result<()> {
+        let species = self
+            .voxel(coordinate)?
+            .ok_or(Error::ParticleNotFound(coordinate))?;
+        if self.species[species.0].location != self.registered(into)?.location {
+            return Err(Error::InvalidLocation(coordinate, coordinate));
+        }
+        if species != into {
+            let entry = self.entry(species, coordinate).expect("listed");
+            let molecule = self.species[species.0].molecules.remove(entry);
+            self.species[into.0].molecules.push(molecule);
+            self.voxels[coordinate.0] = encode(Some(into));
+        }
+        Ok(())
+    }
+
+    fn walk<R: Rng>(&mut self, species: SpeciesID, rng: &mut R) -> Result<()> {
+        for from in self.coordinates_of(species)? {
+            let direction = Direction::ALL[rng.gen_range(0..Direction::ALL.len())];
+            if let Some(to) = self.neighbor(from, direction)? {
+                match self.move_particle(from, to) {
+                    Ok(()) | Err(Error::InvalidLocation(..)) => {}
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HCPLatticeSpace;
+
+    #[test]
+    fn same_geometry_as_the_heap_lattice() {
+        let size = HCPLatticeSize::new(4, 5, 6);
+        let mut stack = StackLattice::<120>::new(1.0, size);
+        let mut heap = HCPLatticeSpace::new(1.0, size);
+        for periodic in [false, true] {
+            stack.set_periodic(periodic).unwrap();
+            heap.set_periodic(periodic).unwrap();
+            for i in 0..120 {
+                let c = Coordinate(i);
+                assert_eq!(
+                    stack.position(c).unwrap(),
+                    heap.coordinate_to_position(c).unwrap()
+                );
+                assert_eq!(stack.neighbors(c).unwrap(), heap.neighbors(c).unwrap());
+            }
+            assert_eq!(
+                LatticeSpace::periodic_lengths(&stack),
+                heap.periodic_lengths()
+            );
+        }
+        assert!(stack.neighbors(Coordinate(120)).is_err());
+        let mut odd = StackLattice::<27>::new(1.0, HCPLatticeSize::new(3, 3, 3));
+        assert!(matches!(
+            odd.set_periodic(true),
+            Err(Error::InvalidPeriodicSize)
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "N voxels")]
+    fn size_has_to_match() {
+        StackLattice::<64>::new(1.0, HCPLatticeSize::new(4, 4, 3));
+    }
+}
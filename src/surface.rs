@@ -0,0 +1,212 @@
+//! Molecules changing location while they diffuse, e.g. adsorbing onto a
+//! membrane.
+//!
+//! The location of a species is fixed at registration, so a molecule that
+//! changes location changes species: the bulk form `A` on vacant voxels
+//! becomes a surface form `As` registered on the membrane species `M`, and
+//! back. `set_transition(a, a_s, p)` lets a hop of an `A` molecule into an
+//! adjacent voxel of `M` turn it into an `As` molecule on that voxel with
+//! probability `p`, the voxel it leaves being handed back to its location;
+//! otherwise the hop is rejected. Adsorption and desorption are two
+//! transitions, each with its own probability per attempted hop:
+//!
+//! - `set_transition(a, a_s, p_adsorb)` for `A` hopping onto `M`,
+//! - `set_transition(a_s, a, p_desorb)` for `As` hopping off into the bulk.
+//!
+//! The molecule keeps its `ParticleID` across a transition, so that
+//! trajectories follow it on and off the surface. Species without
+//! transitions walk without drawing any extra random number.
+
+use crate::{Coordinate, Error, HCPLatticeSpace, Result, SpeciesID, TrackingType};
+use rand::Rng;
+
+impl HCPLatticeSpace {
+    /// Lets a hop of a `from` molecule into a voxel of the location of
+    /// `into` turn it into an `into` molecule there with `probability`.
+    /// Setting a transition again replaces its probability.
+    ///
+    /// Both species must be single-voxel, not obstacles and on different
+    /// locations, and
+    /// `probability` within `[0, 1]`. A hop matching several transitions
+    /// takes the first one set.
+    pub fn set_transition(
+        &mut self,
+        from: SpeciesID,
+        into: SpeciesID,
+        probability: f64,
+    ) -> Result<()> {
+        self.check_species(from)?;
+        self.check_species(into)?;
+        let source = &self.species_cache[from.0];
+        let target = &self.species_cache[into.0];
+        if source.voxel_count > 1
+            || target.voxel_count > 1
+            || source.obstacle
+            || target.obstacle
+            || source.location == target.location
+            || !(0.0..=1.0).contains(&probability)
+        {
+            return Err(Error::InvalidTransition);
+        }
+        let transitions = &mut self.get_species_cache_mut(from)?.transitions;
+        match transitions.iter_mut().find(|(id, _)| *id == into) {
+            Some(transition) => transition.1 = probability,
+            None => transitions.push((into, probability)),
+        }
+        Ok(())
+    }
+
+    /// Returns the probability of the transition from `from` into `into`,
+    /// zero if unset or if `from` is no species of this space.
+    pub fn transition(&self, from: SpeciesID, into: SpeciesID) -> f64 {
+        self.get_species_cache(from)
+            .ok()
+            .and_then(|cache| cache.transitions.iter().find(|(id, _)| *id == into))
+            .map_or(0.0, |&(_, probability)| probability)
+    }
+
+    /// Handles the hop of the molecule of `species` at `from` to `to` if
+    /// `to` is a voxel of the location of one of its transitions, and
+    /// returns whether it did.
+    pub(crate) fn try_transition<R: Rng>(
+        &mut self,
+        species: SpeciesID,
+        from: Coordinate,
+        to: Coordinate,
+        rng: &mut R,
+    ) -> Result<bool> {
+        let cache = &self.species_cache[species.0];
+        if cache.transitions.is_empty() {
+            return Ok(false);
+        }
+        let target = self.voxel(to);
+        let into = cache
+            .transitions
+            .iter()
+            .find(|(into, _)| self.species_cache[into.0].location == target)
+            .copied();
+        let (into, probability) = match into {
+            Some(transition) => transition,
+            None => return Ok(false),
+        };
+        if rng.gen::<f64>() >= probability {
+            return Ok(true);
+        }
+
+        let pid = match &self.species_cache[species.0].cache {
+            TrackingType::Tracking(entries) => entries
+                .iter()
+                .find(|(_, c)| *c == from)
+                .map(|(pid, _)| *pid),
+            TrackingType::Count(_) => None,
+        };
+        self.vacate(from)?;
+        let placed = self.place_particle(into, to)?;
+        if let TrackingType::Tracking(entries) = &mut self.get_species_cache_mut(into)?.cache {
+            entries.set_pid(entries.len() - 1, pid.unwrap_or(placed));
+        }
+        if self.images.is_some() && self.periodic {
+            self.track_image(into, from, to)?;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HCPLatticeSize, ParticleID, Species};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// A membrane on layer 0 under a bulk of `A` molecules.
+    fn membrane_space() -> (HCPLatticeSpace, SpeciesID, SpeciesID, SpeciesID) {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), None);
+        let a_s = space.register_species(Species::new("As"), Some(membrane));
+        for c in space.coordinates().take(36).collect::<Vec<_>>() {
+            space.place_particle(membrane, c).unwrap();
+        }
+        for c in space.coordinates().skip(36).step_by(9).collect::<Vec<_>>() {
+            space.place_particle(a, c).unwrap();
+        }
+        (space, membrane, a, a_s)
+    }
+
+    #[test]
+    fn adsorb_and_keep_identity() {
+        let (mut space, membrane, a, a_s) = membrane_space();
+        space.set_transition(a, a_s, 1.0).unwrap();
+        let pids = space.particles_of(a).unwrap();
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..2000 {
+            space.walk(a, &mut rng).unwrap();
+            space.walk(a_s, &mut rng).unwrap();
+        }
+        space.validate().unwrap();
+        assert_eq!(space.num_molecules(a).unwrap(), 0);
+        assert_eq!(space.num_molecules(a_s).unwrap(), pids.len());
+        assert_eq!(space.num_molecules(membrane).unwrap(), 36 - pids.len());
+        let mut adsorbed = space.particles_of(a_s).unwrap();
+        adsorbed.sort_by_key(|pid| pid.1);
+        assert_eq!(adsorbed, pids);
+    }
+
+    #[test]
+    fn sorted_across_transitions() {
+        let (mut space, _, a, a_s) = membrane_space();
+        space.set_transition(a, a_s, 1.0).unwrap();
+        space.set_transition(a_s, a, 1.0).unwrap();
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..200 {
+            space.walk(a, &mut rng).unwrap();
+            space.walk(a_s, &mut rng).unwrap();
+        }
+        for species in [a, a_s] {
+            let sorted = space.particles_sorted(species).unwrap();
+            assert!(sorted.windows(2).all(|w| w[0].0 < w[1].0));
+            let mut expected: Vec<(ParticleID, Coordinate)> = space
+                .particles_of(species)
+                .unwrap()
+                .into_iter()
+                .map(|pid| (pid, space.find_particle(pid).unwrap().1))
+                .collect();
+            expected.sort_by_key(|&(pid, _)| pid);
+            assert_eq!(sorted, expected);
+        }
+    }
+
+    #[test]
+    fn balance_adsorption_and_desorption() {
+        let (mut space, _, a, a_s) = membrane_space();
+        space.set_transition(a, a_s, 0.5).unwrap();
+        space.set_transition(a_s, a, 0.05).unwrap();
+        assert_eq!(space.transition(a_s, a), 0.05);
+        let mut rng = StdRng::seed_from_u64(9);
+        let mut bound = 0;
+        for _ in 0..4000 {
+            space.walk(a, &mut rng).unwrap();
+            space.walk(a_s, &mut rng).unwrap();
+            bound += space.num_molecules(a_s).unwrap();
+        }
+        space.validate().unwrap();
+        let total = space.num_molecules(a).unwrap() + space.num_molecules(a_s).unwrap();
+        let fraction = bound as f64 / (4000 * total) as f64;
+        assert!(fraction > 0.1 && fraction < 0.9, "bound {}", fraction);
+    }
+
+    #[test]
+    fn invalid_transitions() {
+        let (mut space, membrane, a, a_s) = membrane_space();
+        let b = space.register_species(Species::new("B"), None);
+        assert!(matches!(
+            space.set_transition(a, b, 0.1),
+            Err(Error::InvalidTransition)
+        ));
+        assert!(space.set_transition(a, a_s, 1.5).is_err());
+        assert!(space.set_transition(a_s, a_s, 0.1).is_err());
+        assert!(space.set_transition(a_s, membrane, 0.1).is_ok());
+        assert_eq!(space.transition(a, a_s), 0.0);
+    }
+}
@@ -0,0 +1,199 @@
+//! A diffusion sweep moving every molecule of a species at once.
+//!
+//! `walk_synchronous` draws the hops of all the molecules of a species from
+//! the lattice as it is at the start of the sweep: a hop is proposed if its
+//! target is a voxel of the species' location then, so that no molecule
+//! follows another into the voxel it leaves. Hops proposing the same target
+//! conflict, and only one of them is made.
+//!
+//! Conflicts are resolved by priority. Each sweep draws a 64-bit key from
+//! the caller's generator, after the directions, and gives every molecule
+//! the priority `mix(key ^ mix(lot) ^ serial)` of its `ParticleID`, `mix`
+//! being the SplitMix64 finalizer; a counted species, whose molecules have
+//! no ID, takes the coordinate its molecule starts from as the serial and
+//! `u64::MAX` as the lot. The lowest priority wins, ties, which need a hash
+//! collision, going to the molecule placed first. The winners are then
+//! moved in placement order.
+//!
+//! Given the seed, a sweep is therefore reproducible, whatever the order
+//! the proposals are made in. Every contender has the same chance of
+//! winning a given conflict, but a molecule's priority holds for the whole
+//! sweep: the priorities within a sweep are a random permutation of the
+//! molecules, so that a molecule winning one conflict is ranked ahead of
+//! its rivals in it, and the outcomes of conflicts sharing contenders are
+//! not independent. A new key every sweep makes these rankings independent
+//! from one sweep to the next. Sinks, transitions, collisions and the
+//! potential do not apply, as for `move_particle`.
+
+use crate::{sample_direction, Coordinate, Error, HCPLatticeSpace, ParticleID, Result, SpeciesID};
+use rand::Rng;
+use std::collections::HashMap;
+
+/// The finalizer of SplitMix64, a bijection mixing every bit into every
+/// other.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn priority(key: u64, pid: ParticleID) -> u64 {
+    mix(key ^ mix(pid.lot()) ^ pid.serial())
+}
+
+impl HCPLatticeSpace {
+    /// Attempts one hop for every molecule of `species` at once, drawn with
+    /// the weights of `walk`, and returns the number of hops lost to
+    /// conflicts. See the module documentation for how conflicts are
+    /// resolved. Fails with `InvalidReaction` for a multi-voxel species.
+    pub fn walk_synchronous<R: Rng>(&mut self, species: SpeciesID, rng: &mut R) -> Result<usize> {
+        self.check_species(species)?;
+        let weights = self.hop_weights(species);
+        self.walk_synchronous_biased(species, &weights, rng)
+    }
+
+    /// Same as `walk_synchronous`, with the hop directions drawn with
+    /// `weights` as for `walk_biased`, and failing as it does.
+    pub fn walk_synchronous_biased<R: Rng>(
+        &mut self,
+        species: SpeciesID,
+        weights: &[f64; 12],
+        rng: &mut R,
+    ) -> Result<usize> {
+        self.check_species(species)?;
+        let total = self.check_weights(weights)?;
+        if self.species_cache[species.0].voxel_count > 1 {
+            return Err(Error::InvalidReaction);
+        }
+        let froms = self.coordinates_of(species)?;
+        let mut proposals = Vec::new();
+        for (i, &from) in froms.iter().enumerate() {
+            let direction = sample_direction(weights, total, rng);
+            if let Some(to) = self.neighbor(from, direction)? {
+                if self.can_occupy(species, to) {
+                    proposals.push((i, to));
+                }
+            }
+        }
+        let key: u64 = rng.gen();
+
+        let pids: HashMap<usize, ParticleID> = match self.tracked_entries(species) {
+            Ok(entries) => entries.iter().map(|&(pid, c)| (c.0, pid)).collect(),
+            Err(_) => HashMap::new(),
+        };
+        let identity = |from: Coordinate| {
+            pids.get(&from.0)
+                .copied()
+                .unwrap_or(ParticleID(u64::MAX, from.0 as u64))
+        };
+        let mut ranked: Vec<(usize, u64, usize)> = proposals
+            .iter()
+            .map(|&(i, to)| (to.0, priority(key, identity(froms[i])), i))
+            .collect();
+        ranked.sort_unstable();
+        let mut winners = vec![false; froms.len()];
+        let mut lost = 0;
+        for (k, &(to, _, i)) in ranked.iter().enumerate() {
+            if k > 0 && ranked[k - 1].0 == to {
+                lost += 1;
+            } else {
+                winners[i] = true;
+            }
+        }
+
+        for (i, to) in proposals {
+            if winners[i] {
+                match self.move_particle(froms[i], to) {
+                    Ok(()) | Err(Error::InvalidLocation(..)) => {}
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        Ok(lost)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neighbors::Direction;
+    use crate::{HCPLatticeSize, Species};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// Walks a crowded lattice synchronously and returns the voxel of every
+    /// molecule and the number of conflicts lost.
+    fn crowded_run(seed: u64, tracked: bool) -> (Vec<Coordinate>, usize) {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(8, 8, 8));
+        space.set_periodic(true).unwrap();
+        let a = if tracked {
+            space.register_species(Species::new("A"), None)
+        } else {
+            space.register_counted_species(Species::new("A"), None)
+        };
+        let pids: Vec<ParticleID> = (0..512)
+            .step_by(3)
+            .map(|i| space.place_particle(a, Coordinate(i)).unwrap())
+            .collect();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let lost = (0..50)
+            .map(|_| space.walk_synchronous(a, &mut rng).unwrap())
+            .sum();
+        space.validate().unwrap();
+        assert_eq!(space.num_molecules(a).unwrap(), pids.len());
+        let voxels = if tracked {
+            pids.iter()
+                .map(|&pid| space.find_particle(pid).unwrap().1)
+                .collect()
+        } else {
+            space.coordinates_of(a).unwrap()
+        };
+        (voxels, lost)
+    }
+
+    #[test]
+    fn conflicts_resolve_the_same_given_the_seed() {
+        for &tracked in &[true, false] {
+            let (voxels, lost) = crowded_run(7, tracked);
+            assert!(lost > 100, "{}", lost);
+            assert_eq!(crowded_run(7, tracked), (voxels.clone(), lost));
+            assert_ne!(crowded_run(8, tracked).0, voxels);
+        }
+    }
+
+    #[test]
+    fn lowest_priority_wins() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(1, 3, 1));
+        let a = space.register_species(Species::new("A"), None);
+        let left = space.place_particle(a, Coordinate(0)).unwrap();
+        let right = space.place_particle(a, Coordinate(2)).unwrap();
+        // East or west only, so that both molecules head for the middle
+        // voxel a quarter of the time.
+        let mut weights = [0.0; 12];
+        weights[Direction::East.index()] = 1.0;
+        weights[Direction::West.index()] = 1.0;
+        let mut wins = [0; 2];
+        for seed in 0..400 {
+            let mut copy = space.clone();
+            let mut rng = StdRng::seed_from_u64(seed);
+            let lost = copy.walk_synchronous_biased(a, &weights, &mut rng).unwrap();
+            if lost == 0 {
+                continue;
+            }
+            assert_eq!(copy.species_at(Coordinate(1)).unwrap(), Some(a));
+            let mut replay = StdRng::seed_from_u64(seed);
+            for _ in 0..2 {
+                sample_direction(&weights, 2.0, &mut replay);
+            }
+            let key: u64 = replay.gen();
+            let winner = if priority(key, left) < priority(key, right) {
+                left
+            } else {
+                right
+            };
+            assert_eq!(copy.find_particle(winner).unwrap().1, Coordinate(1));
+            wins[(winner == right) as usize] += 1;
+        }
+        assert!(wins[0] > 30 && wins[1] > 30, "{:?}", wins);
+    }
+}
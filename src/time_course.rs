@@ -0,0 +1,226 @@
+//! Fixed-step simulation recording molecule counts into a flat buffer, and
+//! the time courses of `Simulator::run_time_course`, which also record the
+//! flux of every reaction.
+
+use crate::simulator::ReactionID;
+use crate::{Error, HCPLatticeSpace, Result, SpeciesID};
+use rand::Rng;
+
+/// Molecule counts of every registered species, sampled over time.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TimeCourse {
+    pub times: Vec<f64>,
+    /// Counts in column-major order of shape `(species, samples)`: the counts
+    /// of one sample are contiguous, in registration order.
+    pub counts: Vec<usize>,
+    pub num_species: usize,
+    /// The reactions whose flux is recorded, none for a space without a
+    /// simulator.
+    pub reactions: Vec<ReactionID>,
+    /// The firings per unit time of each reaction of `reactions` over the
+    /// interval ending at each sample, of shape `(reactions, samples)`.
+    pub reaction_flux: Vec<Vec<f64>>,
+}
+
+impl TimeCourse {
+    pub fn num_samples(&self) -> usize {
+        self.times.len()
+    }
+
+    pub fn count(&self, species: SpeciesID, sample: usize) -> usize {
+        self.counts[species.0 + self.num_species * sample]
+    }
+
+    /// Returns the counts of every species at a sample.
+    pub fn sample(&self, sample: usize) -> &[usize] {
+        &self.counts[self.num_species * sample..self.num_species * (sample + 1)]
+    }
+
+    /// Returns the flux of `reaction` at a sample. Panics for a reaction
+    /// whose flux is not recorded.
+    pub fn flux(&self, reaction: ReactionID, sample: usize) -> f64 {
+        let r = self
+            .reactions
+            .iter()
+            .position(|&id| id == reaction)
+            .expect("a recorded reaction");
+        self.reaction_flux[r][sample]
+    }
+}
+
+/// Returns the number of hops due by the end of `step`, forgiving rounding
+/// errors so that an interval dividing `dt` hops the same number of times
+/// on every step.
+fn hops_until(step: usize, dt: f64, interval: f64) -> usize {
+    (step as f64 * dt / interval + 1e-9).floor() as usize
+}
+
+impl HCPLatticeSpace {
+    /// Walks the diffusing species in steps of `dt` for `duration`,
+    /// recording every count every `sample_every`.
+    ///
+    /// A species hops as often as it would in `Simulator`, every
+    /// `diffusion_interval`: `2r²/(3D)`, or `2r²/(2D)` on a 2D lattice. Each
+    /// hop is done at the end of the step it falls in. The `n`-th sample,
+    /// due at `n * sample_every`, is taken at the end of the step nearest to
+    /// it, `round(n * sample_every / dt)`, and recorded at that step's time;
+    /// the first sample is the initial state. Samples due after the last
+    /// step are not taken.
+    ///
+    /// Fails with `InvalidDuration` unless `duration` is finite and not
+    /// negative and `dt` and `sample_every` are finite and positive, and
+    /// with `SizeOverflow` if the samples would not fit in memory.
+    pub fn run<R: Rng>(
+        &mut self,
+        duration: f64,
+        dt: f64,
+        sample_every: f64,
+        rng: &mut R,
+    ) -> Result<TimeCourse> {
+        if !(duration >= 0.0 && duration.is_finite()) {
+            return Err(Error::InvalidDuration(duration));
+        }
+        if let Some(&t) = [dt, sample_every]
+            .iter()
+            .find(|t| !(**t > 0.0 && t.is_finite()))
+        {
+            return Err(Error::InvalidDuration(t));
+        }
+        let num_steps = (duration / dt).round() as usize;
+        let num_species = self.species_cache.len();
+        let num_samples = ((duration / sample_every + 1e-9).floor() as usize)
+            .checked_add(1)
+            .ok_or(Error::SizeOverflow)?;
+        let num_counts = num_samples
+            .checked_mul(num_species)
+            .ok_or(Error::SizeOverflow)?;
+        let mut course = TimeCourse {
+            times: Vec::with_capacity(num_samples),
+            counts: Vec::with_capacity(num_counts),
+            num_species,
+            reactions: Vec::new(),
+            reaction_flux: Vec::new(),
+        };
+        let intervals: Vec<Option<f64>> = (0..num_species)
+            .map(|i| self.diffusion_interval(SpeciesID(i)))
+            .collect();
+        let sample_step = |n: usize| (n as f64 * sample_every / dt).round() as usize;
+
+        let mut next_sample = 0;
+        for step in 0..=num_steps {
+            if step > 0 {
+                for (i, interval) in intervals.iter().enumerate() {
+                    if let Some(interval) = interval {
+                        let hops =
+                            hops_until(step, dt, *interval) - hops_until(step - 1, dt, *interval);
+                        for _ in 0..hops {
+                            self.walk(SpeciesID(i), rng)?;
+                        }
+                    }
+                }
+            }
+            while next_sample < num_samples && sample_step(next_sample) == step {
+                course.times.push(step as f64 * dt);
+                course
+                    .counts
+                    .extend(self.species_cache.iter().map(|cache| cache.num_molecules()));
+                next_sample += 1;
+            }
+        }
+        Ok(course)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Coordinate, HCPLatticeSize, MoleculeInfo, Species};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn sample_times() {
+        let mut space = HCPLatticeSpace::new(1e-8, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        space
+            .set_molecule_info(
+                a,
+                MoleculeInfo {
+                    radius: 1e-8,
+                    diffusion_coefficient: 1e-12,
+                },
+            )
+            .unwrap();
+        for i in 0..5 {
+            space.place_particle(a, Coordinate(i * 3)).unwrap();
+        }
+        space.place_particle(b, Coordinate(1)).unwrap();
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let course = space.run(1e-3, 1e-4, 2.4e-4, &mut rng).unwrap();
+        // Due at 0, 2.4, 4.8, 7.2 and 9.6 (×1e-4).
+        let expected = [0.0, 2e-4, 5e-4, 7e-4, 10e-4];
+        assert_eq!(course.num_samples(), expected.len());
+        for (t, expected) in course.times.iter().zip(&expected) {
+            assert!((t - expected).abs() < 1e-12);
+        }
+        assert_eq!(course.counts.len(), 2 * expected.len());
+        for sample in 0..course.num_samples() {
+            assert_eq!(course.sample(sample), &[5, 1]);
+            assert_eq!(course.count(b, sample), 1);
+        }
+        space.validate().unwrap();
+
+        for (duration, dt, sample_every) in [
+            (1e-3, 0.0, 1e-4),
+            (1e-3, -1e-4, 1e-4),
+            (1e-3, f64::NAN, 1e-4),
+            (1e-3, 1e-4, 0.0),
+            (1e-3, 1e-4, f64::INFINITY),
+            (-1e-3, 1e-4, 1e-4),
+            (f64::NAN, 1e-4, 1e-4),
+        ] {
+            assert!(matches!(
+                space.run(duration, dt, sample_every, &mut rng),
+                Err(Error::InvalidDuration(_))
+            ));
+        }
+        assert!(matches!(
+            space.run(1e300, 1e300, 1e-300, &mut rng),
+            Err(Error::SizeOverflow)
+        ));
+    }
+
+    #[test]
+    fn hops_match_simulator() {
+        let new_space = || {
+            let mut space = HCPLatticeSpace::new(1e-8, HCPLatticeSize::new(6, 6, 6));
+            let a = space.register_species(Species::new("A"), None);
+            space
+                .set_molecule_info(
+                    a,
+                    MoleculeInfo {
+                        radius: 1e-8,
+                        diffusion_coefficient: 1e-12,
+                    },
+                )
+                .unwrap();
+            for i in 0..20 {
+                space.place_particle(a, Coordinate(i * 7)).unwrap();
+            }
+            space
+        };
+        // The hop interval is 2/3 × 1e-4, so steps of 2e-4 hop three times.
+        let mut stepped = new_space();
+        stepped
+            .run(2e-3, 2e-4, 1e-3, &mut StdRng::seed_from_u64(4))
+            .unwrap();
+        let mut walked = new_space();
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..30 {
+            walked.walk(SpeciesID(0), &mut rng).unwrap();
+        }
+        assert_eq!(stepped.voxels, walked.voxels);
+    }
+}
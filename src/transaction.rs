@@ -0,0 +1,158 @@
+//! All-or-nothing compound operations.
+//!
+//! `transaction` runs a closure on the space and, if it fails, undoes what
+//! it did. Rather than copying the lattice up front, the space keeps a
+//! journal while the closure runs: the previous contents of every voxel
+//! written, and a copy of the cache of each species the first time its
+//! molecules change. Rolling back replays the voxel writes backwards and
+//! puts the copies back, so that its cost grows with what the closure did,
+//! not with the lattice. The periodic images and the sink counts are
+//! restored too, as are species registered meanwhile; other settings
+//! changed by the closure are kept. The `ParticleID`s handed out by the
+//! closure are not handed out again, as IDs are never reused.
+//!
+//! Transactions nest: a failing inner transaction undoes its own changes
+//! only, and the outer one that fails undoes those of the inner ones that
+//! succeeded.
+
+use crate::voxels::VoxelStore;
+use crate::{HCPLatticeSpace, ParticleID, Result, SpeciesCache, SpeciesID};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Journal {
+    /// The index and previous raw value of each voxel write, in order.
+    voxels: Vec<(usize, u32)>,
+    /// The caches of the species as they were before their first change.
+    caches: Vec<(SpeciesID, SpeciesCache)>,
+}
+
+impl Journal {
+    pub(crate) fn record_voxel(&mut self, index: usize, raw: u32) {
+        self.voxels.push((index, raw));
+    }
+
+    pub(crate) fn record_cache(&mut self, id: SpeciesID, cache: &SpeciesCache) {
+        if !self.caches.iter().any(|(saved, _)| *saved == id) {
+            self.caches.push((id, cache.clone()));
+        }
+    }
+}
+
+impl HCPLatticeSpace {
+    /// Runs `f` on the space and returns its result, restoring the space to
+    /// its state before the call if `f` fails; see the module documentation
+    /// for what is restored.
+    pub fn transaction<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        let outer = self.journal.replace(Journal::default());
+        let num_species = self.species_cache.len();
+        let images: Option<HashMap<ParticleID, [i32; 3]>> = self.images.clone();
+        let sinks = self.sinks.clone();
+
+        let result = f(self);
+        let journal = std::mem::replace(&mut self.journal, outer).expect("journal kept");
+        match result {
+            Ok(()) => {
+                if let Some(outer) = &mut self.journal {
+                    outer.voxels.extend(journal.voxels);
+                    for (id, cache) in &journal.caches {
+                        if id.0 < num_species {
+                            outer.record_cache(*id, cache);
+                        }
+                    }
+                }
+            }
+            Err(_) => {
+                for (index, raw) in journal.voxels.into_iter().rev() {
+                    self.voxels.set(index, raw);
+                }
+                self.species_cache.truncate(num_species);
+                self.species_ids.retain(|_, id| id.0 < num_species);
+                for (id, cache) in journal.caches {
+                    if id.0 < num_species {
+                        self.species_cache[id.0] = cache;
+                    }
+                }
+                self.images = images;
+                self.sinks = sinks;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Coordinate, Error, HCPLatticeSize, Species};
+
+    #[test]
+    fn aborted_reaction_is_undone() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(6, 6, 6));
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), None);
+        let c = space.register_species(Species::new("C"), None);
+        let origin = space.global_to_coordinate(2, 2, 2).unwrap();
+        let near = space.neighbors(origin).unwrap()[0];
+        let far = Coordinate(0);
+        space.place_particle(a, origin).unwrap();
+        space.place_particle(b, near).unwrap();
+        space.place_particle(b, far).unwrap();
+        let before = space.clone();
+
+        // Consumes A and B into C, then fails placing a second product.
+        let result = space.transaction(|space| {
+            space.react_bimolecular(origin, near, c)?;
+            space.register_species(Species::new("D"), None);
+            space.remove_at(far)?;
+            space.place_particle(c, origin)?;
+            Ok(())
+        });
+        assert!(matches!(result, Err(Error::InvalidLocation(_, _))));
+        space.validate().unwrap();
+        assert_eq!(space.voxels, before.voxels);
+        assert_eq!(space.species_cache, before.species_cache);
+        assert_eq!(space.find_species("D"), None);
+        assert_eq!(space.species().count(), 3);
+
+        space
+            .transaction(|space| {
+                space.react_bimolecular(origin, near, c)?;
+                assert!(space
+                    .transaction(|space| {
+                        space.remove_at(far)?;
+                        space.remove_at(far).map(|_| ())
+                    })
+                    .is_err());
+                Ok(())
+            })
+            .unwrap();
+        space.validate().unwrap();
+        assert_eq!(space.num_molecules(c).unwrap(), 1);
+        assert_eq!(space.num_molecules(b).unwrap(), 1);
+        assert_eq!(space.species_at(far).unwrap(), Some(b));
+        assert!(space.journal.is_none());
+    }
+
+    #[test]
+    fn nested_commits_roll_back_with_the_outer_one() {
+        let mut space = HCPLatticeSpace::new(1.0, HCPLatticeSize::new(4, 4, 4));
+        let a = space.register_species(Species::new("A"), None);
+        space.place_particle(a, Coordinate(1)).unwrap();
+        let before = space.clone();
+        let result = space.transaction(|space| {
+            space.transaction(|space| {
+                space.place_particle(a, Coordinate(2))?;
+                space.move_particle(Coordinate(1), Coordinate(5))
+            })?;
+            space.place_particle(a, Coordinate(2)).map(|_| ())
+        });
+        assert!(result.is_err());
+        space.validate().unwrap();
+        assert_eq!(space.voxels, before.voxels);
+        assert_eq!(space.species_cache, before.species_cache);
+    }
+}
@@ -0,0 +1,156 @@
+//! Conversions between molar concentrations and molecule counts.
+//!
+//! Lengths are in meters, so that `voxel_radius` is in meters and volumes
+//! come out in liters. Each voxel stands for the volume of its cell in the
+//! close packing, `4√2 r³`, so a lattice of `N` voxels has a volume of
+//! `N 4√2 r³`.
+//!
+//! A concentration rarely corresponds to a whole number of molecules.
+//! `concentration_to_count` rounds the expected count half to even, which is
+//! deterministic; `round_stochastic` rounds it up with the probability of its
+//! fractional part, which is unbiased over many runs.
+//!
+//! ```
+//! use spatiocyte::{HCPLatticeSize, HCPLatticeSpace};
+//!
+//! // 56³ voxels of radius 10 nm: very nearly 1 fL.
+//! let space = HCPLatticeSpace::new(1e-8, HCPLatticeSize::new(56, 56, 56));
+//! assert!((space.volume_in_liters() / 1e-15 - 1.0).abs() < 0.01);
+//! let count = space.concentration_to_count(1e-6);
+//! assert!((595..=605).contains(&count));
+//! assert!((space.count_to_concentration(count) / 1e-6 - 1.0).abs() < 0.01);
+//! ```
+
+use crate::region::Region;
+use crate::{HCPLatticeSpace, SpeciesID};
+use rand::Rng;
+
+/// Molecules per mole.
+pub const AVOGADRO: f64 = 6.022_140_76e23;
+
+/// Rounds `expected` down or up to a whole count, up with probability its
+/// fractional part. Negative values count as zero.
+pub fn round_stochastic<R: Rng>(expected: f64, rng: &mut R) -> usize {
+    let expected = expected.max(0.0);
+    let floor = expected.floor();
+    floor as usize + (rng.gen::<f64>() < expected - floor) as usize
+}
+
+impl HCPLatticeSpace {
+    /// Returns the volume a voxel stands for, in cubic meters.
+    pub fn voxel_volume(&self) -> f64 {
+        4.0 * 2f64.sqrt() * self.voxel_radius.powi(3)
+    }
+
+    /// Returns the volume of the lattice in liters.
+    pub fn volume_in_liters(&self) -> f64 {
+        self.liters(self.num_voxels())
+    }
+
+    fn liters(&self, num_voxels: usize) -> f64 {
+        num_voxels as f64 * self.voxel_volume() * 1e3
+    }
+
+    /// Returns the number of molecules, not rounded, at a concentration of
+    /// `molar` over the whole lattice.
+    pub fn expected_count(&self, molar: f64) -> f64 {
+        molar * AVOGADRO * self.volume_in_liters()
+    }
+
+    /// Returns the number of molecules at a concentration of `molar` over
+    /// the whole lattice, rounded half to even.
+    pub fn concentration_to_count(&self, molar: f64) -> usize {
+        to_count(self.expected_count(molar))
+    }
+
+    /// Returns the molar concentration of `n` molecules over the whole
+    /// lattice.
+    pub fn count_to_concentration(&self, n: usize) -> f64 {
+        n as f64 / (AVOGADRO * self.volume_in_liters())
+    }
+
+    /// Same as `concentration_to_count` over the voxels centered in
+    /// `region`.
+    pub fn concentration_to_count_in(&self, region: &dyn Region, molar: f64) -> usize {
+        let liters = self.liters(self.coordinates_in(region).count());
+        to_count(molar * AVOGADRO * liters)
+    }
+
+    /// Same as `count_to_concentration` over the voxels centered in
+    /// `region`, infinite if there are none.
+    pub fn count_to_concentration_in(&self, region: &dyn Region, n: usize) -> f64 {
+        let liters = self.liters(self.coordinates_in(region).count());
+        n as f64 / (AVOGADRO * liters)
+    }
+
+    /// Same as `concentration_to_count` over the voxels of `structure`, for
+    /// species located on it.
+    pub fn concentration_to_count_on(&self, structure: SpeciesID, molar: f64) -> usize {
+        let liters = self.liters(self.structure_voxels(structure));
+        to_count(molar * AVOGADRO * liters)
+    }
+
+    /// Same as `count_to_concentration` over the voxels of `structure`,
+    /// infinite if it has none.
+    pub fn count_to_concentration_on(&self, structure: SpeciesID, n: usize) -> f64 {
+        let liters = self.liters(self.structure_voxels(structure));
+        n as f64 / (AVOGADRO * liters)
+    }
+
+    /// Counts the voxels of `structure`, including those taken by the
+    /// molecules located on it.
+    fn structure_voxels(&self, structure: SpeciesID) -> usize {
+        self.species_cache
+            .iter()
+            .enumerate()
+            .filter(|(i, cache)| *i == structure.0 || cache.location == Some(structure))
+            .map(|(_, cache)| cache.num_molecules() * cache.voxel_count)
+            .sum()
+    }
+}
+
+fn to_count(expected: f64) -> usize {
+    expected.max(0.0).round_ties_even() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::region::Cuboid;
+    use crate::{Coordinate, HCPLatticeSize, Species};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn rounding() {
+        assert_eq!(to_count(2.5), 2);
+        assert_eq!(to_count(3.5), 4);
+        assert_eq!(to_count(-1.0), 0);
+        let mut rng = StdRng::seed_from_u64(0);
+        let total: usize = (0..10000).map(|_| round_stochastic(2.3, &mut rng)).sum();
+        assert!((total as f64 / 10000.0 - 2.3).abs() < 0.02);
+        assert_eq!(round_stochastic(4.0, &mut rng), 4);
+    }
+
+    #[test]
+    fn regions_and_structures() {
+        let mut space = HCPLatticeSpace::new(1e-8, HCPLatticeSize::new(10, 10, 10));
+        let membrane = space.register_species(Species::new("M"), None);
+        let receptor = space.register_species(Species::new("R"), Some(membrane));
+        for c in space.coordinates().take(100).collect::<Vec<_>>() {
+            space.place_particle(membrane, c).unwrap();
+        }
+        space.place_particle(receptor, Coordinate(0)).unwrap();
+
+        let molar = space.count_to_concentration(1000);
+        assert_eq!(space.concentration_to_count(molar), 1000);
+        assert_eq!(space.concentration_to_count_on(membrane, molar), 100);
+        assert!((space.count_to_concentration_on(membrane, 100) / molar - 1.0).abs() < 1e-12);
+
+        let [lx, ly, lz] = space.periodic_lengths();
+        // Layers 0 to 4 of 10.
+        let half = Cuboid::new([-1.0; 3], [lx, ly, 0.45 * lz]);
+        assert_eq!(space.concentration_to_count_in(&half, molar), 500);
+        assert!((space.count_to_concentration_in(&half, 500) / molar - 1.0).abs() < 1e-12);
+    }
+}
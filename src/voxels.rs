@@ -0,0 +1,456 @@
+//! Backing stores for the contents of the voxels.
+//!
+//! The contents of a voxel are encoded as a `u32`, `0` meaning vacant and
+//! `i + 1` species `i`, so that at most `u32::MAX - 1` species fit (the
+//! largest index is `u32::MAX - 2`; `register_species` panics beyond) and the
+//! public `Option<SpeciesID>` API is decoded from it. Vacant is `0` rather
+//! than `u32::MAX` so that a fresh dense store is a zeroed allocation, which
+//! the OS maps lazily: a large lattice costs memory as it fills. The dense
+//! store keeps one of them per voxel, 4 bytes
+//! each, so that a 960×960×960 lattice needs about 3.5 GB. The sparse store
+//! only keeps the occupied voxels in an ordered map, at roughly 40 bytes and
+//! a `log n` lookup each; it pays off below about 10% occupancy, and is
+//! worth its CPU cost when the dense array would not fit in memory.
+//!
+//! The chunked store cuts the index range into bricks of `BRICK_LEN` voxels
+//! and only allocates the bricks holding a molecule, dropping a brick again
+//! once it is vacant. Within an allocated brick it is as fast as the dense
+//! store, so it suits lattices too large for memory whose molecules are
+//! confined to a small part of them.
+//!
+//! Clones share their data until written to: the dense array and each
+//! brick sit behind an `Arc` and are copied by the first write while
+//! another clone holds them, so that a `FrozenLattice` costs nothing until
+//! the space moves on. The sparse store copies its map on clone.
+
+use crate::SpeciesID;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+pub(crate) fn encode(voxel: Option<SpeciesID>) -> u32 {
+    voxel.map_or(0, |id| id.0 as u32 + 1)
+}
+
+pub(crate) fn decode(raw: u32) -> Option<SpeciesID> {
+    raw.checked_sub(1).map(|i| SpeciesID(i as usize))
+}
+
+/// Encoded voxel contents indexed by coordinate. Indices are in range.
+pub(crate) trait VoxelStore {
+    fn len(&self) -> usize;
+    fn get(&self, index: usize) -> u32;
+    fn set(&mut self, index: usize, raw: u32);
+
+    fn swap(&mut self, a: usize, b: usize) {
+        let (raw_a, raw_b) = (self.get(a), self.get(b));
+        self.set(a, raw_b);
+        self.set(b, raw_a);
+    }
+
+    /// Iterates over the occupied voxels in index order.
+    fn occupied(&self) -> Box<dyn Iterator<Item = (usize, u32)> + '_>;
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct DenseVoxels(Arc<Box<[u32]>>);
+
+impl DenseVoxels {
+    pub(crate) fn new(len: usize) -> Self {
+        Self(Arc::new(vec![0; len].into_boxed_slice()))
+    }
+}
+
+impl VoxelStore for DenseVoxels {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn get(&self, index: usize) -> u32 {
+        self.0[index]
+    }
+
+    fn set(&mut self, index: usize, raw: u32) {
+        Arc::make_mut(&mut self.0)[index] = raw;
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        Arc::make_mut(&mut self.0).swap(a, b);
+    }
+
+    fn occupied(&self) -> Box<dyn Iterator<Item = (usize, u32)> + '_> {
+        Box::new(
+            self.0
+                .iter()
+                .enumerate()
+                .filter(|(_, &raw)| raw != 0)
+                .map(|(i, &raw)| (i, raw)),
+        )
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct SparseVoxels {
+    len: usize,
+    occupied: BTreeMap<usize, u32>,
+}
+
+impl SparseVoxels {
+    pub(crate) fn new(len: usize) -> Self {
+        Self {
+            len,
+            occupied: BTreeMap::new(),
+        }
+    }
+}
+
+impl VoxelStore for SparseVoxels {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, index: usize) -> u32 {
+        self.occupied.get(&index).copied().unwrap_or(0)
+    }
+
+    fn set(&mut self, index: usize, raw: u32) {
+        if raw == 0 {
+            self.occupied.remove(&index);
+        } else {
+            self.occupied.insert(index, raw);
+        }
+    }
+
+    fn occupied(&self) -> Box<dyn Iterator<Item = (usize, u32)> + '_> {
+        Box::new(self.occupied.iter().map(|(&i, &raw)| (i, raw)))
+    }
+}
+
+/// The number of voxels per brick of the chunked store, 128 kB of them.
+pub(crate) const BRICK_LEN: usize = 1 << 15;
+
+#[derive(Clone, PartialEq, Debug)]
+struct Brick {
+    voxels: Box<[u32]>,
+    num_occupied: usize,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct ChunkedVoxels {
+    len: usize,
+    bricks: Vec<Option<Arc<Brick>>>,
+}
+
+impl ChunkedVoxels {
+    pub(crate) fn new(len: usize) -> Self {
+        Self {
+            len,
+            bricks: vec![None; len.div_ceil(BRICK_LEN)],
+        }
+    }
+
+    /// Returns the number of allocated bricks.
+    #[cfg(test)]
+    pub(crate) fn num_bricks(&self) -> usize {
+        self.bricks.iter().filter(|brick| brick.is_some()).count()
+    }
+}
+
+impl VoxelStore for ChunkedVoxels {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, index: usize) -> u32 {
+        self.bricks[index / BRICK_LEN]
+            .as_ref()
+            .map_or(0, |brick| brick.voxels[index % BRICK_LEN])
+    }
+
+    fn set(&mut self, index: usize, raw: u32) {
+        let slot = &mut self.bricks[index / BRICK_LEN];
+        if slot.is_none() {
+            if raw == 0 {
+                return;
+            }
+            *slot = Some(Arc::new(Brick {
+                voxels: vec![0; BRICK_LEN].into_boxed_slice(),
+                num_occupied: 0,
+            }));
+        }
+        let brick = Arc::make_mut(slot.as_mut().expect("just allocated"));
+        let voxel = &mut brick.voxels[index % BRICK_LEN];
+        match (*voxel != 0, raw != 0) {
+            (false, true) => brick.num_occupied += 1,
+            (true, false) => brick.num_occupied -= 1,
+            _ => {}
+        }
+        *voxel = raw;
+        if brick.num_occupied == 0 {
+            *slot = None;
+        }
+    }
+
+    fn occupied(&self) -> Box<dyn Iterator<Item = (usize, u32)> + '_> {
+        Box::new(
+            self.bricks
+                .iter()
+                .enumerate()
+                .filter_map(|(i, brick)| brick.as_ref().map(|brick| (i * BRICK_LEN, brick)))
+                .flat_map(|(offset, brick)| {
+                    brick
+                        .voxels
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, &raw)| raw != 0)
+                        .map(move |(i, &raw)| (offset + i, raw))
+                }),
+        )
+    }
+}
+
+/// The store chosen at construction.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) enum Voxels {
+    Dense(DenseVoxels),
+    Sparse(SparseVoxels),
+    Chunked(ChunkedVoxels),
+}
+
+impl Voxels {
+    /// Returns a store of the same kind holding `len` vacant voxels.
+    pub(crate) fn empty_like(&self, len: usize) -> Self {
+        match self {
+            Voxels::Dense(_) => Voxels::Dense(DenseVoxels::new(len)),
+            Voxels::Sparse(_) => Voxels::Sparse(SparseVoxels::new(len)),
+            Voxels::Chunked(_) => Voxels::Chunked(ChunkedVoxels::new(len)),
+        }
+    }
+
+    fn store(&self) -> &dyn VoxelStore {
+        match self {
+            Voxels::Dense(store) => store,
+            Voxels::Sparse(store) => store,
+            Voxels::Chunked(store) => store,
+        }
+    }
+
+    fn store_mut(&mut self) -> &mut dyn VoxelStore {
+        match self {
+            Voxels::Dense(store) => store,
+            Voxels::Sparse(store) => store,
+            Voxels::Chunked(store) => store,
+        }
+    }
+}
+
+impl VoxelStore for Voxels {
+    fn len(&self) -> usize {
+        self.store().len()
+    }
+
+    fn get(&self, index: usize) -> u32 {
+        self.store().get(index)
+    }
+
+    fn set(&mut self, index: usize, raw: u32) {
+        self.store_mut().set(index, raw)
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        self.store_mut().swap(a, b)
+    }
+
+    fn occupied(&self) -> Box<dyn Iterator<Item = (usize, u32)> + '_> {
+        self.store().occupied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Coordinate, Direction, HCPLatticeSize, HCPLatticeSpace, MoleculeInfo, Simulator, Species,
+    };
+
+    /// Runs the same model on a space and returns everything observable.
+    fn observe(mut space: HCPLatticeSpace) -> (Vec<(Coordinate, SpeciesID)>, Vec<u8>) {
+        let membrane = space.register_species(Species::new("M"), None);
+        let a = space.register_species(Species::new("A"), None);
+        let b = space.register_species(Species::new("B"), Some(membrane));
+        let big = space.register_multi_voxel_species(Species::new("R"), None, 3);
+        for &id in &[a, b, big.id()] {
+            space
+                .set_molecule_info(
+                    id,
+                    MoleculeInfo {
+                        radius: 1e-8,
+                        diffusion_coefficient: 1e-12,
+                    },
+                )
+                .unwrap();
+        }
+        for c in space
+            .coordinates()
+            .filter(|c| c.0 % 64 < 16)
+            .collect::<Vec<_>>()
+        {
+            space.place_particle(membrane, c).unwrap();
+        }
+        for c in space.coordinates().step_by(5).collect::<Vec<_>>() {
+            let _ = space.place_particle(a, c);
+            let _ = space.place_particle(b, c);
+        }
+        let anchor = space.global_to_coordinate(3, 3, 4).unwrap();
+        let pid = space.place_particle(big.id(), anchor).unwrap();
+        let _ = space.move_multi_voxel(pid, Direction::East);
+        assert!(space.nearest_empty(Coordinate(0), 3, a).is_some());
+
+        let mut sim = Simulator::with_seed(space, 3);
+        let names = ["M", "A", "B", "R"]
+            .iter()
+            .map(|n| Species::new(n))
+            .collect();
+        let observer = sim.add_number_observer(names, 1e-4).unwrap();
+        sim.run(1e-3).unwrap();
+        sim.space().validate().unwrap();
+
+        let mut csv = Vec::new();
+        sim.number_observer(observer).write_csv(&mut csv).unwrap();
+        (sim.space().occupied().collect(), csv)
+    }
+
+    #[test]
+    fn encoding_sentinel() {
+        assert_eq!(encode(None), 0);
+        assert_eq!(decode(0), None);
+        let largest = SpeciesID(u32::MAX as usize - 2);
+        assert_eq!(encode(Some(largest)), u32::MAX - 1);
+        assert_eq!(decode(encode(Some(largest))), Some(largest));
+        assert_eq!(decode(encode(Some(SpeciesID(0)))), Some(SpeciesID(0)));
+
+        let mut dense = DenseVoxels::new(3);
+        dense.set(1, encode(Some(largest)));
+        assert_eq!(
+            dense.occupied().collect::<Vec<_>>(),
+            vec![(1, u32::MAX - 1)]
+        );
+        dense.set(1, encode(None));
+        assert_eq!(dense.occupied().count(), 0);
+    }
+
+    #[test]
+    fn clones_share_until_written() {
+        let mut dense = DenseVoxels::new(4);
+        dense.set(0, 1);
+        let frozen = dense.clone();
+        assert!(Arc::ptr_eq(&dense.0, &frozen.0));
+        dense.set(1, 2);
+        assert!(!Arc::ptr_eq(&dense.0, &frozen.0));
+        assert_eq!((dense.get(1), frozen.get(1)), (2, 0));
+
+        let mut chunked = ChunkedVoxels::new(3 * BRICK_LEN);
+        chunked.set(0, 1);
+        chunked.set(BRICK_LEN, 1);
+        let frozen = chunked.clone();
+        chunked.set(BRICK_LEN + 1, 3);
+        let shared = |i: usize| {
+            Arc::ptr_eq(
+                chunked.bricks[i].as_ref().unwrap(),
+                frozen.bricks[i].as_ref().unwrap(),
+            )
+        };
+        assert!(shared(0));
+        assert!(!shared(1));
+        assert_eq!(frozen.get(BRICK_LEN + 1), 0);
+        chunked.set(0, 0);
+        assert!(chunked.bricks[0].is_none());
+        assert_eq!(frozen.get(0), 1);
+    }
+
+    #[test]
+    fn backends_behave_identically() {
+        let dense = observe(HCPLatticeSpace::new(1e-8, HCPLatticeSize::new(8, 8, 8)));
+        let sparse = observe(HCPLatticeSpace::new_sparse(
+            1e-8,
+            HCPLatticeSize::new(8, 8, 8),
+        ));
+        let chunked = observe(HCPLatticeSpace::new_chunked(
+            1e-8,
+            HCPLatticeSize::new(8, 8, 8),
+        ));
+        assert_eq!(dense, sparse);
+        assert_eq!(dense, chunked);
+    }
+
+    #[test]
+    fn sparse_keeps_only_occupied() {
+        let mut store = SparseVoxels::new(1 << 40);
+        store.set(12, 3);
+        store.set(1 << 39, 1);
+        store.swap(12, 13);
+        assert_eq!(store.get(12), 0);
+        assert_eq!(
+            store.occupied().collect::<Vec<_>>(),
+            vec![(13, 3), (1 << 39, 1)]
+        );
+        store.set(13, 0);
+        assert_eq!(store.occupied.len(), 1);
+    }
+
+    #[test]
+    fn bricks_come_and_go() {
+        let mut store = ChunkedVoxels::new(4 * BRICK_LEN + 1);
+        assert_eq!(store.num_bricks(), 0);
+        store.set(3, 0);
+        assert_eq!(store.num_bricks(), 0);
+        store.set(4 * BRICK_LEN, 2);
+        store.set(BRICK_LEN - 1, 1);
+        assert_eq!(store.num_bricks(), 2);
+        store.swap(BRICK_LEN - 1, BRICK_LEN);
+        assert_eq!(store.num_bricks(), 2);
+        assert_eq!(
+            store.occupied().collect::<Vec<_>>(),
+            vec![(BRICK_LEN, 1), (4 * BRICK_LEN, 2)]
+        );
+        store.set(BRICK_LEN, 0);
+        store.set(4 * BRICK_LEN, 0);
+        assert_eq!(store.num_bricks(), 0);
+        assert_eq!(store.get(BRICK_LEN), 0);
+    }
+
+    #[test]
+    fn huge_chunked_lattice() {
+        // 32 GB as a dense array.
+        let mut space = HCPLatticeSpace::new_chunked(1e-8, HCPLatticeSize::new(2000, 2000, 2000));
+        let a = space.register_species(Species::new("A"), None);
+        space
+            .set_molecule_info(
+                a,
+                MoleculeInfo {
+                    radius: 1e-8,
+                    diffusion_coefficient: 1e-12,
+                },
+            )
+            .unwrap();
+        for row in 0..20 {
+            for col in 1000..1020 {
+                let c = space.global_to_coordinate(row, col, 1000).unwrap();
+                space.place_particle(a, c).unwrap();
+            }
+        }
+
+        let mut sim = Simulator::with_seed(space, 1);
+        sim.run(1e-3).unwrap();
+        let space = sim.space();
+        assert_eq!(space.num_molecules(a).unwrap(), 400);
+        space.validate().unwrap();
+        match &space.voxels {
+            Voxels::Chunked(store) => {
+                let bricks = store.num_bricks();
+                assert!((1..=40).contains(&bricks));
+                assert!(bricks * BRICK_LEN * 4 < 8 << 20);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
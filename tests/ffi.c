@@ -0,0 +1,47 @@
+/* Drives a simulation through the C interface, as tests/ffi.rs does, and
+ * exits with 0 if every check passes. */
+
+#include <stdio.h>
+
+#include "spatiocyte.h"
+
+#define CHECK(condition)                                                    \
+    do {                                                                    \
+        if (!(condition)) {                                                 \
+            fprintf(stderr, "%s:%d: %s\n", __FILE__, __LINE__, #condition); \
+            return 1;                                                       \
+        }                                                                   \
+    } while (0)
+
+int main(void) {
+    SpatiocyteSpace *space = spatiocyte_space_new(1e-8, 10, 10, 10);
+    CHECK(space != NULL);
+    size_t a, b;
+    CHECK(spatiocyte_add_species(space, "A", 1e-12, &a) == SPATIOCYTE_OK);
+    CHECK(spatiocyte_add_species(space, "B", 1e-12, &b) == SPATIOCYTE_OK);
+    for (size_t c = 0; c < 1000; c += 10) {
+        CHECK(spatiocyte_add_particle(space, a, c) == SPATIOCYTE_OK);
+    }
+    CHECK(spatiocyte_add_particle(space, a, 0) == SPATIOCYTE_INVALID_LOCATION);
+    CHECK(spatiocyte_add_particle(space, 7, 1) == SPATIOCYTE_UNKNOWN_SPECIES);
+    CHECK(spatiocyte_add_particle(space, a, 1000) == SPATIOCYTE_OUT_OF_RANGE);
+
+    SpatiocyteSimulator *simulator = spatiocyte_simulator_new(space, 42);
+    CHECK(simulator != NULL);
+    const char *reactants[] = {"A"};
+    const char *products[] = {"B"};
+    CHECK(spatiocyte_add_reaction(simulator, reactants, 1, products, 1, 1e3) == SPATIOCYTE_OK);
+    CHECK(spatiocyte_run(simulator, 1e-3) == SPATIOCYTE_OK);
+    CHECK(spatiocyte_time(simulator) >= 1e-3);
+
+    const SpatiocyteSpace *state = spatiocyte_simulator_space(simulator);
+    size_t counts[2];
+    CHECK(spatiocyte_counts_into(state, counts, 1) == SPATIOCYTE_BUFFER_TOO_SMALL);
+    CHECK(spatiocyte_counts_into(state, counts, 2) == SPATIOCYTE_OK);
+    CHECK(counts[a] + counts[b] == 100);
+    CHECK(counts[b] > 0);
+    CHECK(spatiocyte_run(NULL, 1.0) == SPATIOCYTE_NULL_POINTER);
+
+    spatiocyte_simulator_free(simulator);
+    return 0;
+}
@@ -0,0 +1,158 @@
+#![cfg(feature = "ffi")]
+
+use spatiocyte::ffi::*;
+use std::ffi::CString;
+use std::fs;
+use std::os::raw::c_char;
+use std::path::Path;
+use std::process::Command;
+use std::ptr;
+
+#[test]
+fn simulate_through_the_c_interface() {
+    let space = spatiocyte_space_new(1e-8, 10, 10, 10);
+    assert!(!space.is_null());
+    let (names, mut ids) = (
+        [CString::new("A").unwrap(), CString::new("B").unwrap()],
+        [0; 2],
+    );
+    unsafe {
+        for (name, id) in names.iter().zip(&mut ids) {
+            assert_eq!(
+                spatiocyte_add_species(space, name.as_ptr(), 1e-12, id),
+                SPATIOCYTE_OK
+            );
+        }
+        let [a, b] = ids;
+        for c in (0..1000).step_by(10) {
+            assert_eq!(spatiocyte_add_particle(space, a, c), SPATIOCYTE_OK);
+        }
+        assert_eq!(
+            spatiocyte_add_particle(space, a, 0),
+            SPATIOCYTE_INVALID_LOCATION
+        );
+        assert_eq!(
+            spatiocyte_add_particle(space, 7, 1),
+            SPATIOCYTE_UNKNOWN_SPECIES
+        );
+        assert_eq!(
+            spatiocyte_add_species(space, ptr::null(), 0.0, &mut 0),
+            SPATIOCYTE_NULL_POINTER
+        );
+        let invalid = [0xffu8, 0];
+        assert_eq!(
+            spatiocyte_add_species(space, invalid.as_ptr() as *const c_char, 0.0, &mut 0),
+            SPATIOCYTE_INVALID_NAME
+        );
+
+        let simulator = spatiocyte_simulator_new(space, 42);
+        assert!(!simulator.is_null());
+        let (reactants, products) = ([names[0].as_ptr()], [names[1].as_ptr()]);
+        assert_eq!(
+            spatiocyte_add_reaction(simulator, reactants.as_ptr(), 1, products.as_ptr(), 1, 1e3),
+            SPATIOCYTE_OK
+        );
+        assert_eq!(spatiocyte_run(simulator, 1e-3), SPATIOCYTE_OK);
+        assert!(spatiocyte_time(simulator) >= 1e-3);
+
+        let state = spatiocyte_simulator_space(simulator);
+        assert_eq!(spatiocyte_num_species(state), 2);
+        let mut counts = [0; 2];
+        assert_eq!(
+            spatiocyte_counts_into(state, counts.as_mut_ptr(), 1),
+            SPATIOCYTE_BUFFER_TOO_SMALL
+        );
+        assert_eq!(
+            spatiocyte_counts_into(state, counts.as_mut_ptr(), 2),
+            SPATIOCYTE_OK
+        );
+        assert_eq!(counts[a] + counts[b], 100);
+        assert!(counts[b] > 0);
+        spatiocyte_simulator_free(simulator);
+
+        assert_eq!(
+            spatiocyte_run(ptr::null_mut(), 1.0),
+            SPATIOCYTE_NULL_POINTER
+        );
+        assert!(spatiocyte_time(ptr::null()).is_nan());
+        assert!(spatiocyte_simulator_new(ptr::null_mut(), 0).is_null());
+        assert!(spatiocyte_space_new(1.0, usize::MAX, 2, 2).is_null());
+        spatiocyte_space_free(ptr::null_mut());
+    }
+}
+
+/// Regenerates the header from `cbindgen.toml` and checks that
+/// `include/spatiocyte.h` matches it, rewriting the header instead when
+/// `SPATIOCYTE_BLESS` is set.
+#[test]
+fn header_is_up_to_date() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(root.join("cbindgen.toml")).unwrap();
+    let mut generated = Vec::new();
+    cbindgen::Builder::new()
+        .with_src(root.join("src").join("ffi.rs"))
+        .with_config(config)
+        .generate()
+        .unwrap()
+        .write(&mut generated);
+    let header = root.join("include").join("spatiocyte.h");
+    if std::env::var_os("SPATIOCYTE_BLESS").is_some() {
+        fs::write(&header, &generated).unwrap();
+    }
+    assert!(
+        fs::read(&header).unwrap() == generated,
+        "include/spatiocyte.h is out of date, rerun with SPATIOCYTE_BLESS=1"
+    );
+}
+
+/// Builds the library as a static one and links `tests/ffi.c` against it
+/// with the C compiler the `cc` crate finds, skipping when there is none.
+#[test]
+fn c_program_runs() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let target = root.join("target").join("ffi");
+    let objects = target.join("objects");
+    fs::create_dir_all(&objects).unwrap();
+    let mut build = cc::Build::new();
+    build
+        .target(env!("SPATIOCYTE_TARGET"))
+        .host(env!("SPATIOCYTE_TARGET"))
+        .opt_level(0)
+        .cargo_metadata(false)
+        .out_dir(&objects)
+        .include(root.join("include"))
+        .file(root.join("tests").join("ffi.c"))
+        .warnings_into_errors(true);
+    let compiler = build.get_compiler();
+    if compiler.to_command().arg("--version").output().is_err() {
+        eprintln!("no C compiler, skipping");
+        return;
+    }
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(cargo)
+        .current_dir(root)
+        .args([
+            "rustc",
+            "--lib",
+            "--features",
+            "ffi",
+            "--crate-type",
+            "staticlib",
+        ])
+        .arg("--target-dir")
+        .arg(&target)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let program = target.join("ffi_test");
+    let status = compiler
+        .to_command()
+        .args(build.compile_intermediates())
+        .arg(target.join("debug").join("libspatiocyte.a"))
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(Command::new(&program).status().unwrap().success());
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "python")]
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Runs the test functions of a module without pytest, skipping those
+/// raising `unittest.SkipTest`.
+const RUNNER: &str = "
+import unittest, test_spatiocyte as suite
+for name, test in sorted(vars(suite).items()):
+    if name.startswith('test_'):
+        try:
+            test()
+            print(name, 'passed')
+        except unittest.SkipTest as skip:
+            print(name, 'skipped:', skip)
+";
+
+/// Builds the extension module as maturin does and runs `tests/python`
+/// against it, with pytest if installed.
+#[test]
+fn pytest_suite() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let target = root.join("target").join("python");
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(cargo)
+        .current_dir(root)
+        .args([
+            "rustc",
+            "--lib",
+            "--features",
+            "python,pyo3/extension-module",
+            "--crate-type",
+            "cdylib",
+        ])
+        .arg("--target-dir")
+        .arg(&target)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let module = target.join("module");
+    std::fs::create_dir_all(&module).unwrap();
+    std::fs::copy(
+        target.join("debug").join("libspatiocyte.so"),
+        module.join("spatiocyte.so"),
+    )
+    .unwrap();
+
+    let python = std::env::var("PYO3_PYTHON").unwrap_or_else(|_| "python3".to_string());
+    let suite = root.join("tests").join("python");
+    let path = std::env::join_paths([&module, &suite]).unwrap();
+    let has_pytest = Command::new(&python)
+        .args(["-c", "import pytest"])
+        .stderr(Stdio::null())
+        .status()
+        .unwrap()
+        .success();
+    let mut command = Command::new(&python);
+    command.env("PYTHONPATH", path).current_dir(&suite);
+    if has_pytest {
+        command.args(["-m", "pytest", "-q"]);
+    } else {
+        command.args(["-c", RUNNER]);
+    }
+    assert!(command.status().unwrap().success());
+}
@@ -0,0 +1,130 @@
+"""End-to-end tests of the Python bindings, run by `tests/python.rs`."""
+
+import _thread
+import threading
+import unittest
+
+import spatiocyte
+
+
+def crowded_space():
+    space = spatiocyte.HCPLatticeSpace(2.5e-9, 10, 10, 10)
+    space.set_periodic(True)
+    for name in ["A", "B", "C"]:
+        space.register_species(name, diffusion_coefficient=1e-10)
+    return space
+
+
+def binding_simulator(seed=0):
+    sim = spatiocyte.Simulator(crowded_space(), seed)
+    sim.add_molecules("A", 100)
+    sim.add_molecules(spatiocyte.Species("B"), 100)
+    p = sim.add_collision_reaction("A", "B", ["C"], 1e8)
+    assert 0.0 < p <= 1.0
+    return sim
+
+
+def test_binding():
+    sim = binding_simulator()
+    assert sim.counts() == {"A": 100, "B": 100, "C": 0}
+    observer = sim.add_number_observer(["A", "C"], 1e-7)
+    steps = []
+    assert sim.run(1e-6, progress=lambda n, t: steps.append((n, t)), every=10)
+    assert sim.t == 1e-6
+    assert steps and all(n % 10 == 0 for n, _ in steps)
+
+    counts = sim.counts()
+    assert counts["A"] == counts["B"]
+    assert counts["A"] + counts["C"] == 100
+    assert counts["C"] > 0
+    assert sim.space.num_molecules("C") == counts["C"]
+    assert len(sim.space.coordinates_of("C")) == counts["C"]
+
+    again = binding_simulator()
+    again.run(1e-6)
+    assert again.counts() == counts
+    try:
+        import numpy
+    except ImportError:
+        raise unittest.SkipTest("numpy is not installed")
+    times, recorded = sim.number_observer(observer)
+    assert recorded.shape == (len(times), 2)
+    assert list(recorded[0]) == [100, 0]
+    assert recorded[-1, 1] == counts["C"]
+    positions = sim.positions_of("C")
+    assert positions.shape == (counts["C"], 3)
+    assert numpy.all(positions >= 0.0)
+
+
+def test_errors():
+    space = crowded_space()
+    try:
+        space.place("X", 0)
+    except spatiocyte.SpatiocyteError as err:
+        assert str(err) == "species X is not registered"
+    else:
+        raise AssertionError("placed an unregistered species")
+    space.place("A", 0)
+    try:
+        space.place("B", 0)
+    except spatiocyte.SpatiocyteError as err:
+        assert "voxel 0" in str(err)
+    else:
+        raise AssertionError("placed on an occupied voxel")
+    try:
+        spatiocyte.Simulator(space).run(-1.0)
+    except ValueError:
+        pass
+    else:
+        raise AssertionError("ran backwards")
+
+
+def test_stop_and_resume():
+    sim = binding_simulator()
+    assert not sim.run(1e-6, progress=lambda n, t: t < 5e-7, every=1)
+    assert 5e-7 <= sim.t < 1e-6
+    assert sim.run(1e-6 - sim.t)
+
+    def fail(n, t):
+        raise RuntimeError("stop here")
+
+    try:
+        sim.run(1e-6, progress=fail, every=1)
+    except RuntimeError as err:
+        assert str(err) == "stop here"
+    else:
+        raise AssertionError("the error of progress was lost")
+
+
+def test_interrupt():
+    sim = binding_simulator()
+    start = sim.t
+    try:
+        sim.run(1e-6, progress=lambda n, t: _thread.interrupt_main(), every=1)
+    except KeyboardInterrupt:
+        pass
+    else:
+        raise AssertionError("Ctrl-C did not stop the run")
+    assert start < sim.t < start + 1e-6
+    assert sim.run(1e-6)
+
+
+def test_run_releases_the_gil():
+    sim = binding_simulator()
+    ticks = []
+    done = threading.Event()
+
+    def tick():
+        while not done.is_set():
+            ticks.append(None)
+            done.wait(1e-4)
+
+    thread = threading.Thread(target=tick)
+    thread.start()
+    while not ticks:
+        pass
+    before = len(ticks)
+    sim.run(1e-5, every=1 << 62)
+    done.set()
+    thread.join()
+    assert len(ticks) > before + 1
@@ -0,0 +1,118 @@
+use spatiocyte::{
+    HCPLatticeSize, HCPLatticeSpace, MoleculeInfo, ReactionRule, Simulator, Species,
+    TrajectoryTarget,
+};
+
+fn simulate(seed: u64) -> (Vec<u8>, Vec<u8>) {
+    let r = 1e-8;
+    let mut space = HCPLatticeSpace::new(r, HCPLatticeSize::new(10, 10, 10));
+    let a = space.register_species(Species::new("A"), None);
+    space.register_species(Species::new("B"), None);
+    space
+        .set_molecule_info(
+            a,
+            MoleculeInfo {
+                radius: r,
+                diffusion_coefficient: 1e-12,
+            },
+        )
+        .unwrap();
+    for coordinate in space.coordinates().step_by(7).collect::<Vec<_>>() {
+        space.place_particle(a, coordinate).unwrap();
+    }
+
+    let mut sim = Simulator::with_seed(space, seed);
+    let rule = ReactionRule::new(vec![Species::new("A")], vec![Species::new("B")], 1e3);
+    sim.add_reaction(rule).unwrap();
+    let numbers = sim
+        .add_number_observer(vec![Species::new("A"), Species::new("B")], 1e-4)
+        .unwrap();
+    let trajectories = sim
+        .add_trajectory_observer(TrajectoryTarget::Species(Species::new("A")), 1e-4, false)
+        .unwrap();
+    sim.run(2e-3).unwrap();
+
+    let mut counts = Vec::new();
+    sim.number_observer(numbers).write_csv(&mut counts).unwrap();
+    let mut positions = Vec::new();
+    sim.trajectory_observer(trajectories)
+        .write_csv(&mut positions)
+        .unwrap();
+    (counts, positions)
+}
+
+#[test]
+fn same_seed_same_output() {
+    let first = simulate(42);
+    let second = simulate(42);
+    assert_eq!(first, second);
+    assert_ne!(first.0, simulate(43).0);
+}
+
+/// Builds the same crowded, interacting model either way, only setting its
+/// interactions in a different order.
+fn simulate_interacting(reversed: bool) -> Vec<u8> {
+    let r = 1e-8;
+    let mut space = HCPLatticeSpace::new(r, HCPLatticeSize::new(8, 8, 8));
+    let names = ["A", "B", "C"];
+    let ids: Vec<_> = names
+        .iter()
+        .map(|name| space.register_species(Species::new(name), None))
+        .collect();
+    for (i, coordinate) in space
+        .coordinates()
+        .step_by(3)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .enumerate()
+    {
+        let id = ids[i % 3];
+        space.place_particle(id, coordinate).unwrap();
+    }
+    for &id in &ids {
+        space
+            .set_molecule_info(
+                id,
+                MoleculeInfo {
+                    radius: r,
+                    diffusion_coefficient: 1e-12,
+                },
+            )
+            .unwrap();
+    }
+    let mut interactions = vec![
+        (ids[0], ids[1], -1.0),
+        (ids[1], ids[2], 0.5),
+        (ids[0], ids[0], -0.3),
+        (ids[2], ids[0], 1.5),
+    ];
+    if reversed {
+        interactions.reverse();
+    }
+    for (a, b, energy) in interactions {
+        space.set_interaction(a, b, energy);
+    }
+
+    let mut sim = Simulator::with_seed(space, 7);
+    let mut species: Vec<Species> = names.iter().map(|name| Species::new(name)).collect();
+    let first = sim.add_number_observer(species.clone(), 1e-4).unwrap();
+    species.reverse();
+    let second = sim.add_number_observer(species, 1e-4).unwrap();
+    let trajectory = sim
+        .add_trajectory_observer(TrajectoryTarget::Species(Species::new("B")), 1e-4, false)
+        .unwrap();
+    sim.run(2e-3).unwrap();
+
+    let mut output = Vec::new();
+    sim.number_observer(first).write_csv(&mut output).unwrap();
+    sim.number_observer(second).write_csv(&mut output).unwrap();
+    sim.trajectory_observer(trajectory)
+        .write_csv(&mut output)
+        .unwrap();
+    output
+}
+
+#[test]
+fn order_of_setup_calls_does_not_matter() {
+    assert_eq!(simulate_interacting(false), simulate_interacting(true));
+}